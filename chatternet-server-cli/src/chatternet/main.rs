@@ -0,0 +1,329 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use chatternet::client::{self, PageQuery};
+use chatternet::didkey::{actor_id_from_did, did_from_jwk};
+use chatternet::model::{
+    find_hashtags, ActivityType, CollectionPageFields, Document, Message, MessageBuilder,
+    MessageFields, NoteMd1kFields, Tag30Fields, Uri, VecUris,
+};
+use clap::{Parser, Subcommand};
+use serde_json::Value;
+use ssi::jwk::JWK;
+use tokio;
+
+use chatternet_server_http::keys::read_key_file;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// URL of the server API, including its prefix
+    #[arg(short = 's', env = "CHATTERNET_SERVER")]
+    server: String,
+    /// Key file of the actor signing the messages
+    #[arg(short = 'k', env = "CHATTERNET_KEY")]
+    path_key: PathBuf,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Post a note to the actor's followers and to the followers of its
+    /// hashtags
+    Post { content: String },
+    /// Post a note in reply to the document with ID `document_id`
+    Reply { document_id: Uri, content: String },
+    /// Follow the actor or document with ID `id`
+    Follow {
+        id: String,
+        /// Interpret the ID as the name of a tag
+        #[arg(short = 't')]
+        tag: bool,
+    },
+    /// Unfollow the actor or document with ID `id`
+    Unfollow {
+        id: String,
+        /// Interpret the ID as the name of a tag
+        #[arg(short = 't')]
+        tag: bool,
+    },
+    /// Delete the document with ID `document_id`
+    Delete { document_id: Uri },
+    /// Print a page of the actor's inbox
+    Inbox {
+        #[arg(short = 'n')]
+        page_size: Option<u64>,
+        #[arg(short = 'i')]
+        start_idx: Option<u64>,
+    },
+    /// Print the document with ID `id`
+    Get { id: String },
+}
+
+/// Sign messages with an actor's key and exchange them with a server.
+struct Client {
+    api: client::Client,
+    jwk: JWK,
+    did: String,
+    actor_id: String,
+}
+
+impl Client {
+    fn new(server: &str, jwk: JWK) -> Result<Self> {
+        let did = did_from_jwk(&jwk)?;
+        let actor_id = actor_id_from_did(&did)?;
+        Ok(Self {
+            api: client::Client::new(server),
+            jwk,
+            did,
+            actor_id,
+        })
+    }
+
+    /// Post a note with `content`, and the message creating it.
+    async fn post_note(&self, content: String, in_reply_to: Option<Uri>) -> Result<MessageFields> {
+        let mut to = vec![format!("{}/followers", self.actor_id)];
+        for name in find_hashtags(&content) {
+            to.push(format!("{}/followers", Tag30Fields::new(name).await?.id()));
+        }
+        let note =
+            NoteMd1kFields::new(content, self.actor_id.as_str().try_into()?, in_reply_to).await?;
+        let message = MessageBuilder::new(
+            &self.jwk,
+            ActivityType::Create,
+            vec![note.id().clone()].try_into()?,
+        )
+        .to(VecUris::from_truncate(
+            to.into_iter()
+                .map(Uri::try_from)
+                .collect::<Result<Vec<Uri>>>()?,
+        ))
+        .build()
+        .await?;
+        // the server accepts a document only once a message references it
+        self.api.post_message(&message).await?;
+        self.api.post_document(&note).await?;
+        Ok(message)
+    }
+
+    /// Add or remove the object with `id`, or the tag named `id`, from the
+    /// actor's following collection.
+    async fn change_following(
+        &self,
+        type_: ActivityType,
+        id: &str,
+        tag: bool,
+    ) -> Result<MessageFields> {
+        let tag = if tag {
+            Some(Tag30Fields::new(id.to_string()).await?)
+        } else {
+            None
+        };
+        let id: Uri = match &tag {
+            Some(tag) => tag.id().clone(),
+            None => id.try_into()?,
+        };
+        let message = MessageBuilder::new(&self.jwk, type_, vec![id].try_into()?)
+            .target(vec![format!("{}/following", self.actor_id).try_into()?].try_into()?)
+            .build()
+            .await?;
+        self.api.post_message(&message).await?;
+        // let others look up the name of the followed tag
+        if let (ActivityType::Add, Some(tag)) = (type_, tag) {
+            self.api.post_document(&tag).await?;
+        }
+        Ok(message)
+    }
+
+    async fn delete(&self, document_id: Uri) -> Result<MessageFields> {
+        let message = MessageBuilder::new(
+            &self.jwk,
+            ActivityType::Delete,
+            vec![document_id].try_into()?,
+        )
+        .build()
+        .await?;
+        self.api.post_message(&message).await?;
+        Ok(message)
+    }
+
+    async fn get_inbox(&self, query: PageQuery) -> Result<CollectionPageFields<MessageFields>> {
+        self.api.get_inbox_page(&self.did, query).await
+    }
+
+    async fn get_document(&self, id: &str) -> Result<Value> {
+        self.api.get_document(id).await
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let client = Client::new(&args.server, read_key_file(&args.path_key)?)?;
+
+    match args.command {
+        Commands::Post { content } => {
+            let message = client.post_note(content, None).await?;
+            println!("{}", message.object()[0]);
+        }
+        Commands::Reply {
+            document_id,
+            content,
+        } => {
+            let message = client.post_note(content, Some(document_id)).await?;
+            println!("{}", message.object()[0]);
+        }
+        Commands::Follow { id, tag } => {
+            client.change_following(ActivityType::Add, &id, tag).await?;
+        }
+        Commands::Unfollow { id, tag } => {
+            client
+                .change_following(ActivityType::Remove, &id, tag)
+                .await?;
+        }
+        Commands::Delete { document_id } => {
+            client.delete(document_id).await?;
+        }
+        Commands::Inbox {
+            page_size,
+            start_idx,
+        } => {
+            let inbox = client
+                .get_inbox(PageQuery {
+                    page_size,
+                    start_idx,
+                })
+                .await?;
+            println!("{}", serde_json::to_string_pretty(&inbox)?);
+        }
+        Commands::Get { id } => {
+            let document = client.get_document(&id).await?;
+            println!("{}", serde_json::to_string_pretty(&document)?);
+        }
+    };
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use chatternet::didkey::build_jwk;
+    use chatternet::model::CollectionPage;
+    use tokio::sync::RwLock;
+
+    use chatternet_server_http::db::Connector;
+    use chatternet_server_http::handlers::{spawn_local_api, AppState};
+
+    use super::*;
+
+    /// Serve the API on a free local port, returning its URL.
+    async fn serve_api() -> String {
+        let connector = Arc::new(RwLock::new(
+            Connector::new("sqlite::memory:").await.unwrap(),
+        ));
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        spawn_local_api(AppState::new(connector, jwk), "api")
+    }
+
+    fn build_client(server: &str) -> Client {
+        Client::new(server, build_jwk(&mut rand::thread_rng()).unwrap()).unwrap()
+    }
+
+    async fn get_inbox_objects(client: &Client) -> Vec<String> {
+        client
+            .get_inbox(PageQuery {
+                page_size: Some(8),
+                start_idx: None,
+            })
+            .await
+            .unwrap()
+            .items()
+            .iter()
+            .flat_map(|x| x.object().iter().map(|x| x.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn posts_and_reads_notes() {
+        let server = serve_api().await;
+        let client_1 = build_client(&server);
+        let client_2 = build_client(&server);
+
+        client_2
+            .change_following(ActivityType::Add, &client_1.actor_id, false)
+            .await
+            .unwrap();
+        let message = client_1.post_note("abc".to_string(), None).await.unwrap();
+        let note_id = message.object()[0].to_string();
+        assert_eq!(get_inbox_objects(&client_2).await, [note_id.clone()]);
+
+        let note = client_2.get_document(&note_id).await.unwrap();
+        assert_eq!(note["content"], "abc");
+
+        let reply = client_2
+            .post_note(
+                "def".to_string(),
+                Some(note_id.as_str().try_into().unwrap()),
+            )
+            .await
+            .unwrap();
+        let reply = client_1
+            .get_document(reply.object()[0].as_str())
+            .await
+            .unwrap();
+        assert_eq!(reply["inReplyTo"], note_id.as_str());
+
+        // deleted notes are no longer served, nor their deleted messages
+        client_1
+            .delete(note_id.as_str().try_into().unwrap())
+            .await
+            .unwrap();
+        client_2.get_document(&note_id).await.unwrap_err();
+        client_1.delete(message.id().clone()).await.unwrap();
+        assert!(get_inbox_objects(&client_2).await.is_empty());
+
+        // unfollowed actors' notes no longer reach the inbox
+        client_2
+            .change_following(ActivityType::Remove, &client_1.actor_id, false)
+            .await
+            .unwrap();
+        client_1.post_note("ghi".to_string(), None).await.unwrap();
+        assert!(get_inbox_objects(&client_2).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn follows_tags() {
+        let server = serve_api().await;
+        let client_1 = build_client(&server);
+        let client_2 = build_client(&server);
+
+        client_2
+            .change_following(ActivityType::Add, "abc", true)
+            .await
+            .unwrap();
+        let tag = Tag30Fields::new("abc".to_string()).await.unwrap();
+        let tag_back = client_1.get_document(tag.id().as_str()).await.unwrap();
+        assert_eq!(tag_back["name"], "abc");
+
+        let message = client_1
+            .post_note("a note about #abc".to_string(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_inbox_objects(&client_2).await,
+            [message.object()[0].to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_server_errors() {
+        let server = serve_api().await;
+        let client = build_client(&server);
+        let error = client.get_document("urn:cid:a").await.unwrap_err();
+        assert!(error.to_string().contains("document is not known"));
+    }
+}
@@ -1,36 +1,80 @@
 use std::fs;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use anyhow::{Error, Result};
 use chatternet::didkey::{actor_id_from_did, did_from_jwk};
-use chatternet::model::Uri;
+use chatternet::model::{MessageFields, Uri};
 use clap::{Parser, Subcommand};
-use serde_json;
+use serde_json::{json, Value};
 use tokio;
+use tokio::sync::RwLock;
 
 use chatternet_server_http::db::{self, Connector};
+use chatternet_server_http::handlers::{
+    delete_message, export_actor, flush_relay, import_actor, ActorArchive, AppState,
+};
+use chatternet_server_http::keys::read_key_file;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     path_key: PathBuf,
     path_db: PathBuf,
+    /// Print the output as JSON
+    #[arg(short = 'j', long)]
+    json: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
+    /// Make the server actor follow the actor with ID `actor_id`
     Follow { actor_id: Uri },
+    /// Make the server actor stop following the actor with ID `actor_id`
+    Unfollow { actor_id: Uri },
+    /// List the IDs followed by the actor with ID `actor_id`
     ListFollows { actor_id: Uri },
+    /// List the IDs followed by the server actor
     ListServerFollows,
+    /// List the IDs of the actors following the actor with ID `actor_id`
+    ListFollowers { actor_id: Uri },
+    /// List the audiences from which the actor with ID `actor_id` receives
+    /// messages
+    ListAudiences { actor_id: Uri },
+    /// Print the document with ID `document_id` and the IDs of the messages
+    /// referencing it
+    ShowDocument { document_id: Uri },
+    /// Delete the message with ID `message_id` with its associations
+    DeleteMessage { message_id: Uri },
+    /// Print the number of rows in each table
+    CountRows,
+    /// Write the archive of the actor with ID `actor_id`, with all of its
+    /// messages, to `path_out`
+    ExportActor { actor_id: Uri, path_out: PathBuf },
+    /// Accept the items of the archive at `path_in` for the actor with ID
+    /// `actor_id`
+    ImportActor { actor_id: Uri, path_in: PathBuf },
+}
+
+/// Print `ids` one per line, or as a JSON array.
+fn print_ids(ids: &[String], json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(ids)?);
+    } else {
+        for id in ids {
+            println!("{}", id);
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    let jwk = serde_json::from_str(&fs::read_to_string(&args.path_key)?)?;
+    let jwk = read_key_file(&args.path_key)?;
     let server_did = did_from_jwk(&jwk)?;
     let server_actor_id = actor_id_from_did(&server_did)?;
     let mut connector = Connector::new(&format!(
@@ -50,16 +94,125 @@ async fn main() -> Result<()> {
             )
             .await?;
         }
+        Commands::Unfollow { actor_id } => {
+            let mut connection = connector.connection_mut().await?;
+            db::delete_actor_following(&mut *connection, &server_actor_id, actor_id.as_str())
+                .await?;
+            db::delete_actor_audience(
+                &mut *connection,
+                &server_actor_id,
+                &format!("{}/followers", actor_id.as_str()),
+            )
+            .await?;
+        }
         Commands::ListFollows { actor_id } => {
             let mut connection = connector.connection_mut().await?;
-            for id in db::get_actor_followings(&mut *connection, actor_id.as_str()).await? {
-                println!("{}", id);
-            }
+            let ids = db::get_actor_followings(&mut *connection, actor_id.as_str()).await?;
+            print_ids(&ids, args.json)?;
         }
         Commands::ListServerFollows => {
             let mut connection = connector.connection_mut().await?;
-            for id in db::get_actor_followings(&mut *connection, &server_actor_id).await? {
-                println!("{}", id);
+            let ids = db::get_actor_followings(&mut *connection, &server_actor_id).await?;
+            print_ids(&ids, args.json)?;
+        }
+        Commands::ListFollowers { actor_id } => {
+            let mut connection = connector.connection_mut().await?;
+            let ids = db::get_actor_all_followers(&mut *connection, actor_id.as_str()).await?;
+            print_ids(&ids, args.json)?;
+        }
+        Commands::ListAudiences { actor_id } => {
+            let mut connection = connector.connection_mut().await?;
+            let ids = db::get_actor_audiences(&mut *connection, actor_id.as_str()).await?;
+            print_ids(&ids, args.json)?;
+        }
+        Commands::ShowDocument { document_id } => {
+            let mut connection = connector.connection_mut().await?;
+            let document = db::get_document(&mut *connection, document_id.as_str())
+                .await?
+                .map(|x| serde_json::from_str::<Value>(&x))
+                .transpose()?;
+            let messages_id =
+                db::get_document_messages(&mut *connection, document_id.as_str(), None).await?;
+            if args.json {
+                let out = json!({ "document": document, "messages": messages_id });
+                println!("{}", serde_json::to_string_pretty(&out)?);
+            } else {
+                match document {
+                    Some(document) => println!("{}", serde_json::to_string_pretty(&document)?),
+                    None => println!("document is not stored"),
+                }
+                println!("referenced by {} messages", messages_id.len());
+                print_ids(&messages_id, false)?;
+            }
+        }
+        Commands::DeleteMessage { message_id } => {
+            let mut connection = connector.transaction().await?;
+            let message = db::get_document(&mut *connection, message_id.as_str())
+                .await?
+                .ok_or(Error::msg("message is not known"))?;
+            let message: MessageFields = serde_json::from_str(&message)
+                .map_err(|_| Error::msg("document is not a message"))?;
+            delete_message(&message, &mut *connection)
+                .await
+                .map_err(|error| Error::msg(format!("failed to delete message: {:?}", error)))?;
+            connection.commit().await?;
+        }
+        Commands::CountRows => {
+            let mut connection = connector.connection_mut().await?;
+            let counts = db::get_table_counts(&mut *connection).await?;
+            if args.json {
+                let out: serde_json::Map<String, Value> =
+                    counts.into_iter().map(|(x, y)| (x, json!(y))).collect();
+                println!("{}", serde_json::to_string_pretty(&out)?);
+            } else {
+                for (table, count) in counts {
+                    println!("{:<24}{}", table, count);
+                }
+            }
+        }
+        Commands::ExportActor { actor_id, path_out } => {
+            let mut connection = connector.connection_mut().await?;
+            let archive = export_actor(&mut *connection, actor_id.as_str(), false)
+                .await
+                .map_err(|error| Error::msg(format!("failed to export actor: {:?}", error)))?;
+            fs::write(path_out, serde_json::to_string(&archive)?)?;
+        }
+        Commands::ImportActor { actor_id, path_in } => {
+            let archive: ActorArchive = serde_json::from_str(&fs::read_to_string(path_in)?)
+                .map_err(|_| Error::msg("archive is invalid"))?;
+            let state = AppState::new(Arc::new(RwLock::new(connector)), jwk);
+            let report = import_actor(&state, actor_id.as_str(), &archive)
+                .await
+                .map_err(|error| Error::msg(format!("failed to import actor: {:?}", error)))?;
+            // the relay lives only as long as the command
+            flush_relay(&state, true)
+                .await
+                .map_err(|error| Error::msg(format!("failed to flush relay: {:?}", error)))?;
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("actor accepted: {}", report.actor_accepted);
+                println!(
+                    "messages accepted: {}, already stored: {}, rejected: {}",
+                    report.messages_accepted,
+                    report.messages_known,
+                    report.messages_rejected.len()
+                );
+                println!(
+                    "documents accepted: {}, rejected: {}",
+                    report.documents_accepted,
+                    report.documents_rejected.len()
+                );
+                for id in report
+                    .messages_rejected
+                    .iter()
+                    .chain(report.documents_rejected.iter())
+                {
+                    println!("rejected {}", id);
+                }
+                for id in report.following_missing {
+                    println!("not following {}", id);
+                }
             }
         }
     };
@@ -1,55 +1,146 @@
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Error, Result};
-use chatternet::didkey::{build_jwk, did_from_jwk};
+use chatternet::didkey::{build_jwk, build_jwk_from_mnemonic, build_mnemonic, did_from_jwk};
+use chatternet::keystore::KdfParams;
 use chatternet::model::{ActorFields, ActorType};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use serde_json;
+use ssi::jwk::JWK;
 use tokio;
 
+use chatternet_server_http::keys::{
+    read_key_file, read_mnemonic, read_mnemonic_passphrase, read_new_passphrase, write_key_file,
+};
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    path_key: PathBuf,
-    path_out: PathBuf,
-    url_base: String,
-    name: String,
-    #[arg(short = 'p')]
-    prefix: Option<String>,
-    #[arg(short = 'k')]
-    new_key: bool,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Write the server actor document, optionally with a new key
+    Create {
+        path_key: PathBuf,
+        path_out: PathBuf,
+        url_base: String,
+        name: String,
+        #[arg(short = 'p')]
+        prefix: Option<String>,
+        #[arg(short = 'k')]
+        new_key: bool,
+        /// Derive the new key from a new mnemonic, which is printed
+        #[arg(short = 'm')]
+        mnemonic: bool,
+        /// Index of the key derived from the mnemonic
+        #[arg(short = 'i', default_value_t = 0)]
+        index: u32,
+        /// Write the new key unencrypted
+        #[arg(short = 'u')]
+        unencrypted: bool,
+    },
+    /// Rebuild a key file from its mnemonic
+    Recover {
+        path_key: PathBuf,
+        /// Index of the key derived from the mnemonic
+        #[arg(short = 'i', default_value_t = 0)]
+        index: u32,
+        /// Write the key unencrypted
+        #[arg(short = 'u')]
+        unencrypted: bool,
+    },
+    /// Encrypt a key file with a new passphrase, including a plain key file
+    Encrypt { path_key: PathBuf },
+    /// Write an unencrypted copy of a key file
+    Export {
+        path_key: PathBuf,
+        path_out: PathBuf,
+    },
+}
+
+/// Write `jwk` to `path_key`, encrypted with a new passphrase unless
+/// `unencrypted`.
+fn write_new_key(path_key: &Path, jwk: &JWK, unencrypted: bool, overwrite: bool) -> Result<()> {
+    let passphrase = if unencrypted {
+        None
+    } else {
+        Some(read_new_passphrase()?)
+    };
+    write_key_file(
+        path_key,
+        jwk,
+        passphrase.as_deref(),
+        KdfParams::default(),
+        overwrite,
+    )
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    if args.new_key {
-        let jwk = build_jwk(&mut rand::thread_rng())?;
-        fs::OpenOptions::new()
-            // ensure not overwriting existing key
-            .create_new(true)
-            .write(true)
-            .open(&args.path_key)
-            .map_err(|_| {
-                Error::msg("unable to write a new key file, ensure no file already exists")
-            })?
-            .write_all(serde_json::to_string(&jwk)?.as_bytes())?;
-    }
-    let jwk = serde_json::from_str(
-        &fs::read_to_string(&args.path_key).map_err(|_| Error::msg("key does not exist"))?,
-    )
-    .map_err(|_| Error::msg("key is invalid"))?;
-    let did = did_from_jwk(&jwk).map_err(|_| Error::msg("key is cannot be parsed as a DID"))?;
-    if args.url_base.ends_with('/') {
-        Err(Error::msg("url base has a trailing /"))?;
-    }
-    let url = match args.prefix {
-        Some(prefix) => format!("{}/{}/{}/actor", args.url_base, prefix, &did),
-        None => format!("{}/{}/actor", args.url_base, &did),
+    match args.command {
+        Commands::Create {
+            path_key,
+            path_out,
+            url_base,
+            name,
+            prefix,
+            new_key,
+            mnemonic,
+            index,
+            unencrypted,
+        } => {
+            let jwk = if mnemonic {
+                let mnemonic = build_mnemonic(&mut rand::thread_rng())?;
+                let jwk =
+                    build_jwk_from_mnemonic(&mnemonic, &read_mnemonic_passphrase(true)?, index)?;
+                write_new_key(&path_key, &jwk, unencrypted, false)?;
+                println!("{}", mnemonic);
+                jwk
+            } else if new_key {
+                let jwk = build_jwk(&mut rand::thread_rng())?;
+                write_new_key(&path_key, &jwk, unencrypted, false)?;
+                jwk
+            } else {
+                read_key_file(&path_key)?
+            };
+            let did =
+                did_from_jwk(&jwk).map_err(|_| Error::msg("key is cannot be parsed as a DID"))?;
+            if url_base.ends_with('/') {
+                Err(Error::msg("url base has a trailing /"))?;
+            }
+            let url = match prefix {
+                Some(prefix) => format!("{}/{}/{}/actor", url_base, prefix, &did),
+                None => format!("{}/{}/actor", url_base, &did),
+            };
+            let actor = ActorFields::new(&jwk, ActorType::Service, Some(name), Some(url)).await?;
+            fs::write(path_out, serde_json::to_string_pretty(&actor)?)?;
+        }
+        Commands::Recover {
+            path_key,
+            index,
+            unencrypted,
+        } => {
+            let jwk = build_jwk_from_mnemonic(
+                &read_mnemonic()?,
+                &read_mnemonic_passphrase(false)?,
+                index,
+            )?;
+            write_new_key(&path_key, &jwk, unencrypted, false)?;
+            println!("{}", did_from_jwk(&jwk)?);
+        }
+        Commands::Encrypt { path_key } => {
+            let jwk = read_key_file(&path_key)?;
+            write_new_key(&path_key, &jwk, false, true)?;
+        }
+        Commands::Export { path_key, path_out } => {
+            let jwk = read_key_file(&path_key)?;
+            write_key_file(&path_out, &jwk, None, KdfParams::default(), false)?;
+        }
     };
-    let actor = ActorFields::new(&jwk, ActorType::Service, Some(args.name), Some(url)).await?;
-    fs::write(args.path_out, serde_json::to_string_pretty(&actor)?)?;
     Ok(())
 }
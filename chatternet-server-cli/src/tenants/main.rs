@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+
+use chatternet_server_http::tenants::{parse_actor_url, TenantConfig, TenantsConfig};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    path_tenants: PathBuf,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    Add {
+        path_actor: PathBuf,
+        path_key: PathBuf,
+        /// DB of the tenant, instead of the server's DB
+        #[arg(short = 'd')]
+        path_db: Option<PathBuf>,
+        /// JSON file with the policy for relaying messages to the tenant followers
+        #[arg(short = 'r')]
+        path_relay_policy: Option<PathBuf>,
+    },
+    Remove {
+        did: String,
+    },
+    List,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let mut tenants = TenantsConfig::load(&args.path_tenants)?;
+
+    match args.command {
+        Commands::Add {
+            path_actor,
+            path_key,
+            path_db,
+            path_relay_policy,
+        } => {
+            tenants.add(TenantConfig {
+                path_actor,
+                path_key,
+                path_db,
+                path_relay_policy,
+            })?;
+            tenants.save(&args.path_tenants)?;
+        }
+        Commands::Remove { did } => {
+            tenants.remove(&did)?;
+            tenants.save(&args.path_tenants)?;
+        }
+        Commands::List => {
+            for tenant in tenants.tenants.iter() {
+                let actor = tenant.read_actor()?;
+                let parsed_url = parse_actor_url(&actor)?;
+                println!("{} /{}", parsed_url.did, parsed_url.prefix);
+            }
+        }
+    };
+
+    Ok(())
+}
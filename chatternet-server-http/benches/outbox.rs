@@ -0,0 +1,104 @@
+//! Compare posting messages to the outbox one at a time and concurrently.
+//!
+//! Messages are verified before the DB lock is taken, so concurrent posts
+//! should take a fraction of the time of sequential ones on a multi-core
+//! machine.
+
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use chatternet::cache::VerificationCache;
+use chatternet::didkey::{build_jwk, did_from_jwk};
+use chatternet::model::{ActivityType, MessageBuilder, MessageFields};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use tokio::runtime::Runtime;
+use tokio::sync::RwLock;
+use tower::ServiceExt;
+
+use chatternet_server_http::db::Connector;
+use chatternet_server_http::handlers::{build_api, AppState};
+
+const NUM_MESSAGES: usize = 32;
+
+async fn build_test_api() -> Router {
+    let connector = Arc::new(RwLock::new(
+        Connector::new("sqlite::memory:").await.unwrap(),
+    ));
+    // verify every message rather than remember the verifications
+    let state = AppState {
+        cache: Arc::new(VerificationCache::new(0)),
+        ..AppState::new(connector, build_jwk(&mut rand::thread_rng()).unwrap())
+    };
+    build_api(state, "api", "did:example:server")
+}
+
+async fn build_messages() -> Vec<(String, MessageFields)> {
+    let mut messages = Vec::new();
+    for _ in 0..NUM_MESSAGES {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let message = MessageBuilder::new(
+            &jwk,
+            ActivityType::Create,
+            vec!["id:a".try_into().unwrap()].try_into().unwrap(),
+        )
+        .build()
+        .await
+        .unwrap();
+        messages.push((did, message));
+    }
+    messages
+}
+
+async fn post_message(api: Router, did: String, message: MessageFields) {
+    let request = Request::builder()
+        .method("POST")
+        .uri(format!("/api/{}/actor/outbox", did))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(&message).unwrap()))
+        .unwrap();
+    let response = api.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+fn bench_outbox(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let messages = runtime.block_on(build_messages());
+
+    let mut group = c.benchmark_group("outbox");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.to_async(&runtime).iter_batched(
+            || messages.clone(),
+            |messages| async move {
+                let api = build_test_api().await;
+                for (did, message) in messages {
+                    post_message(api.clone(), did, message).await;
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("concurrent", |b| {
+        b.to_async(&runtime).iter_batched(
+            || messages.clone(),
+            |messages| async move {
+                let api = build_test_api().await;
+                let requests = messages
+                    .into_iter()
+                    .map(|(did, message)| tokio::spawn(post_message(api.clone(), did, message)))
+                    .collect::<Vec<_>>();
+                for request in requests {
+                    request.await.unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_outbox);
+criterion_main!(benches);
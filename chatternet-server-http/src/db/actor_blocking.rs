@@ -0,0 +1,153 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use sqlx::{Row, SqliteConnection};
+
+use super::joint_id;
+
+pub async fn create_actor_blocking(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `ActorsBlockings` \
+        (\
+            `joint_id` TEXT PRIMARY KEY, \
+            `actor_id` TEXT NOT NULL, \
+            `blocked_id` TEXT NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `actors_blockings_actor_id` \
+        ON `ActorsBlockings`(`actor_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn put_actor_blocking(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    blocked_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        INSERT OR IGNORE INTO `ActorsBlockings` \
+        (`joint_id`, `actor_id`, `blocked_id`) \
+        VALUES($1, $2, $3);\
+        ",
+    )
+    .bind(joint_id(&[actor_id, blocked_id]))
+    .bind(actor_id)
+    .bind(blocked_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_actor_blocking(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    blocked_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `ActorsBlockings` \
+        WHERE `joint_id` = $1;\
+        ",
+    )
+    .bind(joint_id(&[actor_id, blocked_id]))
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_actor_all_blocking(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `ActorsBlockings` \
+        WHERE `actor_id` = $1;\
+        ",
+    )
+    .bind(actor_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_actor_blockings(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+) -> Result<Vec<String>> {
+    let query = sqlx::query(
+        "\
+        SELECT `blocked_id` FROM `ActorsBlockings` \
+        WHERE `actor_id` = $1;\
+        ",
+    )
+    .bind(actor_id);
+    let mut blocked_ids = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let blocked_id: &str = row.try_get("blocked_id")?;
+        blocked_ids.push(blocked_id.to_string());
+    }
+    Ok(blocked_ids)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio;
+
+    use super::super::Connector;
+    use super::*;
+
+    #[tokio::test]
+    async fn puts_gets_deletes_actor_blockings() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        put_actor_blocking(&mut connection, "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+        put_actor_blocking(&mut connection, "did:1/actor", "did:3/actor")
+            .await
+            .unwrap();
+        put_actor_blocking(&mut connection, "did:2/actor", "did:1/actor")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_actor_blockings(&mut connection, "did:1/actor")
+                .await
+                .unwrap(),
+            ["did:2/actor", "did:3/actor"]
+        );
+        delete_actor_blocking(&mut connection, "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_actor_blockings(&mut connection, "did:1/actor")
+                .await
+                .unwrap(),
+            ["did:3/actor"]
+        );
+        delete_actor_all_blocking(&mut connection, "did:1/actor")
+            .await
+            .unwrap();
+        assert!(get_actor_blockings(&mut connection, "did:1/actor")
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            get_actor_blockings(&mut connection, "did:2/actor")
+                .await
+                .unwrap(),
+            ["did:1/actor"]
+        );
+    }
+}
@@ -111,6 +111,27 @@ pub async fn get_actor_followings(
     Ok(followings_id)
 }
 
+/// Get the IDs of all the actors following `actor_id`.
+pub async fn get_actor_all_followers(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+) -> Result<Vec<String>> {
+    let query = sqlx::query(
+        "\
+        SELECT `actor_id` FROM `ActorsFollowings` \
+        WHERE `following_id` = $1;\
+        ",
+    )
+    .bind(actor_id);
+    let mut followers_id = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let follower_id: &str = row.try_get("actor_id")?;
+        followers_id.push(follower_id.to_string());
+    }
+    Ok(followers_id)
+}
+
 pub async fn get_actor_followers(
     connection: &mut SqliteConnection,
     actor_id: &str,
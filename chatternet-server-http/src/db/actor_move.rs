@@ -0,0 +1,267 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use sqlx::{Row, SqliteConnection};
+
+use super::{build_inbox_messages, joint_id, CollectionPageOut};
+
+pub async fn create_actor_moves(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `ActorsMoves` \
+        (\
+            `idx` INTEGER PRIMARY KEY AUTOINCREMENT, \
+            `joint_id` TEXT UNIQUE NOT NULL, \
+            `actor_id` TEXT NOT NULL, \
+            `old_id` TEXT NOT NULL, \
+            `new_id` TEXT NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `actors_moves_old_id` \
+        ON `ActorsMoves`(`old_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `MovedMessages` \
+        (\
+            `idx` INTEGER PRIMARY KEY AUTOINCREMENT, \
+            `message_id` TEXT UNIQUE NOT NULL, \
+            `actor_id` TEXT NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `moved_messages_actor_id` \
+        ON `MovedMessages`(`actor_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Record that `actor_id` signed the move of the actor `old_id` to `new_id`.
+pub async fn put_actor_move(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    old_id: &str,
+    new_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        INSERT OR IGNORE INTO `ActorsMoves` \
+        (`joint_id`, `actor_id`, `old_id`, `new_id`) \
+        VALUES($1, $2, $3, $4);\
+        ",
+    )
+    .bind(joint_id(&[actor_id, old_id, new_id]))
+    .bind(actor_id)
+    .bind(old_id)
+    .bind(new_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Get the ID to which the actor `old_id` moved, if any.
+///
+/// A move is complete only once signed by both the old and the new actor.
+/// If many moves are complete, the one first signed by the old actor is used.
+pub async fn get_actor_moved_to(
+    connection: &mut SqliteConnection,
+    old_id: &str,
+) -> Result<Option<String>> {
+    let query = sqlx::query(
+        "\
+        SELECT `FromOld`.`new_id` FROM `ActorsMoves` AS `FromOld` \
+        INNER JOIN `ActorsMoves` AS `FromNew` \
+        ON `FromOld`.`old_id` = `FromNew`.`old_id` \
+        AND `FromOld`.`new_id` = `FromNew`.`new_id` \
+        WHERE `FromOld`.`old_id` = $1 \
+        AND `FromOld`.`actor_id` = `FromOld`.`old_id` \
+        AND `FromNew`.`actor_id` = `FromNew`.`new_id` \
+        ORDER BY `FromOld`.`idx` \
+        LIMIT 1;\
+        ",
+    )
+    .bind(old_id);
+    let mut rows = query.fetch(&mut *connection);
+    Ok(match rows.try_next().await? {
+        Some(row) => Some(row.try_get::<&str, &str>("new_id")?.to_string()),
+        None => None,
+    })
+}
+
+/// Flag the message `message_id` as sent by `actor_id` after it moved.
+pub async fn put_moved_message(
+    connection: &mut SqliteConnection,
+    message_id: &str,
+    actor_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        INSERT OR IGNORE INTO `MovedMessages` \
+        (`message_id`, `actor_id`) \
+        VALUES($1, $2);\
+        ",
+    )
+    .bind(message_id)
+    .bind(actor_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_moved_message(
+    connection: &mut SqliteConnection,
+    message_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `MovedMessages` \
+        WHERE `message_id` = $1;\
+        ",
+    )
+    .bind(message_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Get the messages flagged as sent by `actor_id` after it moved, most
+/// recent first.
+pub async fn get_moved_messages(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    count: u64,
+    start_idx: Option<u64>,
+) -> Result<Option<CollectionPageOut>> {
+    let query_str = format!(
+        "\
+        SELECT `idx`, `document` FROM `Documents` \
+        INNER JOIN `MovedMessages` \
+        ON `Documents`.`document_id` = `MovedMessages`.`message_id` \
+        WHERE `MovedMessages`.`actor_id` = $1 \
+        {} \
+        ORDER BY `idx` DESC \
+        LIMIT $2;\
+        ",
+        if start_idx.is_some() {
+            "AND `idx` <= $3"
+        } else {
+            ""
+        }
+    );
+    let query = match start_idx {
+        Some(start_idx) => sqlx::query(&query_str)
+            .bind(actor_id)
+            .bind(i64::try_from(count)?)
+            .bind(u32::try_from(start_idx)?),
+        None => sqlx::query(&query_str)
+            .bind(actor_id)
+            .bind(i64::try_from(count)?),
+    };
+    build_inbox_messages(query, connection).await
+}
+
+#[cfg(test)]
+mod test {
+    use tokio;
+
+    use super::super::{put_document, Connector};
+    use super::*;
+
+    #[tokio::test]
+    async fn puts_and_gets_actor_moves() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+
+        // signed only by the old actor
+        put_actor_move(&mut connection, "did:1/actor", "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+        assert!(get_actor_moved_to(&mut connection, "did:1/actor")
+            .await
+            .unwrap()
+            .is_none());
+
+        // accepted by another actor
+        put_actor_move(&mut connection, "did:3/actor", "did:1/actor", "did:3/actor")
+            .await
+            .unwrap();
+        assert!(get_actor_moved_to(&mut connection, "did:1/actor")
+            .await
+            .unwrap()
+            .is_none());
+
+        // accepted by the new actor
+        put_actor_move(&mut connection, "did:2/actor", "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_actor_moved_to(&mut connection, "did:1/actor")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("did:2/actor")
+        );
+        assert!(get_actor_moved_to(&mut connection, "did:2/actor")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn puts_gets_deletes_moved_messages() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        for message_id in ["id:m1", "id:m2", "id:m3"] {
+            put_document(&mut connection, message_id, message_id)
+                .await
+                .unwrap();
+        }
+        put_moved_message(&mut connection, "id:m1", "did:1/actor")
+            .await
+            .unwrap();
+        put_moved_message(&mut connection, "id:m2", "did:1/actor")
+            .await
+            .unwrap();
+        put_moved_message(&mut connection, "id:m3", "did:2/actor")
+            .await
+            .unwrap();
+        // is idempotent
+        put_moved_message(&mut connection, "id:m1", "did:1/actor")
+            .await
+            .unwrap();
+
+        let out = get_moved_messages(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:m2", "id:m1"]);
+        let out = get_moved_messages(&mut connection, "did:1/actor", 3, Some(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:m1"]);
+
+        delete_moved_message(&mut connection, "id:m2")
+            .await
+            .unwrap();
+        let out = get_moved_messages(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:m1"]);
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use sqlx::{Row, SqliteConnection};
+
+use super::joint_id;
+
+pub async fn create_actor_muting(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `ActorsMutings` \
+        (\
+            `joint_id` TEXT PRIMARY KEY, \
+            `actor_id` TEXT NOT NULL, \
+            `muted_id` TEXT NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `actors_mutings_actor_id` \
+        ON `ActorsMutings`(`actor_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn put_actor_muting(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    muted_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        INSERT OR IGNORE INTO `ActorsMutings` \
+        (`joint_id`, `actor_id`, `muted_id`) \
+        VALUES($1, $2, $3);\
+        ",
+    )
+    .bind(joint_id(&[actor_id, muted_id]))
+    .bind(actor_id)
+    .bind(muted_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_actor_muting(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    muted_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `ActorsMutings` \
+        WHERE `joint_id` = $1;\
+        ",
+    )
+    .bind(joint_id(&[actor_id, muted_id]))
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_actor_all_muting(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `ActorsMutings` \
+        WHERE `actor_id` = $1;\
+        ",
+    )
+    .bind(actor_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_actor_mutings(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+) -> Result<Vec<String>> {
+    let query = sqlx::query(
+        "\
+        SELECT `muted_id` FROM `ActorsMutings` \
+        WHERE `actor_id` = $1;\
+        ",
+    )
+    .bind(actor_id);
+    let mut muted_ids = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let muted_id: &str = row.try_get("muted_id")?;
+        muted_ids.push(muted_id.to_string());
+    }
+    Ok(muted_ids)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio;
+
+    use super::super::Connector;
+    use super::*;
+
+    #[tokio::test]
+    async fn puts_gets_deletes_actor_mutings() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        put_actor_muting(&mut connection, "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+        put_actor_muting(&mut connection, "did:1/actor", "did:3/actor")
+            .await
+            .unwrap();
+        put_actor_muting(&mut connection, "did:2/actor", "did:1/actor")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_actor_mutings(&mut connection, "did:1/actor")
+                .await
+                .unwrap(),
+            ["did:2/actor", "did:3/actor"]
+        );
+        delete_actor_muting(&mut connection, "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_actor_mutings(&mut connection, "did:1/actor")
+                .await
+                .unwrap(),
+            ["did:3/actor"]
+        );
+        delete_actor_all_muting(&mut connection, "did:1/actor")
+            .await
+            .unwrap();
+        assert!(get_actor_mutings(&mut connection, "did:1/actor")
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            get_actor_mutings(&mut connection, "did:2/actor")
+                .await
+                .unwrap(),
+            ["did:1/actor"]
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use sqlx::{Row, SqliteConnection};
+
+use super::joint_id;
+
+pub async fn create_actor_revocation(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `ActorsRevocations` \
+        (\
+            `joint_id` TEXT PRIMARY KEY, \
+            `actor_id` TEXT NOT NULL, \
+            `revoked_id` TEXT NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `actors_revocations_actor_id` \
+        ON `ActorsRevocations`(`actor_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn put_actor_revocation(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    revoked_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        INSERT OR IGNORE INTO `ActorsRevocations` \
+        (`joint_id`, `actor_id`, `revoked_id`) \
+        VALUES($1, $2, $3);\
+        ",
+    )
+    .bind(joint_id(&[actor_id, revoked_id]))
+    .bind(actor_id)
+    .bind(revoked_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_actor_revocation(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    revoked_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `ActorsRevocations` \
+        WHERE `joint_id` = $1;\
+        ",
+    )
+    .bind(joint_id(&[actor_id, revoked_id]))
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_actor_all_revocations(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `ActorsRevocations` \
+        WHERE `actor_id` = $1;\
+        ",
+    )
+    .bind(actor_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_actor_revocations(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+) -> Result<Vec<String>> {
+    let query = sqlx::query(
+        "\
+        SELECT `revoked_id` FROM `ActorsRevocations` \
+        WHERE `actor_id` = $1;\
+        ",
+    )
+    .bind(actor_id);
+    let mut revoked_ids = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let revoked_id: &str = row.try_get("revoked_id")?;
+        revoked_ids.push(revoked_id.to_string());
+    }
+    Ok(revoked_ids)
+}
+
+pub async fn has_actor_revocation(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    revoked_id: &str,
+) -> Result<bool> {
+    let query = sqlx::query(
+        "\
+        SELECT 1 FROM `ActorsRevocations` \
+        WHERE `joint_id` = $1 \
+        LIMIT 1;\
+        ",
+    )
+    .bind(joint_id(&[actor_id, revoked_id]));
+    Ok(query.fetch_optional(&mut *connection).await?.is_some())
+}
+
+#[cfg(test)]
+mod test {
+    use tokio;
+
+    use super::super::Connector;
+    use super::*;
+
+    #[tokio::test]
+    async fn puts_gets_deletes_actor_revocations() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        put_actor_revocation(&mut connection, "did:1/actor", "urn:cid:2")
+            .await
+            .unwrap();
+        put_actor_revocation(&mut connection, "did:1/actor", "urn:cid:3")
+            .await
+            .unwrap();
+        put_actor_revocation(&mut connection, "did:2/actor", "urn:cid:1")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_actor_revocations(&mut connection, "did:1/actor")
+                .await
+                .unwrap(),
+            ["urn:cid:2", "urn:cid:3"]
+        );
+        assert!(
+            has_actor_revocation(&mut connection, "did:1/actor", "urn:cid:2")
+                .await
+                .unwrap()
+        );
+        delete_actor_revocation(&mut connection, "did:1/actor", "urn:cid:2")
+            .await
+            .unwrap();
+        assert!(
+            !has_actor_revocation(&mut connection, "did:1/actor", "urn:cid:2")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            get_actor_revocations(&mut connection, "did:1/actor")
+                .await
+                .unwrap(),
+            ["urn:cid:3"]
+        );
+        delete_actor_all_revocations(&mut connection, "did:1/actor")
+            .await
+            .unwrap();
+        assert!(get_actor_revocations(&mut connection, "did:1/actor")
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            get_actor_revocations(&mut connection, "did:2/actor")
+                .await
+                .unwrap(),
+            ["urn:cid:1"]
+        );
+    }
+}
@@ -0,0 +1,765 @@
+//! A materialized view of each actor's inbox.
+//!
+//! The inbox queries in the parent module join the followings, audiences,
+//! lists, blockings and mutings of an actor at every read. The `Inboxes`
+//! table instead lists, for each actor, the messages delivered to it: those
+//! by itself and by the actors it follows, less those by actors it blocks or
+//! mutes. Each row flags if the message is also addressed to the actor, so
+//! that reading an inbox is a range scan over a single index, and keeps the
+//! sender so that the inbox from a single followed actor is one too. The
+//! inbox with audiences filters the delivered messages of the actor by the
+//! audience index of `MessagesAudiences`.
+//!
+//! The table exists only in DBs served with the materialized inbox. It is
+//! created along with triggers on the tables it depends on, which deliver
+//! each stored message to its recipients and re-deliver or re-address only
+//! the rows affected by a change to a relationship. The writes to those
+//! tables then don't need to know whether the table exists.
+//!
+//! Checking if an inbox contains a message is a single lookup, and reads
+//! from the relationship tables, so that it works in any DB.
+
+use anyhow::Result;
+use chatternet::model::PUBLIC_AUDIENCE_ID;
+use sqlx::SqliteConnection;
+
+use super::{
+    build_audiences_condition, build_inbox_from_condition, build_inbox_messages,
+    build_list_owner_condition, CollectionPageOut,
+};
+
+/// Build the condition for the messages of the `sender` SQL expression to be
+/// delivered to the inbox of the `recipient` SQL expression.
+fn build_delivered_condition(recipient: &str, sender: &str) -> String {
+    format!(
+        "\
+        (\
+            {sender} = {recipient} \
+            OR {sender} IN (\
+                SELECT `following_id` FROM `ActorsFollowings` \
+                WHERE `ActorsFollowings`.`actor_id` = {recipient}\
+            )\
+        ) \
+        AND {sender} NOT IN (\
+            SELECT `blocked_id` FROM `ActorsBlockings` \
+            WHERE `ActorsBlockings`.`actor_id` = {recipient}\
+        ) \
+        AND {sender} NOT IN (\
+            SELECT `muted_id` FROM `ActorsMutings` \
+            WHERE `ActorsMutings`.`actor_id` = {recipient}\
+        )\
+        ",
+        recipient = recipient,
+        sender = sender,
+    )
+}
+
+/// Build the condition for the `message` SQL expression, sent by the
+/// `sender` SQL expression, to be addressed to the `recipient` SQL
+/// expression.
+fn build_addressed_condition(recipient: &str, message: &str, sender: &str) -> String {
+    format!(
+        "\
+        {message} IN (\
+            SELECT `message_id` FROM `MessagesAudiences` \
+            WHERE `MessagesAudiences`.`audience_id` = {recipient} \
+            OR `MessagesAudiences`.`audience_id` = '{public}' \
+            OR `MessagesAudiences`.`audience_id` IN (\
+                SELECT `audience_id` FROM `ActorsAudiences` \
+                WHERE `ActorsAudiences`.`actor_id` = {recipient}\
+            )\
+            OR (\
+                `MessagesAudiences`.`audience_id` IN (\
+                    SELECT `list_id` FROM `ListsMembers` \
+                    WHERE `ListsMembers`.`member_id` = {recipient}\
+                ) \
+                AND {list_owned}\
+            )\
+        )\
+        ",
+        recipient = recipient,
+        message = message,
+        public = PUBLIC_AUDIENCE_ID,
+        list_owned = build_list_owner_condition("`MessagesAudiences`.`audience_id`", sender),
+    )
+}
+
+/// Build the statements replacing the messages of the `sender` SQL
+/// expression in the inbox of the `recipient` SQL expression, after a change
+/// to whether the recipient follows, blocks or mutes the sender.
+fn build_redeliver_statements(recipient: &str, sender: &str) -> String {
+    format!(
+        "\
+        DELETE FROM `Inboxes` \
+        WHERE `actor_id` = {recipient} \
+        AND `sender_id` = {sender}; \
+        INSERT INTO `Inboxes` \
+        (`actor_id`, `message_id`, `sender_id`, `idx`, `addressed`) \
+        SELECT {recipient}, `Messages`.`message_id`, `Messages`.`actor_id`, \
+        `Messages`.`idx`, {addressed} \
+        FROM `Messages` \
+        WHERE `Messages`.`actor_id` = {sender} \
+        AND {delivered};\
+        ",
+        recipient = recipient,
+        sender = sender,
+        addressed = build_addressed_condition(
+            recipient,
+            "`Messages`.`message_id`",
+            "`Messages`.`actor_id`"
+        ),
+        delivered = build_delivered_condition(recipient, sender),
+    )
+}
+
+/// Build the statement updating which of the rows in `Inboxes` matching the
+/// `condition` SQL expression are addressed to their actor, after a change
+/// to the audiences of the messages or of the actors.
+fn build_readdress_statement(condition: &str) -> String {
+    format!(
+        "\
+        UPDATE `Inboxes` \
+        SET `addressed` = {addressed} \
+        WHERE {condition};\
+        ",
+        addressed = build_addressed_condition(
+            "`Inboxes`.`actor_id`",
+            "`Inboxes`.`message_id`",
+            "`Inboxes`.`sender_id`"
+        ),
+        condition = condition,
+    )
+}
+
+/// Build the triggers keeping `Inboxes` up to date, as tuples of the trigger
+/// name, the event, the table and the statements it runs.
+fn build_inboxes_triggers() -> Vec<(&'static str, &'static str, &'static str, String)> {
+    let deliver_message = format!(
+        "\
+        INSERT INTO `Inboxes` \
+        (`actor_id`, `message_id`, `sender_id`, `idx`, `addressed`) \
+        SELECT `Recipients`.`recipient_id`, NEW.`message_id`, NEW.`actor_id`, \
+        NEW.`idx`, {addressed} \
+        FROM (\
+            SELECT `actor_id` AS `recipient_id` FROM `ActorsFollowings` \
+            WHERE `following_id` = NEW.`actor_id` \
+            UNION \
+            SELECT NEW.`actor_id` AS `recipient_id`\
+        ) AS `Recipients` \
+        WHERE {delivered};\
+        ",
+        addressed = build_addressed_condition(
+            "`Recipients`.`recipient_id`",
+            "NEW.`message_id`",
+            "NEW.`actor_id`"
+        ),
+        delivered = build_delivered_condition("`Recipients`.`recipient_id`", "NEW.`actor_id`"),
+    );
+    let readdress_to = |actor_id: &str, audience_id: &str| {
+        build_readdress_statement(&format!(
+            "\
+            `actor_id` = {} \
+            AND `message_id` IN (\
+                SELECT `message_id` FROM `MessagesAudiences` \
+                WHERE `audience_id` = {}\
+            )\
+            ",
+            actor_id, audience_id
+        ))
+    };
+    vec![
+        (
+            "inboxes_messages_insert",
+            "INSERT",
+            "Messages",
+            deliver_message,
+        ),
+        (
+            "inboxes_messages_delete",
+            "DELETE",
+            "Messages",
+            "DELETE FROM `Inboxes` WHERE `message_id` = OLD.`message_id`;".to_string(),
+        ),
+        (
+            "inboxes_messages_audiences_insert",
+            "INSERT",
+            "MessagesAudiences",
+            build_readdress_statement("`message_id` = NEW.`message_id`"),
+        ),
+        (
+            "inboxes_messages_audiences_delete",
+            "DELETE",
+            "MessagesAudiences",
+            build_readdress_statement("`message_id` = OLD.`message_id`"),
+        ),
+        (
+            "inboxes_actors_followings_insert",
+            "INSERT",
+            "ActorsFollowings",
+            build_redeliver_statements("NEW.`actor_id`", "NEW.`following_id`"),
+        ),
+        (
+            "inboxes_actors_followings_delete",
+            "DELETE",
+            "ActorsFollowings",
+            build_redeliver_statements("OLD.`actor_id`", "OLD.`following_id`"),
+        ),
+        (
+            "inboxes_actors_blockings_insert",
+            "INSERT",
+            "ActorsBlockings",
+            build_redeliver_statements("NEW.`actor_id`", "NEW.`blocked_id`"),
+        ),
+        (
+            "inboxes_actors_blockings_delete",
+            "DELETE",
+            "ActorsBlockings",
+            build_redeliver_statements("OLD.`actor_id`", "OLD.`blocked_id`"),
+        ),
+        (
+            "inboxes_actors_mutings_insert",
+            "INSERT",
+            "ActorsMutings",
+            build_redeliver_statements("NEW.`actor_id`", "NEW.`muted_id`"),
+        ),
+        (
+            "inboxes_actors_mutings_delete",
+            "DELETE",
+            "ActorsMutings",
+            build_redeliver_statements("OLD.`actor_id`", "OLD.`muted_id`"),
+        ),
+        (
+            "inboxes_actors_audiences_insert",
+            "INSERT",
+            "ActorsAudiences",
+            readdress_to("NEW.`actor_id`", "NEW.`audience_id`"),
+        ),
+        (
+            "inboxes_actors_audiences_delete",
+            "DELETE",
+            "ActorsAudiences",
+            readdress_to("OLD.`actor_id`", "OLD.`audience_id`"),
+        ),
+        (
+            "inboxes_lists_members_insert",
+            "INSERT",
+            "ListsMembers",
+            readdress_to("NEW.`member_id`", "NEW.`list_id`"),
+        ),
+        (
+            "inboxes_lists_members_delete",
+            "DELETE",
+            "ListsMembers",
+            readdress_to("OLD.`member_id`", "OLD.`list_id`"),
+        ),
+    ]
+}
+
+/// Create the `Inboxes` table and the triggers which keep it up to date.
+pub async fn create_inboxes(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `Inboxes` \
+        (\
+            `actor_id` TEXT NOT NULL, \
+            `message_id` TEXT NOT NULL, \
+            `sender_id` TEXT NOT NULL, \
+            `idx` INTEGER NOT NULL, \
+            `addressed` INTEGER NOT NULL, \
+            PRIMARY KEY (`actor_id`, `message_id`)\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `inboxes_actor_id_idx` \
+        ON `Inboxes`(`actor_id`, `addressed`, `idx`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `inboxes_sender_id_idx` \
+        ON `Inboxes`(`actor_id`, `sender_id`, `idx`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `inboxes_message_id` \
+        ON `Inboxes`(`message_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    for (name, event, table, statements) in build_inboxes_triggers() {
+        // triggers are part of the schema and can't be bound as parameters
+        let query_str = format!(
+            "\
+            CREATE TRIGGER IF NOT EXISTS `{}` \
+            AFTER {} ON `{}` \
+            FOR EACH ROW \
+            BEGIN {} END;\
+            ",
+            name, event, table, statements
+        );
+        sqlx::query(&query_str).execute(&mut *connection).await?;
+    }
+    Ok(())
+}
+
+/// Drop the `Inboxes` table and the triggers which keep it up to date.
+pub async fn drop_inboxes(connection: &mut SqliteConnection) -> Result<()> {
+    for (name, _, _, _) in build_inboxes_triggers() {
+        sqlx::query(&format!("DROP TRIGGER IF EXISTS `{}`;", name))
+            .execute(&mut *connection)
+            .await?;
+    }
+    sqlx::query("DROP TABLE IF EXISTS `Inboxes`;")
+        .execute(&mut *connection)
+        .await?;
+    Ok(())
+}
+
+/// Check if the DB has the `Inboxes` table.
+pub async fn inboxes_exist(connection: &mut SqliteConnection) -> Result<bool> {
+    Ok(sqlx::query(
+        "\
+        SELECT 1 FROM `sqlite_master` \
+        WHERE `type` = 'table' \
+        AND `name` = 'Inboxes';\
+        ",
+    )
+    .fetch_optional(&mut *connection)
+    .await?
+    .is_some())
+}
+
+/// Rebuild the inboxes of all actors, such as when the table is added to an
+/// existing DB.
+pub async fn update_all_inboxes(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query("DELETE FROM `Inboxes`;")
+        .execute(&mut *connection)
+        .await?;
+    let query_str = format!(
+        "\
+        INSERT INTO `Inboxes` \
+        (`actor_id`, `message_id`, `sender_id`, `idx`, `addressed`) \
+        SELECT `Recipients`.`recipient_id`, `Messages`.`message_id`, \
+        `Messages`.`actor_id`, `Messages`.`idx`, {addressed} \
+        FROM `Messages` \
+        INNER JOIN (\
+            SELECT `actor_id` AS `recipient_id`, `following_id` AS `sender_id` \
+            FROM `ActorsFollowings` \
+            UNION \
+            SELECT `actor_id` AS `recipient_id`, `actor_id` AS `sender_id` \
+            FROM `Messages`\
+        ) AS `Recipients` \
+        ON `Recipients`.`sender_id` = `Messages`.`actor_id` \
+        WHERE {delivered};\
+        ",
+        addressed = build_addressed_condition(
+            "`Recipients`.`recipient_id`",
+            "`Messages`.`message_id`",
+            "`Messages`.`actor_id`"
+        ),
+        delivered =
+            build_delivered_condition("`Recipients`.`recipient_id`", "`Messages`.`actor_id`"),
+    );
+    sqlx::query(&query_str).execute(&mut *connection).await?;
+    Ok(())
+}
+
+/// Get the same page as [`super::get_inbox_for_actor`] from the materialized
+/// inboxes.
+pub async fn get_materialized_inbox_for_actor(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    count: u64,
+    start_idx: Option<u64>,
+) -> Result<Option<CollectionPageOut>> {
+    let query_str = format!(
+        "\
+        SELECT `Inboxes`.`idx` AS `idx`, `document` FROM `Inboxes` \
+        INNER JOIN `Documents` \
+        ON `Documents`.`document_id` = `Inboxes`.`message_id` \
+        WHERE `Inboxes`.`actor_id` = $1 \
+        AND `Inboxes`.`addressed` = 1 \
+        {} \
+        ORDER BY `Inboxes`.`idx` DESC \
+        LIMIT $2;\
+        ",
+        if start_idx.is_some() {
+            "AND `Inboxes`.`idx` <= $3"
+        } else {
+            ""
+        }
+    );
+    let query = match start_idx {
+        Some(start_idx) => sqlx::query(&query_str)
+            .bind(actor_id)
+            .bind(i64::try_from(count)?)
+            .bind(u32::try_from(start_idx)?),
+        None => sqlx::query(&query_str)
+            .bind(actor_id)
+            .bind(i64::try_from(count)?),
+    };
+    build_inbox_messages(query, connection).await
+}
+
+/// Get the same page as [`super::get_inbox_from_actor`] from the materialized
+/// inboxes.
+///
+/// The messages of a sender delivered to the recipient are read from the
+/// sender index of `Inboxes`. Those of a sender which the recipient doesn't
+/// follow, or mutes, aren't delivered, and are read from the sender index of
+/// `Messages` instead.
+pub async fn get_materialized_inbox_from_actor(
+    connection: &mut SqliteConnection,
+    for_actor_id: &str,
+    from_actor_id: &str,
+    count: u64,
+    start_idx: Option<u64>,
+) -> Result<Option<CollectionPageOut>> {
+    // the candidates are named `Messages` so that the audience conditions
+    // are the same as those of the relational query
+    let query_str = format!(
+        "\
+        SELECT `idx`, `document` FROM `Documents` \
+        INNER JOIN (\
+            SELECT `message_id`, `sender_id` AS `actor_id`, `idx` FROM `Inboxes` \
+            WHERE `Inboxes`.`actor_id` = $1 \
+            AND `Inboxes`.`sender_id` = $2 \
+            UNION ALL \
+            SELECT `message_id`, `actor_id`, `idx` FROM `Messages` \
+            WHERE `Messages`.`actor_id` = $2 \
+            AND NOT ({}) \
+        ) AS `Messages` \
+        ON `Documents`.`document_id` = `Messages`.`message_id` \
+        WHERE {} \
+        {} \
+        ORDER BY `idx` DESC \
+        LIMIT $3;\
+        ",
+        build_delivered_condition("$1", "$2"),
+        build_inbox_from_condition("$1", "$2", true),
+        if start_idx.is_some() {
+            "AND `idx` <= $4"
+        } else {
+            ""
+        }
+    );
+    let query = match start_idx {
+        Some(start_idx) => sqlx::query(&query_str)
+            .bind(for_actor_id)
+            .bind(from_actor_id)
+            .bind(i64::try_from(count)?)
+            .bind(u32::try_from(start_idx)?),
+        None => sqlx::query(&query_str)
+            .bind(for_actor_id)
+            .bind(from_actor_id)
+            .bind(i64::try_from(count)?),
+    };
+    build_inbox_messages(query, connection).await
+}
+
+/// Get the same page as [`super::get_inbox_with_audiences`] from the
+/// materialized inboxes.
+pub async fn get_materialized_inbox_with_audiences(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    audiences: &Vec<String>,
+    count: u64,
+    start_idx: Option<u64>,
+) -> Result<Option<CollectionPageOut>> {
+    let query_str = format!(
+        "\
+        SELECT `Inboxes`.`idx` AS `idx`, `document` FROM `Inboxes` \
+        INNER JOIN `Documents` \
+        ON `Documents`.`document_id` = `Inboxes`.`message_id` \
+        WHERE `Inboxes`.`actor_id` = $1 \
+        AND `Inboxes`.`message_id` IN (\
+            SELECT `message_id` FROM `MessagesAudiences` \
+            WHERE `MessagesAudiences`.`audience_id` {}\
+        ) \
+        {} \
+        ORDER BY `Inboxes`.`idx` DESC \
+        LIMIT $2;\
+        ",
+        build_audiences_condition(audiences.len()),
+        if start_idx.is_some() {
+            format!("AND `Inboxes`.`idx` <= ${}", audiences.len() + 3)
+        } else {
+            "".to_string()
+        }
+    );
+    let mut query = sqlx::query(&query_str)
+        .bind(actor_id)
+        .bind(i64::try_from(count)?);
+    for audience in audiences {
+        query = query.bind(audience.as_str());
+    }
+    if let Some(start_idx) = start_idx {
+        query = query.bind(u32::try_from(start_idx)?);
+    }
+    build_inbox_messages(query, connection).await
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use tokio;
+
+    use super::super::*;
+    use super::*;
+
+    const NUM_ACTORS: usize = 5;
+
+    fn actor_id(i: usize) -> String {
+        format!("did:{}/actor", i)
+    }
+
+    fn page_tuple(page: Option<CollectionPageOut>) -> Option<(Vec<String>, u64, u64)> {
+        page.map(|x| (x.items, x.low_idx, x.high_idx))
+    }
+
+    async fn assert_inboxes_match(connection: &mut SqliteConnection) {
+        let actors_id: Vec<String> = (0..NUM_ACTORS).map(actor_id).collect();
+        for (i, actor_id) in actors_id.iter().enumerate() {
+            let audiences_sets = [
+                vec![],
+                vec![PUBLIC_AUDIENCE_ID.to_string()],
+                actors_id
+                    .iter()
+                    .map(|x| format!("{}/followers", x))
+                    .collect(),
+                vec![
+                    actor_id.clone(),
+                    format!("{}/lists/a", actors_id[(i + 1) % NUM_ACTORS]),
+                ],
+            ];
+            for (count, start_idx) in [(100, None), (3, None), (3, Some(4)), (1, Some(9))] {
+                let expected = get_inbox_for_actor(&mut *connection, actor_id, count, start_idx)
+                    .await
+                    .unwrap();
+                let materialized =
+                    get_materialized_inbox_for_actor(&mut *connection, actor_id, count, start_idx)
+                        .await
+                        .unwrap();
+                assert_eq!(page_tuple(materialized), page_tuple(expected));
+                for from_actor_id in actors_id.iter() {
+                    let expected = get_inbox_from_actor(
+                        &mut *connection,
+                        actor_id,
+                        from_actor_id,
+                        count,
+                        start_idx,
+                    )
+                    .await
+                    .unwrap();
+                    let materialized = get_materialized_inbox_from_actor(
+                        &mut *connection,
+                        actor_id,
+                        from_actor_id,
+                        count,
+                        start_idx,
+                    )
+                    .await
+                    .unwrap();
+                    assert_eq!(page_tuple(materialized), page_tuple(expected));
+                }
+                for audiences in audiences_sets.iter() {
+                    let expected = get_inbox_with_audiences(
+                        &mut *connection,
+                        actor_id,
+                        audiences,
+                        count,
+                        start_idx,
+                    )
+                    .await
+                    .unwrap();
+                    let materialized = get_materialized_inbox_with_audiences(
+                        &mut *connection,
+                        actor_id,
+                        audiences,
+                        count,
+                        start_idx,
+                    )
+                    .await
+                    .unwrap();
+                    assert_eq!(page_tuple(materialized), page_tuple(expected));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn materialized_inbox_matches_inbox() {
+        let mut connector = Connector::new("sqlite::memory:").await.unwrap();
+        connector.use_materialized_inbox().await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut messages_id = Vec::new();
+
+        for step in 0..300 {
+            let actor_id_a = actor_id(rng.gen_range(0..NUM_ACTORS));
+            let actor_id_b = actor_id(rng.gen_range(0..NUM_ACTORS));
+            let list_id = format!("{}/lists/a", actor_id_b);
+            match rng.gen_range(0..13) {
+                0..=2 => {
+                    let message_id = format!("id:{}", step);
+                    put_document(&mut connection, &message_id, &format!("message {}", step))
+                        .await
+                        .unwrap();
+                    let audiences_id = [
+                        actor_id_b.clone(),
+                        format!("{}/followers", actor_id_a),
+                        format!("{}/followers", actor_id_b),
+                        list_id.clone(),
+                        PUBLIC_AUDIENCE_ID.to_string(),
+                    ];
+                    let audience_id = &audiences_id[rng.gen_range(0..audiences_id.len())];
+                    put_message_audience(&mut connection, &message_id, audience_id)
+                        .await
+                        .unwrap();
+                    put_message_id(&mut connection, &message_id, &actor_id_a)
+                        .await
+                        .unwrap();
+                    messages_id.push(message_id);
+                }
+                3 => {
+                    if messages_id.is_empty() {
+                        continue;
+                    }
+                    let message_id = messages_id.remove(rng.gen_range(0..messages_id.len()));
+                    delete_message_audiences(&mut connection, &message_id)
+                        .await
+                        .unwrap();
+                    delete_message(&mut connection, &message_id).await.unwrap();
+                }
+                4 => {
+                    put_actor_following(&mut connection, &actor_id_a, &actor_id_b)
+                        .await
+                        .unwrap();
+                    put_actor_audience(
+                        &mut connection,
+                        &actor_id_a,
+                        &format!("{}/followers", actor_id_b),
+                    )
+                    .await
+                    .unwrap();
+                }
+                5 => {
+                    delete_actor_following(&mut connection, &actor_id_a, &actor_id_b)
+                        .await
+                        .unwrap();
+                    delete_actor_audience(
+                        &mut connection,
+                        &actor_id_a,
+                        &format!("{}/followers", actor_id_b),
+                    )
+                    .await
+                    .unwrap();
+                }
+                6 => {
+                    put_list_member(&mut connection, &list_id, &actor_id_a)
+                        .await
+                        .unwrap();
+                }
+                7 => {
+                    if rng.gen_bool(0.5) {
+                        delete_list_member(&mut connection, &list_id, &actor_id_a)
+                            .await
+                            .unwrap();
+                    } else {
+                        delete_list_all_members(&mut connection, &list_id)
+                            .await
+                            .unwrap();
+                    }
+                }
+                8 => {
+                    put_actor_blocking(&mut connection, &actor_id_a, &actor_id_b)
+                        .await
+                        .unwrap();
+                }
+                9 => {
+                    delete_actor_blocking(&mut connection, &actor_id_a, &actor_id_b)
+                        .await
+                        .unwrap();
+                }
+                10 => {
+                    put_actor_muting(&mut connection, &actor_id_a, &actor_id_b)
+                        .await
+                        .unwrap();
+                }
+                11 => {
+                    delete_actor_muting(&mut connection, &actor_id_a, &actor_id_b)
+                        .await
+                        .unwrap();
+                }
+                _ => match rng.gen_range(0..4) {
+                    0 => delete_actor_all_following(&mut connection, &actor_id_a)
+                        .await
+                        .unwrap(),
+                    1 => delete_actor_all_audiences(&mut connection, &actor_id_a)
+                        .await
+                        .unwrap(),
+                    2 => delete_actor_all_blocking(&mut connection, &actor_id_a)
+                        .await
+                        .unwrap(),
+                    _ => delete_actor_all_muting(&mut connection, &actor_id_a)
+                        .await
+                        .unwrap(),
+                },
+            }
+            assert_inboxes_match(&mut connection).await;
+        }
+
+        // rebuilding from scratch gives the same inboxes
+        sqlx::query("DELETE FROM `Inboxes`;")
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+        update_all_inboxes(&mut connection).await.unwrap();
+        assert_inboxes_match(&mut connection).await;
+    }
+
+    #[tokio::test]
+    async fn skips_inboxes_unless_materialized() {
+        let mut connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection_mut().await.unwrap();
+        assert!(!inboxes_exist(&mut connection).await.unwrap());
+        put_actor_following(&mut connection, "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+        put_message_audience(&mut connection, "id:1", PUBLIC_AUDIENCE_ID)
+            .await
+            .unwrap();
+        put_message_id(&mut connection, "id:1", "did:2/actor")
+            .await
+            .unwrap();
+        assert!(!inboxes_exist(&mut connection).await.unwrap());
+        drop(connection);
+
+        // the table is built from the existing messages
+        connector.use_materialized_inbox().await.unwrap();
+        let mut connection = connector.connection_mut().await.unwrap();
+        let rows = sqlx::query("SELECT `message_id` FROM `Inboxes` WHERE `actor_id` = $1;")
+            .bind("did:1/actor")
+            .fetch_all(&mut *connection)
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        drop(connection);
+
+        connector.drop_materialized_inbox().await.unwrap();
+        let mut connection = connector.connection_mut().await.unwrap();
+        assert!(!inboxes_exist(&mut connection).await.unwrap());
+        delete_message(&mut connection, "id:1").await.unwrap();
+    }
+}
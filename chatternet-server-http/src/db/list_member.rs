@@ -0,0 +1,174 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use sqlx::{Row, SqliteConnection};
+
+use super::joint_id;
+
+pub async fn create_lists_members(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `ListsMembers` \
+        (\
+            `joint_id` TEXT PRIMARY KEY, \
+            `list_id` TEXT NOT NULL, \
+            `member_id` TEXT NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `lists_members_list_id` \
+        ON `ListsMembers`(`list_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `lists_members_member_id` \
+        ON `ListsMembers`(`member_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Build the SQL condition for the `list` expression to be the ID of a list
+/// owned by the actor with the `owner` ID expression.
+///
+/// Lists have IDs `{owner}/lists/{name}`, so that only the owner can address
+/// messages to the members of its lists.
+pub(crate) fn build_list_owner_condition(list: &str, owner: &str) -> String {
+    format!(
+        "SUBSTR({list}, 1, LENGTH({owner}) + 7) = {owner} || '/lists/'",
+        list = list,
+        owner = owner,
+    )
+}
+
+pub async fn put_list_member(
+    connection: &mut SqliteConnection,
+    list_id: &str,
+    member_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        INSERT OR IGNORE INTO `ListsMembers` \
+        (`joint_id`, `list_id`, `member_id`) \
+        VALUES($1, $2, $3);\
+        ",
+    )
+    .bind(joint_id(&[list_id, member_id]))
+    .bind(list_id)
+    .bind(member_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_list_member(
+    connection: &mut SqliteConnection,
+    list_id: &str,
+    member_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `ListsMembers` \
+        WHERE `joint_id` = $1;\
+        ",
+    )
+    .bind(joint_id(&[list_id, member_id]))
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_list_all_members(
+    connection: &mut SqliteConnection,
+    list_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `ListsMembers` \
+        WHERE `list_id` = $1;\
+        ",
+    )
+    .bind(list_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_list_members(
+    connection: &mut SqliteConnection,
+    list_id: &str,
+) -> Result<Vec<String>> {
+    let query = sqlx::query(
+        "\
+        SELECT `member_id` FROM `ListsMembers` \
+        WHERE `list_id` = $1;\
+        ",
+    )
+    .bind(list_id);
+    let mut members_id = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let member_id: &str = row.try_get("member_id")?;
+        members_id.push(member_id.to_string());
+    }
+    Ok(members_id)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio;
+
+    use super::super::Connector;
+    use super::*;
+
+    #[tokio::test]
+    async fn puts_gets_deletes_lists_members() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        put_list_member(&mut connection, "did:1/actor/lists/a", "did:2/actor")
+            .await
+            .unwrap();
+        put_list_member(&mut connection, "did:1/actor/lists/a", "did:3/actor")
+            .await
+            .unwrap();
+        put_list_member(&mut connection, "did:1/actor/lists/b", "did:2/actor")
+            .await
+            .unwrap();
+        // is idempotent
+        put_list_member(&mut connection, "did:1/actor/lists/a", "did:2/actor")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_list_members(&mut connection, "did:1/actor/lists/a")
+                .await
+                .unwrap(),
+            ["did:2/actor", "did:3/actor"]
+        );
+
+        delete_list_member(&mut connection, "did:1/actor/lists/a", "did:2/actor")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_list_members(&mut connection, "did:1/actor/lists/a")
+                .await
+                .unwrap(),
+            ["did:3/actor"]
+        );
+
+        delete_list_all_members(&mut connection, "did:1/actor/lists/b")
+            .await
+            .unwrap();
+        assert!(get_list_members(&mut connection, "did:1/actor/lists/b")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
-use sqlx::SqliteConnection;
+use futures::TryStreamExt;
+use sqlx::{Row, SqliteConnection};
 
 pub async fn create_messages(connection: &mut SqliteConnection) -> Result<()> {
     sqlx::query(
@@ -72,16 +73,40 @@ pub async fn delete_message(connection: &mut SqliteConnection, message_id: &str)
         ",
     )
     .bind(message_id)
-    .execute(connection)
+    .execute(&mut *connection)
     .await?;
     Ok(())
 }
 
+/// Get the stored messages by `actor_id`, oldest first.
+pub async fn get_actor_messages(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+) -> Result<Vec<String>> {
+    let query = sqlx::query(
+        "\
+        SELECT `document` FROM `Documents` \
+        INNER JOIN `Messages` \
+        ON `Documents`.`document_id` = `Messages`.`message_id` \
+        WHERE `Messages`.`actor_id` = $1 \
+        ORDER BY `idx` ASC;\
+        ",
+    )
+    .bind(actor_id);
+    let mut messages = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let message: &str = row.try_get("document")?;
+        messages.push(message.to_string());
+    }
+    Ok(messages)
+}
+
 #[cfg(test)]
 mod test {
     use tokio;
 
-    use super::super::Connector;
+    use super::super::{put_document, Connector};
     use super::*;
 
     #[tokio::test]
@@ -100,4 +125,36 @@ mod test {
         delete_message(&mut connection, "id:1").await.unwrap();
         assert!(!has_message(&mut connection, "id:1").await.unwrap());
     }
+
+    #[tokio::test]
+    async fn gets_actor_messages() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        for (message_id, actor_id) in [
+            ("id:1", "did:1/actor"),
+            ("id:2", "did:2/actor"),
+            ("id:3", "did:1/actor"),
+        ] {
+            put_document(
+                &mut connection,
+                message_id,
+                &format!("message {}", message_id),
+            )
+            .await
+            .unwrap();
+            put_message_id(&mut connection, message_id, actor_id)
+                .await
+                .unwrap();
+        }
+        // messages without a stored document are skipped
+        put_message_id(&mut connection, "id:4", "did:1/actor")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_actor_messages(&mut connection, "did:1/actor")
+                .await
+                .unwrap(),
+            ["message id:1", "message id:3"]
+        );
+    }
 }
@@ -120,6 +120,28 @@ pub async fn get_document_messages(
     Ok(messages_id)
 }
 
+/// Get the IDs of the actors which created the document `document_id`.
+pub async fn get_document_creators(
+    connection: &mut SqliteConnection,
+    document_id: &str,
+) -> Result<Vec<String>> {
+    let query = sqlx::query(
+        "\
+        SELECT DISTINCT `created_by` FROM `MessageDocuments` \
+        WHERE `document_id` = $1 \
+        AND `created_by` IS NOT NULL;\
+        ",
+    )
+    .bind(document_id);
+    let mut actors_id = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let actor_id: &str = row.try_get("created_by")?;
+        actors_id.push(actor_id.to_string());
+    }
+    Ok(actors_id)
+}
+
 pub async fn has_message_with_document(
     connection: &mut SqliteConnection,
     document_id: &str,
@@ -212,6 +234,16 @@ mod test {
                 .unwrap(),
             ["urn:cid:message1"]
         );
+        assert_eq!(
+            get_document_creators(&mut connection, "urn:cid:document1")
+                .await
+                .unwrap(),
+            ["did:example:a", "did:example:b"]
+        );
+        assert!(get_document_creators(&mut connection, "urn:cid:document2")
+            .await
+            .unwrap()
+            .is_empty());
         assert!(
             has_message_with_document(&mut connection, "urn:cid:document1")
                 .await
@@ -1,6 +1,7 @@
 use std::str::FromStr;
 
 use anyhow::Result;
+use chatternet::model::PUBLIC_AUDIENCE_ID;
 use futures::TryStreamExt;
 use sha2::{Digest, Sha256};
 use sqlx::pool::PoolConnection;
@@ -9,20 +10,40 @@ use sqlx::sqlite::{SqliteArguments, SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::{Row, Sqlite, SqliteConnection, SqlitePool, Transaction};
 
 mod actor_audience;
+mod actor_blocking;
 mod actor_following;
+mod actor_move;
+mod actor_muting;
+mod actor_revocation;
 mod documents;
+mod inbox;
+mod list_member;
 mod message;
 mod message_audience;
 mod message_document;
 mod mutable_modified;
+mod note_tag;
+mod notification;
+mod reaction;
+mod stats;
 
 pub use actor_audience::*;
+pub use actor_blocking::*;
 pub use actor_following::*;
+pub use actor_move::*;
+pub use actor_muting::*;
+pub use actor_revocation::*;
 pub use documents::*;
+pub use inbox::*;
+pub use list_member::*;
 pub use message::*;
 pub use message_audience::*;
 pub use message_document::*;
 pub use mutable_modified::*;
+pub use note_tag::*;
+pub use notification::*;
+pub use reaction::*;
+pub use stats::*;
 
 fn joint_id(ids: &[&str]) -> String {
     // IDs are generic, one ID could contain many IDs, so need to use a
@@ -88,20 +109,41 @@ pub async fn get_inbox_for_actor(
         AND `Messages`.`message_id` IN (\
             SELECT `message_id` FROM `MessagesAudiences` \
             WHERE `MessagesAudiences`.`audience_id` = $1 \
+            OR `MessagesAudiences`.`audience_id` = '{}' \
             OR `MessagesAudiences`.`audience_id` IN (\
                 SELECT `audience_id` FROM `ActorsAudiences` \
                 WHERE `ActorsAudiences`.`actor_id` = $1\
             )\
+            OR (\
+                `MessagesAudiences`.`audience_id` IN (\
+                    SELECT `list_id` FROM `ListsMembers` \
+                    WHERE `ListsMembers`.`member_id` = $1\
+                ) \
+                AND {list_owned}\
+            )\
+        ) \
+        AND `Messages`.`actor_id` NOT IN (\
+            SELECT `blocked_id` FROM `ActorsBlockings` \
+            WHERE `ActorsBlockings`.`actor_id` = $1\
+        ) \
+        AND `Messages`.`actor_id` NOT IN (\
+            SELECT `muted_id` FROM `ActorsMutings` \
+            WHERE `ActorsMutings`.`actor_id` = $1\
         ) \
         {} \
         ORDER BY `idx` DESC \
         LIMIT $2;\
         ",
+        PUBLIC_AUDIENCE_ID,
         if start_idx.is_some() {
             "AND `idx` <= $3"
         } else {
             ""
-        }
+        },
+        list_owned = build_list_owner_condition(
+            "`MessagesAudiences`.`audience_id`",
+            "`Messages`.`actor_id`"
+        )
     );
     let query = match start_idx {
         Some(start_idx) => sqlx::query(&query_str)
@@ -115,6 +157,66 @@ pub async fn get_inbox_for_actor(
     build_inbox_messages(query, connection).await
 }
 
+/// Build the conditions for a row of `Messages` to be in the inbox of the
+/// `recipient` SQL expression from the `sender` SQL expression, as read with
+/// [`get_inbox_from_actor`].
+///
+/// Without `broadcasts`, only the messages addressed to the recipient itself,
+/// or to a list of the sender which has the recipient as a member, match.
+/// Messages to the sender's followers, to the public and to the other
+/// audiences of the recipient don't.
+fn build_inbox_from_condition(recipient: &str, sender: &str, broadcasts: bool) -> String {
+    let broadcasts = if broadcasts {
+        format!(
+            "\
+            OR `MessagesAudiences`.`audience_id` = {sender} || '/followers' \
+            OR `MessagesAudiences`.`audience_id` = '{public}' \
+            OR `MessagesAudiences`.`audience_id` IN (\
+                SELECT `audience_id` FROM `ActorsAudiences` \
+                WHERE `ActorsAudiences`.`actor_id` = {recipient}\
+            ) \
+            ",
+            recipient = recipient,
+            sender = sender,
+            public = PUBLIC_AUDIENCE_ID,
+        )
+    } else {
+        "".to_string()
+    };
+    format!(
+        "\
+        `Messages`.`actor_id` = {sender} \
+        AND `Messages`.`message_id` IN (\
+            SELECT `message_id` FROM `MessagesAudiences` \
+            WHERE `MessagesAudiences`.`audience_id` = {recipient} \
+            {broadcasts}\
+            OR (\
+                `MessagesAudiences`.`audience_id` IN (\
+                    SELECT `list_id` FROM `ListsMembers` \
+                    WHERE `ListsMembers`.`member_id` = {recipient}\
+                ) \
+                AND {list_owned}\
+            )\
+        ) \
+        AND `Messages`.`actor_id` NOT IN (\
+            SELECT `blocked_id` FROM `ActorsBlockings` \
+            WHERE `ActorsBlockings`.`actor_id` = {recipient}\
+        )\
+        ",
+        recipient = recipient,
+        sender = sender,
+        broadcasts = broadcasts,
+        list_owned = build_list_owner_condition(
+            "`MessagesAudiences`.`audience_id`",
+            "`Messages`.`actor_id`"
+        ),
+    )
+}
+
+/// Get the messages from `from_actor_id` in the inbox of `for_actor_id`.
+///
+/// This includes messages to the followers of the sender even if the
+/// recipient doesn't follow it.
 pub async fn get_inbox_from_actor(
     connection: &mut SqliteConnection,
     for_actor_id: &str,
@@ -127,20 +229,12 @@ pub async fn get_inbox_from_actor(
         SELECT `idx`, `document` FROM `Documents` \
         INNER JOIN `Messages` \
         ON `Documents`.`document_id` = `Messages`.`message_id` \
-        WHERE `Messages`.`actor_id` = $2 \
-        AND `Messages`.`message_id` IN (\
-            SELECT `message_id` FROM `MessagesAudiences` \
-            WHERE `MessagesAudiences`.`audience_id` = $1 \
-            OR `MessagesAudiences`.`audience_id` = $2 || '/followers' \
-            OR `MessagesAudiences`.`audience_id` IN (\
-                SELECT `audience_id` FROM `ActorsAudiences` \
-                WHERE `ActorsAudiences`.`actor_id` = $1\
-            )\
-        ) \
+        WHERE {} \
         {} \
         ORDER BY `idx` DESC \
         LIMIT $3;\
         ",
+        build_inbox_from_condition("$1", "$2", true),
         if start_idx.is_some() {
             "AND `idx` <= $4"
         } else {
@@ -161,24 +255,78 @@ pub async fn get_inbox_from_actor(
     build_inbox_messages(query, connection).await
 }
 
-pub async fn get_inbox_with_audiences(
+/// Get the messages between `actor_id` and `other_actor_id` in both
+/// directions.
+///
+/// Each direction follows the rules of [`get_inbox_from_actor`] for its
+/// recipient, but only messages addressed directly to the recipient are
+/// included: broadcasts to followers, tags or the public are not part of a
+/// conversation.
+pub async fn get_conversation(
     connection: &mut SqliteConnection,
     actor_id: &str,
-    audiences: &Vec<String>,
+    other_actor_id: &str,
     count: u64,
     start_idx: Option<u64>,
 ) -> Result<Option<CollectionPageOut>> {
-    let audience_condition = if audiences.is_empty() {
+    let query_str = format!(
+        "\
+        SELECT `idx`, `document` FROM `Documents` \
+        INNER JOIN `Messages` \
+        ON `Documents`.`document_id` = `Messages`.`message_id` \
+        WHERE (({}) OR ({})) \
+        {} \
+        ORDER BY `idx` DESC \
+        LIMIT $3;\
+        ",
+        build_inbox_from_condition("$1", "$2", false),
+        build_inbox_from_condition("$2", "$1", false),
+        if start_idx.is_some() {
+            "AND `idx` <= $4"
+        } else {
+            ""
+        }
+    );
+    let query = match start_idx {
+        Some(start_idx) => sqlx::query(&query_str)
+            .bind(actor_id)
+            .bind(other_actor_id)
+            .bind(i64::try_from(count)?)
+            .bind(u32::try_from(start_idx)?),
+        None => sqlx::query(&query_str)
+            .bind(actor_id)
+            .bind(other_actor_id)
+            .bind(i64::try_from(count)?),
+    };
+    build_inbox_messages(query, connection).await
+}
+
+/// Build the condition for an audience ID to be one of `num_audiences`
+/// audiences, bound as parameters from `$3` onwards.
+fn build_audiences_condition(num_audiences: usize) -> String {
+    if num_audiences == 0 {
         "IS NULL".to_string()
-    } else if audiences.len() == 1 {
-        format!("= $3")
+    } else if num_audiences == 1 {
+        "= $3".to_string()
     } else {
-        let audience_parameters = (0..audiences.len())
+        let audience_parameters = (0..num_audiences)
             .map(|i| format!("${}", i + 3))
             .collect::<Vec<String>>()
             .join(", ");
         format!("IN ({})", audience_parameters)
-    };
+    }
+}
+
+/// Get the messages in the inbox of `actor_id` addressed to one of
+/// `audiences`.
+pub async fn get_inbox_with_audiences(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    audiences: &Vec<String>,
+    count: u64,
+    start_idx: Option<u64>,
+) -> Result<Option<CollectionPageOut>> {
+    let audience_condition = build_audiences_condition(audiences.len());
     let query_str = format!(
         "\
         SELECT `idx`, `document` FROM `Documents` \
@@ -195,6 +343,14 @@ pub async fn get_inbox_with_audiences(
             SELECT `message_id` FROM `MessagesAudiences` \
             WHERE `MessagesAudiences`.`audience_id` {}\
         ) \
+        AND `Messages`.`actor_id` NOT IN (\
+            SELECT `blocked_id` FROM `ActorsBlockings` \
+            WHERE `ActorsBlockings`.`actor_id` = $1\
+        ) \
+        AND `Messages`.`actor_id` NOT IN (\
+            SELECT `muted_id` FROM `ActorsMutings` \
+            WHERE `ActorsMutings`.`actor_id` = $1\
+        ) \
         {} \
         ORDER BY `idx` DESC \
         LIMIT $2;\
@@ -218,12 +374,17 @@ pub async fn get_inbox_with_audiences(
     build_inbox_messages(query, connection).await
 }
 
+/// Check if the message with `message_id` is in the inbox of `actor_id`.
+///
+/// This looks up a single message, and reads from the relationship tables
+/// so that it gives the same answer whether or not the inboxes are
+/// materialized.
 pub async fn inbox_contains_message(
     connection: &mut SqliteConnection,
     actor_id: &str,
     message_id: &str,
 ) -> Result<bool> {
-    let query = sqlx::query(
+    let query_str = format!(
         "\
         SELECT 1 FROM `Messages` \
         WHERE `message_id` = $2 \
@@ -237,23 +398,123 @@ pub async fn inbox_contains_message(
         AND `message_id` IN (\
             SELECT `message_id` FROM `MessagesAudiences` \
             WHERE `MessagesAudiences`.`audience_id` = $1
+            OR `MessagesAudiences`.`audience_id` = '{}' \
             OR `MessagesAudiences`.`audience_id` IN (\
                 SELECT `audience_id` FROM `ActorsAudiences` \
                 WHERE `ActorsAudiences`.`actor_id` = $1
             )\
+            OR (\
+                `MessagesAudiences`.`audience_id` IN (\
+                    SELECT `list_id` FROM `ListsMembers` \
+                    WHERE `ListsMembers`.`member_id` = $1\
+                ) \
+                AND {list_owned}\
+            )\
+        ) \
+        AND `actor_id` NOT IN (\
+            SELECT `blocked_id` FROM `ActorsBlockings` \
+            WHERE `ActorsBlockings`.`actor_id` = $1\
         ) \
         LIMIT 1;\
         ",
-    )
-    .bind(actor_id)
-    .bind(message_id);
+        PUBLIC_AUDIENCE_ID,
+        list_owned = build_list_owner_condition(
+            "`MessagesAudiences`.`audience_id`",
+            "`Messages`.`actor_id`"
+        )
+    );
+    let query = sqlx::query(&query_str).bind(actor_id).bind(message_id);
     Ok(query.fetch_optional(&mut *connection).await?.is_some())
 }
 
+/// Get the messages addressed to the public collection.
+///
+/// If `local_actor_id` is given, get only those by that actor and the actors
+/// it follows.
+pub async fn get_public_messages(
+    connection: &mut SqliteConnection,
+    local_actor_id: Option<&str>,
+    count: u64,
+    start_idx: Option<u64>,
+) -> Result<Option<CollectionPageOut>> {
+    let query_str = format!(
+        "\
+        SELECT `idx`, `document` FROM `Documents` \
+        INNER JOIN `Messages` \
+        ON `Documents`.`document_id` = `Messages`.`message_id` \
+        WHERE `Messages`.`message_id` IN (\
+            SELECT `message_id` FROM `MessagesAudiences` \
+            WHERE `MessagesAudiences`.`audience_id` = '{}'\
+        ) \
+        {} \
+        {} \
+        ORDER BY `idx` DESC \
+        LIMIT $1;\
+        ",
+        PUBLIC_AUDIENCE_ID,
+        if local_actor_id.is_some() {
+            "\
+            AND (\
+                `Messages`.`actor_id` = $2 \
+                OR `Messages`.`actor_id` IN (\
+                    SELECT `following_id` FROM `ActorsFollowings` \
+                    WHERE `ActorsFollowings`.`actor_id` = $2\
+                )\
+            )\
+            "
+        } else {
+            ""
+        },
+        match (local_actor_id.is_some(), start_idx.is_some()) {
+            (true, true) => "AND `idx` <= $3",
+            (false, true) => "AND `idx` <= $2",
+            _ => "",
+        }
+    );
+    let mut query = sqlx::query(&query_str).bind(i64::try_from(count)?);
+    if let Some(local_actor_id) = local_actor_id {
+        query = query.bind(local_actor_id);
+    }
+    if let Some(start_idx) = start_idx {
+        query = query.bind(u32::try_from(start_idx)?);
+    }
+    build_inbox_messages(query, connection).await
+}
+
+/// Get the number of rows in each table, by table name.
+pub async fn get_table_counts(connection: &mut SqliteConnection) -> Result<Vec<(String, u64)>> {
+    let query = sqlx::query(
+        "\
+        SELECT `name` FROM `sqlite_master` \
+        WHERE `type` = 'table' \
+        AND `name` NOT LIKE 'sqlite_%' \
+        ORDER BY `name`;\
+        ",
+    );
+    let mut tables = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let table: &str = row.try_get("name")?;
+        tables.push(table.to_string());
+    }
+    drop(rows);
+    let mut counts = Vec::new();
+    for table in tables {
+        // table names come from the schema and can't be bound as parameters
+        let count: i64 = sqlx::query(&format!("SELECT COUNT(*) AS `count` FROM `{}`;", table))
+            .fetch_one(&mut *connection)
+            .await?
+            .try_get("count")?;
+        counts.push((table, u64::try_from(count)?));
+    }
+    Ok(counts)
+}
+
 #[derive(Debug)]
 pub struct Connector {
     pool_read: Option<SqlitePool>,
     pool_write: SqlitePool,
+    materialized_inbox: bool,
 }
 
 impl Connector {
@@ -272,8 +533,17 @@ impl Connector {
         create_message_documents(&mut *connection).await?;
         create_actors_audiences(&mut *connection).await?;
         create_actor_following(&mut *connection).await?;
+        create_actor_moves(&mut *connection).await?;
+        create_actor_blocking(&mut *connection).await?;
+        create_actor_muting(&mut *connection).await?;
+        create_actor_revocation(&mut *connection).await?;
+        create_lists_members(&mut *connection).await?;
         create_documents(&mut *connection).await?;
         create_mutable_modified(&mut *connection).await?;
+        create_reactions(&mut *connection).await?;
+        create_notifications(&mut *connection).await?;
+        create_notes_tags(&mut *connection).await?;
+        create_stats(&mut *connection).await?;
 
         let pool_read = if url == "sqlite::memory:" {
             None
@@ -292,9 +562,37 @@ impl Connector {
         Ok(Connector {
             pool_read,
             pool_write,
+            materialized_inbox: false,
         })
     }
 
+    /// Serve inboxes from the materialized `Inboxes` table, building it if
+    /// the DB doesn't have it yet. Once built, the table is kept up to date
+    /// by triggers on the tables it depends on, so writes don't check for it.
+    pub async fn use_materialized_inbox(&mut self) -> Result<()> {
+        let mut transaction = self.pool_write.begin().await?;
+        if !inboxes_exist(&mut *transaction).await? {
+            create_inboxes(&mut *transaction).await?;
+            update_all_inboxes(&mut *transaction).await?;
+        }
+        transaction.commit().await?;
+        self.materialized_inbox = true;
+        Ok(())
+    }
+
+    /// Serve inboxes from the relationship tables, dropping the materialized
+    /// `Inboxes` table and its triggers so that writes no longer maintain it.
+    pub async fn drop_materialized_inbox(&mut self) -> Result<()> {
+        let mut connection = self.pool_write.acquire().await?;
+        drop_inboxes(&mut *connection).await?;
+        self.materialized_inbox = false;
+        Ok(())
+    }
+
+    pub fn materialized_inbox(&self) -> bool {
+        self.materialized_inbox
+    }
+
     pub async fn connection(&self) -> Result<PoolConnection<Sqlite>> {
         Ok(self
             .pool_read
@@ -328,6 +626,47 @@ mod test {
         assert_ne!(id, joint_id(&["ab"]));
     }
 
+    #[tokio::test]
+    async fn db_creates_indexes_on_their_tables() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        // index names are shared by all tables, a reused name isn't created
+        for (index, table) in [
+            ("actors_blockings_actor_id", "ActorsBlockings"),
+            ("actors_mutings_actor_id", "ActorsMutings"),
+        ] {
+            let table_back: String = sqlx::query(
+                "SELECT `tbl_name` FROM `sqlite_master` WHERE `type` = 'index' AND `name` = $1;",
+            )
+            .bind(index)
+            .fetch_one(&mut *connection)
+            .await
+            .unwrap()
+            .get("tbl_name");
+            assert_eq!(table_back, table);
+        }
+    }
+
+    #[tokio::test]
+    async fn db_gets_table_counts() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        put_document(&mut connection, "id:1", "message 1")
+            .await
+            .unwrap();
+        put_document(&mut connection, "id:2", "message 2")
+            .await
+            .unwrap();
+        put_message_id(&mut connection, "id:1", "did:1/actor")
+            .await
+            .unwrap();
+        let counts = get_table_counts(&mut connection).await.unwrap();
+        let count = |table: &str| counts.iter().find(|(x, _)| x == table).unwrap().1;
+        assert_eq!(count("Documents"), 2);
+        assert_eq!(count("Messages"), 1);
+        assert_eq!(count("ActorsFollowings"), 0);
+    }
+
     #[tokio::test]
     async fn db_gets_inbox_and_has_message_for_actor() {
         let connector = Connector::new("sqlite::memory:").await.unwrap();
@@ -456,6 +795,61 @@ mod test {
         assert_eq!(out.high_idx, 2);
     }
 
+    #[tokio::test]
+    async fn db_gets_public_messages() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+
+        for (message_id, actor_id, audience_id) in [
+            ("id:1", "did:1/actor", PUBLIC_AUDIENCE_ID),
+            ("id:2", "did:2/actor", "did:2/actor/followers"),
+            ("id:3", "did:2/actor", PUBLIC_AUDIENCE_ID),
+            ("id:4", "did:3/actor", PUBLIC_AUDIENCE_ID),
+        ] {
+            put_document(&mut connection, message_id, message_id)
+                .await
+                .unwrap();
+            put_message_id(&mut connection, message_id, actor_id)
+                .await
+                .unwrap();
+            put_message_audience(&mut connection, message_id, audience_id)
+                .await
+                .unwrap();
+        }
+        put_actor_following(&mut connection, "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+
+        let out = get_public_messages(&mut connection, None, 4, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:4", "id:3", "id:1"]);
+        let out = get_public_messages(&mut connection, None, 4, Some(3))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:3", "id:1"]);
+
+        let out = get_public_messages(&mut connection, Some("did:1/actor"), 4, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:3", "id:1"]);
+        let out = get_public_messages(&mut connection, Some("did:1/actor"), 4, Some(2))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:1"]);
+
+        // public messages from followed actors are in the inbox
+        let out = get_inbox_for_actor(&mut connection, "did:1/actor", 4, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:3", "id:1"]);
+    }
+
     #[tokio::test]
     async fn db_gets_inbox_from_actor() {
         let connector = Connector::new("sqlite::memory:").await.unwrap();
@@ -504,6 +898,99 @@ mod test {
         assert_eq!(out.items, ["message 1"]);
     }
 
+    #[tokio::test]
+    async fn db_gets_conversation() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+
+        for (message_id, actor_id, audience_id) in [
+            ("id:1", "did:1/actor", "did:2/actor"),
+            ("id:2", "did:2/actor", "did:2/actor/followers"),
+            ("id:3", "did:2/actor", "did:1/actor"),
+            ("id:4", "did:3/actor", "did:1/actor"),
+            ("id:5", "did:1/actor", "did:1/actor/followers"),
+        ] {
+            put_document(&mut connection, message_id, message_id)
+                .await
+                .unwrap();
+            put_message_id(&mut connection, message_id, actor_id)
+                .await
+                .unwrap();
+            put_message_audience(&mut connection, message_id, audience_id)
+                .await
+                .unwrap();
+        }
+        // did:1 follows did:2 but broadcasts are still excluded
+        put_actor_audience(&mut connection, "did:1/actor", "did:2/actor/followers")
+            .await
+            .unwrap();
+
+        let out = get_conversation(&mut connection, "did:1/actor", "did:2/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:3", "id:1"]);
+        let out = get_conversation(&mut connection, "did:2/actor", "did:1/actor", 3, Some(2))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:1"]);
+
+        put_actor_blocking(&mut connection, "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+        let out = get_conversation(&mut connection, "did:1/actor", "did:2/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:1"]);
+    }
+
+    #[tokio::test]
+    async fn db_gets_conversation_with_inbox_from_rules() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+
+        for (message_id, actor_id, audience_id) in [
+            ("id:1", "did:1/actor", "did:1/actor/followers"),
+            ("id:2", "did:1/actor", "did:1/actor/lists/a"),
+            ("id:3", "did:1/actor", PUBLIC_AUDIENCE_ID),
+            ("id:4", "did:2/actor", "did:1/actor/lists/a"),
+        ] {
+            put_document(&mut connection, message_id, message_id)
+                .await
+                .unwrap();
+            put_message_audience(&mut connection, message_id, audience_id)
+                .await
+                .unwrap();
+            put_message_id(&mut connection, message_id, actor_id)
+                .await
+                .unwrap();
+        }
+        put_list_member(&mut connection, "did:1/actor/lists/a", "did:2/actor")
+            .await
+            .unwrap();
+
+        // the broadcasts are in the inbox from did:1
+        let out = get_inbox_from_actor(&mut connection, "did:2/actor", "did:1/actor", 8, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:3", "id:2", "id:1"]);
+
+        // but only the message to the list of its sender is in the conversation
+        let out = get_conversation(&mut connection, "did:1/actor", "did:2/actor", 8, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:2"]);
+        let out = get_conversation(&mut connection, "did:2/actor", "did:1/actor", 8, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:2"]);
+    }
+
     #[tokio::test]
     async fn db_gets_inbox_with_audiences() {
         let connector = Connector::new("sqlite::memory:").await.unwrap();
@@ -598,4 +1085,107 @@ mod test {
         .unwrap();
         assert_eq!(out.items, ["message 2"]);
     }
+
+    #[tokio::test]
+    async fn db_inbox_excludes_blocked_and_muted() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+
+        put_document(&mut connection, "id:1", "message 1")
+            .await
+            .unwrap();
+        put_message_id(&mut connection, "id:1", "did:2/actor")
+            .await
+            .unwrap();
+        put_message_audience(&mut connection, "id:1", "tag:1/followers")
+            .await
+            .unwrap();
+
+        put_document(&mut connection, "id:2", "message 2")
+            .await
+            .unwrap();
+        put_message_id(&mut connection, "id:2", "did:3/actor")
+            .await
+            .unwrap();
+        put_message_audience(&mut connection, "id:2", "tag:1/followers")
+            .await
+            .unwrap();
+
+        // did:1 follows both authors and the shared tag
+        put_actor_following(&mut connection, "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+        put_actor_following(&mut connection, "did:1/actor", "did:3/actor")
+            .await
+            .unwrap();
+        put_actor_audience(&mut connection, "did:1/actor", "tag:1/followers")
+            .await
+            .unwrap();
+        let out = get_inbox_for_actor(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["message 2", "message 1"]);
+
+        // did:1 mutes did:3, hidden from the inbox but not when asking for it
+        put_actor_muting(&mut connection, "did:1/actor", "did:3/actor")
+            .await
+            .unwrap();
+        let out = get_inbox_for_actor(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["message 1"]);
+        let out = get_inbox_with_audiences(
+            &mut connection,
+            "did:1/actor",
+            &vec!["tag:1/followers".to_string()],
+            3,
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(out.items, ["message 1"]);
+        let out = get_inbox_from_actor(&mut connection, "did:1/actor", "did:3/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["message 2"]);
+        assert!(
+            inbox_contains_message(&mut connection, "did:1/actor", "id:2")
+                .await
+                .unwrap()
+        );
+
+        // did:1 blocks did:2, hidden everywhere
+        put_actor_blocking(&mut connection, "did:1/actor", "did:2/actor")
+            .await
+            .unwrap();
+        assert!(get_inbox_for_actor(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(get_inbox_with_audiences(
+            &mut connection,
+            "did:1/actor",
+            &vec!["tag:1/followers".to_string()],
+            3,
+            None,
+        )
+        .await
+        .unwrap()
+        .is_none());
+        assert!(
+            get_inbox_from_actor(&mut connection, "did:1/actor", "did:2/actor", 3, None)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            !inbox_contains_message(&mut connection, "did:1/actor", "id:1")
+                .await
+                .unwrap()
+        );
+    }
 }
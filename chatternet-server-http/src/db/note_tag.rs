@@ -0,0 +1,208 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use sqlx::{Row, SqliteConnection};
+
+use super::{joint_id, CollectionPageOut};
+
+pub async fn create_notes_tags(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `NotesTags` \
+        (\
+            `idx` INTEGER PRIMARY KEY AUTOINCREMENT, \
+            `joint_id` TEXT UNIQUE NOT NULL, \
+            `note_id` TEXT NOT NULL, \
+            `tag_id` TEXT NOT NULL, \
+            `message_id` TEXT NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `notes_tags_tag_id` \
+        ON `NotesTags`(`tag_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `notes_tags_message_id` \
+        ON `NotesTags`(`message_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Link the note `note_id`, created by the message `message_id`, to the tag
+/// `tag_id` it mentions.
+pub async fn put_note_tag(
+    connection: &mut SqliteConnection,
+    note_id: &str,
+    tag_id: &str,
+    message_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        INSERT OR IGNORE INTO `NotesTags` \
+        (`joint_id`, `note_id`, `tag_id`, `message_id`) \
+        VALUES($1, $2, $3, $4);\
+        ",
+    )
+    .bind(joint_id(&[note_id, tag_id]))
+    .bind(note_id)
+    .bind(tag_id)
+    .bind(message_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_message_notes_tags(
+    connection: &mut SqliteConnection,
+    message_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `NotesTags` \
+        WHERE `message_id` = $1;\
+        ",
+    )
+    .bind(message_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Unlink the note `note_id` from all the tags it mentions.
+pub async fn delete_note_notes_tags(
+    connection: &mut SqliteConnection,
+    note_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `NotesTags` \
+        WHERE `note_id` = $1;\
+        ",
+    )
+    .bind(note_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Get the IDs of the notes linked to `tag_id`, most recent first.
+pub async fn get_tag_notes(
+    connection: &mut SqliteConnection,
+    tag_id: &str,
+    count: u64,
+    start_idx: Option<u64>,
+) -> Result<Option<CollectionPageOut>> {
+    let query_str = format!(
+        "\
+        SELECT `idx`, `note_id` FROM `NotesTags` \
+        WHERE `tag_id` = $1 \
+        {} \
+        ORDER BY `idx` DESC \
+        LIMIT $2;\
+        ",
+        if start_idx.is_some() {
+            "AND `idx` <= $3"
+        } else {
+            ""
+        }
+    );
+    let query = match start_idx {
+        Some(start_idx) => sqlx::query(&query_str)
+            .bind(tag_id)
+            .bind(i64::try_from(count)?)
+            .bind(u32::try_from(start_idx)?),
+        None => sqlx::query(&query_str)
+            .bind(tag_id)
+            .bind(i64::try_from(count)?),
+    };
+    let mut notes_id = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    let mut low_idx: Option<u64> = None;
+    let mut high_idx: Option<u64> = None;
+    while let Some(row) = rows.try_next().await? {
+        let note_id: &str = row.try_get("note_id")?;
+        let idx: u32 = row.try_get("idx")?;
+        notes_id.push(note_id.to_string());
+        low_idx = low_idx.map(|x| x.min(idx as u64)).or(Some(idx as u64));
+        high_idx = high_idx.map(|x| x.max(idx as u64)).or(Some(idx as u64));
+    }
+    Ok(match (low_idx, high_idx) {
+        (Some(low_idx), Some(high_idx)) => Some(CollectionPageOut {
+            items: notes_id,
+            low_idx,
+            high_idx,
+        }),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use tokio;
+
+    use super::super::Connector;
+    use super::*;
+
+    #[tokio::test]
+    async fn puts_gets_deletes_notes_tags() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        put_note_tag(&mut connection, "id:1", "tag:1", "id:m1")
+            .await
+            .unwrap();
+        put_note_tag(&mut connection, "id:2", "tag:1", "id:m2")
+            .await
+            .unwrap();
+        put_note_tag(&mut connection, "id:2", "tag:2", "id:m2")
+            .await
+            .unwrap();
+        // is idempotent
+        put_note_tag(&mut connection, "id:1", "tag:1", "id:m1")
+            .await
+            .unwrap();
+
+        let out = get_tag_notes(&mut connection, "tag:1", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:2", "id:1"]);
+        assert_eq!(out.low_idx, 1);
+        assert_eq!(out.high_idx, 2);
+        let out = get_tag_notes(&mut connection, "tag:1", 3, Some(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:1"]);
+
+        delete_message_notes_tags(&mut connection, "id:m2")
+            .await
+            .unwrap();
+        let out = get_tag_notes(&mut connection, "tag:1", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:1"]);
+        assert!(get_tag_notes(&mut connection, "tag:2", 3, None)
+            .await
+            .unwrap()
+            .is_none());
+
+        delete_note_notes_tags(&mut connection, "id:1")
+            .await
+            .unwrap();
+        assert!(get_tag_notes(&mut connection, "tag:1", 3, None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,394 @@
+use anyhow::Result;
+use chatternet::model::PUBLIC_AUDIENCE_ID;
+use sqlx::SqliteConnection;
+
+use super::{build_inbox_messages, build_list_owner_condition, joint_id, CollectionPageOut};
+
+/// The kinds of events an actor is notified of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotificationKind {
+    Mention,
+    Reply,
+    Follow,
+    Like,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mention => "Mention",
+            Self::Reply => "Reply",
+            Self::Follow => "Follow",
+            Self::Like => "Like",
+        }
+    }
+}
+
+pub async fn create_notifications(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `Notifications` \
+        (\
+            `idx` INTEGER PRIMARY KEY AUTOINCREMENT, \
+            `joint_id` TEXT UNIQUE NOT NULL, \
+            `actor_id` TEXT NOT NULL, \
+            `kind` TEXT NOT NULL, \
+            `from_actor_id` TEXT NOT NULL, \
+            `object_id` TEXT NOT NULL, \
+            `message_id` TEXT NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `notifications_actor_id` \
+        ON `Notifications`(`actor_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `notifications_message_id` \
+        ON `Notifications`(`message_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Notify `actor_id` that `from_actor_id` did `kind` to `object_id` with the
+/// message `message_id`.
+///
+/// An actor is not notified of its own actions, and is notified only once of
+/// the same action.
+pub async fn put_notification(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    kind: NotificationKind,
+    from_actor_id: &str,
+    object_id: &str,
+    message_id: &str,
+) -> Result<()> {
+    if actor_id == from_actor_id {
+        return Ok(());
+    }
+    sqlx::query(
+        "\
+        INSERT OR IGNORE INTO `Notifications` \
+        (`joint_id`, `actor_id`, `kind`, `from_actor_id`, `object_id`, `message_id`) \
+        VALUES($1, $2, $3, $4, $5, $6);\
+        ",
+    )
+    .bind(joint_id(&[
+        actor_id,
+        kind.as_str(),
+        from_actor_id,
+        object_id,
+    ]))
+    .bind(actor_id)
+    .bind(kind.as_str())
+    .bind(from_actor_id)
+    .bind(object_id)
+    .bind(message_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_notification(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    kind: NotificationKind,
+    from_actor_id: &str,
+    object_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `Notifications` \
+        WHERE `joint_id` = $1;\
+        ",
+    )
+    .bind(joint_id(&[
+        actor_id,
+        kind.as_str(),
+        from_actor_id,
+        object_id,
+    ]))
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Delete the notifications of `kind` sent by `from_actor_id`.
+pub async fn delete_actor_all_notifications(
+    connection: &mut SqliteConnection,
+    from_actor_id: &str,
+    kind: NotificationKind,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `Notifications` \
+        WHERE `from_actor_id` = $1 \
+        AND `kind` = $2;\
+        ",
+    )
+    .bind(from_actor_id)
+    .bind(kind.as_str())
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_message_notifications(
+    connection: &mut SqliteConnection,
+    message_id: &str,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        DELETE FROM `Notifications` \
+        WHERE `message_id` = $1;\
+        ",
+    )
+    .bind(message_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Get the messages which notified `actor_id`, most recent first.
+///
+/// Mentions and replies are included only if their message is public or
+/// addressed to an audience of `actor_id`, including the lists of the author
+/// it is a member of, so that notifications don't reveal messages the actor
+/// couldn't otherwise see. Notifications from blocked actors are
+/// excluded.
+pub async fn get_notifications(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    count: u64,
+    start_idx: Option<u64>,
+) -> Result<Option<CollectionPageOut>> {
+    let query_str = format!(
+        "\
+        SELECT MAX(`Notifications`.`idx`) AS `idx`, `document` FROM `Notifications` \
+        INNER JOIN `Documents` \
+        ON `Documents`.`document_id` = `Notifications`.`message_id` \
+        WHERE `Notifications`.`actor_id` = $1 \
+        AND (\
+            `Notifications`.`kind` NOT IN ('Mention', 'Reply') \
+            OR `Notifications`.`message_id` IN (\
+                SELECT `message_id` FROM `MessagesAudiences` \
+                WHERE `MessagesAudiences`.`audience_id` = $1 \
+                OR `MessagesAudiences`.`audience_id` = '{}' \
+                OR `MessagesAudiences`.`audience_id` IN (\
+                    SELECT `audience_id` FROM `ActorsAudiences` \
+                    WHERE `ActorsAudiences`.`actor_id` = $1\
+                )\
+                OR (\
+                    `MessagesAudiences`.`audience_id` IN (\
+                        SELECT `list_id` FROM `ListsMembers` \
+                        WHERE `ListsMembers`.`member_id` = $1\
+                    ) \
+                    AND {list_owned}\
+                )\
+            )\
+        ) \
+        AND `Notifications`.`from_actor_id` NOT IN (\
+            SELECT `blocked_id` FROM `ActorsBlockings` \
+            WHERE `ActorsBlockings`.`actor_id` = $1\
+        ) \
+        {} \
+        GROUP BY `Notifications`.`message_id` \
+        ORDER BY `idx` DESC \
+        LIMIT $2;\
+        ",
+        PUBLIC_AUDIENCE_ID,
+        if start_idx.is_some() {
+            "AND `Notifications`.`idx` <= $3"
+        } else {
+            ""
+        },
+        list_owned = build_list_owner_condition(
+            "`MessagesAudiences`.`audience_id`",
+            "`Notifications`.`from_actor_id`"
+        ),
+    );
+    let query = match start_idx {
+        Some(start_idx) => sqlx::query(&query_str)
+            .bind(actor_id)
+            .bind(i64::try_from(count)?)
+            .bind(u32::try_from(start_idx)?),
+        None => sqlx::query(&query_str)
+            .bind(actor_id)
+            .bind(i64::try_from(count)?),
+    };
+    build_inbox_messages(query, connection).await
+}
+
+#[cfg(test)]
+mod test {
+    use tokio;
+
+    use super::super::{
+        put_actor_audience, put_document, put_list_member, put_message_audience, Connector,
+    };
+    use super::*;
+
+    #[tokio::test]
+    async fn puts_gets_deletes_notifications() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+
+        for (message_id, audience_id) in [
+            ("id:m1", "did:1/actor"),
+            ("id:m2", "did:2/actor/followers"),
+            ("id:m3", "did:2/actor"),
+        ] {
+            put_document(&mut connection, message_id, message_id)
+                .await
+                .unwrap();
+            put_message_audience(&mut connection, message_id, audience_id)
+                .await
+                .unwrap();
+        }
+
+        // addressed to did:1
+        put_notification(
+            &mut connection,
+            "did:1/actor",
+            NotificationKind::Mention,
+            "did:2/actor",
+            "id:1",
+            "id:m1",
+        )
+        .await
+        .unwrap();
+        // not addressed to did:1
+        put_notification(
+            &mut connection,
+            "did:1/actor",
+            NotificationKind::Reply,
+            "did:2/actor",
+            "id:2",
+            "id:m2",
+        )
+        .await
+        .unwrap();
+        put_notification(
+            &mut connection,
+            "did:1/actor",
+            NotificationKind::Follow,
+            "did:2/actor",
+            "did:1/actor",
+            "id:m3",
+        )
+        .await
+        .unwrap();
+        // not notified of own actions
+        put_notification(
+            &mut connection,
+            "did:1/actor",
+            NotificationKind::Like,
+            "did:1/actor",
+            "id:1",
+            "id:m1",
+        )
+        .await
+        .unwrap();
+
+        let out = get_notifications(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:m3", "id:m1"]);
+
+        // did:1 follows did:2 so can see the reply
+        put_actor_audience(&mut connection, "did:1/actor", "did:2/actor/followers")
+            .await
+            .unwrap();
+        let out = get_notifications(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:m3", "id:m2", "id:m1"]);
+        let out = get_notifications(&mut connection, "did:1/actor", 3, Some(2))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:m2", "id:m1"]);
+
+        delete_notification(
+            &mut connection,
+            "did:1/actor",
+            NotificationKind::Follow,
+            "did:2/actor",
+            "did:1/actor",
+        )
+        .await
+        .unwrap();
+        delete_message_notifications(&mut connection, "id:m2")
+            .await
+            .unwrap();
+        let out = get_notifications(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:m1"]);
+
+        delete_actor_all_notifications(&mut connection, "did:2/actor", NotificationKind::Mention)
+            .await
+            .unwrap();
+        assert!(get_notifications(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn gets_notifications_addressed_to_lists_of_author() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+
+        for (message_id, audience_id) in [
+            ("id:m1", "did:2/actor/lists/a"),
+            // a list of another actor than the author
+            ("id:m2", "did:3/actor/lists/a"),
+        ] {
+            put_document(&mut connection, message_id, message_id)
+                .await
+                .unwrap();
+            put_message_audience(&mut connection, message_id, audience_id)
+                .await
+                .unwrap();
+            put_notification(
+                &mut connection,
+                "did:1/actor",
+                NotificationKind::Mention,
+                "did:2/actor",
+                "id:1",
+                message_id,
+            )
+            .await
+            .unwrap();
+        }
+        assert!(get_notifications(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .is_none());
+
+        for list_id in ["did:2/actor/lists/a", "did:3/actor/lists/a"] {
+            put_list_member(&mut connection, list_id, "did:1/actor")
+                .await
+                .unwrap();
+        }
+        let out = get_notifications(&mut connection, "did:1/actor", 3, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(out.items, ["id:m1"]);
+    }
+}
@@ -0,0 +1,474 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use sqlx::{Row, SqliteConnection};
+
+use super::joint_id;
+
+/// The kinds of reactions an actor can have to an object.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reaction {
+    Like,
+    Share,
+}
+
+impl Reaction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Like => "Like",
+            Self::Share => "Share",
+        }
+    }
+}
+
+pub async fn create_reactions(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `Reactions` \
+        (\
+            `idx` INTEGER PRIMARY KEY AUTOINCREMENT, \
+            `joint_id` TEXT UNIQUE NOT NULL, \
+            `actor_id` TEXT NOT NULL, \
+            `object_id` TEXT NOT NULL, \
+            `reaction` TEXT NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `reactions_object_id` \
+        ON `Reactions`(`object_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    // an actor can send the same reaction in many messages, the reaction
+    // lasts until all of them are deleted
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `ReactionsMessages` \
+        (\
+            `reaction_id` TEXT NOT NULL, \
+            `message_id` TEXT NOT NULL, \
+            UNIQUE(`reaction_id`, `message_id`)\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `reactions_messages_message_id` \
+        ON `ReactionsMessages`(`message_id`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `ReactionsCounts` \
+        (\
+            `joint_id` TEXT PRIMARY KEY, \
+            `object_id` TEXT NOT NULL, \
+            `reaction` TEXT NOT NULL, \
+            `count` INTEGER NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+async fn add_reaction_count(
+    connection: &mut SqliteConnection,
+    object_id: &str,
+    reaction: Reaction,
+    delta: i64,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        INSERT INTO `ReactionsCounts` \
+        (`joint_id`, `object_id`, `reaction`, `count`) \
+        VALUES($1, $2, $3, MAX($4, 0)) \
+        ON CONFLICT(`joint_id`) DO UPDATE \
+        SET `count` = MAX(`count` + $4, 0);\
+        ",
+    )
+    .bind(joint_id(&[object_id, reaction.as_str()]))
+    .bind(object_id)
+    .bind(reaction.as_str())
+    .bind(delta)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Store the `reaction` of `actor_id` to `object_id` made by `message_id`.
+///
+/// An actor has at most one reaction of each kind to an object, so this
+/// counts the reaction only if it isn't already known.
+pub async fn put_reaction(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    object_id: &str,
+    reaction: Reaction,
+    message_id: &str,
+) -> Result<()> {
+    let reaction_id = joint_id(&[actor_id, object_id, reaction.as_str()]);
+    sqlx::query(
+        "\
+        INSERT OR IGNORE INTO `ReactionsMessages` \
+        (`reaction_id`, `message_id`) \
+        VALUES($1, $2);\
+        ",
+    )
+    .bind(&reaction_id)
+    .bind(message_id)
+    .execute(&mut *connection)
+    .await?;
+    let inserted = sqlx::query(
+        "\
+        INSERT OR IGNORE INTO `Reactions` \
+        (`joint_id`, `actor_id`, `object_id`, `reaction`) \
+        VALUES($1, $2, $3, $4);\
+        ",
+    )
+    .bind(&reaction_id)
+    .bind(actor_id)
+    .bind(object_id)
+    .bind(reaction.as_str())
+    .execute(&mut *connection)
+    .await?
+    .rows_affected();
+    if inserted > 0 {
+        add_reaction_count(&mut *connection, object_id, reaction, 1).await?;
+    }
+    Ok(())
+}
+
+/// Delete the `reaction` of `actor_id` to `object_id`, regardless of how
+/// many messages made it.
+pub async fn delete_reaction(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    object_id: &str,
+    reaction: Reaction,
+) -> Result<()> {
+    let reaction_id = joint_id(&[actor_id, object_id, reaction.as_str()]);
+    sqlx::query(
+        "\
+        DELETE FROM `ReactionsMessages` \
+        WHERE `reaction_id` = $1;\
+        ",
+    )
+    .bind(&reaction_id)
+    .execute(&mut *connection)
+    .await?;
+    let deleted = sqlx::query(
+        "\
+        DELETE FROM `Reactions` \
+        WHERE `joint_id` = $1;\
+        ",
+    )
+    .bind(&reaction_id)
+    .execute(&mut *connection)
+    .await?
+    .rows_affected();
+    if deleted > 0 {
+        add_reaction_count(&mut *connection, object_id, reaction, -1).await?;
+    }
+    Ok(())
+}
+
+/// Delete the reactions made by the message with `message_id`, unless
+/// another message still makes them.
+pub async fn delete_message_reactions(
+    connection: &mut SqliteConnection,
+    message_id: &str,
+) -> Result<()> {
+    let query = sqlx::query(
+        "\
+        SELECT `actor_id`, `object_id`, `reaction` FROM `Reactions` \
+        WHERE `joint_id` IN (\
+            SELECT `reaction_id` FROM `ReactionsMessages` \
+            WHERE `message_id` = $1\
+        );\
+        ",
+    )
+    .bind(message_id);
+    let mut reactions = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let actor_id: &str = row.try_get("actor_id")?;
+        let object_id: &str = row.try_get("object_id")?;
+        let reaction: &str = row.try_get("reaction")?;
+        let reaction = if reaction == Reaction::Like.as_str() {
+            Reaction::Like
+        } else {
+            Reaction::Share
+        };
+        reactions.push((actor_id.to_string(), object_id.to_string(), reaction));
+    }
+    drop(rows);
+    sqlx::query(
+        "\
+        DELETE FROM `ReactionsMessages` \
+        WHERE `message_id` = $1;\
+        ",
+    )
+    .bind(message_id)
+    .execute(&mut *connection)
+    .await?;
+    for (actor_id, object_id, reaction) in reactions {
+        let remaining: i64 = sqlx::query(
+            "\
+            SELECT COUNT(*) FROM `ReactionsMessages` \
+            WHERE `reaction_id` = $1;\
+            ",
+        )
+        .bind(joint_id(&[&actor_id, &object_id, reaction.as_str()]))
+        .fetch_one(&mut *connection)
+        .await?
+        .get(0);
+        if remaining > 0 {
+            continue;
+        }
+        delete_reaction(&mut *connection, &actor_id, &object_id, reaction).await?;
+    }
+    Ok(())
+}
+
+/// Get the IDs of the objects to which `actor_id` has a `reaction`.
+pub async fn get_actor_reactions(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    reaction: Reaction,
+) -> Result<Vec<String>> {
+    let query = sqlx::query(
+        "\
+        SELECT `object_id` FROM `Reactions` \
+        WHERE `actor_id` = $1 \
+        AND `reaction` = $2 \
+        ORDER BY `idx`;\
+        ",
+    )
+    .bind(actor_id)
+    .bind(reaction.as_str());
+    let mut objects_id = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let object_id: &str = row.try_get("object_id")?;
+        objects_id.push(object_id.to_string());
+    }
+    Ok(objects_id)
+}
+
+/// Get the IDs of the last `count` actors with a `reaction` to `object_id`.
+pub async fn get_object_reactions(
+    connection: &mut SqliteConnection,
+    object_id: &str,
+    reaction: Reaction,
+    count: u64,
+) -> Result<Vec<String>> {
+    let query = sqlx::query(
+        "\
+        SELECT `actor_id` FROM `Reactions` \
+        WHERE `object_id` = $1 \
+        AND `reaction` = $2 \
+        ORDER BY `idx` DESC \
+        LIMIT $3;\
+        ",
+    )
+    .bind(object_id)
+    .bind(reaction.as_str())
+    .bind(i64::try_from(count)?);
+    let mut actors_id = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let actor_id: &str = row.try_get("actor_id")?;
+        actors_id.push(actor_id.to_string());
+    }
+    Ok(actors_id)
+}
+
+/// Get the number of actors with a `reaction` to `object_id`.
+pub async fn get_reaction_count(
+    connection: &mut SqliteConnection,
+    object_id: &str,
+    reaction: Reaction,
+) -> Result<u64> {
+    let count: Option<i64> = sqlx::query(
+        "\
+        SELECT `count` FROM `ReactionsCounts` \
+        WHERE `joint_id` = $1;\
+        ",
+    )
+    .bind(joint_id(&[object_id, reaction.as_str()]))
+    .fetch_optional(&mut *connection)
+    .await?
+    .map(|x| x.get(0));
+    Ok(count.unwrap_or(0).max(0) as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio;
+
+    use super::super::Connector;
+    use super::*;
+
+    #[tokio::test]
+    async fn puts_counts_deletes_reactions() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        put_reaction(
+            &mut connection,
+            "did:1/actor",
+            "id:1",
+            Reaction::Like,
+            "id:m1",
+        )
+        .await
+        .unwrap();
+        // is idempotent
+        put_reaction(
+            &mut connection,
+            "did:1/actor",
+            "id:1",
+            Reaction::Like,
+            "id:m2",
+        )
+        .await
+        .unwrap();
+        put_reaction(
+            &mut connection,
+            "did:2/actor",
+            "id:1",
+            Reaction::Like,
+            "id:m3",
+        )
+        .await
+        .unwrap();
+        put_reaction(
+            &mut connection,
+            "did:2/actor",
+            "id:1",
+            Reaction::Share,
+            "id:m3",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            get_reaction_count(&mut connection, "id:1", Reaction::Like)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            get_reaction_count(&mut connection, "id:1", Reaction::Share)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            get_reaction_count(&mut connection, "id:2", Reaction::Like)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            get_object_reactions(&mut connection, "id:1", Reaction::Like, 3)
+                .await
+                .unwrap(),
+            ["did:2/actor", "did:1/actor"]
+        );
+        assert_eq!(
+            get_actor_reactions(&mut connection, "did:2/actor", Reaction::Share)
+                .await
+                .unwrap(),
+            ["id:1"]
+        );
+
+        delete_reaction(&mut connection, "did:1/actor", "id:1", Reaction::Like)
+            .await
+            .unwrap();
+        // deleting again doesn't change the count
+        delete_reaction(&mut connection, "did:1/actor", "id:1", Reaction::Like)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_reaction_count(&mut connection, "id:1", Reaction::Like)
+                .await
+                .unwrap(),
+            1
+        );
+
+        delete_message_reactions(&mut connection, "id:m3")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_reaction_count(&mut connection, "id:1", Reaction::Like)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            get_reaction_count(&mut connection, "id:1", Reaction::Share)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_reactions_of_remaining_messages() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+        for message_id in ["id:m1", "id:m2"] {
+            put_reaction(
+                &mut connection,
+                "did:1/actor",
+                "id:1",
+                Reaction::Like,
+                message_id,
+            )
+            .await
+            .unwrap();
+        }
+
+        // the second message still likes the object
+        delete_message_reactions(&mut connection, "id:m1")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_reaction_count(&mut connection, "id:1", Reaction::Like)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            get_object_reactions(&mut connection, "id:1", Reaction::Like, 3)
+                .await
+                .unwrap(),
+            ["did:1/actor"]
+        );
+
+        delete_message_reactions(&mut connection, "id:m2")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_reaction_count(&mut connection, "id:1", Reaction::Like)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            get_object_reactions(&mut connection, "id:1", Reaction::Like, 3)
+                .await
+                .unwrap(),
+            Vec::<String>::new()
+        );
+    }
+}
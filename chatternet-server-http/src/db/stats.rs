@@ -0,0 +1,331 @@
+use anyhow::Result;
+use futures::TryStreamExt;
+use sqlx::{Row, SqliteConnection};
+
+use super::joint_id;
+
+/// The duration of the time buckets in which statistics are counted.
+pub const STATS_BUCKET_MILLIS: i64 = 10 * 60 * 1000;
+
+/// The kinds of IDs for which statistics are counted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsKind {
+    Tag,
+    Author,
+}
+
+impl StatsKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tag => "Tag",
+            Self::Author => "Author",
+        }
+    }
+}
+
+pub async fn create_stats(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `StatsCounts` \
+        (\
+            `joint_id` TEXT PRIMARY KEY, \
+            `kind` TEXT NOT NULL, \
+            `id` TEXT NOT NULL, \
+            `bucket` INTEGER NOT NULL, \
+            `count` INTEGER NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `stats_counts_kind_bucket` \
+        ON `StatsCounts`(`kind`, `bucket`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `stats_counts_bucket` \
+        ON `StatsCounts`(`bucket`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    // the time at which a message was counted, which needn't be the time
+    // it was published at
+    sqlx::query(
+        "\
+        CREATE TABLE IF NOT EXISTS `StatsMessages` \
+        (\
+            `message_id` TEXT PRIMARY KEY, \
+            `timestamp_millis` INTEGER NOT NULL\
+        );\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        CREATE INDEX IF NOT EXISTS `stats_messages_timestamp_millis` \
+        ON `StatsMessages`(`timestamp_millis`);\
+        ",
+    )
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+fn stats_bucket(timestamp_millis: i64) -> i64 {
+    timestamp_millis.div_euclid(STATS_BUCKET_MILLIS)
+}
+
+/// Add `delta` to the count of `id` in the time bucket of `timestamp_millis`.
+pub async fn add_stats_count(
+    connection: &mut SqliteConnection,
+    kind: StatsKind,
+    id: &str,
+    timestamp_millis: i64,
+    delta: i64,
+) -> Result<()> {
+    let bucket = stats_bucket(timestamp_millis);
+    sqlx::query(
+        "\
+        INSERT INTO `StatsCounts` \
+        (`joint_id`, `kind`, `id`, `bucket`, `count`) \
+        VALUES($1, $2, $3, $4, MAX($5, 0)) \
+        ON CONFLICT(`joint_id`) DO UPDATE \
+        SET `count` = MAX(`count` + $5, 0);\
+        ",
+    )
+    .bind(joint_id(&[kind.as_str(), id, &bucket.to_string()]))
+    .bind(kind.as_str())
+    .bind(id)
+    .bind(bucket)
+    .bind(delta)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Store the time at which the message with `message_id` was counted.
+pub async fn put_stats_message(
+    connection: &mut SqliteConnection,
+    message_id: &str,
+    timestamp_millis: i64,
+) -> Result<()> {
+    sqlx::query(
+        "\
+        INSERT OR REPLACE INTO `StatsMessages` \
+        (`message_id`, `timestamp_millis`) \
+        VALUES($1, $2);\
+        ",
+    )
+    .bind(message_id)
+    .bind(timestamp_millis)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Delete and return the time at which the message with `message_id` was
+/// counted, if it is counted in a bucket which wasn't yet deleted.
+pub async fn take_stats_message(
+    connection: &mut SqliteConnection,
+    message_id: &str,
+) -> Result<Option<i64>> {
+    let timestamp_millis: Option<i64> = sqlx::query(
+        "\
+        SELECT `timestamp_millis` FROM `StatsMessages` \
+        WHERE `message_id` = $1;\
+        ",
+    )
+    .bind(message_id)
+    .fetch_optional(&mut *connection)
+    .await?
+    .map(|x| x.get(0));
+    sqlx::query(
+        "\
+        DELETE FROM `StatsMessages` \
+        WHERE `message_id` = $1;\
+        ",
+    )
+    .bind(message_id)
+    .execute(&mut *connection)
+    .await?;
+    Ok(timestamp_millis)
+}
+
+/// Delete the counts in time buckets entirely before `timestamp_millis`.
+pub async fn delete_stats_before(
+    connection: &mut SqliteConnection,
+    timestamp_millis: i64,
+) -> Result<()> {
+    let bucket = stats_bucket(timestamp_millis);
+    sqlx::query(
+        "\
+        DELETE FROM `StatsCounts` \
+        WHERE `bucket` < $1;\
+        ",
+    )
+    .bind(bucket)
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "\
+        DELETE FROM `StatsMessages` \
+        WHERE `timestamp_millis` < $1;\
+        ",
+    )
+    .bind(bucket * STATS_BUCKET_MILLIS)
+    .execute(&mut *connection)
+    .await?;
+    Ok(())
+}
+
+/// Get the `count` IDs of `kind` with the largest counts in the time buckets
+/// from `since_millis` up to `until_millis`, along with their counts.
+pub async fn get_stats_top(
+    connection: &mut SqliteConnection,
+    kind: StatsKind,
+    since_millis: i64,
+    until_millis: i64,
+    count: u64,
+) -> Result<Vec<(String, u64)>> {
+    let query = sqlx::query(
+        "\
+        SELECT `id`, SUM(`count`) AS `total` FROM `StatsCounts` \
+        WHERE `kind` = $1 \
+        AND `bucket` >= $2 \
+        AND `bucket` <= $3 \
+        GROUP BY `id` \
+        HAVING `total` > 0 \
+        ORDER BY `total` DESC, `id` \
+        LIMIT $4;\
+        ",
+    )
+    .bind(kind.as_str())
+    .bind(stats_bucket(since_millis))
+    .bind(stats_bucket(until_millis))
+    .bind(i64::try_from(count)?);
+    let mut counts = Vec::new();
+    let mut rows = query.fetch(&mut *connection);
+    while let Some(row) = rows.try_next().await? {
+        let id: &str = row.try_get("id")?;
+        let total: i64 = row.try_get("total")?;
+        counts.push((id.to_string(), total.max(0) as u64));
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod test {
+    use tokio;
+
+    use super::super::Connector;
+    use super::*;
+
+    #[tokio::test]
+    async fn counts_and_gets_top_stats() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+
+        let now = 1_000 * STATS_BUCKET_MILLIS;
+        let before = now - 2 * STATS_BUCKET_MILLIS;
+        for (id, timestamp_millis) in [
+            ("tag:1", now),
+            ("tag:1", now),
+            ("tag:2", now),
+            ("tag:2", before),
+            ("tag:2", before),
+            ("tag:3", before),
+        ] {
+            add_stats_count(&mut connection, StatsKind::Tag, id, timestamp_millis, 1)
+                .await
+                .unwrap();
+        }
+        add_stats_count(&mut connection, StatsKind::Author, "did:1/actor", now, 1)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            get_stats_top(&mut connection, StatsKind::Tag, now, now, 3)
+                .await
+                .unwrap(),
+            [("tag:1".to_string(), 2), ("tag:2".to_string(), 1)]
+        );
+        assert_eq!(
+            get_stats_top(&mut connection, StatsKind::Tag, before, now, 2)
+                .await
+                .unwrap(),
+            [("tag:2".to_string(), 3), ("tag:1".to_string(), 2)]
+        );
+        // the buckets after the end of the window aren't counted
+        assert_eq!(
+            get_stats_top(&mut connection, StatsKind::Tag, before, before, 3)
+                .await
+                .unwrap(),
+            [("tag:2".to_string(), 2), ("tag:3".to_string(), 1)]
+        );
+
+        // counts are decremented, never below 0
+        add_stats_count(&mut connection, StatsKind::Tag, "tag:1", now, -1)
+            .await
+            .unwrap();
+        add_stats_count(&mut connection, StatsKind::Tag, "tag:3", now, -1)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_stats_top(&mut connection, StatsKind::Tag, now, now, 3)
+                .await
+                .unwrap(),
+            [("tag:1".to_string(), 1), ("tag:2".to_string(), 1)]
+        );
+
+        delete_stats_before(&mut connection, now).await.unwrap();
+        assert_eq!(
+            get_stats_top(&mut connection, StatsKind::Tag, before, now, 3)
+                .await
+                .unwrap(),
+            [("tag:1".to_string(), 1), ("tag:2".to_string(), 1)]
+        );
+        assert_eq!(
+            get_stats_top(&mut connection, StatsKind::Author, now, now, 3)
+                .await
+                .unwrap(),
+            [("did:1/actor".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn puts_takes_deletes_stats_messages() {
+        let connector = Connector::new("sqlite::memory:").await.unwrap();
+        let mut connection = connector.connection().await.unwrap();
+
+        let now = 1_000 * STATS_BUCKET_MILLIS;
+        put_stats_message(&mut connection, "id:m1", now)
+            .await
+            .unwrap();
+        put_stats_message(&mut connection, "id:m2", now - 1)
+            .await
+            .unwrap();
+        assert_eq!(
+            take_stats_message(&mut connection, "id:m1").await.unwrap(),
+            Some(now)
+        );
+        // is taken only once
+        assert_eq!(
+            take_stats_message(&mut connection, "id:m1").await.unwrap(),
+            None
+        );
+
+        // is deleted along with its bucket
+        delete_stats_before(&mut connection, now).await.unwrap();
+        assert_eq!(
+            take_stats_message(&mut connection, "id:m2").await.unwrap(),
+            None
+        );
+    }
+}
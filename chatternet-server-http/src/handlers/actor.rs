@@ -1,11 +1,13 @@
 use anyhow::Result;
 use axum::extract::{Json, Path, Query, State};
 use axum::http::StatusCode;
+use axum::response::{IntoResponse, Redirect, Response};
 use chatternet::didkey::actor_id_from_did;
 use chatternet::model::{
     Actor, ActorFields, CollectionFields, CollectionPageFields, CollectionPageType, CollectionType,
     Document, Uri,
 };
+use sqlx::SqliteConnection;
 use tap::Pipe;
 
 use super::error::AppError;
@@ -14,22 +16,31 @@ use crate::db::{self};
 
 /// Get the Actor document with `did` using a DB connection obtained from
 /// `connector`.
+///
+/// Redirects to the new actor if the actor has moved.
 pub async fn handle_actor_get(
     State(AppState { connector, .. }): State<AppState>,
     Path(did): Path<String>,
-) -> Result<Json<ActorFields>, AppError> {
+) -> Result<Response, AppError> {
     let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
     let connector = connector.read().await;
     let mut connection = connector
         .connection()
         .await
         .map_err(|_| AppError::DbConnectionFailed)?;
+    if let Some(new_id) = db::get_actor_moved_to(&mut connection, &actor_id)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?
+    {
+        // relative to the path `{did}/actor`
+        return Ok(Redirect::permanent(&format!("../{}", new_id)).into_response());
+    }
     let actor = db::get_document(&mut connection, &actor_id).await;
     match actor {
         Ok(Some(actor)) => {
             let actor: ActorFields =
                 serde_json::from_str(&actor).map_err(|_| AppError::ActorNotValid)?;
-            Ok(Json(actor))
+            Ok(Json(actor).into_response())
         }
         _ => Err(AppError::ActorNotKnown),
     }
@@ -38,31 +49,46 @@ pub async fn handle_actor_get(
 /// Post an Actor `actor` for the actor with `did`. Stores the document using
 /// a DB connection obtained from `connector`.
 pub async fn handle_actor_post(
-    State(AppState { connector, .. }): State<AppState>,
+    State(AppState {
+        connector,
+        cache,
+        resolver,
+        ..
+    }): State<AppState>,
     Path(did): Path<String>,
     Json(actor): Json<ActorFields>,
 ) -> Result<StatusCode, AppError> {
     let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
+    // the posted Actor must have the same ID as that in the path
+    if actor.id().as_str() != actor_id {
+        Err(AppError::ActorIdWrong)?;
+    }
+    // verify before taking the lock so concurrent posts verify in parallel
+    if !actor.verify_cached_with(&cache, &*resolver).await.is_ok() {
+        Err(AppError::ActorNotValid)?;
+    }
     let mut connector = connector.write().await;
     let mut connection = connector
         .connection_mut()
         .await
         .map_err(|_| AppError::DbConnectionFailed)?;
-    // the posted Actor must have the same ID as that in the path
-    if actor.id().as_str() != actor_id {
-        Err(AppError::ActorIdWrong)?;
-    }
+    accept_actor(&actor, &mut *connection).await
+}
+
+/// Store the verified `actor`, unless a newer version is already stored.
+pub async fn accept_actor(
+    actor: &ActorFields,
+    connection: &mut SqliteConnection,
+) -> Result<StatusCode, AppError> {
+    let actor_id = actor.id().as_str();
     use_mutable(
-        &actor_id,
+        actor_id,
         actor.published().timestamp_millis(),
         &mut *connection,
     )
     .await?;
-    if !actor.verify().await.is_ok() {
-        Err(AppError::ActorNotValid)?;
-    }
-    let actor = serde_json::to_string(&actor).map_err(|_| AppError::ActorNotValid)?;
-    db::put_document(&mut *connection, &actor_id, &actor)
+    let actor = serde_json::to_string(actor).map_err(|_| AppError::ActorNotValid)?;
+    db::put_document(&mut *connection, actor_id, &actor)
         .await
         .map_err(|_| AppError::DbQueryFailed)?;
     Ok(StatusCode::OK)
@@ -88,6 +114,44 @@ pub async fn handle_actor_following(
     Ok(Json(following))
 }
 
+/// Get the collection of IDs blocked by the actor with `did`.
+pub async fn handle_actor_blocked(
+    State(AppState { connector, .. }): State<AppState>,
+    Path(did): Path<String>,
+) -> Result<Json<CollectionFields<String>>, AppError> {
+    let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
+    let connector = connector.read().await;
+    let mut connection = connector
+        .connection()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    let ids = db::get_actor_blockings(&mut *connection, &actor_id)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    let uri = Uri::try_from(format!("{}/blocked", actor_id)).map_err(|_| AppError::ActorIdWrong)?;
+    let blocked = CollectionFields::new(uri, CollectionType::Collection, ids);
+    Ok(Json(blocked))
+}
+
+/// Get the collection of delegations revoked by the actor with `did`.
+pub async fn handle_actor_revoked(
+    State(AppState { connector, .. }): State<AppState>,
+    Path(did): Path<String>,
+) -> Result<Json<CollectionFields<String>>, AppError> {
+    let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
+    let connector = connector.read().await;
+    let mut connection = connector
+        .connection()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    let ids = db::get_actor_revocations(&mut *connection, &actor_id)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    let uri = Uri::try_from(format!("{}/revoked", actor_id)).map_err(|_| AppError::ActorIdWrong)?;
+    let revoked = CollectionFields::new(uri, CollectionType::Collection, ids);
+    Ok(Json(revoked))
+}
+
 /// Get the collection of IDs of follower of the actor with `did`.
 pub async fn handle_actor_followers(
     State(AppState { connector, .. }): State<AppState>,
@@ -158,14 +222,15 @@ pub async fn handle_actor_followers(
 
 #[cfg(test)]
 mod test {
-    use axum::http::StatusCode;
+    use axum::http::{header, StatusCode};
     use tap::Pipe;
     use tokio;
     use tower::ServiceExt;
 
     use chatternet::didkey::{build_jwk, did_from_jwk};
     use chatternet::model::{
-        Actor, ActorFields, ActorType, CollectionPage, CollectionPageFields, Document,
+        ActivityType, Actor, ActorFields, ActorType, Collection, CollectionFields, CollectionPage,
+        CollectionPageFields, Document, Message, MessageBuilder, MessageFields,
     };
 
     use super::super::test_utils::*;
@@ -360,4 +425,135 @@ mod test {
             &[format!("{}/actor", did_2), format!("{}/actor", did_1),]
         );
     }
+
+    #[tokio::test]
+    async fn moves_actor_with_followings_and_followers() {
+        let api = build_test_api().await;
+
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_3 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
+        let did_3 = did_from_jwk(&jwk_3).unwrap();
+
+        // 1 follows a tag and 3 follows 1
+        for (jwk, did, following_id) in [
+            (&jwk_1, &did_1, "tag:1".to_string()),
+            (&jwk_3, &did_3, format!("{}/actor", did_1)),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did),
+                    &build_follow(vec![following_id], jwk).await,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        // 1 moves to 2 and 2 accepts
+        for (jwk, did) in [(&jwk_1, &did_1), (&jwk_2, &did_2)] {
+            let message = MessageBuilder::new(
+                jwk,
+                ActivityType::Move,
+                vec![format!("{}/actor", did_1).try_into().unwrap()]
+                    .try_into()
+                    .unwrap(),
+            )
+            .target(
+                vec![format!("{}/actor", did_2).try_into().unwrap()]
+                    .try_into()
+                    .unwrap(),
+            )
+            .build()
+            .await
+            .unwrap();
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did),
+                    &message,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = api
+            .clone()
+            .oneshot(request_empty("GET", &format!("/api/{}/actor", did_1)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION].to_str().unwrap(),
+            format!("../{}/actor", did_2)
+        );
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/following", did_2),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let following: CollectionFields<String> = get_body(response).await;
+        assert_eq!(following.items(), &["tag:1".to_string()]);
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/followers", did_2),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let followers: CollectionPageFields<String> = get_body(response).await;
+        assert_eq!(followers.items(), &[format!("{}/actor", did_3)]);
+
+        // later messages from 1 are flagged
+        let message = build_message(&jwk_1, "id:1", Some(vec![format!("{}/actor", did_3)])).await;
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_1),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/flagged", did_1),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let flagged: CollectionPageFields<MessageFields> = get_body(response).await;
+        assert_eq!(flagged.items().len(), 1);
+        assert_eq!(flagged.items()[0].id(), message.id());
+
+        // and are kept out of the inboxes
+        let response = api
+            .clone()
+            .oneshot(request_empty("GET", &format!("/api/{}/actor/inbox", did_3)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let inbox: CollectionPageFields<MessageFields> = get_body(response).await;
+        assert!(inbox
+            .items()
+            .iter()
+            .all(|x| x.object()[0].as_str() != "id:1"));
+    }
 }
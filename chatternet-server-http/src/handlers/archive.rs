@@ -0,0 +1,533 @@
+//! Export and import the data of an actor.
+//!
+//! An archive holds the actor's document, the messages it authored, the CID
+//! documents those messages reference and the IDs it follows. Importing
+//! verifies each item and accepts it as if it had been posted to the server,
+//! so that an actor can move its data from one server to another.
+//!
+//! The following set is restored by replaying the messages which changed
+//! it. Those messages are exported even when not addressed to the public,
+//! since the following set is already served to anyone. Otherwise, the
+//! archive served over HTTP, without authenticating the requester, holds
+//! only the public messages, while the server operator can export all of
+//! them.
+
+use anyhow::Result;
+use axum::extract::{Json, Path, State};
+use axum::http::StatusCode;
+use chatternet::didkey::actor_id_from_did;
+use chatternet::model::{
+    is_public_audience, ActivityType, ActorFields, Document, Message, MessageFields,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, SqliteConnection};
+
+use super::actor::accept_actor;
+use super::documents::{accept_document, build_tags_id, ServerCidDocument};
+use super::error::AppError;
+use super::outbox::{accept_message, relay_message, relay_message_to_peers, Accepted};
+use super::AppState;
+use crate::db;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActorArchive {
+    pub actor: Option<ActorFields>,
+    /// The messages authored by the actor, oldest first.
+    pub messages: Vec<MessageFields>,
+    pub documents: Vec<ServerCidDocument>,
+    pub following: Vec<String>,
+}
+
+/// The outcome of importing an [`ActorArchive`].
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportReport {
+    pub actor_accepted: bool,
+    pub messages_accepted: u64,
+    /// The number of messages which were already stored.
+    pub messages_known: u64,
+    /// IDs of the messages which failed verification or were rejected.
+    pub messages_rejected: Vec<String>,
+    pub documents_accepted: u64,
+    pub documents_rejected: Vec<String>,
+    /// IDs in the archive's following set which the accepted messages didn't
+    /// add to the actor's following.
+    pub following_missing: Vec<String>,
+}
+
+/// Whether `message` changes the following set of its actor.
+fn is_following_change(message: &MessageFields) -> bool {
+    let following_id = format!("{}/following", message.actor().as_str());
+    match message.type_() {
+        ActivityType::Add | ActivityType::Remove => message
+            .target()
+            .as_ref()
+            .map_or(false, |x| x.iter().any(|x| x.as_str() == following_id)),
+        ActivityType::Delete => message.object().iter().any(|x| x.as_str() == following_id),
+        _ => false,
+    }
+}
+
+/// Whether `message` is addressed to the public.
+fn is_public_message(message: &MessageFields) -> bool {
+    message
+        .to()
+        .as_ref()
+        .map_or(false, |x| x.iter().any(|x| is_public_audience(x.as_str())))
+}
+
+/// Collect the data of the actor with `actor_id` into an archive.
+///
+/// With `public_only`, the archive holds only the messages addressed to the
+/// public and those changing the actor's following set.
+pub async fn export_actor(
+    connection: &mut SqliteConnection,
+    actor_id: &str,
+    public_only: bool,
+) -> Result<ActorArchive, AppError> {
+    let actor = db::get_document(&mut *connection, actor_id)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?
+        .map(|x| serde_json::from_str::<ActorFields>(&x).map_err(|_| AppError::ActorNotValid))
+        .transpose()?;
+
+    let messages = db::get_actor_messages(&mut *connection, actor_id)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?
+        .iter()
+        .map(|x| serde_json::from_str::<MessageFields>(x).map_err(|_| AppError::MessageNotValid))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|x| !public_only || is_public_message(x) || is_following_change(x))
+        .collect::<Vec<_>>();
+
+    let mut documents_id: Vec<&str> = Vec::new();
+    for message in &messages {
+        for document_id in message.object().iter() {
+            let document_id = document_id.as_str();
+            if document_id.starts_with("urn:cid:") && !documents_id.contains(&document_id) {
+                documents_id.push(document_id);
+            }
+        }
+    }
+    let mut documents = Vec::new();
+    for document_id in documents_id {
+        // deleted documents are no longer stored
+        let document = match db::get_document(&mut *connection, document_id)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?
+        {
+            Some(document) => document,
+            None => continue,
+        };
+        if let Ok(document) = serde_json::from_str::<ServerCidDocument>(&document) {
+            documents.push(document);
+        }
+    }
+
+    let following = db::get_actor_followings(&mut *connection, actor_id)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+
+    Ok(ActorArchive {
+        actor,
+        messages,
+        documents,
+        following,
+    })
+}
+
+/// Accept the items of `archive` for the actor with `actor_id`.
+///
+/// Each item is verified and accepted on its own, so that the items which
+/// are rejected, such as messages by other actors, don't prevent importing
+/// the others.
+pub async fn import_actor(
+    state: &AppState,
+    actor_id: &str,
+    archive: &ActorArchive,
+) -> Result<ImportReport, AppError> {
+    if let Some(actor) = &archive.actor {
+        if actor.id().as_str() != actor_id {
+            Err(AppError::ActorIdWrong)?;
+        }
+    }
+
+    // verify before taking the lock, as when the items are posted
+    let actor_valid = match &archive.actor {
+        Some(actor) => actor
+            .verify_cached_with(&state.cache, &*state.resolver)
+            .await
+            .is_ok(),
+        None => false,
+    };
+    let mut messages_valid = Vec::new();
+    for message in &archive.messages {
+        messages_valid.push(
+            message.actor().as_str() == actor_id
+                && message
+                    .verify_cached_with(&state.cache, &*state.resolver)
+                    .await
+                    .is_ok(),
+        );
+    }
+    let mut documents_valid = Vec::new();
+    for document in &archive.documents {
+        let valid = document.id().as_str().starts_with("urn:cid:")
+            && document.verify_cached(&state.cache).await.is_ok();
+        // the tags are built for the valid documents only
+        let tags_id = if valid {
+            build_tags_id(document).await.ok()
+        } else {
+            None
+        };
+        documents_valid.push(tags_id);
+    }
+
+    let mut report = ImportReport::default();
+    let mut connector = state.connector.write().await;
+    let mut connection = connector
+        .connection_mut()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+
+    if let (Some(actor), true) = (&archive.actor, actor_valid) {
+        let mut connection = connection
+            .begin()
+            .await
+            .map_err(|_| AppError::DbConnectionFailed)?;
+        if let Ok(StatusCode::OK) = accept_actor(actor, &mut *connection).await {
+            connection
+                .commit()
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?;
+            report.actor_accepted = true;
+        }
+    }
+
+    // messages go first since documents are accepted only once a message
+    // references them
+    for (message, valid) in archive.messages.iter().zip(messages_valid) {
+        if !valid {
+            report.messages_rejected.push(message.id().to_string());
+            continue;
+        }
+        let mut transaction = connection
+            .begin()
+            .await
+            .map_err(|_| AppError::DbConnectionFailed)?;
+        match accept_message(message, actor_id, &mut *transaction, &state.jwk).await {
+            Ok(Accepted {
+                status: StatusCode::OK,
+                relay,
+            }) => {
+                transaction
+                    .commit()
+                    .await
+                    .map_err(|_| AppError::DbQueryFailed)?;
+                report.messages_accepted += 1;
+                if relay {
+                    relay_message(message, &mut *connection, &state.jwk, &state.relay).await?;
+                }
+                relay_message_to_peers(message, &mut *connection, &state.peers).await?;
+            }
+            Ok(_) => report.messages_known += 1,
+            Err(_) => report.messages_rejected.push(message.id().to_string()),
+        }
+    }
+
+    for (document, tags_id) in archive.documents.iter().zip(documents_valid) {
+        let mut connection = connection
+            .begin()
+            .await
+            .map_err(|_| AppError::DbConnectionFailed)?;
+        let accepted = match tags_id {
+            Some(tags_id) => matches!(
+                accept_document(document, &tags_id, &mut *connection).await,
+                Ok(StatusCode::OK)
+            ),
+            None => false,
+        };
+        if accepted {
+            connection
+                .commit()
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?;
+            report.documents_accepted += 1;
+        } else {
+            report.documents_rejected.push(document.id().to_string());
+        }
+    }
+
+    let following = db::get_actor_followings(&mut *connection, actor_id)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    report.following_missing = archive
+        .following
+        .iter()
+        .filter(|x| !following.contains(x))
+        .cloned()
+        .collect();
+
+    Ok(report)
+}
+
+/// Get the public archive of the actor with `did`.
+pub async fn handle_actor_archive_get(
+    State(AppState { connector, .. }): State<AppState>,
+    Path(did): Path<String>,
+) -> Result<Json<ActorArchive>, AppError> {
+    let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
+    let connector = connector.read().await;
+    let mut connection = connector
+        .connection()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    Ok(Json(export_actor(&mut connection, &actor_id, true).await?))
+}
+
+/// Import the archive of the actor with `did`.
+pub async fn handle_actor_archive_post(
+    State(state): State<AppState>,
+    Path(did): Path<String>,
+    Json(archive): Json<ActorArchive>,
+) -> Result<Json<ImportReport>, AppError> {
+    let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
+    Ok(Json(import_actor(&state, &actor_id, &archive).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use chatternet::didkey::{build_jwk, did_from_jwk};
+    use chatternet::model::{ActivityType, ActorType, NoteMd1kFields, PUBLIC_AUDIENCE_ID};
+    use tower::ServiceExt;
+
+    use super::super::test_utils::*;
+    use super::super::{build_api, AppState};
+    use super::*;
+    use crate::relay::RelayPolicy;
+
+    async fn post(state: &AppState, path: &str, value: &(impl Serialize + ?Sized)) -> StatusCode {
+        build_api(state.clone(), "api", "did:example:server")
+            .oneshot(request_json("POST", &format!("/api/{}", path), value))
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn exports_and_imports_actor() {
+        let state_1 = build_test_state(
+            build_jwk(&mut rand::thread_rng()).unwrap(),
+            RelayPolicy::default(),
+        )
+        .await;
+        let state_2 = build_test_state(
+            build_jwk(&mut rand::thread_rng()).unwrap(),
+            RelayPolicy::default(),
+        )
+        .await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let actor_id = actor_id_from_did(&did).unwrap();
+        let jwk_other = build_jwk(&mut rand::thread_rng()).unwrap();
+        let actor_id_other = actor_id_from_did(&did_from_jwk(&jwk_other).unwrap()).unwrap();
+
+        let actor = ActorFields::new(&jwk, ActorType::Person, Some("abc".to_string()), None)
+            .await
+            .unwrap();
+        let path_actor = format!("{}/actor", did);
+        assert_eq!(post(&state_1, &path_actor, &actor).await, StatusCode::OK);
+
+        let note = NoteMd1kFields::new(
+            "abc".to_string(),
+            actor_id.as_str().try_into().unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        let message = build_message(
+            &jwk,
+            note.id().as_str(),
+            Some(vec![PUBLIC_AUDIENCE_ID.to_string()]),
+        )
+        .await;
+        let path_outbox = format!("{}/actor/outbox", did);
+        assert_eq!(post(&state_1, &path_outbox, &message).await, StatusCode::OK);
+        assert_eq!(
+            post(&state_1, note.id().as_str(), &note).await,
+            StatusCode::OK
+        );
+        // messages not addressed to the public aren't served
+        let message_private = build_message(&jwk, "id:2", Some(vec![actor_id_other.clone()])).await;
+        assert_eq!(
+            post(&state_1, &path_outbox, &message_private).await,
+            StatusCode::OK
+        );
+        // but changes to the following set are, though not public
+        let follow = build_follow(vec![actor_id_other.clone()], &jwk).await;
+        assert_eq!(post(&state_1, &path_outbox, &follow).await, StatusCode::OK);
+
+        // another actor's messages aren't exported
+        let message_other = build_message(&jwk_other, "id:1", None).await;
+        let path_outbox_other = format!("{}/actor/outbox", did_from_jwk(&jwk_other).unwrap());
+        assert_eq!(
+            post(&state_1, &path_outbox_other, &message_other).await,
+            StatusCode::OK
+        );
+
+        let response = build_api(state_1.clone(), "api", "did:example:server")
+            .oneshot(request_empty("GET", &format!("/api/{}/actor/archive", did)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let archive: ActorArchive = get_body(response).await;
+        assert_eq!(archive.actor.as_ref().unwrap().id(), actor.id());
+        assert_eq!(
+            archive
+                .messages
+                .iter()
+                .map(|x| x.id().as_str())
+                .collect::<Vec<_>>(),
+            [message.id().as_str(), follow.id().as_str()]
+        );
+        assert_eq!(archive.documents.len(), 1);
+        assert_eq!(archive.documents[0].id(), note.id());
+        assert_eq!(archive.following, [actor_id_other.clone()]);
+
+        let response = build_api(state_2.clone(), "api", "did:example:server")
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/archive", did),
+                &archive,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let report: ImportReport = get_body(response).await;
+        assert_eq!(
+            report,
+            ImportReport {
+                actor_accepted: true,
+                messages_accepted: 2,
+                documents_accepted: 1,
+                ..ImportReport::default()
+            }
+        );
+
+        // the second server has the same public data and following set
+        let mut connection = state_2.connector.read().await.connection().await.unwrap();
+        let archive_back = export_actor(&mut connection, &actor_id, false)
+            .await
+            .unwrap();
+        drop(connection);
+        assert_eq!(
+            serde_json::to_value(&archive_back.messages).unwrap(),
+            serde_json::to_value(&archive.messages).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(&archive_back.documents).unwrap(),
+            serde_json::to_value(&archive.documents).unwrap()
+        );
+        assert_eq!(archive_back.following, [actor_id_other]);
+
+        // importing again reports the stored messages apart from the rejected
+        let report = import_actor(&state_2, &actor_id, &archive).await.unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                actor_accepted: true,
+                messages_known: 2,
+                documents_accepted: 1,
+                ..ImportReport::default()
+            }
+        );
+
+        // the server operator exports all of the actor's messages
+        let mut connection = state_1.connector.read().await.connection().await.unwrap();
+        let archive_full = export_actor(&mut connection, &actor_id, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            archive_full
+                .messages
+                .iter()
+                .map(|x| x.id().as_str())
+                .collect::<Vec<_>>(),
+            [
+                message.id().as_str(),
+                message_private.id().as_str(),
+                follow.id().as_str()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn import_rejects_invalid_items() {
+        let state = build_test_state(
+            build_jwk(&mut rand::thread_rng()).unwrap(),
+            RelayPolicy::default(),
+        )
+        .await;
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let actor_id = actor_id_from_did(&did_from_jwk(&jwk).unwrap()).unwrap();
+        let jwk_other = build_jwk(&mut rand::thread_rng()).unwrap();
+
+        let message = build_message(&jwk, "id:1", None).await;
+        let message_other = build_message(&jwk_other, "id:2", None).await;
+        let mut message_modified = serde_json::to_value(
+            build_message_with_type(&jwk, ActivityType::Create, "id:3", None).await,
+        )
+        .unwrap();
+        message_modified["object"] = serde_json::json!(["id:4"]);
+        let message_modified: MessageFields = serde_json::from_value(message_modified).unwrap();
+        // a note whose message isn't in the archive
+        let note = NoteMd1kFields::new(
+            "abc".to_string(),
+            actor_id.as_str().try_into().unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let archive = ActorArchive {
+            actor: None,
+            messages: vec![
+                message.clone(),
+                message_other.clone(),
+                message_modified.clone(),
+            ],
+            documents: vec![ServerCidDocument::NoteMd1k(note.clone())],
+            following: vec!["did:example:a/actor".to_string()],
+        };
+        let report = import_actor(&state, &actor_id, &archive).await.unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                actor_accepted: false,
+                messages_accepted: 1,
+                messages_rejected: vec![
+                    message_other.id().to_string(),
+                    message_modified.id().to_string()
+                ],
+                documents_accepted: 0,
+                documents_rejected: vec![note.id().to_string()],
+                following_missing: vec!["did:example:a/actor".to_string()],
+            }
+        );
+
+        // an actor document must be that of the archive's actor
+        let actor = ActorFields::new(&jwk_other, ActorType::Person, None, None)
+            .await
+            .unwrap();
+        let archive = ActorArchive {
+            actor: Some(actor),
+            messages: vec![],
+            documents: vec![],
+            following: vec![],
+        };
+        import_actor(&state, &actor_id, &archive).await.unwrap_err();
+    }
+}
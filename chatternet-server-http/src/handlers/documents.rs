@@ -6,16 +6,19 @@ use anyhow::Result;
 use async_trait::async_trait;
 use axum::extract::{Json, Path, State};
 use axum::http::StatusCode;
-use chatternet::didkey::is_valid_did;
-use did_method_key::DIDKey;
+use chatternet::didkey::{find_actor_mentions, is_valid_did};
 use serde_json::Value;
+use sqlx::SqliteConnection;
 use ssi::did_resolve::{DIDResolver, ResolutionInputMetadata};
 use tap::Pipe;
 
 use super::error::AppError;
 use super::AppState;
-use crate::db::{self};
-use chatternet::model::{Document, NoteMd1kFields, Tag30Fields, Uri};
+use crate::db::{self, NotificationKind};
+use chatternet::model::{
+    find_hashtags, Document, EncryptedNoteFields, NoteMd1k, NoteMd1kFields, Tag30Fields, Uri,
+    PUBLIC_AUDIENCE_ID,
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -27,6 +30,7 @@ use serde::{Deserialize, Serialize};
 #[serde(untagged)]
 pub enum ServerCidDocument {
     Tag30(Tag30Fields),
+    EncryptedNote(EncryptedNoteFields),
     NoteMd1k(NoteMd1kFields),
 }
 
@@ -35,12 +39,14 @@ impl Document for ServerCidDocument {
     fn id(&self) -> &Uri {
         match self {
             ServerCidDocument::Tag30(x) => x.id(),
+            ServerCidDocument::EncryptedNote(x) => x.id(),
             ServerCidDocument::NoteMd1k(x) => x.id(),
         }
     }
     async fn verify(&self) -> Result<()> {
         match self {
             ServerCidDocument::Tag30(x) => x.verify().await,
+            ServerCidDocument::EncryptedNote(x) => x.verify().await,
             ServerCidDocument::NoteMd1k(x) => x.verify().await,
         }
     }
@@ -51,12 +57,16 @@ impl Document for ServerCidDocument {
 /// Generates a DID document if a the ID is a DID, otherwise will lookup
 /// the ID in the document table.
 pub async fn handle_document_get(
-    State(AppState { connector, .. }): State<AppState>,
+    State(AppState {
+        connector,
+        resolver,
+        ..
+    }): State<AppState>,
     Path(id): Path<String>,
 ) -> Result<Json<Value>, AppError> {
     // if this is DID, generate its corresponding DID document
     if is_valid_did(id.as_str()) {
-        let (_, document, _) = DIDKey
+        let (_, document, _) = resolver
             .resolve(&id, &ResolutionInputMetadata::default())
             .await;
         let document = document.ok_or(AppError::DidNotValid)?;
@@ -84,15 +94,12 @@ pub async fn handle_document_get(
 
 /// Handle a post request for a message `document` with ID `id`.
 pub async fn handle_document_post(
-    State(AppState { connector, .. }): State<AppState>,
+    State(AppState {
+        connector, cache, ..
+    }): State<AppState>,
     Path(id): Path<String>,
     Json(document): Json<ServerCidDocument>,
 ) -> Result<StatusCode, AppError> {
-    let mut connector = connector.write().await;
-    let mut connection = connector
-        .connection_mut()
-        .await
-        .map_err(|_| AppError::DbConnectionFailed)?;
     // this handler handles only CID documents only
     if !id.starts_with("urn:cid:") {
         Err(AppError::DocumentIdWrong)?;
@@ -100,25 +107,150 @@ pub async fn handle_document_post(
     if document.id().as_str() != id {
         Err(AppError::DocumentIdWrong)?;
     }
+    // verify before taking the lock so concurrent posts verify in parallel
+    if !document.verify_cached(&cache).await.is_ok() {
+        Err(AppError::DocumentNotValid)?;
+    }
+    let tags_id = build_tags_id(&document).await?;
+    let mut connector = connector.write().await;
+    let mut connection = connector
+        .connection_mut()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    accept_document(&document, &tags_id, &mut *connection).await
+}
+
+/// Build the IDs of the tags for the hashtags in `document`.
+///
+/// Computing the tag CIDs takes time, so it is done before taking the lock
+/// to accept the document.
+pub async fn build_tags_id(document: &ServerCidDocument) -> Result<Vec<String>, AppError> {
+    let note = match document {
+        ServerCidDocument::NoteMd1k(note) => note,
+        _ => return Ok(vec![]),
+    };
+    let mut tags_id = Vec::new();
+    for name in find_hashtags(note.content()) {
+        let tag = Tag30Fields::new(name)
+            .await
+            .map_err(|_| AppError::DocumentNotValid)?;
+        tags_id.push(tag.id().as_str().to_string());
+    }
+    Ok(tags_id)
+}
+
+/// Store the verified CID `document`, if a known message references it.
+///
+/// The `tags_id` are those built from the document by [`build_tags_id`].
+pub async fn accept_document(
+    document: &ServerCidDocument,
+    tags_id: &[String],
+    connection: &mut SqliteConnection,
+) -> Result<StatusCode, AppError> {
+    let id = document.id().as_str();
     // only accept document if a known (signed) message is associated with it
-    if !db::has_message_with_document(&mut *connection, &id)
+    if !db::has_message_with_document(&mut *connection, id)
         .await
         .map_err(|_| AppError::DbQueryFailed)?
     {
         Err(AppError::DocumentNotKnown)?;
     }
-    if !document.verify().await.is_ok() {
-        Err(AppError::DocumentNotValid)?;
+    if let ServerCidDocument::NoteMd1k(note) = document {
+        index_note(note, tags_id, &mut *connection).await?;
     }
-    let document = serde_json::to_string(&document).map_err(|_| AppError::DocumentNotValid)?;
-    // this handler handles only CID documents whose content cannot change
-    // (since it is encoded in the ID), so there is no need to update
-    db::put_document_if_new(&mut *connection, &id, &document)
+    let document = serde_json::to_string(document).map_err(|_| AppError::DocumentNotValid)?;
+    // only CID documents are handled, whose content cannot change (since it
+    // is encoded in the ID), so there is no need to update
+    db::put_document_if_new(&mut *connection, id, &document)
         .await
         .map_err(|_| AppError::DbQueryFailed)?;
     Ok(StatusCode::OK)
 }
 
+/// Notify the actors mentioned in the `note` and the creators of the note it
+/// replies to, and link the note to the tags `tags_id` of its hashtags.
+///
+/// The notifications and links are tied to the message with which the note's
+/// author created it, so nothing is indexed if there is no such message. The
+/// tag collections are public, so the note is linked to a tag only if it was
+/// created publicly or for the tag's followers.
+async fn index_note(
+    note: &NoteMd1kFields,
+    tags_id: &[String],
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let note_id = note.id().as_str();
+    let author_id = note.attributed_to().as_str();
+    let messages_id = db::get_document_messages(&mut *connection, note_id, Some(author_id))
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    let message_id = match messages_id.last() {
+        Some(message_id) => message_id.as_str(),
+        None => return Ok(()),
+    };
+
+    for actor_id in find_actor_mentions(note.content()) {
+        db::put_notification(
+            &mut *connection,
+            &actor_id,
+            NotificationKind::Mention,
+            author_id,
+            note_id,
+            message_id,
+        )
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    }
+
+    if let Some(in_reply_to) = note.in_reply_to() {
+        let creators_id = db::get_document_creators(&mut *connection, in_reply_to.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        for creator_id in creators_id {
+            db::put_notification(
+                &mut *connection,
+                &creator_id,
+                NotificationKind::Reply,
+                author_id,
+                note_id,
+                message_id,
+            )
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        }
+    }
+
+    if tags_id.is_empty() {
+        return Ok(());
+    }
+    let mut messages_audiences = Vec::new();
+    for message_id in &messages_id {
+        let audiences_id = db::get_message_audiences(&mut *connection, message_id)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        messages_audiences.push((message_id, audiences_id));
+    }
+    for tag_id in tags_id {
+        let tag_followers_id = format!("{}/followers", tag_id);
+        let message_id = messages_audiences
+            .iter()
+            .rev()
+            .find(|(_, audiences_id)| {
+                audiences_id
+                    .iter()
+                    .any(|x| x == PUBLIC_AUDIENCE_ID || *x == tag_followers_id)
+            })
+            .map(|(message_id, _)| message_id.as_str());
+        if let Some(message_id) = message_id {
+            db::put_note_tag(&mut *connection, note_id, tag_id, message_id)
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Handle a get request for a the create message for document with ID `id`.
 ///
 /// Returns the last create message by the actor with `did`.
@@ -158,8 +290,11 @@ mod test {
     use tokio;
     use tower::ServiceExt;
 
-    use chatternet::didkey::{build_jwk, did_from_jwk};
-    use chatternet::model::{Document, Message, MessageFields, NoteMd1kFields, Tag30Fields};
+    use chatternet::didkey::{actor_id_from_did, build_jwk, did_from_jwk};
+    use chatternet::model::{
+        Document, EncryptedNote, EncryptedNoteFields, Message, MessageFields, NoteMd1kFields,
+        Tag30Fields,
+    };
 
     use super::super::test_utils::*;
 
@@ -279,6 +414,53 @@ mod test {
         assert_eq!(tag_back.id(), tag.id());
     }
 
+    #[tokio::test]
+    async fn document_stores_encrypted_note() {
+        let api = build_test_api().await;
+
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let actor_id_2 = actor_id_from_did(&did_from_jwk(&jwk_2).unwrap()).unwrap();
+
+        let note = EncryptedNoteFields::new(
+            "abc".to_string(),
+            actor_id_from_did(&did_1).unwrap().try_into().unwrap(),
+            actor_id_2.clone().try_into().unwrap(),
+        )
+        .await
+        .unwrap();
+        let note_id = note.id().as_str();
+        let message = build_message(&jwk_1, note_id, Some(vec![actor_id_2])).await;
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_1),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = api
+            .clone()
+            .oneshot(request_json("POST", &format!("/api/{}", note_id), &note))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .clone()
+            .oneshot(request_empty("GET", &format!("/api/{}", note_id)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let note_back: EncryptedNoteFields = get_body(response).await;
+        note_back.verify().await.unwrap();
+        assert_eq!(note_back.content(), note.content());
+        assert_eq!(note_back.decrypt(&jwk_2).unwrap().as_str(), "abc");
+    }
+
     #[tokio::test]
     async fn document_gets_message_from_creator() {
         let api = build_test_api().await;
@@ -1,8 +1,8 @@
 use anyhow::{Error as AnyError, Result};
 use axum::extract::{Json, Path, Query, State};
 use chatternet::{
-    didkey::actor_id_from_did,
-    model::{new_inbox, CollectionPageFields, MessageFields},
+    didkey::{actor_id_from_did, did_from_jwk},
+    model::{new_messages_page, CollectionPageFields, MessageFields},
 };
 use tap::Pipe;
 
@@ -14,6 +14,20 @@ fn build_inbox(
     actor_id: &str,
     start_idx: Option<u64>,
     page_size: u64,
+) -> Result<CollectionPageFields<MessageFields>, AppError> {
+    build_messages_page(
+        collection,
+        &format!("{}/inbox", actor_id),
+        start_idx,
+        page_size,
+    )
+}
+
+fn build_messages_page(
+    collection: Option<CollectionPageOut>,
+    collection_id: &str,
+    start_idx: Option<u64>,
+    page_size: u64,
 ) -> Result<CollectionPageFields<MessageFields>, AppError> {
     match collection {
         Some(CollectionPageOut {
@@ -32,12 +46,17 @@ fn build_inbox(
             } else {
                 None
             };
-            new_inbox(actor_id, messages, page_size, start_idx, next_start_idx)
-                .map_err(|_| AppError::ActorIdWrong)?
-        }
-        None => {
-            new_inbox(actor_id, vec![], page_size, 0, None).map_err(|_| AppError::ActorIdWrong)?
+            new_messages_page(
+                collection_id,
+                messages,
+                page_size,
+                start_idx,
+                next_start_idx,
+            )
+            .map_err(|_| AppError::ActorIdWrong)?
         }
+        None => new_messages_page(collection_id, vec![], page_size, 0, None)
+            .map_err(|_| AppError::ActorIdWrong)?,
     }
     .pipe(Ok)
 }
@@ -54,9 +73,13 @@ pub async fn handle_inbox(
         .connection()
         .await
         .map_err(|_| AppError::DbConnectionFailed)?;
-    let inbox_out = db::get_inbox_for_actor(&mut connection, &actor_id, page_size, query.start_idx)
-        .await
-        .map_err(|_| AppError::DbQueryFailed)?;
+    let inbox_out = if connector.materialized_inbox() {
+        db::get_materialized_inbox_for_actor(&mut connection, &actor_id, page_size, query.start_idx)
+            .await
+    } else {
+        db::get_inbox_for_actor(&mut connection, &actor_id, page_size, query.start_idx).await
+    }
+    .map_err(|_| AppError::DbQueryFailed)?;
     let inbox = build_inbox(inbox_out, &actor_id, query.start_idx, page_size)?;
     Ok(Json(inbox))
 }
@@ -74,17 +97,61 @@ pub async fn handle_inbox_from(
         .connection()
         .await
         .map_err(|_| AppError::DbConnectionFailed)?;
-    let inbox_out = db::get_inbox_from_actor(
+    let inbox_out = if connector.materialized_inbox() {
+        db::get_materialized_inbox_from_actor(
+            &mut connection,
+            &actor_id,
+            from_actor_id.as_str(),
+            page_size,
+            query.start_idx,
+        )
+        .await
+    } else {
+        db::get_inbox_from_actor(
+            &mut connection,
+            &actor_id,
+            from_actor_id.as_str(),
+            page_size,
+            query.start_idx,
+        )
+        .await
+    }
+    .map_err(|_| AppError::DbQueryFailed)?;
+    let inbox = build_inbox(inbox_out, &actor_id, query.start_idx, page_size)?;
+    Ok(Json(inbox))
+}
+
+/// Handle a get request for the messages addressed directly between the
+/// actors with `did` and `did_other`, in both directions.
+pub async fn handle_conversation(
+    State(AppState { connector, .. }): State<AppState>,
+    Path((did, did_other)): Path<(String, String)>,
+    Query(query): Query<CollectionPageQuery>,
+) -> Result<Json<CollectionPageFields<MessageFields>>, AppError> {
+    let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
+    let other_actor_id = actor_id_from_did(&did_other).map_err(|_| AppError::DidNotValid)?;
+    let page_size = query.page_size.unwrap_or(32);
+    let connector = connector.read().await;
+    let mut connection = connector
+        .connection()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    let conversation_out = db::get_conversation(
         &mut connection,
         &actor_id,
-        from_actor_id.as_str(),
+        &other_actor_id,
         page_size,
         query.start_idx,
     )
     .await
     .map_err(|_| AppError::DbQueryFailed)?;
-    let inbox = build_inbox(inbox_out, &actor_id, query.start_idx, page_size)?;
-    Ok(Json(inbox))
+    let conversation = build_messages_page(
+        conversation_out,
+        &format!("{}/conversation/{}", actor_id, other_actor_id),
+        query.start_idx,
+        page_size,
+    )?;
+    Ok(Json(conversation))
 }
 
 pub async fn handle_inbox_with(
@@ -101,24 +168,146 @@ pub async fn handle_inbox_with(
         .connection()
         .await
         .map_err(|_| AppError::DbConnectionFailed)?;
-    let inbox_out = db::get_inbox_with_audiences(
+    let inbox_out = if connector.materialized_inbox() {
+        db::get_materialized_inbox_with_audiences(
+            &mut connection,
+            &actor_id,
+            &audiences,
+            page_size,
+            query.start_idx,
+        )
+        .await
+    } else {
+        db::get_inbox_with_audiences(
+            &mut connection,
+            &actor_id,
+            &audiences,
+            page_size,
+            query.start_idx,
+        )
+        .await
+    }
+    .map_err(|_| AppError::DbQueryFailed)?;
+    let inbox = build_inbox(inbox_out, &actor_id, query.start_idx, page_size)?;
+    Ok(Json(inbox))
+}
+
+/// Handle a get request for the messages notifying the actor with `did` of
+/// mentions, replies, new followers and likes.
+pub async fn handle_notifications(
+    State(AppState { connector, .. }): State<AppState>,
+    Path(did): Path<String>,
+    Query(query): Query<CollectionPageQuery>,
+) -> Result<Json<CollectionPageFields<MessageFields>>, AppError> {
+    let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
+    let page_size = query.page_size.unwrap_or(32);
+    let connector = connector.read().await;
+    let mut connection = connector
+        .connection()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    let notifications_out =
+        db::get_notifications(&mut connection, &actor_id, page_size, query.start_idx)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+    let notifications = build_messages_page(
+        notifications_out,
+        &format!("{}/notifications", actor_id),
+        query.start_idx,
+        page_size,
+    )?;
+    Ok(Json(notifications))
+}
+
+/// Handle a get request for the messages the actor with `did` sent after it
+/// moved, which are stored flagged and kept out of all other collections.
+pub async fn handle_flagged(
+    State(AppState { connector, .. }): State<AppState>,
+    Path(did): Path<String>,
+    Query(query): Query<CollectionPageQuery>,
+) -> Result<Json<CollectionPageFields<MessageFields>>, AppError> {
+    let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
+    let page_size = query.page_size.unwrap_or(32);
+    let connector = connector.read().await;
+    let mut connection = connector
+        .connection()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    let flagged_out =
+        db::get_moved_messages(&mut connection, &actor_id, page_size, query.start_idx)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+    let flagged = build_messages_page(
+        flagged_out,
+        &format!("{}/flagged", actor_id),
+        query.start_idx,
+        page_size,
+    )?;
+    Ok(Json(flagged))
+}
+
+async fn get_public_timeline(
+    AppState { connector, jwk, .. }: AppState,
+    local: bool,
+    query: CollectionPageQuery,
+) -> Result<CollectionPageFields<MessageFields>, AppError> {
+    let server_did = did_from_jwk(&jwk).map_err(|_| AppError::ServerMisconfigured)?;
+    let server_actor_id =
+        actor_id_from_did(&server_did).map_err(|_| AppError::ServerMisconfigured)?;
+    let page_size = query.page_size.unwrap_or(32);
+    let connector = connector.read().await;
+    let mut connection = connector
+        .connection()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    let messages_out = db::get_public_messages(
         &mut connection,
-        &actor_id,
-        &audiences,
+        if local {
+            Some(server_actor_id.as_str())
+        } else {
+            None
+        },
         page_size,
         query.start_idx,
     )
     .await
     .map_err(|_| AppError::DbQueryFailed)?;
-    let inbox = build_inbox(inbox_out, &actor_id, query.start_idx, page_size)?;
-    Ok(Json(inbox))
+    build_messages_page(
+        messages_out,
+        &format!(
+            "{}/{}",
+            server_actor_id,
+            if local { "local" } else { "public" }
+        ),
+        query.start_idx,
+        page_size,
+    )
+}
+
+/// Handle a get request for the public messages known to the server.
+pub async fn handle_public(
+    State(state): State<AppState>,
+    Query(query): Query<CollectionPageQuery>,
+) -> Result<Json<CollectionPageFields<MessageFields>>, AppError> {
+    Ok(Json(get_public_timeline(state, false, query).await?))
+}
+
+/// Handle a get request for the public messages by the server actor and the
+/// actors it follows.
+pub async fn handle_local(
+    State(state): State<AppState>,
+    Query(query): Query<CollectionPageQuery>,
+) -> Result<Json<CollectionPageFields<MessageFields>>, AppError> {
+    Ok(Json(get_public_timeline(state, true, query).await?))
 }
 
 #[cfg(test)]
 mod test {
     use axum::http::StatusCode;
+    use axum::Router;
     use chatternet::didkey::{build_jwk, did_from_jwk};
-    use chatternet::model::{CollectionPage, Message};
+    use chatternet::model::{ActivityType, CollectionPage, Document, Message, NoteMd1kFields};
+    use ssi::jwk::JWK;
     use tokio;
     use tower::ServiceExt;
 
@@ -343,6 +532,89 @@ mod test {
         );
     }
 
+    #[tokio::test]
+    async fn api_conversation_returns_messages_both_ways() {
+        let api = build_test_api().await;
+
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
+
+        for (jwk, did, object_id, audience_id) in [
+            (&jwk_1, &did_1, "id:1", format!("{}/actor", did_2)),
+            (&jwk_2, &did_2, "id:2", format!("{}/actor/followers", did_2)),
+            (&jwk_2, &did_2, "id:3", format!("{}/actor", did_1)),
+        ] {
+            let message = build_message(jwk, object_id, Some(vec![audience_id])).await;
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did),
+                    &message,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!(
+                    "/api/{}/actor/conversation/{}/actor?pageSize=1",
+                    did_1, did_2
+                ),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let conversation: CollectionPageFields<MessageFields> = get_body(response).await;
+        assert_eq!(
+            conversation
+                .items()
+                .iter()
+                .map(|x| x.object().iter().map(|x| x.as_str()))
+                .flatten()
+                .collect::<Vec<&str>>(),
+            ["id:3"]
+        );
+        // the cursor is shared by both directions
+        let next = conversation.next().as_ref().unwrap().as_str();
+        assert_eq!(
+            next,
+            format!(
+                "{}/actor/conversation/{}/actor?startIdx=2&pageSize=1",
+                did_1, did_2
+            )
+        );
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!(
+                    "/api/{}/actor/conversation/{}/actor?startIdx=2&pageSize=1",
+                    did_1, did_2
+                ),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let conversation: CollectionPageFields<MessageFields> = get_body(response).await;
+        assert_eq!(
+            conversation
+                .items()
+                .iter()
+                .map(|x| x.object().iter().map(|x| x.as_str()))
+                .flatten()
+                .collect::<Vec<&str>>(),
+            ["id:1"]
+        );
+    }
+
     #[tokio::test]
     async fn api_inbox_returns_messages_with_audiences() {
         let api = build_test_api().await;
@@ -399,4 +671,207 @@ mod test {
             ["id:1"]
         );
     }
+
+    async fn post_note(
+        api: Router,
+        jwk: &JWK,
+        content: String,
+        in_reply_to: Option<&str>,
+        to: Vec<String>,
+    ) -> (String, MessageFields) {
+        let did = did_from_jwk(jwk).unwrap();
+        let note = NoteMd1kFields::new(
+            content,
+            format!("{}/actor", did).try_into().unwrap(),
+            in_reply_to.map(|x| x.try_into().unwrap()),
+        )
+        .await
+        .unwrap();
+        let note_id = note.id().as_str().to_string();
+        let message = build_message(jwk, &note_id, Some(to)).await;
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = api
+            .oneshot(request_json("POST", &format!("/api/{}", note_id), &note))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        (note_id, message)
+    }
+
+    #[tokio::test]
+    async fn api_notifications_returns_visible_messages() {
+        let api = build_test_api().await;
+
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
+
+        // did_2 follows did_1
+        let follow = build_follow(vec![format!("{}/actor", did_1)], &jwk_2).await;
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_2),
+                &follow,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let (note_id, _) = post_note(
+            api.clone(),
+            &jwk_1,
+            "abc".to_string(),
+            None,
+            vec![format!("{}/actor/followers", did_1)],
+        )
+        .await;
+
+        // did_2 replies to did_1 and mentions it, addressed to did_1
+        let (_, reply) = post_note(
+            api.clone(),
+            &jwk_2,
+            format!("hi {}/actor", did_1),
+            Some(&note_id),
+            vec![format!("{}/actor", did_1)],
+        )
+        .await;
+
+        // did_2 mentions did_1 in a message did_1 can't see
+        post_note(
+            api.clone(),
+            &jwk_2,
+            format!("about {}/actor", did_1),
+            None,
+            vec![format!("{}/actor/followers", did_2)],
+        )
+        .await;
+
+        // did_2 likes the did_1 note
+        let like = build_message_with_type(&jwk_2, ActivityType::Like, &note_id, None).await;
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_2),
+                &like,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/notifications?pageSize=4", did_1),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let notifications: CollectionPageFields<MessageFields> = get_body(response).await;
+        assert_eq!(
+            notifications
+                .items()
+                .iter()
+                .map(|x| x.id().as_str())
+                .collect::<Vec<&str>>(),
+            [
+                like.id().as_str(),
+                reply.id().as_str(),
+                follow.id().as_str()
+            ]
+        );
+
+        // did_2 has no notifications
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/notifications", did_2),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let notifications: CollectionPageFields<MessageFields> = get_body(response).await;
+        assert!(notifications.items().is_empty());
+    }
+
+    #[tokio::test]
+    async fn api_public_and_local_return_public_messages() {
+        let jwk_server = build_jwk(&mut rand::thread_rng()).unwrap();
+        let api = build_test_api_jwk(jwk_server.clone()).await;
+        let did_server = did_from_jwk(&jwk_server).unwrap();
+
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
+
+        // server follows did_1
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_server),
+                &build_follow(vec![format!("{}/actor", did_1)], &jwk_server).await,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for (jwk, did, object_id, audience_id) in [
+            (&jwk_1, &did_1, "id:1", "as:Public"),
+            (&jwk_1, &did_1, "id:2", "id:x"),
+            (
+                &jwk_2,
+                &did_2,
+                "id:3",
+                "https://www.w3.org/ns/activitystreams#Public",
+            ),
+        ] {
+            let message = build_message(jwk, object_id, Some(vec![audience_id.to_string()])).await;
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did),
+                    &message,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let get_objects = |path: &'static str| {
+            let api = api.clone();
+            async move {
+                let response = api.oneshot(request_empty("GET", path)).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let timeline: CollectionPageFields<MessageFields> = get_body(response).await;
+                timeline
+                    .items()
+                    .iter()
+                    .map(|x| x.object().iter().map(|x| x.to_string()))
+                    .flatten()
+                    .collect::<Vec<String>>()
+            }
+        };
+
+        // the server relays the did_1 public message with a view, which is
+        // public too since it has the same audiences
+        assert_eq!(get_objects("/api/public").await, ["id:3", "id:1", "id:1"]);
+        assert_eq!(get_objects("/api/local").await, ["id:1", "id:1"]);
+    }
 }
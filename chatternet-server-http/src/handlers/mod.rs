@@ -1,26 +1,42 @@
 use axum::http::{header, Method};
 use axum::routing::{get, post};
 use axum::Router;
+use chatternet::cache::VerificationCache;
+use chatternet::resolver::Resolver;
 use serde::{Deserialize, Serialize};
 use sqlx::SqliteConnection;
 use ssi::jwk::JWK;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower_http::trace::TraceLayer;
 
 use crate::db::{self, Connector};
+use crate::relay::Relay;
 
 mod actor;
+mod archive;
 mod documents;
 mod error;
 mod inbox;
 mod outbox;
+mod reactions;
+mod stats;
+mod tags;
 
 use actor::*;
+use archive::*;
 use documents::*;
 use inbox::*;
 use outbox::*;
+use reactions::*;
+use stats::*;
+use tags::*;
+
+pub use archive::{export_actor, import_actor, ActorArchive, ImportReport};
+pub use outbox::{delete_message, flush_relay};
+pub use stats::{prune_stats, STATS_PRUNE_PERIOD};
 
 use self::error::AppError;
 
@@ -30,10 +46,40 @@ pub struct ErrorMessage {
     message: String,
 }
 
+/// A server actor with its relay.
+#[derive(Clone, Debug)]
+pub struct RelayPeer {
+    pub jwk: Arc<JWK>,
+    pub relay: Arc<Relay>,
+}
+
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub connector: Arc<RwLock<Connector>>,
     pub jwk: Arc<JWK>,
+    pub relay: Arc<Relay>,
+    pub cache: Arc<VerificationCache>,
+    pub resolver: Arc<Resolver>,
+    /// The other server actors storing their data through the same
+    /// connector, whose relays also view the messages posted to this one.
+    pub peers: Arc<Vec<RelayPeer>>,
+}
+
+impl AppState {
+    /// Build the state of the server actor with `jwk` storing its data
+    /// through `connector`. It doesn't relay, remembers few verifications,
+    /// resolves DIDs over HTTP and has no peers; set the other fields to
+    /// change these.
+    pub fn new(connector: Arc<RwLock<Connector>>, jwk: JWK) -> Self {
+        Self {
+            connector,
+            jwk: Arc::new(jwk),
+            relay: Arc::new(Relay::default()),
+            cache: Arc::new(VerificationCache::new(64)),
+            resolver: Arc::new(Resolver::default()),
+            peers: Arc::new(Vec::new()),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -72,12 +118,31 @@ pub fn build_api(state: AppState, prefix: &str, _did: &str) -> Router {
                 .route("/:id/actor", get(handle_actor_get).post(handle_actor_post))
                 .route("/:id/actor/following", get(handle_actor_following))
                 .route("/:id/actor/followers", get(handle_actor_followers))
+                .route("/:id/actor/blocked", get(handle_actor_blocked))
+                .route("/:id/actor/revoked", get(handle_actor_revoked))
                 .route("/:id/actor/outbox", post(handle_outbox))
+                .route(
+                    "/:id/actor/archive",
+                    get(handle_actor_archive_get).post(handle_actor_archive_post),
+                )
                 .route("/:id/actor/inbox", get(handle_inbox))
                 .route("/:id/actor/inbox/from/:id2/actor", get(handle_inbox_from))
                 .route("/:id/actor/inbox/with", get(handle_inbox_with))
+                .route(
+                    "/:id/actor/conversation/:id2/actor",
+                    get(handle_conversation),
+                )
+                .route("/:id/actor/notifications", get(handle_notifications))
+                .route("/:id/actor/flagged", get(handle_flagged))
+                .route("/:id/actor/stats/tags", get(handle_stats_tags))
+                .route("/:id/actor/stats/actors", get(handle_stats_actors))
+                .route("/public", get(handle_public))
+                .route("/local", get(handle_local))
                 .route("/:id", get(handle_document_get).post(handle_document_post))
-                .route("/:id/createdBy/:id2/actor", get(handle_document_get_create)),
+                .route("/:id/createdBy/:id2/actor", get(handle_document_get_create))
+                .route("/:id/likes", get(handle_document_likes))
+                .route("/:id/shares", get(handle_document_shares))
+                .route("/:id/notes", get(handle_tag_notes)),
         )
         .layer(TraceLayer::new_for_http())
         .layer(
@@ -94,6 +159,17 @@ pub fn build_api(state: AppState, prefix: &str, _did: &str) -> Router {
         .with_state(state)
 }
 
+/// Serve the API of `state` under `prefix` on a free loopback port in the
+/// background, returning the URL of the API.
+pub fn spawn_local_api(state: AppState, prefix: &str) -> String {
+    let app = build_api(state, prefix, "did:example:server");
+    let server =
+        axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(app.into_make_service());
+    let url = format!("http://{}/{}", server.local_addr(), prefix);
+    tokio::spawn(server);
+    url
+}
+
 async fn use_mutable(
     id: &str,
     timestamp_millis: i64,
@@ -114,7 +190,7 @@ async fn use_mutable(
 }
 
 #[cfg(test)]
-mod test_utils {
+pub(crate) mod test_utils {
     use std::fmt::Debug;
     use std::sync::Arc;
 
@@ -123,6 +199,7 @@ mod test_utils {
     use axum::routing::Router;
     use chatternet::didkey::{build_jwk, did_from_jwk};
     use chatternet::model::{ActivityType, MessageBuilder, MessageFields, Uri};
+    use chatternet::resolver::{Resolver, StaticDidFetcher};
     use hyper;
     use hyper::body::HttpBody;
     use mime;
@@ -133,6 +210,7 @@ mod test_utils {
 
     use super::{build_api, AppState};
     use crate::db::Connector;
+    use crate::relay::{Relay, RelayPolicy};
 
     pub async fn build_message_with_type(
         jwk: &JWK,
@@ -167,11 +245,20 @@ mod test_utils {
     }
 
     pub async fn build_follow(follows_id: Vec<String>, jwk: &JWK) -> MessageFields {
+        build_collection_change(ActivityType::Add, "following", follows_id, jwk).await
+    }
+
+    pub async fn build_collection_change(
+        activity_type: ActivityType,
+        collection: &str,
+        objects_id: Vec<String>,
+        jwk: &JWK,
+    ) -> MessageFields {
         let did = did_from_jwk(jwk).unwrap();
         MessageBuilder::new(
             &jwk,
-            ActivityType::Add,
-            follows_id
+            activity_type,
+            objects_id
                 .into_iter()
                 .map(|x| x.try_into().unwrap())
                 .collect::<Vec<Uri>>()
@@ -179,7 +266,7 @@ mod test_utils {
                 .unwrap(),
         )
         .target(
-            vec![format!("{}/actor/following", did).try_into().unwrap()]
+            vec![format!("{}/actor/{}", did, collection).try_into().unwrap()]
                 .try_into()
                 .unwrap(),
         )
@@ -223,24 +310,31 @@ mod test_utils {
             .unwrap()
     }
 
-    pub async fn build_test_api_jwk(jwk: JWK) -> Router {
+    pub async fn build_test_state(jwk: JWK, policy: RelayPolicy) -> AppState {
         let connector = Arc::new(RwLock::new(
             Connector::new("sqlite::memory:").await.unwrap(),
         ));
-        let state = AppState {
-            connector,
-            jwk: Arc::new(jwk),
-        };
-        build_api(state, "api", "did:example:server")
+        AppState {
+            relay: Arc::new(Relay::new(policy)),
+            resolver: Arc::new(Resolver::new(Arc::new(StaticDidFetcher::new()))),
+            ..AppState::new(connector, jwk)
+        }
+    }
+
+    pub async fn build_test_api_relay(jwk: JWK, policy: RelayPolicy) -> Router {
+        build_api(
+            build_test_state(jwk, policy).await,
+            "api",
+            "did:example:server",
+        )
+    }
+
+    pub async fn build_test_api_jwk(jwk: JWK) -> Router {
+        build_test_api_relay(jwk, RelayPolicy::default()).await
     }
 
     pub async fn build_test_api() -> Router {
-        let connector = Arc::new(RwLock::new(
-            Connector::new("sqlite::memory:").await.unwrap(),
-        ));
-        let jwk = Arc::new(build_jwk(&mut rand::thread_rng()).unwrap());
-        let state = AppState { connector, jwk };
-        build_api(state, "api", "did:example:server")
+        build_test_api_jwk(build_jwk(&mut rand::thread_rng()).unwrap()).await
     }
 }
 
@@ -1,24 +1,46 @@
 use anyhow::Result;
 use axum::extract::{Json, Path, State};
 use axum::http::StatusCode;
-use chatternet::didkey::{actor_id_from_did, did_from_jwk};
+use chatternet::didkey::{actor_id_from_did, did_from_actor_id, did_from_jwk};
 use chatternet::model::{
-    ActivityType, CtxStreamLast, Message, MessageBuilder, MessageFields, Uri, VecUris,
+    is_public_audience, ActivityType, CtxStreamLast, Delegation, Message, MessageBuilder,
+    MessageFields, Uri, VecUris, PUBLIC_AUDIENCE_ID,
 };
+use chrono::Utc;
 use sqlx::{Connection, SqliteConnection};
 use ssi::jwk::JWK;
-use tap::Pipe;
 
 use super::error::AppError;
-use super::{use_mutable, AppState};
-use crate::db::{self};
-
+use super::{use_mutable, AppState, RelayPeer};
+use crate::db::{self, NotificationKind, Reaction, StatsKind};
+use crate::relay::{Relay, RelayBatch};
+
+/// Build the IDs of the audiences of `message`.
+///
+/// The public collection is always given by its full IRI so that it can be
+/// looked up regardless of how the message compacted it. Lists are audiences
+/// only of the messages by their owner, so the lists of other actors are left
+/// out.
 pub fn build_audiences_id(message: &MessageFields) -> Vec<String> {
+    let mut audiences_id: Vec<String> = Vec::new();
     if let Some(to) = message.to() {
-        to.as_ref().iter().map(|x| x.to_string()).collect()
-    } else {
-        vec![]
+        for audience_id in to.iter() {
+            if audience_id.as_str().contains("/lists/")
+                && !is_actor_list(message.actor().as_str(), audience_id.as_str())
+            {
+                continue;
+            }
+            let audience_id = if is_public_audience(audience_id.as_str()) {
+                PUBLIC_AUDIENCE_ID
+            } else {
+                audience_id.as_str()
+            };
+            if !audiences_id.iter().any(|x| x == audience_id) {
+                audiences_id.push(audience_id.to_string());
+            }
+        }
     }
+    audiences_id
 }
 
 async fn handle_follow(
@@ -31,6 +53,19 @@ async fn handle_follow(
         db::put_actor_following(&mut *connection, &actor_id, &object_id)
             .await
             .map_err(|_| AppError::DbQueryFailed)?;
+        // let a followed actor know of its new follower
+        if did_from_actor_id(object_id).is_ok() {
+            db::put_notification(
+                &mut *connection,
+                object_id,
+                NotificationKind::Follow,
+                actor_id,
+                object_id,
+                message.id().as_str(),
+            )
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        }
         // also store the audience form of this follow for quick lookup
         db::put_actor_audience(
             &mut *connection,
@@ -53,6 +88,15 @@ async fn handle_unfollow(
         db::delete_actor_following(&mut *connection, &actor_id, &object_id)
             .await
             .map_err(|_| AppError::DbQueryFailed)?;
+        db::delete_notification(
+            &mut *connection,
+            object_id,
+            NotificationKind::Follow,
+            actor_id,
+            object_id,
+        )
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
         db::delete_actor_audience(
             &mut *connection,
             &actor_id,
@@ -64,38 +108,199 @@ async fn handle_unfollow(
     Ok(())
 }
 
-async fn handle_add(
+async fn handle_block(
     message: &MessageFields,
     connection: &mut SqliteConnection,
 ) -> Result<(), AppError> {
-    let target = match message.target() {
-        Some(target) => target,
-        None => return Err(AppError::MessageNotValid),
-    };
-    if target.len() != 1 {
-        return Err(AppError::MessageNotValid);
+    let actor_id = message.actor().as_str();
+    for object_id in message.object().iter() {
+        db::put_actor_blocking(&mut *connection, &actor_id, object_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
     }
-    let target = match target.first() {
-        Some(target) => target,
-        None => return Err(AppError::MessageNotValid),
-    };
-    if target.as_str() != format!("{}/following", message.actor().as_str()) {
-        return Err(AppError::MessageNotValid);
+    Ok(())
+}
+
+async fn handle_unblock(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let actor_id = message.actor().as_str();
+    for object_id in message.object().iter() {
+        db::delete_actor_blocking(&mut *connection, &actor_id, object_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
     }
-    use_mutable(
-        target.as_str(),
-        message.published().timestamp_millis(),
-        &mut *connection,
-    )
-    .await?;
-    handle_follow(message, connection).await?;
     Ok(())
 }
 
-async fn handle_remove(
+async fn handle_mute(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let actor_id = message.actor().as_str();
+    for object_id in message.object().iter() {
+        db::put_actor_muting(&mut *connection, &actor_id, object_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+    }
+    Ok(())
+}
+
+async fn handle_unmute(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let actor_id = message.actor().as_str();
+    for object_id in message.object().iter() {
+        db::delete_actor_muting(&mut *connection, &actor_id, object_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+    }
+    Ok(())
+}
+
+async fn handle_revoke(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let actor_id = message.actor().as_str();
+    for object_id in message.object().iter() {
+        db::put_actor_revocation(&mut *connection, &actor_id, object_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+    }
+    Ok(())
+}
+
+async fn handle_unrevoke(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let actor_id = message.actor().as_str();
+    for object_id in message.object().iter() {
+        db::delete_actor_revocation(&mut *connection, &actor_id, object_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+    }
+    Ok(())
+}
+
+async fn handle_react(
+    message: &MessageFields,
+    reaction: Reaction,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let actor_id = message.actor().as_str();
+    for object_id in message.object().iter() {
+        db::put_reaction(
+            &mut *connection,
+            &actor_id,
+            object_id.as_str(),
+            reaction,
+            message.id().as_str(),
+        )
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+        if reaction != Reaction::Like {
+            continue;
+        }
+        // let the creators of the object know it was liked
+        let creators_id = db::get_document_creators(&mut *connection, object_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        for creator_id in creators_id {
+            db::put_notification(
+                &mut *connection,
+                &creator_id,
+                NotificationKind::Like,
+                actor_id,
+                object_id.as_str(),
+                message.id().as_str(),
+            )
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle_unreact(
     message: &MessageFields,
+    reaction: Reaction,
     connection: &mut SqliteConnection,
 ) -> Result<(), AppError> {
+    let actor_id = message.actor().as_str();
+    for object_id in message.object().iter() {
+        db::delete_reaction(&mut *connection, &actor_id, object_id.as_str(), reaction)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        if reaction != Reaction::Like {
+            continue;
+        }
+        let creators_id = db::get_document_creators(&mut *connection, object_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        for creator_id in creators_id {
+            db::delete_notification(
+                &mut *connection,
+                &creator_id,
+                NotificationKind::Like,
+                actor_id,
+                object_id.as_str(),
+            )
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        }
+    }
+    Ok(())
+}
+
+/// The collections owned by an actor which it can modify with messages.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ActorCollection {
+    Following,
+    Blocked,
+    Muted,
+    Liked,
+    Shared,
+    Revoked,
+}
+
+impl ActorCollection {
+    /// Identify which of the `actor_id` collections is the `id`, if any.
+    fn from_id(actor_id: &str, id: &str) -> Option<Self> {
+        let path = id.strip_prefix(actor_id)?;
+        [
+            Self::Following,
+            Self::Blocked,
+            Self::Muted,
+            Self::Liked,
+            Self::Shared,
+            Self::Revoked,
+        ]
+        .into_iter()
+        .find(|x| x.path() == path)
+    }
+
+    /// The path of the collection relative to the ID of its actor.
+    fn path(&self) -> &'static str {
+        match self {
+            Self::Following => "/following",
+            Self::Blocked => "/blocked",
+            Self::Muted => "/muted",
+            Self::Liked => "/liked",
+            Self::Shared => "/shared",
+            Self::Revoked => "/revoked",
+        }
+    }
+}
+
+/// Get the one collection targeted by `message`, which must be a collection
+/// owned by the message actor.
+fn get_actor_collection_target(
+    message: &MessageFields,
+) -> Result<(&Uri, ActorCollection), AppError> {
     let target = match message.target() {
         Some(target) => target,
         None => return Err(AppError::MessageNotValid),
@@ -107,105 +312,480 @@ async fn handle_remove(
         Some(target) => target,
         None => return Err(AppError::MessageNotValid),
     };
-    if target.as_str() != format!("{}/following", message.actor().as_str()) {
+    let collection = ActorCollection::from_id(message.actor().as_str(), target.as_str())
+        .ok_or(AppError::MessageNotValid)?;
+    // delegates can't change which delegations are revoked
+    if collection == ActorCollection::Revoked && message.instrument().is_some() {
         return Err(AppError::MessageNotValid);
     }
+    Ok((target, collection))
+}
+
+/// Check if `id` is a list owned by `actor_id`.
+///
+/// Lists have IDs `{actor}/lists/{name}` where the name has only ASCII
+/// letters, digits, `-` and `_`.
+fn is_actor_list(actor_id: &str, id: &str) -> bool {
+    match id
+        .strip_prefix(actor_id)
+        .and_then(|x| x.strip_prefix("/lists/"))
+    {
+        Some(name) => {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '_')
+        }
+        None => false,
+    }
+}
+
+/// Get the one list targeted by `message`, if it targets a list owned by the
+/// message actor.
+fn get_actor_list_target(message: &MessageFields) -> Option<&Uri> {
+    match message.target() {
+        Some(target) if target.len() == 1 => target
+            .first()
+            .filter(|x| is_actor_list(message.actor().as_str(), x.as_str())),
+        _ => None,
+    }
+}
+
+/// Add or remove the objects of `message` as members of the list `list_id`.
+///
+/// Members receive the messages addressed to the list as they would the
+/// messages addressed to them directly.
+async fn handle_list_change(
+    message: &MessageFields,
+    list_id: &str,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
     use_mutable(
-        target.as_str(),
+        list_id,
         message.published().timestamp_millis(),
         &mut *connection,
     )
     .await?;
-    handle_unfollow(message, connection).await?;
+    for object_id in message.object().iter() {
+        match message.type_() {
+            ActivityType::Add => db::put_list_member(&mut *connection, list_id, object_id.as_str())
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?,
+            ActivityType::Remove => {
+                db::delete_list_member(&mut *connection, list_id, object_id.as_str())
+                    .await
+                    .map_err(|_| AppError::DbQueryFailed)?
+            }
+            _ => Err(AppError::MessageNotValid)?,
+        }
+    }
     Ok(())
 }
 
-async fn clear_followings(
+/// Ensure `message` is newer than the last change to the part of
+/// `collection` it modifies.
+///
+/// Reactions are independent of each other, so the liked and shared
+/// collections are modified per object: a reaction to one object can arrive
+/// after a newer reaction to another.
+async fn use_mutable_collection(
     message: &MessageFields,
+    target: &str,
+    collection: ActorCollection,
     connection: &mut SqliteConnection,
 ) -> Result<(), AppError> {
-    db::delete_actor_all_following(&mut *connection, message.actor().as_str())
-        .await
-        .map_err(|_| AppError::DbQueryFailed)?;
-    db::delete_actor_all_audiences(&mut *connection, &message.actor().as_str())
-        .await
-        .map_err(|_| AppError::DbQueryFailed)?;
-    return Ok(());
+    let timestamp_millis = message.published().timestamp_millis();
+    match collection {
+        ActorCollection::Liked | ActorCollection::Shared => {
+            // the whole collection is modified when it is cleared, reject
+            // messages older than that without claiming the collection
+            if db::get_mutable_modified(&mut *connection, target)
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?
+                .map_or(false, |x| x > timestamp_millis)
+            {
+                Err(AppError::StaleMessage)?;
+            }
+            for object_id in message.object().iter() {
+                use_mutable(
+                    &format!("{}/{}", target, object_id.as_str()),
+                    timestamp_millis,
+                    &mut *connection,
+                )
+                .await?;
+            }
+        }
+        _ => use_mutable(target, timestamp_millis, &mut *connection).await?,
+    }
+    Ok(())
 }
 
-async fn delete_message(
+async fn handle_add(
     message: &MessageFields,
     connection: &mut SqliteConnection,
 ) -> Result<(), AppError> {
-    // delete the message document
-    db::delete_document(&mut *connection, message.id().as_str())
+    let (target, collection) = get_actor_collection_target(message)?;
+    use_mutable_collection(message, target.as_str(), collection, &mut *connection).await?;
+    match collection {
+        ActorCollection::Following => handle_follow(message, connection).await?,
+        ActorCollection::Blocked => handle_block(message, connection).await?,
+        ActorCollection::Muted => handle_mute(message, connection).await?,
+        ActorCollection::Liked => handle_react(message, Reaction::Like, connection).await?,
+        ActorCollection::Shared => handle_react(message, Reaction::Share, connection).await?,
+        ActorCollection::Revoked => handle_revoke(message, connection).await?,
+    }
+    Ok(())
+}
+
+async fn handle_remove(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let (target, collection) = get_actor_collection_target(message)?;
+    use_mutable_collection(message, target.as_str(), collection, &mut *connection).await?;
+    match collection {
+        ActorCollection::Following => handle_unfollow(message, connection).await?,
+        ActorCollection::Blocked => handle_unblock(message, connection).await?,
+        ActorCollection::Muted => handle_unmute(message, connection).await?,
+        ActorCollection::Liked => handle_unreact(message, Reaction::Like, connection).await?,
+        ActorCollection::Shared => handle_unreact(message, Reaction::Share, connection).await?,
+        ActorCollection::Revoked => handle_unrevoke(message, connection).await?,
+    }
+    Ok(())
+}
+
+/// Get the one ID in `ids`, which must be an actor ID.
+fn get_single_actor_id(ids: Option<&VecUris>) -> Result<&str, AppError> {
+    match ids.map(|x| x.as_slice()) {
+        Some([id]) if did_from_actor_id(id.as_str()).is_ok() => Ok(id.as_str()),
+        _ => Err(AppError::MessageNotValid),
+    }
+}
+
+/// Handle a `Move` of the actor in `object` to the actor in `target`.
+///
+/// The move must be signed by both the old and the new actor, in any order.
+/// Once it is, the followings and audiences of the old actor, and its
+/// followers, are carried over to the new actor.
+async fn handle_move(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    // delegates can't hand the actor's identity over to another actor
+    if message.instrument().is_some() {
+        Err(AppError::MessageNotValid)?;
+    }
+    let actor_id = message.actor().as_str();
+    let old_id = get_single_actor_id(Some(message.object()))?;
+    let new_id = get_single_actor_id(message.target().as_ref())?;
+    if old_id == new_id || (actor_id != old_id && actor_id != new_id) {
+        Err(AppError::MessageNotValid)?;
+    }
+    db::put_actor_move(&mut *connection, actor_id, old_id, new_id)
         .await
         .map_err(|_| AppError::DbQueryFailed)?;
+    if db::get_actor_moved_to(&mut *connection, old_id)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?
+        .as_deref()
+        != Some(new_id)
+    {
+        return Ok(());
+    }
 
-    // delete the message associations
-    db::delete_message_audiences(&mut *connection, message.id().as_str())
+    let followings_id = db::get_actor_followings(&mut *connection, old_id)
         .await
         .map_err(|_| AppError::DbQueryFailed)?;
-    db::delete_message_documents(&mut *connection, message.id().as_str())
+    for following_id in followings_id {
+        db::put_actor_following(&mut *connection, new_id, &following_id)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+    }
+    let audiences_id = db::get_actor_audiences(&mut *connection, old_id)
         .await
         .map_err(|_| AppError::DbQueryFailed)?;
-    db::delete_message(&mut *connection, message.id().as_str())
+    for audience_id in audiences_id {
+        db::put_actor_audience(&mut *connection, new_id, &audience_id)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+    }
+    let followers_id = db::get_actor_all_followers(&mut *connection, old_id)
         .await
         .map_err(|_| AppError::DbQueryFailed)?;
-
-    // delete the document documents if they are not associated to any other message
-    for document_id in message.object().iter() {
-        if db::has_message_with_document(&mut *connection, document_id.as_str())
-            .await
-            .map_err(|_| AppError::DbQueryFailed)?
-        {
-            continue;
-        }
-        db::delete_document(&mut *connection, document_id.as_str())
+    for follower_id in followers_id {
+        db::put_actor_following(&mut *connection, &follower_id, new_id)
             .await
             .map_err(|_| AppError::DbQueryFailed)?;
+        db::put_actor_audience(
+            &mut *connection,
+            &follower_id,
+            &format!("{}/followers", new_id),
+        )
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
     }
-
     Ok(())
 }
 
-async fn handle_delete(
+/// Handle a `Like` or `Announce` message, which is equivalent to adding its
+/// objects to the actor's liked or shared collection.
+async fn handle_reaction_message(
     message: &MessageFields,
+    reaction: Reaction,
     connection: &mut SqliteConnection,
 ) -> Result<(), AppError> {
-    // can delete only one document at a time
-    let document_id = message.object().first().ok_or(AppError::MessageNotValid)?;
-
-    // object to delete is the followers collection
-    if document_id.as_str() == format!("{}/following", message.actor().as_str()) {
-        use_mutable(
-            document_id.as_str(),
-            message.published().timestamp_millis(),
-            &mut *connection,
-        )
-        .await?;
-        clear_followings(message, connection).await?;
-        return Ok(());
-    }
+    let collection = match reaction {
+        Reaction::Like => ActorCollection::Liked,
+        Reaction::Share => ActorCollection::Shared,
+    };
+    let target = format!("{}{}", message.actor().as_str(), collection.path());
+    use_mutable_collection(message, &target, collection, &mut *connection).await?;
+    handle_react(message, reaction, connection).await?;
+    Ok(())
+}
 
-    let document = match db::get_document(&mut *connection, document_id.as_str())
+async fn clear_reactions(
+    actor_id: &str,
+    reaction: Reaction,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let objects_id = db::get_actor_reactions(&mut *connection, actor_id, reaction)
         .await
-        .map_err(|_| AppError::DbQueryFailed)?
-    {
-        Some(object) => object,
-        None => return Ok(()),
-    };
+        .map_err(|_| AppError::DbQueryFailed)?;
+    for object_id in objects_id {
+        db::delete_reaction(&mut *connection, actor_id, &object_id, reaction)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+    }
+    Ok(())
+}
 
-    if let Ok(message_to_delete) = serde_json::from_str::<MessageFields>(&document) {
-        // only the creator of a message can delete that message
-        if message_to_delete.actor() != message.actor() {
-            Err(AppError::MessageNotValid)?
+async fn clear_collection(
+    message: &MessageFields,
+    collection: ActorCollection,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let actor_id = message.actor().as_str();
+    match collection {
+        ActorCollection::Following => {
+            db::delete_actor_all_following(&mut *connection, actor_id)
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?;
+            db::delete_actor_all_audiences(&mut *connection, actor_id)
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?;
+            db::delete_actor_all_notifications(
+                &mut *connection,
+                actor_id,
+                NotificationKind::Follow,
+            )
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        }
+        ActorCollection::Blocked => {
+            db::delete_actor_all_blocking(&mut *connection, actor_id)
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?;
+        }
+        ActorCollection::Muted => {
+            db::delete_actor_all_muting(&mut *connection, actor_id)
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?;
+        }
+        ActorCollection::Liked => {
+            clear_reactions(actor_id, Reaction::Like, connection).await?;
+            db::delete_actor_all_notifications(&mut *connection, actor_id, NotificationKind::Like)
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?;
+        }
+        ActorCollection::Shared => clear_reactions(actor_id, Reaction::Share, connection).await?,
+        ActorCollection::Revoked => {
+            if message.instrument().is_some() {
+                Err(AppError::MessageNotValid)?;
+            }
+            db::delete_actor_all_revocations(&mut *connection, actor_id)
+                .await
+                .map_err(|_| AppError::DbQueryFailed)?;
         }
-        delete_message(&message_to_delete, connection).await?;
-        return Ok(());
     }
+    return Ok(());
+}
 
-    if let Ok(document_to_delete) = serde_json::from_str::<serde_json::Value>(&document) {
+/// Add `delta` to the statistics counts of the author and tag audiences of
+/// `message` in the time bucket of `timestamp_millis`.
+async fn add_message_stats(
+    message: &MessageFields,
+    timestamp_millis: i64,
+    delta: i64,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    db::add_stats_count(
+        &mut *connection,
+        StatsKind::Author,
+        message.actor().as_str(),
+        timestamp_millis,
+        delta,
+    )
+    .await
+    .map_err(|_| AppError::DbQueryFailed)?;
+    for audience_id in build_audiences_id(message) {
+        let tag_id = match audience_id.strip_suffix("/followers") {
+            Some(tag_id) if tag_id.starts_with("urn:cid:") => tag_id,
+            _ => continue,
+        };
+        db::add_stats_count(
+            &mut *connection,
+            StatsKind::Tag,
+            tag_id,
+            timestamp_millis,
+            delta,
+        )
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    }
+    Ok(())
+}
+
+/// Count a `Create` message in the statistics of its author and tag
+/// audiences.
+///
+/// A message published ahead of the server clock is counted at `now_millis`,
+/// so that it can't inflate the counts of future time buckets. The time it
+/// is counted at is stored so that it is uncounted from the same bucket.
+async fn count_message_stats(
+    message: &MessageFields,
+    now_millis: i64,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    if message.type_() != ActivityType::Create {
+        return Ok(());
+    }
+    let timestamp_millis = message.published().timestamp_millis().min(now_millis);
+    db::put_stats_message(&mut *connection, message.id().as_str(), timestamp_millis)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    add_message_stats(message, timestamp_millis, 1, connection).await
+}
+
+/// Remove a message from the statistics, if it is still counted.
+async fn uncount_message_stats(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    let timestamp_millis = match db::take_stats_message(&mut *connection, message.id().as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?
+    {
+        Some(timestamp_millis) => timestamp_millis,
+        None => return Ok(()),
+    };
+    add_message_stats(message, timestamp_millis, -1, connection).await
+}
+
+/// Delete `message` with its associations, and the documents which no other
+/// message references.
+pub async fn delete_message(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    // delete the message document
+    db::delete_document(&mut *connection, message.id().as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+
+    // delete the message associations
+    db::delete_message_audiences(&mut *connection, message.id().as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    db::delete_message_documents(&mut *connection, message.id().as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    db::delete_message_reactions(&mut *connection, message.id().as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    db::delete_message_notifications(&mut *connection, message.id().as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    db::delete_message_notes_tags(&mut *connection, message.id().as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    db::delete_moved_message(&mut *connection, message.id().as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    uncount_message_stats(message, &mut *connection).await?;
+    db::delete_message(&mut *connection, message.id().as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+
+    // delete the document documents if they are not associated to any other message
+    for document_id in message.object().iter() {
+        if db::has_message_with_document(&mut *connection, document_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?
+        {
+            continue;
+        }
+        db::delete_document(&mut *connection, document_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+    }
+
+    Ok(())
+}
+
+async fn handle_delete(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+) -> Result<(), AppError> {
+    // can delete only one document at a time
+    let document_id = message.object().first().ok_or(AppError::MessageNotValid)?;
+
+    // object to delete is one of the actor's lists
+    if is_actor_list(message.actor().as_str(), document_id.as_str()) {
+        use_mutable(
+            document_id.as_str(),
+            message.published().timestamp_millis(),
+            &mut *connection,
+        )
+        .await?;
+        db::delete_list_all_members(&mut *connection, document_id.as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        return Ok(());
+    }
+
+    // object to delete is one of the actor's collections
+    if let Some(collection) =
+        ActorCollection::from_id(message.actor().as_str(), document_id.as_str())
+    {
+        use_mutable(
+            document_id.as_str(),
+            message.published().timestamp_millis(),
+            &mut *connection,
+        )
+        .await?;
+        clear_collection(message, collection, connection).await?;
+        return Ok(());
+    }
+
+    let document = match db::get_document(&mut *connection, document_id.as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?
+    {
+        Some(object) => object,
+        None => return Ok(()),
+    };
+
+    if let Ok(message_to_delete) = serde_json::from_str::<MessageFields>(&document) {
+        // only the creator of a message can delete that message
+        if message_to_delete.actor() != message.actor() {
+            Err(AppError::MessageNotValid)?
+        }
+        delete_message(&message_to_delete, connection).await?;
+        return Ok(());
+    }
+
+    if let Ok(document_to_delete) = serde_json::from_str::<serde_json::Value>(&document) {
         // ensure the correct context to interpret the attributedTo member
         document_to_delete
             .get("@context")
@@ -223,6 +803,9 @@ async fn handle_delete(
         db::delete_document(&mut *connection, document_id)
             .await
             .map_err(|_| AppError::DbQueryFailed)?;
+        db::delete_note_notes_tags(&mut *connection, document_id)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
         return Ok(());
     };
 
@@ -278,6 +861,10 @@ async fn store_message(
             .map_err(|_| AppError::DbQueryFailed)?;
     }
 
+    // count the message in the statistics, the old counts are pruned
+    // periodically
+    count_message_stats(message, Utc::now().timestamp_millis(), &mut *connection).await?;
+
     // store the message itself
     let message = serde_json::to_string(&message).map_err(|_| AppError::MessageNotValid)?;
     db::put_document_if_new(&mut *connection, &message_id, &message)
@@ -290,39 +877,36 @@ async fn store_message(
     Ok(())
 }
 
-async fn handle_view(
-    message: &MessageFields,
+async fn store_relay_batch(
+    batch: RelayBatch,
     connection: &mut SqliteConnection,
     jwk: &JWK,
 ) -> Result<(), AppError> {
     let server_did = did_from_jwk(&jwk).map_err(|_| AppError::ServerMisconfigured)?;
     let server_actor_id =
         actor_id_from_did(&server_did).map_err(|_| AppError::ServerMisconfigured)?;
-    if message.actor().as_str() == server_actor_id.as_str() {
-        return Ok(());
-    }
-    if !db::inbox_contains_message(&mut *connection, &server_actor_id, message.id().as_str())
-        .await
-        .map_err(|_| AppError::DbQueryFailed)?
-    {
-        return Ok(());
-    }
-
     let server_followers: Uri = format!("{}/followers", server_actor_id)
         .try_into()
         .map_err(|_| AppError::ServerMisconfigured)?;
-    let extended_to: VecUris = if let Some(previous_to) = message.to() {
-        std::iter::once(&server_followers)
-            .chain(previous_to.as_ref())
-            .cloned()
-            .collect::<Vec<Uri>>()
-            .pipe(VecUris::from_truncate)
-    } else {
-        VecUris::from_truncate(vec![server_followers])
-    };
-    let view_message = MessageBuilder::new(&jwk, ActivityType::View, message.object().clone())
+    // the relay keeps batches within the message limits, so nothing is dropped
+    let mut extended_to = batch.audiences;
+    if !extended_to.contains(&server_followers) {
+        extended_to.insert(0, server_followers);
+    }
+    let extended_to: VecUris = extended_to
+        .try_into()
+        .map_err(|_| AppError::ServerMisconfigured)?;
+    let objects: VecUris = batch
+        .objects
+        .try_into()
+        .map_err(|_| AppError::ServerMisconfigured)?;
+    let origins: VecUris = batch
+        .origins
+        .try_into()
+        .map_err(|_| AppError::ServerMisconfigured)?;
+    let view_message = MessageBuilder::new(&jwk, ActivityType::View, objects)
         .to(extended_to)
-        .origin(vec![message.id().clone()].pipe(VecUris::from_truncate))
+        .origin(origins)
         .build()
         .await
         .map_err(|_| AppError::ServerMisconfigured)?;
@@ -332,216 +916,999 @@ async fn handle_view(
     Ok(())
 }
 
-pub async fn handle_outbox(
-    State(AppState { connector, jwk }): State<AppState>,
-    Path(did): Path<String>,
-    Json(message): Json<MessageFields>,
-) -> Result<StatusCode, AppError> {
-    let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
-    if actor_id != message.actor().as_str() {
-        Err(AppError::ActorIdWrong)?;
+/// Check if `message` should be offered to the relay once it is stored: the
+/// relay views the messages which reach the server actor's inbox.
+async fn is_relayed(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+    jwk: &JWK,
+) -> Result<bool, AppError> {
+    if message.type_() == ActivityType::View {
+        return Ok(false);
+    }
+    let server_did = did_from_jwk(&jwk).map_err(|_| AppError::ServerMisconfigured)?;
+    let server_actor_id =
+        actor_id_from_did(&server_did).map_err(|_| AppError::ServerMisconfigured)?;
+    if message.actor().as_str() == server_actor_id.as_str() {
+        return Ok(false);
+    }
+    db::inbox_contains_message(&mut *connection, &server_actor_id, message.id().as_str())
+        .await
+        .map_err(|_| AppError::DbQueryFailed)
+}
+
+/// Store the `View` messages for `batches` in their own transaction.
+async fn store_relay_batches(
+    batches: Vec<RelayBatch>,
+    connection: &mut SqliteConnection,
+    jwk: &JWK,
+) -> Result<(), AppError> {
+    if batches.is_empty() {
+        return Ok(());
+    }
+
+    let mut connection = connection
+        .begin()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+
+    for batch in batches {
+        store_relay_batch(batch, &mut *connection, jwk).await?;
+    }
+
+    connection
+        .commit()
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+
+    Ok(())
+}
+
+/// Offer the stored `message` to the relay, storing the `View` messages for
+/// the batches which are ready as a result.
+///
+/// This runs only once the message is committed, so that a message which is
+/// rolled back neither uses up the rate caps nor reaches a batch.
+pub async fn relay_message(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+    jwk: &JWK,
+    relay: &Relay,
+) -> Result<(), AppError> {
+    let batches = relay.offer(message, Utc::now());
+    store_relay_batches(batches, connection, jwk).await
+}
+
+/// Offer the stored `message` to the relays of the `peers` whose inbox it
+/// reaches, as is done for the server actor the message is posted to.
+pub async fn relay_message_to_peers(
+    message: &MessageFields,
+    connection: &mut SqliteConnection,
+    peers: &[RelayPeer],
+) -> Result<(), AppError> {
+    for peer in peers {
+        if is_relayed(message, &mut *connection, &peer.jwk).await? {
+            relay_message(message, &mut *connection, &peer.jwk, &peer.relay).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Send the relay batches which have been pending for too long, or all of
+/// the pending batches if `force` is set.
+pub async fn flush_relay(state: &AppState, force: bool) -> Result<(), AppError> {
+    let batches = state.relay.flush(Utc::now(), force);
+    if batches.is_empty() {
+        return Ok(());
     }
 
     // read write
-    let mut connector = connector.write().await;
+    let mut connector = state.connector.write().await;
     let mut connection = connector
         .connection_mut()
         .await
         .map_err(|_| AppError::DbConnectionFailed)?;
-    let mut connection = connection
-        .begin()
-        .await
-        .map_err(|_| AppError::DbConnectionFailed)?;
 
+    store_relay_batches(batches, &mut *connection, &state.jwk).await
+}
+
+/// The outcome of accepting a message with [`accept_message`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Accepted {
+    /// [`StatusCode::ACCEPTED`] if the message is already known.
+    pub status: StatusCode,
+    /// Whether to offer the message to the relay with [`relay_message`] once
+    /// the transaction accepting it commits.
+    pub relay: bool,
+}
+
+/// Accept the verified `message` by the actor with `actor_id`, running its
+/// side effects and storing it.
+///
+/// The relay isn't offered the message here, since the caller's transaction
+/// can still roll back: the caller relays it after committing if told to.
+pub async fn accept_message(
+    message: &MessageFields,
+    actor_id: &str,
+    connection: &mut SqliteConnection,
+    jwk: &JWK,
+) -> Result<Accepted, AppError> {
     // if already known, take no actions
     let message_id = message.id().to_string();
-    message
-        .verify()
-        .await
-        .map_err(|_| AppError::MessageNotValid)?;
     if db::has_message(&mut *connection, &message_id)
         .await
         .map_err(|_| AppError::DbQueryFailed)?
     {
-        return Ok(StatusCode::ACCEPTED);
+        return Ok(Accepted {
+            status: StatusCode::ACCEPTED,
+            relay: false,
+        });
     };
 
+    // a message signed by a delegate is rejected once the actor revokes the
+    // delegation, or once it expires: the message verification checks only
+    // the publication time, which the delegate chooses
+    if let Some(delegation) = message.instrument() {
+        let now = Utc::now();
+        if now < *delegation.published() || now > *delegation.end_time() {
+            Err(AppError::MessageNotValid)?;
+        }
+        if db::has_actor_revocation(&mut *connection, actor_id, delegation.id().as_str())
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?
+        {
+            Err(AppError::MessageNotValid)?;
+        }
+    }
+
+    // the key of a moved actor is no longer trusted, so its messages are
+    // stored flagged, without side effects and out of every collection but
+    // that of its flagged messages
+    if db::get_actor_moved_to(&mut *connection, actor_id)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?
+        .is_some()
+    {
+        let document = serde_json::to_string(&message).map_err(|_| AppError::MessageNotValid)?;
+        db::put_document_if_new(&mut *connection, &message_id, &document)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        db::put_moved_message(&mut *connection, &message_id, actor_id)
+            .await
+            .map_err(|_| AppError::DbQueryFailed)?;
+        return Ok(Accepted {
+            status: StatusCode::OK,
+            relay: false,
+        });
+    }
+
+    // list membership is private to its owner, so the changes are applied
+    // but the messages are neither stored nor relayed
+    if let (ActivityType::Add | ActivityType::Remove, Some(list_id)) =
+        (message.type_(), get_actor_list_target(message))
+    {
+        handle_list_change(message, list_id.as_str(), &mut *connection).await?;
+        return Ok(Accepted {
+            status: StatusCode::OK,
+            relay: false,
+        });
+    }
+
     // run type-dependent side effects
     match message.type_() {
         // activity expresses a follow relationship
-        ActivityType::Delete => handle_delete(&message, &mut *connection).await?,
-        ActivityType::Add => handle_add(&message, &mut *connection).await?,
-        ActivityType::Remove => handle_remove(&message, &mut *connection).await?,
+        ActivityType::Delete => handle_delete(message, &mut *connection).await?,
+        ActivityType::Add => handle_add(message, &mut *connection).await?,
+        ActivityType::Remove => handle_remove(message, &mut *connection).await?,
+        ActivityType::Move => handle_move(message, &mut *connection).await?,
+        ActivityType::Like => {
+            handle_reaction_message(message, Reaction::Like, &mut *connection).await?
+        }
+        ActivityType::Announce => {
+            handle_reaction_message(message, Reaction::Share, &mut *connection).await?
+        }
         _ => (),
     }
 
-    store_message(&message, &mut *connection).await?;
+    store_message(message, &mut *connection).await?;
+
+    Ok(Accepted {
+        status: StatusCode::OK,
+        relay: is_relayed(message, &mut *connection, jwk).await?,
+    })
+}
 
-    if message.type_() != ActivityType::View {
-        handle_view(&message, &mut *connection, &jwk).await?;
+pub async fn handle_outbox(
+    State(AppState {
+        connector,
+        jwk,
+        relay,
+        cache,
+        resolver,
+        peers,
+    }): State<AppState>,
+    Path(did): Path<String>,
+    Json(message): Json<MessageFields>,
+) -> Result<StatusCode, AppError> {
+    let actor_id = actor_id_from_did(&did).map_err(|_| AppError::DidNotValid)?;
+    if actor_id != message.actor().as_str() {
+        Err(AppError::ActorIdWrong)?;
     }
 
-    connection
-        .commit()
+    // verification is expensive and needs no DB access, so it runs before
+    // taking the lock to let concurrent posts verify in parallel
+    message
+        .verify_cached_with(&cache, &*resolver)
         .await
-        .map_err(|_| AppError::DbQueryFailed)?;
+        .map_err(|_| AppError::MessageNotValid)?;
+
+    // read write
+    let mut connector = connector.write().await;
+    let mut connection = connector
+        .connection_mut()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    let mut transaction = connection
+        .begin()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+
+    let accepted = accept_message(&message, &actor_id, &mut *transaction, &jwk).await?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+
+    if accepted.relay {
+        relay_message(&message, &mut *connection, &jwk, &relay).await?;
+    }
+    if accepted.status == StatusCode::OK {
+        relay_message_to_peers(&message, &mut *connection, &peers).await?;
+    }
+
+    Ok(accepted.status)
+}
+
+#[cfg(test)]
+mod test {
+    use axum::Router;
+    use chatternet::model::{
+        Collection, CollectionFields, CollectionPage, CollectionPageFields, DelegationFields,
+        Document, NoteMd1kFields,
+    };
+    use chrono::Duration;
+    use tokio;
+    use tower::ServiceExt;
+
+    use chatternet::canon::Canonicalization;
+    use chatternet::didkey::{build_jwk, did_from_jwk};
+
+    use super::super::build_api;
+    use super::super::test_utils::*;
+    use super::*;
+    use crate::relay::{RelayPolicy, RelayRateCap};
+
+    async fn get_inbox_objects(api: Router, did: &str) -> Vec<String> {
+        let response = api
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/inbox?pageSize=4", did),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let inbox: CollectionPageFields<MessageFields> = get_body(response).await;
+        inbox
+            .items()
+            .iter()
+            .map(|x| x.object().iter().map(|x| x.to_string()))
+            .flatten()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn builds_audiences_id() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let audiences_id = build_audiences_id(
+            &build_message(
+                &jwk,
+                "id:1",
+                Some(vec![
+                    "did:example:a".to_string(),
+                    "did:example:b".to_string(),
+                ]),
+            )
+            .await,
+        );
+        assert_eq!(audiences_id, ["did:example:a", "did:example:b",]);
+    }
+
+    #[tokio::test]
+    async fn builds_audiences_id_with_public() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let audiences_id = build_audiences_id(
+            &build_message(
+                &jwk,
+                "id:1",
+                Some(vec![
+                    "as:Public".to_string(),
+                    "did:example:a".to_string(),
+                    PUBLIC_AUDIENCE_ID.to_string(),
+                ]),
+            )
+            .await,
+        );
+        assert_eq!(audiences_id, [PUBLIC_AUDIENCE_ID, "did:example:a"]);
+    }
+
+    #[tokio::test]
+    async fn handles_message_with_jcs() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let message = MessageBuilder::new(
+            &jwk,
+            ActivityType::Create,
+            vec!["id:1".try_into().unwrap()].try_into().unwrap(),
+        )
+        .canonicalization(Canonicalization::Jcs)
+        .build()
+        .await
+        .unwrap();
+
+        // modified message isn't accepted
+        let mut modified = serde_json::to_value(&message).unwrap();
+        modified["object"] = serde_json::json!(["id:2"]);
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &modified,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}", &message.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let message_back: Option<MessageFields> = get_body(response).await;
+        message_back.unwrap().verify().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn verifies_message_without_waiting_for_lock() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let state = build_test_state(jwk.clone(), RelayPolicy::default()).await;
+        let api = build_api(state.clone(), "api", "did:example:server");
+
+        let did = did_from_jwk(&jwk).unwrap();
+        let message = build_message(&jwk, "id:1", None).await;
+        let mut invalid = serde_json::to_value(&message).unwrap();
+        invalid["object"] = serde_json::json!(["id:2"]);
+
+        // while another request holds the DB, an invalid message is still
+        // rejected since its verification doesn't need the lock
+        let guard = state.connector.write().await;
+        let response = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            api.clone().oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &invalid,
+            )),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        drop(guard);
+
+        let response = api
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn handles_concurrent_messages() {
+        let api = build_test_api().await;
+        let mut messages = Vec::new();
+        for _ in 0..16 {
+            let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+            let did = did_from_jwk(&jwk).unwrap();
+            messages.push((did, build_message(&jwk, "id:1", None).await));
+        }
+        let requests = messages
+            .into_iter()
+            .map(|(did, message)| {
+                let api = api.clone();
+                tokio::spawn(async move {
+                    api.oneshot(request_json(
+                        "POST",
+                        &format!("/api/{}/actor/outbox", did),
+                        &message,
+                    ))
+                    .await
+                    .unwrap()
+                    .status()
+                })
+            })
+            .collect::<Vec<_>>();
+        for request in requests {
+            assert_eq!(request.await.unwrap(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn handles_message() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let message = build_message(&jwk, "id:1", None).await;
+
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // second post returns status accepted
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}", &message.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let message_back: Option<MessageFields> = get_body(response).await;
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            serde_json::to_string(&message_back.unwrap()).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_did() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let message = build_message(&jwk, "id:1", None).await;
+
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                "/api/did:example:a/actor/outbox",
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_message() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let message = build_message(&jwk, "id:1", None).await;
+
+        let mut invalid = serde_json::to_value(&message).unwrap();
+        *invalid.get_mut("id").unwrap() = serde_json::to_value("id:a").unwrap();
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &invalid,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn handles_follow_gets_following() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+
+        let message = build_follow(vec!["tag:1".to_string(), "tag:2".to_string()], &jwk).await;
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/following", did),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let following: CollectionFields<String> = get_body(response).await;
+        assert_eq!(following.items(), &vec!["tag:1", "tag:2"]);
+    }
+
+    #[tokio::test]
+    async fn removes_follow() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+
+        let message_1 = build_follow(vec!["tag:1".to_string(), "tag:2".to_string()], &jwk).await;
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message_1,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let message_remove = MessageBuilder::new(
+            &jwk,
+            ActivityType::Remove,
+            vec!["tag:2".try_into().unwrap()].try_into().unwrap(),
+        )
+        .target(
+            vec![format!("{}/actor/following", did).try_into().unwrap()]
+                .try_into()
+                .unwrap(),
+        )
+        .build()
+        .await
+        .unwrap();
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message_remove,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/following", did),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let following: CollectionFields<String> = get_body(response).await;
+        assert_eq!(following.items(), &vec!["tag:1"]);
+    }
+
+    #[tokio::test]
+    async fn doesnt_add_stale_following() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+
+        let message_1 = build_follow(vec!["tag:1".to_string()], &jwk).await;
+        let message_2 = MessageBuilder::new(
+            &jwk,
+            ActivityType::Remove,
+            vec!["tag:1".try_into().unwrap()].try_into().unwrap(),
+        )
+        .target(
+            vec![format!("{}/actor/following", did).try_into().unwrap()]
+                .try_into()
+                .unwrap(),
+        )
+        .build()
+        .await
+        .unwrap();
+        let message_3 = loop {
+            let message = build_follow(vec!["tag:1".to_string()], &jwk).await;
+            if message.published() > message_2.published() {
+                break message;
+            };
+        };
+
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message_3,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message_2,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message_1,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/following", did),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let following: CollectionFields<String> = get_body(response).await;
+        assert_eq!(following.items(), &vec!["tag:1"]);
+    }
+
+    #[tokio::test]
+    async fn deletes_message() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let document = NoteMd1kFields::new(
+            "abc".to_string(),
+            "did:example:a".to_string().try_into().unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        let message = build_message(&jwk, document.id().as_str(), None).await;
+        let message_delete =
+            build_message_with_type(&jwk, ActivityType::Delete, message.id().as_str(), None).await;
+
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}", document.id().as_str()),
+                &document,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-    Ok(StatusCode::OK)
-}
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}", &message.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-#[cfg(test)]
-mod test {
-    use chatternet::model::{
-        Collection, CollectionFields, CollectionPage, CollectionPageFields, Document,
-        NoteMd1kFields,
-    };
-    use tokio;
-    use tower::ServiceExt;
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}", &document.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-    use chatternet::didkey::{build_jwk, did_from_jwk};
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message_delete,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-    use super::super::test_utils::*;
-    use super::*;
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}", &message.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
-    #[tokio::test]
-    async fn builds_audiences_id() {
-        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
-        let audiences_id = build_audiences_id(
-            &build_message(
-                &jwk,
-                "id:1",
-                Some(vec![
-                    "did:example:a".to_string(),
-                    "did:example:b".to_string(),
-                ]),
-            )
-            .await,
-        );
-        assert_eq!(audiences_id, ["did:example:a", "did:example:b",]);
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}", &document.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn handles_message() {
+    async fn deletes_message_and_keeps_shared_document() {
         let api = build_test_api().await;
 
         let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
         let did = did_from_jwk(&jwk).unwrap();
-        let message = build_message(&jwk, "id:1", None).await;
+        let document = NoteMd1kFields::new(
+            "abc".to_string(),
+            "did:example:a".to_string().try_into().unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        let message_1 = build_message(&jwk, document.id().as_str(), None).await;
+        let message_2 = build_message(&jwk, document.id().as_str(), None).await;
+        let message_delete =
+            build_message_with_type(&jwk, ActivityType::Delete, message_1.id().as_str(), None)
+                .await;
 
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did),
-                &message,
+                &message_1,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
-        // second post returns status accepted
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did),
-                &message,
+                &message_2,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}", document.id().as_str()),
+                &document,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message_delete,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
         let response = api
             .clone()
             .oneshot(request_empty(
                 "GET",
-                &format!("/api/{}", &message.id().as_str()),
+                &format!("/api/{}", &message_1.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}", &document.id().as_str()),
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-        let message_back: Option<MessageFields> = get_body(response).await;
-        assert_eq!(
-            serde_json::to_string(&message).unwrap(),
-            serde_json::to_string(&message_back.unwrap()).unwrap()
-        );
     }
 
     #[tokio::test]
-    async fn rejects_wrong_did() {
+    async fn deletes_document() {
         let api = build_test_api().await;
 
         let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
-        let message = build_message(&jwk, "id:1", None).await;
+        let did = did_from_jwk(&jwk).unwrap();
+        let document = NoteMd1kFields::new(
+            "abc".to_string(),
+            format!("{}/actor", did).try_into().unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        let message = build_message(&jwk, document.id().as_str(), None).await;
+        let message_delete =
+            build_message_with_type(&jwk, ActivityType::Delete, document.id().as_str(), None).await;
 
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                "/api/did:example:a/actor/outbox",
+                &format!("/api/{}/actor/outbox", did),
                 &message,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
-    }
+        assert_eq!(response.status(), StatusCode::OK);
 
-    #[tokio::test]
-    async fn rejects_invalid_message() {
-        let api = build_test_api().await;
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}", document.id().as_str()),
+                &document,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
-        let did = did_from_jwk(&jwk).unwrap();
-        let message = build_message(&jwk, "id:1", None).await;
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}", &document.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let mut invalid = serde_json::to_value(&message).unwrap();
-        *invalid.get_mut("id").unwrap() = serde_json::to_value("id:a").unwrap();
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did),
-                &invalid,
+                &message_delete,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}", &document.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn handles_follow_gets_following() {
+    async fn doesnt_delete_others_messages() {
         let api = build_test_api().await;
 
-        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
-        let did = did_from_jwk(&jwk).unwrap();
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let message = build_message(&jwk_1, "id:1", None).await;
+
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
+        let message_delete =
+            build_message_with_type(&jwk_2, ActivityType::Delete, message.id().as_str(), None)
+                .await;
 
-        let message = build_follow(vec!["tag:1".to_string(), "tag:2".to_string()], &jwk).await;
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did),
+                &format!("/api/{}/actor/outbox", did_1),
                 &message,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_2),
+                &message_delete,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
         let response = api
             .clone()
             .oneshot(request_empty(
                 "GET",
-                &format!("/api/{}/actor/following", did),
+                &format!("/api/{}", &message.id().as_str()),
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-        let following: CollectionFields<String> = get_body(response).await;
-        assert_eq!(following.items(), &vec!["tag:1", "tag:2"]);
     }
 
     #[tokio::test]
-    async fn removes_follow() {
+    async fn delete_following_clears_following() {
         let api = build_test_api().await;
 
         let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
@@ -559,25 +1926,19 @@ mod test {
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
-        let message_remove = MessageBuilder::new(
+        let message_2 = build_message_with_type(
             &jwk,
-            ActivityType::Remove,
-            vec!["tag:2".try_into().unwrap()].try_into().unwrap(),
-        )
-        .target(
-            vec![format!("{}/actor/following", did).try_into().unwrap()]
-                .try_into()
-                .unwrap(),
+            ActivityType::Delete,
+            &format!("{}/actor/following", did),
+            None,
         )
-        .build()
-        .await
-        .unwrap();
+        .await;
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did),
-                &message_remove,
+                &message_2,
             ))
             .await
             .unwrap();
@@ -593,33 +1954,26 @@ mod test {
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
         let following: CollectionFields<String> = get_body(response).await;
-        assert_eq!(following.items(), &vec!["tag:1"]);
+        assert!(following.items().is_empty());
     }
 
     #[tokio::test]
-    async fn doesnt_add_stale_following() {
+    async fn doesnt_delete_stale_following() {
         let api = build_test_api().await;
 
         let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
         let did = did_from_jwk(&jwk).unwrap();
 
-        let message_1 = build_follow(vec!["tag:1".to_string()], &jwk).await;
-        let message_2 = MessageBuilder::new(
+        let message_1 = build_message_with_type(
             &jwk,
-            ActivityType::Remove,
-            vec!["tag:1".try_into().unwrap()].try_into().unwrap(),
-        )
-        .target(
-            vec![format!("{}/actor/following", did).try_into().unwrap()]
-                .try_into()
-                .unwrap(),
+            ActivityType::Delete,
+            &format!("{}/actor/following", did),
+            None,
         )
-        .build()
-        .await
-        .unwrap();
-        let message_3 = loop {
+        .await;
+        let message_2 = loop {
             let message = build_follow(vec!["tag:1".to_string()], &jwk).await;
-            if message.published() > message_2.published() {
+            if message.published() > message_1.published() {
                 break message;
             };
         };
@@ -629,7 +1983,7 @@ mod test {
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did),
-                &message_3,
+                &message_2,
             ))
             .await
             .unwrap();
@@ -640,101 +1994,130 @@ mod test {
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did),
-                &message_2,
+                &message_1,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn views_message_from_followed() {
+        let jwk_server = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+
+        let api = build_test_api_jwk(jwk_server.clone()).await;
+
+        let did_server = did_from_jwk(&jwk_server).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
 
+        // server follows 1
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did),
-                &message_1,
+                &format!("/api/{}/actor/outbox", did_server),
+                &build_follow(vec![format!("{}/actor", did_1)], &jwk_server).await,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.status(), StatusCode::OK);
 
+        // from 1 to 1's followers
         let response = api
             .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}/actor/following", did),
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_1),
+                &build_message(
+                    &jwk_1,
+                    "id:1",
+                    Some(vec![format!("{}/actor/followers", did_1)]),
+                )
+                .await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-        let following: CollectionFields<String> = get_body(response).await;
-        assert_eq!(following.items(), &vec!["tag:1"]);
-    }
-
-    #[tokio::test]
-    async fn deletes_message() {
-        let api = build_test_api().await;
-
-        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
-        let did = did_from_jwk(&jwk).unwrap();
-        let document = NoteMd1kFields::new(
-            "abc".to_string(),
-            "did:example:a".to_string().try_into().unwrap(),
-            None,
-        )
-        .await
-        .unwrap();
-        let message = build_message(&jwk, document.id().as_str(), None).await;
-        let message_delete =
-            build_message_with_type(&jwk, ActivityType::Delete, message.id().as_str(), None).await;
 
+        // 2 has no messages (doesn't follow 1)
         let response = api
             .clone()
-            .oneshot(request_json(
-                "POST",
-                &format!("/api/{}/actor/outbox", did),
-                &message,
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/inbox?pageSize=4", did_2),
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        let inbox: CollectionPageFields<MessageFields> = get_body(response).await;
+        assert!(inbox.items().is_empty());
 
+        // 2 follows server
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}", document.id().as_str()),
-                &document,
+                &format!("/api/{}/actor/outbox", did_2),
+                &build_follow(vec![format!("{}/actor", did_server)], &jwk_2).await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
+        // 2 follows server follows 1 so get 1's message
         let response = api
             .clone()
             .oneshot(request_empty(
                 "GET",
-                &format!("/api/{}", &message.id().as_str()),
+                &format!("/api/{}/actor/inbox?pageSize=4", did_2),
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        let inbox: CollectionPageFields<MessageFields> = get_body(response).await;
+        assert_eq!(
+            inbox
+                .items()
+                .iter()
+                .map(|x| x.object().iter().map(|x| x.as_str()))
+                .flatten()
+                .collect::<Vec<&str>>(),
+            ["id:1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn doesnt_view_message_with_relay_disabled() {
+        let jwk_server = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+
+        let api = build_test_api_relay(jwk_server.clone(), RelayPolicy::disabled()).await;
+
+        let did_server = did_from_jwk(&jwk_server).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
 
+        // server follows 1 and 2 follows server
         let response = api
             .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}", &document.id().as_str()),
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_server),
+                &build_follow(vec![format!("{}/actor", did_1)], &jwk_server).await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did),
-                &message_delete,
+                &format!("/api/{}/actor/outbox", did_2),
+                &build_follow(vec![format!("{}/actor", did_server)], &jwk_2).await,
             ))
             .await
             .unwrap();
@@ -742,406 +2125,676 @@ mod test {
 
         let response = api
             .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}", &message.id().as_str()),
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_1),
+                &build_message(
+                    &jwk_1,
+                    "id:1",
+                    Some(vec![format!("{}/actor/followers", did_1)]),
+                )
+                .await,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let response = api
-            .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}", &document.id().as_str()),
-            ))
-            .await
-            .unwrap();
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // server doesn't relay the message to 2
+        assert!(get_inbox_objects(api.clone(), &did_2).await.is_empty());
     }
 
     #[tokio::test]
-    async fn deletes_message_and_keeps_shared_document() {
-        let api = build_test_api().await;
+    async fn doesnt_relay_rolled_back_message() {
+        let jwk_server = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
 
-        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
-        let did = did_from_jwk(&jwk).unwrap();
-        let document = NoteMd1kFields::new(
-            "abc".to_string(),
-            "did:example:a".to_string().try_into().unwrap(),
-            None,
-        )
-        .await
-        .unwrap();
-        let message_1 = build_message(&jwk, document.id().as_str(), None).await;
-        let message_2 = build_message(&jwk, document.id().as_str(), None).await;
-        let message_delete =
-            build_message_with_type(&jwk, ActivityType::Delete, message_1.id().as_str(), None)
-                .await;
+        // relays a single message
+        let policy = RelayPolicy {
+            rate_cap: Some(RelayRateCap {
+                window_seconds: 3600,
+                max_messages: Some(1),
+                max_messages_per_author: None,
+            }),
+            ..Default::default()
+        };
+        let state = build_test_state(jwk_server.clone(), policy).await;
+        let api = build_api(state.clone(), "api", "did:example:server");
+
+        let did_server = did_from_jwk(&jwk_server).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
+        let actor_id_1 = actor_id_from_did(&did_1).unwrap();
 
+        // server follows 1 and 2 follows server
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did),
-                &message_1,
+                &format!("/api/{}/actor/outbox", did_server),
+                &build_follow(vec![actor_id_1.clone()], &jwk_server).await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did),
-                &message_2,
+                &format!("/api/{}/actor/outbox", did_2),
+                &build_follow(vec![format!("{}/actor", did_server)], &jwk_2).await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
+        // the message would be relayed, but its transaction rolls back
+        let message = build_message(
+            &jwk_1,
+            "id:1",
+            Some(vec![format!("{}/followers", actor_id_1)]),
+        )
+        .await;
+        {
+            let mut connector = state.connector.write().await;
+            let mut connection = connector.connection_mut().await.unwrap();
+            let mut transaction = connection.begin().await.unwrap();
+            let accepted = accept_message(&message, &actor_id_1, &mut *transaction, &jwk_server)
+                .await
+                .unwrap();
+            assert!(accepted.relay);
+        }
+
+        // so the rate cap is still available for the next message
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}", document.id().as_str()),
-                &document,
+                &format!("/api/{}/actor/outbox", did_1),
+                &build_message(
+                    &jwk_1,
+                    "id:2",
+                    Some(vec![format!("{}/followers", actor_id_1)]),
+                )
+                .await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(get_inbox_objects(api.clone(), &did_2).await, ["id:2"]);
+    }
+
+    #[tokio::test]
+    async fn blocks_and_unblocks_actor() {
+        let api = build_test_api().await;
+
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
 
+        // 1 follows a tag, 2 posts to that tag
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did),
-                &message_delete,
+                &format!("/api/{}/actor/outbox", did_1),
+                &build_follow(
+                    vec![format!("{}/actor", did_2), "tag:1".to_string()],
+                    &jwk_1,
+                )
+                .await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_2),
+                &build_message(&jwk_2, "id:1", Some(vec!["tag:1/followers".to_string()])).await,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        assert_eq!(get_inbox_objects(api.clone(), &did_1).await, ["id:1"]);
 
+        // 1 blocks 2
         let response = api
             .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}", &message_1.id().as_str()),
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_1),
+                &build_collection_change(
+                    ActivityType::Add,
+                    "blocked",
+                    vec![format!("{}/actor", did_2)],
+                    &jwk_1,
+                )
+                .await,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(get_inbox_objects(api.clone(), &did_1).await.is_empty());
 
         let response = api
             .clone()
             .oneshot(request_empty(
                 "GET",
-                &format!("/api/{}", &document.id().as_str()),
+                &format!("/api/{}/actor/blocked", did_1),
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-    }
-
-    #[tokio::test]
-    async fn deletes_document() {
-        let api = build_test_api().await;
-
-        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
-        let did = did_from_jwk(&jwk).unwrap();
-        let document = NoteMd1kFields::new(
-            "abc".to_string(),
-            format!("{}/actor", did).try_into().unwrap(),
-            None,
-        )
-        .await
-        .unwrap();
-        let message = build_message(&jwk, document.id().as_str(), None).await;
-        let message_delete =
-            build_message_with_type(&jwk, ActivityType::Delete, document.id().as_str(), None).await;
+        let blocked: CollectionFields<String> = get_body(response).await;
+        assert_eq!(blocked.items(), &vec![format!("{}/actor", did_2)]);
 
+        // 1 unblocks 2
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did),
-                &message,
+                &format!("/api/{}/actor/outbox", did_1),
+                &build_collection_change(
+                    ActivityType::Remove,
+                    "blocked",
+                    vec![format!("{}/actor", did_2)],
+                    &jwk_1,
+                )
+                .await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(get_inbox_objects(api.clone(), &did_1).await, ["id:1"]);
 
+        // 1 mutes 2
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}", document.id().as_str()),
-                &document,
+                &format!("/api/{}/actor/outbox", did_1),
+                &build_collection_change(
+                    ActivityType::Add,
+                    "muted",
+                    vec![format!("{}/actor", did_2)],
+                    &jwk_1,
+                )
+                .await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        assert!(get_inbox_objects(api.clone(), &did_1).await.is_empty());
 
+        // the muted collection isn't served, so 2 can't tell it is muted
         let response = api
             .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}", &document.id().as_str()),
-            ))
+            .oneshot(request_empty("GET", &format!("/api/{}/actor/muted", did_1)))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
+        // 1 clears its muted collection
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did),
-                &message_delete,
+                &format!("/api/{}/actor/outbox", did_1),
+                &build_message_with_type(
+                    &jwk_1,
+                    ActivityType::Delete,
+                    &format!("{}/actor/muted", did_1),
+                    None,
+                )
+                .await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(get_inbox_objects(api.clone(), &did_1).await, ["id:1"]);
+    }
+
+    #[tokio::test]
+    async fn doesnt_add_to_other_collection() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
 
         let response = api
             .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}", &document.id().as_str()),
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &build_collection_change(
+                    ActivityType::Add,
+                    "other",
+                    vec!["did:example:a/actor".to_string()],
+                    &jwk,
+                )
+                .await,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn doesnt_delete_others_messages() {
+    async fn delivers_message_to_list_members() {
         let api = build_test_api().await;
 
         let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
-        let did_1 = did_from_jwk(&jwk_1).unwrap();
-        let message = build_message(&jwk_1, "id:1", None).await;
-
         let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_3 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
         let did_2 = did_from_jwk(&jwk_2).unwrap();
-        let message_delete =
-            build_message_with_type(&jwk_2, ActivityType::Delete, message.id().as_str(), None)
-                .await;
+        let did_3 = did_from_jwk(&jwk_3).unwrap();
+
+        // 2 and 3 follow 1
+        for (jwk, did) in [(&jwk_2, &did_2), (&jwk_3, &did_3)] {
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did),
+                    &build_follow(vec![format!("{}/actor", did_1)], jwk).await,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
 
+        // 1 adds 2 to a list
+        let add = build_collection_change(
+            ActivityType::Add,
+            "lists/friends",
+            vec![format!("{}/actor", did_2)],
+            &jwk_1,
+        )
+        .await;
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did_1),
-                &message,
+                &add,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
+        // the membership isn't revealed by the message
+        let response = api
+            .clone()
+            .oneshot(request_empty("GET", &format!("/api/{}", add.id())))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        for (object_id, list) in [("id:1", "friends"), ("id:2", "other")] {
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did_1),
+                    &build_message(
+                        &jwk_1,
+                        object_id,
+                        Some(vec![format!("{}/actor/lists/{}", did_1, list)]),
+                    )
+                    .await,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(get_inbox_objects(api.clone(), &did_2).await, ["id:1"]);
+        assert!(get_inbox_objects(api.clone(), &did_3).await.is_empty());
+
+        // 3 can't address the list of 1, even to its own followers
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did_2),
-                &message_delete,
+                &build_follow(vec![format!("{}/actor", did_3)], &jwk_2).await,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_3),
+                &build_message(
+                    &jwk_3,
+                    "id:3",
+                    Some(vec![format!("{}/actor/lists/friends", did_1)]),
+                )
+                .await,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(get_inbox_objects(api.clone(), &did_2).await, ["id:1"]);
 
+        // 1 removes 2 from the list
         let response = api
             .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}", &message.id().as_str()),
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_1),
+                &build_collection_change(
+                    ActivityType::Remove,
+                    "lists/friends",
+                    vec![format!("{}/actor", did_2)],
+                    &jwk_1,
+                )
+                .await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        assert!(get_inbox_objects(api.clone(), &did_2).await.is_empty());
     }
 
     #[tokio::test]
-    async fn delete_following_clears_following() {
+    async fn handles_delegated_message_until_revoked() {
         let api = build_test_api().await;
 
         let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_delegate = build_jwk(&mut rand::thread_rng()).unwrap();
         let did = did_from_jwk(&jwk).unwrap();
+        let did_delegate = did_from_jwk(&jwk_delegate).unwrap();
+        let delegation = DelegationFields::new(
+            &jwk,
+            &did_delegate,
+            &[ActivityType::Create, ActivityType::Add],
+            Utc::now() + Duration::days(1),
+        )
+        .await
+        .unwrap();
+        let build_delegated = |object_id: &'static str| {
+            let jwk_delegate = jwk_delegate.clone();
+            let delegation = delegation.clone();
+            let did = did.clone();
+            async move {
+                MessageBuilder::new(
+                    &jwk_delegate,
+                    ActivityType::Create,
+                    vec![object_id.try_into().unwrap()].try_into().unwrap(),
+                )
+                .to(vec![format!("{}/actor", did).try_into().unwrap()]
+                    .try_into()
+                    .unwrap())
+                .delegation(delegation)
+                .build()
+                .await
+                .unwrap()
+            }
+        };
 
-        let message_1 = build_follow(vec!["tag:1".to_string(), "tag:2".to_string()], &jwk).await;
+        // the delegate posts to the actor's outbox
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did),
-                &message_1,
+                &build_delegated("id:1").await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(get_inbox_objects(api.clone(), &did).await, ["id:1"]);
 
-        let message_2 = build_message_with_type(
-            &jwk,
-            ActivityType::Delete,
-            &format!("{}/actor/following", did),
-            None,
+        // the delegate can't revoke delegations even if it can add
+        let revoke = MessageBuilder::new(
+            &jwk_delegate,
+            ActivityType::Add,
+            vec![delegation.id().clone()].try_into().unwrap(),
         )
-        .await;
+        .target(
+            vec![format!("{}/actor/revoked", did).try_into().unwrap()]
+                .try_into()
+                .unwrap(),
+        )
+        .delegation(delegation.clone())
+        .build()
+        .await
+        .unwrap();
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did),
-                &message_2,
+                &revoke,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
+        // the actor revokes the delegation
         let response = api
             .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}/actor/following", did),
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &build_collection_change(
+                    ActivityType::Add,
+                    "revoked",
+                    vec![delegation.id().to_string()],
+                    &jwk,
+                )
+                .await,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-        let following: CollectionFields<String> = get_body(response).await;
-        assert!(following.items().is_empty());
-    }
-
-    #[tokio::test]
-    async fn doesnt_delete_stale_following() {
-        let api = build_test_api().await;
-
-        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
-        let did = did_from_jwk(&jwk).unwrap();
-
-        let message_1 = build_message_with_type(
-            &jwk,
-            ActivityType::Delete,
-            &format!("{}/actor/following", did),
-            None,
-        )
-        .await;
-        let message_2 = loop {
-            let message = build_follow(vec!["tag:1".to_string()], &jwk).await;
-            if message.published() > message_1.published() {
-                break message;
-            };
-        };
-
         let response = api
             .clone()
-            .oneshot(request_json(
-                "POST",
-                &format!("/api/{}/actor/outbox", did),
-                &message_2,
-            ))
+            .oneshot(request_empty("GET", &format!("/api/{}/actor/revoked", did)))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        let revoked: CollectionFields<String> = get_body(response).await;
+        assert_eq!(revoked.items(), &vec![delegation.id().to_string()]);
 
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
                 &format!("/api/{}/actor/outbox", did),
-                &message_1,
+                &build_delegated("id:2").await,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(get_inbox_objects(api.clone(), &did).await, ["id:1"]);
     }
 
     #[tokio::test]
-    async fn views_message_from_followed() {
-        let jwk_server = build_jwk(&mut rand::thread_rng()).unwrap();
-        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
-        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
-
-        let api = build_test_api_jwk(jwk_server.clone()).await;
+    async fn rejects_delegated_move() {
+        let api = build_test_api().await;
 
-        let did_server = did_from_jwk(&jwk_server).unwrap();
-        let did_1 = did_from_jwk(&jwk_1).unwrap();
-        let did_2 = did_from_jwk(&jwk_2).unwrap();
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_delegate = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let did_delegate = did_from_jwk(&jwk_delegate).unwrap();
+        let delegation = DelegationFields::new(
+            &jwk,
+            &did_delegate,
+            &[ActivityType::Move],
+            Utc::now() + Duration::days(1),
+        )
+        .await
+        .unwrap();
 
-        // server follows 1
+        // the delegate accepts a move to its own actor
+        let accept = MessageBuilder::new(
+            &jwk_delegate,
+            ActivityType::Move,
+            vec![format!("{}/actor", did).try_into().unwrap()]
+                .try_into()
+                .unwrap(),
+        )
+        .target(
+            vec![format!("{}/actor", did_delegate).try_into().unwrap()]
+                .try_into()
+                .unwrap(),
+        )
+        .build()
+        .await
+        .unwrap();
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did_server),
-                &build_follow(vec![format!("{}/actor", did_1)], &jwk_server).await,
+                &format!("/api/{}/actor/outbox", did_delegate),
+                &accept,
             ))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
-        // from 1 to 1's followers
+        // and tries to move the actor on its behalf
+        let message = MessageBuilder::new(
+            &jwk_delegate,
+            ActivityType::Move,
+            vec![format!("{}/actor", did).try_into().unwrap()]
+                .try_into()
+                .unwrap(),
+        )
+        .target(
+            vec![format!("{}/actor", did_delegate).try_into().unwrap()]
+                .try_into()
+                .unwrap(),
+        )
+        .delegation(delegation)
+        .build()
+        .await
+        .unwrap();
+        assert!(message.verify().await.is_ok());
+
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did_1),
-                &build_message(
-                    &jwk_1,
-                    "id:1",
-                    Some(vec![format!("{}/actor/followers", did_1)]),
-                )
-                .await,
+                &format!("/api/{}/actor/outbox", did),
+                &message,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
-        // 2 has no messages (doesn't follow 1)
+        // the actor hasn't moved
         let response = api
             .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}/actor/inbox?pageSize=4", did_2),
-            ))
+            .oneshot(request_empty("GET", &format!("/api/{}/actor", did)))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
-        let inbox: CollectionPageFields<MessageFields> = get_body(response).await;
-        assert!(inbox.items().is_empty());
+        assert_ne!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    }
+
+    #[tokio::test]
+    async fn rejects_backdated_message_by_expired_delegate() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_delegate = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let did_delegate = did_from_jwk(&jwk_delegate).unwrap();
+        let delegation = DelegationFields::new(
+            &jwk,
+            &did_delegate,
+            &[ActivityType::Create],
+            Utc::now() + Duration::milliseconds(500),
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+
+        // the message claims to be published while the delegation was valid
+        let message = MessageBuilder::new(
+            &jwk_delegate,
+            ActivityType::Create,
+            vec!["id:1".try_into().unwrap()].try_into().unwrap(),
+        )
+        .to(vec![format!("{}/actor", did).try_into().unwrap()]
+            .try_into()
+            .unwrap())
+        .delegation(delegation.clone())
+        .published(*delegation.published())
+        .build()
+        .await
+        .unwrap();
+        assert!(message.verify().await.is_ok());
 
-        // 2 follows server
         let response = api
             .clone()
             .oneshot(request_json(
                 "POST",
-                &format!("/api/{}/actor/outbox", did_2),
-                &build_follow(vec![format!("{}/actor", did_server)], &jwk_2).await,
+                &format!("/api/{}/actor/outbox", did),
+                &message,
             ))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(get_inbox_objects(api.clone(), &did).await.is_empty());
+    }
 
-        // 2 follows server follows 1 so get 1's message
-        let response = api
-            .clone()
-            .oneshot(request_empty(
-                "GET",
-                &format!("/api/{}/actor/inbox?pageSize=4", did_2),
-            ))
+    #[tokio::test]
+    async fn uncounts_message_stats_from_counted_bucket() {
+        let state = build_test_state(
+            build_jwk(&mut rand::thread_rng()).unwrap(),
+            RelayPolicy::default(),
+        )
+        .await;
+        let mut connector = state.connector.write().await;
+        let mut connection = connector.connection_mut().await.unwrap();
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let published = Utc::now() + Duration::days(1);
+        let message = MessageBuilder::new(
+            &jwk,
+            ActivityType::Create,
+            vec!["id:1".try_into().unwrap()].try_into().unwrap(),
+        )
+        .to(vec!["urn:cid:a/followers".try_into().unwrap()]
+            .try_into()
+            .unwrap())
+        .published(published)
+        .build()
+        .await
+        .unwrap();
+
+        // the message published ahead is counted in the bucket of the time
+        // it is received, which is buckets before the time it is deleted
+        let now_millis = Utc::now().timestamp_millis() - 2 * db::STATS_BUCKET_MILLIS;
+        count_message_stats(&message, now_millis, &mut *connection)
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
-        let inbox: CollectionPageFields<MessageFields> = get_body(response).await;
         assert_eq!(
-            inbox
-                .items()
-                .iter()
-                .map(|x| x.object().iter().map(|x| x.as_str()))
-                .flatten()
-                .collect::<Vec<&str>>(),
-            ["id:1"]
+            db::get_stats_top(&mut *connection, StatsKind::Tag, now_millis, now_millis, 3)
+                .await
+                .unwrap(),
+            [("urn:cid:a".to_string(), 1)]
         );
+
+        // and uncounted from that bucket rather than the one it is deleted in
+        delete_message(&message, &mut *connection).await.unwrap();
+        let until_millis = published.timestamp_millis();
+        for kind in [StatsKind::Tag, StatsKind::Author] {
+            assert!(
+                db::get_stats_top(&mut *connection, kind, now_millis, until_millis, 3)
+                    .await
+                    .unwrap()
+                    .is_empty()
+            );
+        }
     }
 }
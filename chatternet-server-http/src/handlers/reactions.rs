@@ -0,0 +1,288 @@
+//! Handle collections of reactions to documents.
+
+use anyhow::Result;
+use axum::extract::{Json, Path, Query, State};
+use chatternet::model::{CollectionFields, CollectionType, Uri};
+
+use super::error::AppError;
+use super::{AppState, CollectionPageQuery};
+use crate::db::{self, Reaction};
+
+async fn get_reactions_collection(
+    AppState { connector, .. }: AppState,
+    id: &str,
+    reaction: Reaction,
+    query: CollectionPageQuery,
+) -> Result<CollectionFields<String>, AppError> {
+    let connector = connector.read().await;
+    let mut connection = connector
+        .connection()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    let collection_id = Uri::try_from(format!(
+        "{}/{}",
+        id,
+        match reaction {
+            Reaction::Like => "likes",
+            Reaction::Share => "shares",
+        }
+    ))
+    .map_err(|_| AppError::DocumentIdWrong)?;
+    let page_size = query.page_size.unwrap_or(32);
+    let total_items = db::get_reaction_count(&mut *connection, id, reaction)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    let actors_id = db::get_object_reactions(&mut *connection, id, reaction, page_size)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    Ok(
+        CollectionFields::new(collection_id, CollectionType::Collection, actors_id)
+            .with_total_items(total_items),
+    )
+}
+
+/// Get the collection of actors who liked the document with ID `id`.
+///
+/// The collection lists the last `pageSize` actors and counts all of them.
+pub async fn handle_document_likes(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<CollectionPageQuery>,
+) -> Result<Json<CollectionFields<String>>, AppError> {
+    Ok(Json(
+        get_reactions_collection(state, &id, Reaction::Like, query).await?,
+    ))
+}
+
+/// Get the collection of actors who shared the document with ID `id`.
+///
+/// The collection lists the last `pageSize` actors and counts all of them.
+pub async fn handle_document_shares(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<CollectionPageQuery>,
+) -> Result<Json<CollectionFields<String>>, AppError> {
+    Ok(Json(
+        get_reactions_collection(state, &id, Reaction::Share, query).await?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode;
+    use axum::Router;
+    use chatternet::didkey::{build_jwk, did_from_jwk};
+    use chatternet::model::{ActivityType, Collection, CollectionFields, Message};
+    use tokio;
+    use tower::ServiceExt;
+
+    use super::super::test_utils::*;
+
+    async fn get_count(api: Router, uri: &str) -> (Option<u64>, Vec<String>) {
+        let response = api.oneshot(request_empty("GET", uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let collection: CollectionFields<String> = get_body(response).await;
+        (collection.total_items(), collection.items().to_owned())
+    }
+
+    #[tokio::test]
+    async fn counts_likes_and_shares() {
+        let api = build_test_api().await;
+
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
+
+        assert_eq!(
+            get_count(api.clone(), "/api/urn:cid:a/likes").await,
+            (Some(0), vec![])
+        );
+
+        let like_1 = build_message_with_type(&jwk_1, ActivityType::Like, "urn:cid:a", None).await;
+        // a second like by the same actor doesn't change the count
+        let like_1_again =
+            build_message_with_type(&jwk_1, ActivityType::Like, "urn:cid:a", None).await;
+        let like_2 = build_message_with_type(&jwk_2, ActivityType::Like, "urn:cid:a", None).await;
+        let share_2 =
+            build_message_with_type(&jwk_2, ActivityType::Announce, "urn:cid:a", None).await;
+        for (did, message) in [
+            (&did_1, &like_1),
+            (&did_1, &like_1_again),
+            (&did_2, &like_2),
+            (&did_2, &share_2),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did),
+                    message,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        assert_eq!(
+            get_count(api.clone(), "/api/urn:cid:a/likes").await,
+            (
+                Some(2),
+                vec![format!("{}/actor", did_2), format!("{}/actor", did_1)]
+            )
+        );
+        assert_eq!(
+            get_count(api.clone(), "/api/urn:cid:a/shares").await,
+            (Some(1), vec![format!("{}/actor", did_2)])
+        );
+
+        // undo a like by removing it from the liked collection
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_1),
+                &build_collection_change(
+                    ActivityType::Remove,
+                    "liked",
+                    vec!["urn:cid:a".to_string()],
+                    &jwk_1,
+                )
+                .await,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            get_count(api.clone(), "/api/urn:cid:a/likes").await,
+            (Some(1), vec![format!("{}/actor", did_2)])
+        );
+
+        // undo a share by deleting its message
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_2),
+                &build_message_with_type(&jwk_2, ActivityType::Delete, share_2.id().as_str(), None)
+                    .await,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            get_count(api.clone(), "/api/urn:cid:a/shares").await,
+            (Some(0), vec![])
+        );
+    }
+
+    #[tokio::test]
+    async fn accepts_reactions_out_of_order() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+
+        let like_a = build_message_with_type(&jwk, ActivityType::Like, "urn:cid:a", None).await;
+        let unlike_a = loop {
+            let message = build_collection_change(
+                ActivityType::Remove,
+                "liked",
+                vec!["urn:cid:a".to_string()],
+                &jwk,
+            )
+            .await;
+            if message.published() > like_a.published() {
+                break message;
+            }
+        };
+        let like_b = loop {
+            let message =
+                build_message_with_type(&jwk, ActivityType::Like, "urn:cid:b", None).await;
+            if message.published() > unlike_a.published() {
+                break message;
+            }
+        };
+
+        // a like of another object arriving after a newer one is accepted,
+        // but a like older than the latest change for its object isn't
+        for (message, status) in [
+            (&like_b, StatusCode::OK),
+            (&unlike_a, StatusCode::OK),
+            (&like_a, StatusCode::CONFLICT),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did),
+                    message,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        assert_eq!(
+            get_count(api.clone(), "/api/urn:cid:a/likes").await,
+            (Some(0), vec![])
+        );
+        assert_eq!(
+            get_count(api.clone(), "/api/urn:cid:b/likes").await,
+            (Some(1), vec![format!("{}/actor", did)])
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_reactions_older_than_cleared_collection() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+
+        let like_a = build_message_with_type(&jwk, ActivityType::Like, "urn:cid:a", None).await;
+        let like_b = build_message_with_type(&jwk, ActivityType::Like, "urn:cid:b", None).await;
+        let delete_liked = loop {
+            let message = build_message_with_type(
+                &jwk,
+                ActivityType::Delete,
+                &format!("{}/actor/liked", did),
+                None,
+            )
+            .await;
+            if message.published() > like_a.published() && message.published() > like_b.published()
+            {
+                break message;
+            }
+        };
+
+        // a like which is replayed is already known, and a like which was
+        // never seen but predates the cleared collection is stale
+        for (message, status) in [
+            (&like_a, StatusCode::OK),
+            (&delete_liked, StatusCode::OK),
+            (&like_a, StatusCode::ACCEPTED),
+            (&like_b, StatusCode::CONFLICT),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did),
+                    message,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        assert_eq!(
+            get_count(api.clone(), "/api/urn:cid:a/likes").await,
+            (Some(0), vec![])
+        );
+        assert_eq!(
+            get_count(api.clone(), "/api/urn:cid:b/likes").await,
+            (Some(0), vec![])
+        );
+    }
+}
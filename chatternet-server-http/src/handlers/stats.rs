@@ -0,0 +1,296 @@
+//! Handle statistics about the recent activity seen by the server.
+//!
+//! Statistics are properties of the server actor, so they are served only
+//! under the server actor's ID.
+
+use anyhow::Result;
+use axum::extract::{Json, Path, Query, State};
+use chatternet::didkey::{actor_id_from_did, did_from_jwk};
+use chatternet::model::{CollectionFields, CollectionType, Uri};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use super::error::AppError;
+use super::AppState;
+use crate::db::{self, StatsKind};
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum StatsWindow {
+    Hour,
+    Day,
+}
+
+impl StatsWindow {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+
+    fn millis(&self) -> i64 {
+        match self {
+            Self::Hour => 60 * 60 * 1000,
+            Self::Day => 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsQuery {
+    window: Option<StatsWindow>,
+    page_size: Option<u64>,
+}
+
+/// An item in a statistics collection, and the number of messages counted
+/// for it.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsItem {
+    pub id: String,
+    pub count: u64,
+}
+
+/// How often the counts too old to be served should be pruned.
+pub const STATS_PRUNE_PERIOD: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// Delete the counts older than the longest statistics window.
+pub async fn prune_stats(state: &AppState) -> Result<(), AppError> {
+    let mut connector = state.connector.write().await;
+    let mut connection = connector
+        .connection_mut()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    db::delete_stats_before(
+        &mut *connection,
+        Utc::now().timestamp_millis() - StatsWindow::Day.millis(),
+    )
+    .await
+    .map_err(|_| AppError::DbQueryFailed)?;
+    Ok(())
+}
+
+async fn get_stats_collection(
+    AppState { connector, jwk, .. }: AppState,
+    did: &str,
+    kind: StatsKind,
+    query: StatsQuery,
+) -> Result<CollectionFields<StatsItem>, AppError> {
+    let server_did = did_from_jwk(&jwk).map_err(|_| AppError::ServerMisconfigured)?;
+    if did != server_did {
+        Err(AppError::ActorNotKnown)?;
+    }
+    let server_actor_id =
+        actor_id_from_did(&server_did).map_err(|_| AppError::ServerMisconfigured)?;
+    let window = query.window.unwrap_or(StatsWindow::Day);
+    let page_size = query.page_size.unwrap_or(32);
+    let collection_id = Uri::try_from(format!(
+        "{}/stats/{}?window={}",
+        server_actor_id,
+        match kind {
+            StatsKind::Tag => "tags",
+            StatsKind::Author => "actors",
+        },
+        window.as_str()
+    ))
+    .map_err(|_| AppError::ServerMisconfigured)?;
+
+    let connector = connector.read().await;
+    let mut connection = connector
+        .connection()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    let now = Utc::now().timestamp_millis();
+    let items = db::get_stats_top(&mut connection, kind, now - window.millis(), now, page_size)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?
+        .into_iter()
+        .map(|(id, count)| StatsItem { id, count })
+        .collect();
+    Ok(CollectionFields::new(
+        collection_id,
+        CollectionType::OrderedCollection,
+        items,
+    ))
+}
+
+/// Get the tags most used as audiences of the notes created in the window.
+pub async fn handle_stats_tags(
+    State(state): State<AppState>,
+    Path(did): Path<String>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<CollectionFields<StatsItem>>, AppError> {
+    Ok(Json(
+        get_stats_collection(state, &did, StatsKind::Tag, query).await?,
+    ))
+}
+
+/// Get the actors which created the most notes in the window.
+pub async fn handle_stats_actors(
+    State(state): State<AppState>,
+    Path(did): Path<String>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<CollectionFields<StatsItem>>, AppError> {
+    Ok(Json(
+        get_stats_collection(state, &did, StatsKind::Author, query).await?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode;
+    use chatternet::didkey::build_jwk;
+    use chatternet::model::{ActivityType, Collection, Message, MessageBuilder};
+    use chrono::Duration;
+    use tokio;
+    use tower::ServiceExt;
+
+    use super::super::test_utils::*;
+    use super::*;
+
+    #[tokio::test]
+    async fn api_stats_counts_tags_and_actors() {
+        let jwk_server = build_jwk(&mut rand::thread_rng()).unwrap();
+        let api = build_test_api_jwk(jwk_server.clone()).await;
+        let did_server = did_from_jwk(&jwk_server).unwrap();
+
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
+
+        let mut messages = Vec::new();
+        for (jwk, did, object_id, tag_id) in [
+            (&jwk_1, &did_1, "id:1", "urn:cid:a"),
+            (&jwk_1, &did_1, "id:2", "urn:cid:b"),
+            (&jwk_2, &did_2, "id:3", "urn:cid:b"),
+        ] {
+            let message =
+                build_message(jwk, object_id, Some(vec![format!("{}/followers", tag_id)])).await;
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did),
+                    &message,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            messages.push(message);
+        }
+
+        let get_stats = |path: String| {
+            let api = api.clone();
+            async move {
+                let response = api.oneshot(request_empty("GET", &path)).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let stats: CollectionFields<StatsItem> = get_body(response).await;
+                stats
+                    .items()
+                    .iter()
+                    .map(|x| (x.id.clone(), x.count))
+                    .collect::<Vec<(String, u64)>>()
+            }
+        };
+
+        assert_eq!(
+            get_stats(format!("/api/{}/actor/stats/tags?window=hour", did_server)).await,
+            [("urn:cid:b".to_string(), 2), ("urn:cid:a".to_string(), 1)]
+        );
+        assert_eq!(
+            get_stats(format!("/api/{}/actor/stats/actors", did_server)).await,
+            [
+                (format!("{}/actor", did_1), 2),
+                (format!("{}/actor", did_2), 1)
+            ]
+        );
+
+        // deleting a message removes it from the counts
+        let delete = build_message_with_type(
+            &jwk_1,
+            ActivityType::Delete,
+            messages[1].id().as_str(),
+            None,
+        )
+        .await;
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did_1),
+                &delete,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            get_stats(format!("/api/{}/actor/stats/tags", did_server)).await,
+            [("urn:cid:a".to_string(), 1), ("urn:cid:b".to_string(), 1)]
+        );
+
+        // only the server serves statistics
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/stats/tags", did_1),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn api_stats_counts_message_published_ahead_now() {
+        let jwk_server = build_jwk(&mut rand::thread_rng()).unwrap();
+        let api = build_test_api_jwk(jwk_server.clone()).await;
+        let did_server = did_from_jwk(&jwk_server).unwrap();
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+
+        let message = MessageBuilder::new(
+            &jwk,
+            ActivityType::Create,
+            vec!["id:1".try_into().unwrap()].try_into().unwrap(),
+        )
+        .to(vec!["urn:cid:a/followers".try_into().unwrap()]
+            .try_into()
+            .unwrap())
+        .published(Utc::now() + Duration::days(1))
+        .build()
+        .await
+        .unwrap();
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/actor/stats/tags?window=hour", did_server),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stats: CollectionFields<StatsItem> = get_body(response).await;
+        assert_eq!(
+            stats.items(),
+            &vec![StatsItem {
+                id: "urn:cid:a".to_string(),
+                count: 1
+            }]
+        );
+    }
+}
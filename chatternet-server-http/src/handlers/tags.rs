@@ -0,0 +1,200 @@
+//! Handle collections of documents linked to tags.
+
+use anyhow::Result;
+use axum::extract::{Json, Path, Query, State};
+use chatternet::model::{CollectionPageFields, CollectionPageType, Uri};
+
+use super::error::AppError;
+use super::{AppState, CollectionPageQuery};
+use crate::db::{self, CollectionPageOut};
+
+fn build_notes_page(
+    notes_out: Option<CollectionPageOut>,
+    tag_id: &str,
+    start_idx: Option<u64>,
+    page_size: u64,
+) -> Result<CollectionPageFields<String>, AppError> {
+    let (notes_id, start_idx, next_start_idx) = match notes_out {
+        Some(CollectionPageOut {
+            items,
+            low_idx,
+            high_idx,
+        }) => {
+            let start_idx = start_idx.unwrap_or(high_idx);
+            let next_start_idx = if low_idx > 0 && low_idx <= start_idx {
+                Some(low_idx - 1)
+            } else {
+                None
+            };
+            (items, start_idx, next_start_idx)
+        }
+        None => (vec![], 0, None),
+    };
+    let page_id = |start_idx: u64| {
+        Uri::try_from(format!(
+            "{}/notes?startIdx={}&pageSize={}",
+            tag_id, start_idx, page_size
+        ))
+        .map_err(|_| AppError::DocumentIdWrong)
+    };
+    let collection_id =
+        Uri::try_from(format!("{}/notes", tag_id)).map_err(|_| AppError::DocumentIdWrong)?;
+    Ok(CollectionPageFields::new(
+        page_id(start_idx)?,
+        CollectionPageType::OrderedCollectionPage,
+        notes_id,
+        collection_id,
+        next_start_idx.map(page_id).transpose()?,
+    ))
+}
+
+/// Get the page of notes whose content has a hashtag for the tag with ID
+/// `id`, most recent first.
+pub async fn handle_tag_notes(
+    State(AppState { connector, .. }): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<CollectionPageQuery>,
+) -> Result<Json<CollectionPageFields<String>>, AppError> {
+    let page_size = query.page_size.unwrap_or(32);
+    let connector = connector.read().await;
+    let mut connection = connector
+        .connection()
+        .await
+        .map_err(|_| AppError::DbConnectionFailed)?;
+    let notes_out = db::get_tag_notes(&mut connection, &id, page_size, query.start_idx)
+        .await
+        .map_err(|_| AppError::DbQueryFailed)?;
+    Ok(Json(build_notes_page(
+        notes_out,
+        &id,
+        query.start_idx,
+        page_size,
+    )?))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode;
+    use chatternet::didkey::{build_jwk, did_from_jwk};
+    use chatternet::model::{
+        ActivityType, CollectionPage, Document, NoteMd1kFields, Tag30Fields, PUBLIC_AUDIENCE_ID,
+    };
+    use tokio;
+    use tower::ServiceExt;
+
+    use super::super::test_utils::*;
+    use super::*;
+
+    #[tokio::test]
+    async fn api_tag_notes_returns_notes_with_hashtag() {
+        let api = build_test_api().await;
+
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+
+        let tag = Tag30Fields::new("abc".to_string()).await.unwrap();
+        let tag_followers = format!("{}/followers", tag.id().as_str());
+        let public = PUBLIC_AUDIENCE_ID.to_string();
+
+        let mut notes_id = Vec::new();
+        for (content, to) in [
+            ("about #abc", Some(tag_followers.as_str())),
+            ("about #def", Some(public.as_str())),
+            ("also #abc", Some("as:Public")),
+            // the note isn't addressed to the tag nor publicly
+            ("hidden #abc", None),
+        ] {
+            let note = NoteMd1kFields::new(
+                content.to_string(),
+                format!("{}/actor", did).try_into().unwrap(),
+                None,
+            )
+            .await
+            .unwrap();
+            let note_id = note.id().as_str().to_string();
+            let message = build_message(&jwk, &note_id, to.map(|x| vec![x.to_string()])).await;
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/api/{}/actor/outbox", did),
+                    &message,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let response = api
+                .clone()
+                .oneshot(request_json("POST", &format!("/api/{}", note_id), &note))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            notes_id.push(note_id);
+        }
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/notes?pageSize=1", tag.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page: CollectionPageFields<String> = get_body(response).await;
+        assert_eq!(page.items(), &[notes_id[2].clone()]);
+        let next = page.next().as_ref().unwrap().as_str();
+        assert_eq!(
+            next,
+            format!("{}/notes?startIdx=2&pageSize=1", tag.id().as_str())
+        );
+
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/notes?startIdx=2&pageSize=1", tag.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page: CollectionPageFields<String> = get_body(response).await;
+        assert_eq!(page.items(), &[notes_id[0].clone()]);
+
+        // the note not shared with the tag isn't listed
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/notes", tag.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page: CollectionPageFields<String> = get_body(response).await;
+        assert_eq!(page.items(), &[notes_id[2].clone(), notes_id[0].clone()]);
+
+        // a deleted note is no longer listed
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/api/{}/actor/outbox", did),
+                &build_message_with_type(&jwk, ActivityType::Delete, &notes_id[2], None).await,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = api
+            .clone()
+            .oneshot(request_empty(
+                "GET",
+                &format!("/api/{}/notes", tag.id().as_str()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page: CollectionPageFields<String> = get_body(response).await;
+        assert_eq!(page.items(), &[notes_id[0].clone()]);
+    }
+}
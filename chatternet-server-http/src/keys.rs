@@ -0,0 +1,193 @@
+//! Read and write the key files of server actors.
+//!
+//! Key files are encrypted with a passphrase (see [`chatternet::keystore`]),
+//! which is read from an environment variable when set, and is otherwise
+//! prompted for on the terminal. Plain key files are still read.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Error, Result};
+use chatternet::keystore::{encrypt_key, KdfParams, KeyFile};
+use ssi::jwk::JWK;
+
+/// Variable holding the passphrase used to unlock key files.
+pub const PASSPHRASE_ENV: &str = "CHATTERNET_KEY_PASSPHRASE";
+/// Variable holding the passphrase used to encrypt new key files.
+pub const NEW_PASSPHRASE_ENV: &str = "CHATTERNET_NEW_KEY_PASSPHRASE";
+/// Variable holding the mnemonic from which to rebuild a key.
+pub const MNEMONIC_ENV: &str = "CHATTERNET_MNEMONIC";
+/// Variable holding the passphrase of the mnemonic.
+pub const MNEMONIC_PASSPHRASE_ENV: &str = "CHATTERNET_MNEMONIC_PASSPHRASE";
+
+/// Get the passphrase to unlock the key file at `path`.
+pub fn read_passphrase(path: &Path) -> Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    Ok(rpassword::prompt_password(format!(
+        "Passphrase for {}: ",
+        path.display()
+    ))?)
+}
+
+/// Get the passphrase with which to encrypt a key file.
+pub fn read_new_passphrase() -> Result<String> {
+    if let Ok(passphrase) = env::var(NEW_PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("New passphrase: ")?;
+    if passphrase.is_empty() {
+        Err(Error::msg("passphrase is empty"))?;
+    }
+    if rpassword::prompt_password("Repeat new passphrase: ")? != passphrase {
+        Err(Error::msg("passphrases don't match"))?;
+    }
+    Ok(passphrase)
+}
+
+/// Get the mnemonic from which to rebuild a key.
+pub fn read_mnemonic() -> Result<String> {
+    if let Ok(mnemonic) = env::var(MNEMONIC_ENV) {
+        return Ok(mnemonic);
+    }
+    Ok(rpassword::prompt_password("Mnemonic: ")?)
+}
+
+/// Get the passphrase of a mnemonic, which can be empty. A new passphrase is
+/// prompted for twice.
+pub fn read_mnemonic_passphrase(new: bool) -> Result<String> {
+    if let Ok(passphrase) = env::var(MNEMONIC_PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let passphrase = rpassword::prompt_password("Mnemonic passphrase (empty for none): ")?;
+    if new && rpassword::prompt_password("Repeat mnemonic passphrase: ")? != passphrase {
+        Err(Error::msg("passphrases don't match"))?;
+    }
+    Ok(passphrase)
+}
+
+/// Read the key file at `path`, getting a passphrase from `passphrase` only
+/// if the file is encrypted.
+pub fn read_key_file_with(path: &Path, passphrase: impl FnOnce() -> Result<String>) -> Result<JWK> {
+    let contents = fs::read_to_string(path).map_err(|_| Error::msg("key does not exist"))?;
+    contents.parse::<KeyFile>()?.unlock(passphrase)
+}
+
+/// Read the key file at `path`, prompting for its passphrase if needed.
+pub fn read_key_file(path: &Path) -> Result<JWK> {
+    read_key_file_with(path, || read_passphrase(path))
+}
+
+/// Create a new key file at `path`, readable only by its owner.
+fn create_key_file(path: &Path) -> std::io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// Write `jwk` to the key file at `path`, encrypted if given a `passphrase`.
+/// Unless `overwrite`, fails if the file already exists. The file is
+/// readable only by its owner.
+pub fn write_key_file(
+    path: &Path,
+    jwk: &JWK,
+    passphrase: Option<&str>,
+    params: KdfParams,
+    overwrite: bool,
+) -> Result<()> {
+    let contents = match passphrase {
+        Some(passphrase) => serde_json::to_string_pretty(&KeyFile::Encrypted(encrypt_key(
+            jwk,
+            passphrase,
+            params,
+            &mut rand::thread_rng(),
+        )?))?,
+        None => serde_json::to_string(jwk)?,
+    };
+    if overwrite {
+        // write the new file aside then replace the old one, so that a failed
+        // write can't lose the key
+        let mut path_new = path.as_os_str().to_owned();
+        path_new.push(".new");
+        let path_new = Path::new(&path_new);
+        // a file left by a failed write may not be private
+        if path_new.exists() {
+            fs::remove_file(path_new)?;
+        }
+        create_key_file(path_new)?.write_all(contents.as_bytes())?;
+        fs::rename(path_new, path)?;
+    } else {
+        create_key_file(path)
+            .map_err(|_| {
+                Error::msg("unable to write a new key file, ensure no file already exists")
+            })?
+            .write_all(contents.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chatternet::didkey::{build_jwk, did_from_jwk};
+
+    use super::*;
+
+    const TEST_PARAMS: KdfParams = KdfParams {
+        memory_cost: 64,
+        time_cost: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn writes_and_reads_key_files() {
+        let dir = std::env::temp_dir().join(format!("keys-{}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+
+        // old plain files are read without a passphrase
+        let path = dir.join("plain.json");
+        write_key_file(&path, &jwk, None, TEST_PARAMS, false).unwrap();
+        let jwk_back = read_key_file_with(&path, || Err(Error::msg("no passphrase"))).unwrap();
+        assert_eq!(did_from_jwk(&jwk_back).unwrap(), did);
+
+        // migrating the file encrypts it in place
+        write_key_file(&path, &jwk, Some("abc"), TEST_PARAMS, false).unwrap_err();
+        write_key_file(&path, &jwk, Some("abc"), TEST_PARAMS, true).unwrap();
+        read_key_file_with(&path, || Err(Error::msg("no passphrase"))).unwrap_err();
+        read_key_file_with(&path, || Ok("abd".to_string())).unwrap_err();
+        let jwk_back = read_key_file_with(&path, || Ok("abc".to_string())).unwrap();
+        assert_eq!(did_from_jwk(&jwk_back).unwrap(), did);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_key_files_readable_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("keys-{}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let path = dir.join("key.json");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        write_key_file(&path, &jwk, None, TEST_PARAMS, false).unwrap();
+        assert_eq!(mode(&path), 0o600);
+        // a replaced file is also private, even if the old one wasn't
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_key_file(&path, &jwk, Some("abc"), TEST_PARAMS, true).unwrap();
+        assert_eq!(mode(&path), 0o600);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
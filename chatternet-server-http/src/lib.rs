@@ -1,2 +1,5 @@
 pub mod db;
 pub mod handlers;
+pub mod keys;
+pub mod relay;
+pub mod tenants;
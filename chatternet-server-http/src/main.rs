@@ -1,18 +1,24 @@
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use anyhow::{Error, Result};
 use axum;
-use chatternet::didkey::did_from_actor_id;
-use chatternet::model::{Actor, ActorFields, Document};
+use chatternet::cache::VerificationCache;
+use chatternet::model::{ActorFields, Document};
+use chatternet::resolver::Resolver;
 use clap::Parser;
 use serde_json;
 use tokio;
 use tokio::sync::RwLock;
 
 use chatternet_server_http::db::{self, Connector};
-use chatternet_server_http::handlers::{build_api, AppState};
+use chatternet_server_http::handlers::{flush_relay, prune_stats, AppState, STATS_PRUNE_PERIOD};
+use chatternet_server_http::relay::{Relay, RelayPolicy};
+use chatternet_server_http::tenants::{
+    build_tenants_api, link_relay_peers, parse_actor_url, Tenant, TenantConfig, TenantsConfig,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -23,43 +29,29 @@ struct Args {
     path_db: PathBuf,
     #[arg(short = 'l')]
     loopback: bool,
+    /// JSON file with the policy for relaying messages to the server followers
+    #[arg(short = 'r')]
+    path_relay_policy: Option<PathBuf>,
+    /// Serve inboxes from a table updated as messages are written. Without
+    /// this, the table is dropped from the DB.
+    #[arg(short = 'i')]
+    materialized_inbox: bool,
+    /// Number of verified messages and documents to remember
+    #[arg(short = 'c', default_value_t = 4096)]
+    verification_cache_size: usize,
+    /// JSON file listing other server actors to host, as edited by the
+    /// `tenants` command
+    #[arg(short = 't')]
+    path_tenants: Option<PathBuf>,
 }
 
-struct ParsedUrl {
-    did: String,
-    prefix: String,
-}
-
-fn parse_actor_url(actor: &(impl Actor + Document)) -> Result<ParsedUrl> {
-    let actor_id = actor.id().as_str();
-    let slash_actor_id = format!("/{}", actor_id);
-    let actor_url = actor
-        .url()
-        .as_ref()
-        .ok_or(Error::msg("server actor has no URL"))?
-        .as_str();
-    let actor_url = if actor_url.starts_with("https://") {
-        &actor_url["https://".len()..]
-    } else if actor_url.starts_with("http://") {
-        &actor_url["http://".len()..]
-    } else {
-        Err(Error::msg("actor URL is not an HTTP endpoint"))?
-    };
-    if !actor_url.ends_with(&slash_actor_id) {
-        Err(Error::msg("actor URL is not a path to the actor ID"))?;
-    }
-    let actor_url = &actor_url[..actor_url.len() - slash_actor_id.len()];
-    let prefix = match actor_url.split_once('/') {
-        Some((_, prefix)) => prefix,
-        None => "",
-    }
-    .to_string();
-    let did = did_from_actor_id(actor_id)?;
-    Ok(ParsedUrl { did, prefix })
-}
-
-async fn store_actor(actor: &ActorFields, connector: Arc<RwLock<Connector>>) -> Result<()> {
-    actor.verify().await?;
+async fn store_actor(
+    actor: &ActorFields,
+    connector: Arc<RwLock<Connector>>,
+    cache: &VerificationCache,
+    resolver: &Resolver,
+) -> Result<()> {
+    actor.verify_cached_with(cache, resolver).await?;
     let mut connector = connector.write().await;
     let mut connection = connector.connection_mut().await?;
     db::put_document(
@@ -71,29 +63,132 @@ async fn store_actor(actor: &ActorFields, connector: Arc<RwLock<Connector>>) ->
     Ok(())
 }
 
+/// Get the connector to the DB at `path_db`, opening it if no other tenant
+/// already has.
+///
+/// Tenants can name the same DB by different paths, so connectors are keyed
+/// by the canonical path of the DB, which is created if it doesn't exist.
+async fn get_connector(
+    path_db: &PathBuf,
+    connectors: &mut HashMap<PathBuf, Arc<RwLock<Connector>>>,
+    materialized_inbox: bool,
+) -> Result<Arc<RwLock<Connector>>> {
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path_db)?;
+    let path_db = fs::canonicalize(path_db)?;
+    if let Some(connector) = connectors.get(&path_db) {
+        return Ok(connector.clone());
+    }
+    let mut connector = Connector::new(&format!(
+        "sqlite:{}",
+        path_db.to_str().ok_or(Error::msg("invalid DB path"))?
+    ))
+    .await?;
+    if materialized_inbox {
+        connector.use_materialized_inbox().await?;
+    } else {
+        connector.drop_materialized_inbox().await?;
+    }
+    let connector = Arc::new(RwLock::new(connector));
+    connectors.insert(path_db, connector.clone());
+    Ok(connector)
+}
+
+async fn load_tenant(
+    config: &TenantConfig,
+    args: &Args,
+    connectors: &mut HashMap<PathBuf, Arc<RwLock<Connector>>>,
+    cache: Arc<VerificationCache>,
+    resolver: Arc<Resolver>,
+) -> Result<Tenant> {
+    let (actor, jwk) = config.read()?;
+    tracing::info!("{}", serde_json::to_string_pretty(&actor)?);
+
+    let path_db = config.path_db.as_ref().unwrap_or(&args.path_db);
+    let connector = get_connector(path_db, connectors, args.materialized_inbox).await?;
+    store_actor(&actor, connector.clone(), &cache, &resolver).await?;
+    let relay_policy: RelayPolicy = match &config.path_relay_policy {
+        Some(path) => serde_json::from_slice(&fs::read(path)?)?,
+        None => RelayPolicy::default(),
+    };
+    let state = AppState {
+        relay: Arc::new(Relay::new(relay_policy)),
+        cache,
+        resolver,
+        ..AppState::new(connector, jwk)
+    };
+
+    let parsed_url = parse_actor_url(&actor)?;
+    Ok(Tenant {
+        state,
+        prefix: parsed_url.prefix,
+        did: parsed_url.did,
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
     tracing_subscriber::fmt::init();
 
-    let actor: ActorFields = serde_json::from_slice(&fs::read(args.path_actor)?)?;
-    tracing::info!("{}", serde_json::to_string_pretty(&actor)?);
+    let mut configs = vec![TenantConfig {
+        path_actor: args.path_actor.clone(),
+        path_key: args.path_key.clone(),
+        path_db: None,
+        path_relay_policy: args.path_relay_policy.clone(),
+    }];
+    if let Some(path) = &args.path_tenants {
+        configs.extend(TenantsConfig::load(path)?.tenants);
+    }
 
-    let connector = Arc::new(RwLock::new(
-        Connector::new(&format!(
-            "sqlite:{}",
-            args.path_db.to_str().ok_or(Error::msg("invalid DB path"))?
-        ))
-        .await?,
-    ));
-    store_actor(&actor, connector.clone()).await?;
-    let jwk = Arc::new(serde_json::from_str(&fs::read_to_string(&args.path_key)?)?);
-    let state = AppState { connector, jwk };
+    let cache = Arc::new(VerificationCache::new(args.verification_cache_size));
+    let resolver = Arc::new(Resolver::default());
+    let mut connectors = HashMap::new();
+    let mut tenants = Vec::new();
+    for config in configs.iter() {
+        tenants.push(
+            load_tenant(
+                config,
+                &args,
+                &mut connectors,
+                cache.clone(),
+                resolver.clone(),
+            )
+            .await?,
+        );
+    }
 
-    let parsed_url = parse_actor_url(&actor)?;
+    link_relay_peers(&mut tenants);
+
+    for tenant in tenants.iter() {
+        let state = tenant.state.clone();
+        if let Some(period) = state.relay.flush_period() {
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(period);
+                loop {
+                    interval.tick().await;
+                    if let Err(error) = flush_relay(&state, false).await {
+                        tracing::warn!("failed to flush relay: {:?}", error);
+                    }
+                }
+            });
+        }
+        let state = tenant.state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STATS_PRUNE_PERIOD);
+            loop {
+                interval.tick().await;
+                if let Err(error) = prune_stats(&state).await {
+                    tracing::warn!("failed to prune stats: {:?}", error);
+                }
+            }
+        });
+    }
 
-    let app = build_api(state, &parsed_url.prefix, &parsed_url.did);
+    let app = build_tenants_api(&tenants)?;
     let address = if args.loopback {
         "127.0.0.1"
     } else {
@@ -102,46 +197,18 @@ async fn main() -> Result<()> {
 
     axum::Server::bind(&format!("{}:{}", address, args.port).parse().unwrap())
         .serve(app.into_make_service())
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
         .await
         .unwrap();
 
-    Ok(())
-}
-
-#[cfg(test)]
-mod test {
-    use chatternet::{
-        didkey::{actor_id_from_did, build_jwk, did_from_jwk},
-        model::ActorType,
-    };
-
-    use super::*;
-
-    #[tokio::test]
-    async fn parses_actor_url_no_prefix() {
-        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
-        let did = did_from_jwk(&jwk).unwrap();
-        let actor_id = actor_id_from_did(&did).unwrap();
-        let url = format!("https://abc.example/{}", actor_id);
-        let actor = ActorFields::new(&jwk, ActorType::Service, None, Some(url))
-            .await
-            .unwrap();
-        let parsed_url = parse_actor_url(&actor).unwrap();
-        assert_eq!(parsed_url.did, did);
-        assert_eq!(parsed_url.prefix, "");
+    // send the batches still waiting to be filled rather than drop them
+    for tenant in tenants.iter() {
+        if let Err(error) = flush_relay(&tenant.state, true).await {
+            tracing::warn!("failed to flush relay: {:?}", error);
+        }
     }
 
-    #[tokio::test]
-    async fn parses_actor_url_with_prefix() {
-        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
-        let did = did_from_jwk(&jwk).unwrap();
-        let actor_id = actor_id_from_did(&did).unwrap();
-        let url = format!("https://abc.example/a/b/{}", actor_id);
-        let actor = ActorFields::new(&jwk, ActorType::Service, None, Some(url))
-            .await
-            .unwrap();
-        let parsed_url = parse_actor_url(&actor).unwrap();
-        assert_eq!(parsed_url.did, did);
-        assert_eq!(parsed_url.prefix, "a/b");
-    }
+    Ok(())
 }
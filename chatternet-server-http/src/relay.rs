@@ -0,0 +1,583 @@
+//! Decide which messages the server actor relays to its followers.
+//!
+//! When a message reaches the server actor's inbox, the server can sign a
+//! `View` message for its objects and address it to its own followers. The
+//! [`RelayPolicy`] decides which messages are relayed, how many can be relayed
+//! in a time window, and how many objects are batched into each `View`.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chatternet::model::{ActivityType, Message, MessageFields, Uri};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The maximum number of objects in a single `View` message.
+const MAX_BATCH_SIZE: usize = 256;
+/// The maximum number of audiences of a single `View` message.
+const MAX_AUDIENCES: usize = 256;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum RelayAction {
+    Relay,
+    Skip,
+}
+
+/// A rule matching messages to a relay action.
+///
+/// A rule matches a message when every non-empty criterion matches it. A
+/// criterion matches when any of its values matches.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayRule {
+    pub action: RelayAction,
+    /// IDs of the actors authoring the message.
+    #[serde(default)]
+    pub authors: Vec<String>,
+    /// IDs of the audiences the message is addressed to.
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    /// IDs of the tags whose followers the message is addressed to.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Types of the message activity.
+    #[serde(default)]
+    pub types: Vec<ActivityType>,
+}
+
+impl RelayRule {
+    pub fn matches(&self, message: &MessageFields) -> bool {
+        let audiences: Vec<&str> = message
+            .to()
+            .as_ref()
+            .map(|x| x.iter().map(|x| x.as_str()).collect())
+            .unwrap_or_default();
+        if !self.authors.is_empty() && !self.authors.iter().any(|x| x == message.actor().as_str()) {
+            return false;
+        }
+        if !self.audiences.is_empty()
+            && !audiences
+                .iter()
+                .any(|x| self.audiences.iter().any(|y| y == x))
+        {
+            return false;
+        }
+        if !self.tags.is_empty()
+            && !audiences
+                .iter()
+                .filter_map(|x| x.strip_suffix("/followers"))
+                .any(|x| self.tags.iter().any(|y| y == x))
+        {
+            return false;
+        }
+        if !self.types.is_empty() && !self.types.contains(&message.type_()) {
+            return false;
+        }
+        true
+    }
+}
+
+/// Limits on the number of messages relayed in a time window.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayRateCap {
+    pub window_seconds: u64,
+    /// Maximum number of messages relayed in a window.
+    pub max_messages: Option<u64>,
+    /// Maximum number of messages from any one author relayed in a window.
+    pub max_messages_per_author: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayPolicy {
+    /// Relay no messages at all when `false`.
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Rules evaluated in order, the first matching rule decides the action.
+    #[serde(default)]
+    pub rules: Vec<RelayRule>,
+    /// The action when no rule matches.
+    #[serde(default = "default_action")]
+    pub default_action: RelayAction,
+    #[serde(default)]
+    pub rate_cap: Option<RelayRateCap>,
+    /// Number of objects to collect before signing a `View` for all of them.
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Longest time objects can wait for a batch to fill before it is sent.
+    #[serde(default = "default_batch_max_age_seconds")]
+    pub batch_max_age_seconds: u64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_action() -> RelayAction {
+    RelayAction::Relay
+}
+
+fn default_batch_size() -> usize {
+    1
+}
+
+fn default_batch_max_age_seconds() -> u64 {
+    60
+}
+
+impl Default for RelayPolicy {
+    fn default() -> Self {
+        RelayPolicy {
+            enabled: default_enabled(),
+            rules: vec![],
+            default_action: default_action(),
+            rate_cap: None,
+            batch_size: default_batch_size(),
+            batch_max_age_seconds: default_batch_max_age_seconds(),
+        }
+    }
+}
+
+impl RelayPolicy {
+    /// A policy which relays no messages.
+    pub fn disabled() -> Self {
+        RelayPolicy {
+            enabled: false,
+            ..Default::default()
+        }
+    }
+
+    /// Decide the action for `message` based only on the policy rules.
+    pub fn decide(&self, message: &MessageFields) -> RelayAction {
+        if !self.enabled {
+            return RelayAction::Skip;
+        }
+        self.rules
+            .iter()
+            .find(|x| x.matches(message))
+            .map_or(self.default_action, |x| x.action)
+    }
+
+    fn batch_size(&self) -> usize {
+        self.batch_size.clamp(1, MAX_BATCH_SIZE)
+    }
+}
+
+/// The content of a `View` message to be signed by the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayBatch {
+    /// The viewed objects.
+    pub objects: Vec<Uri>,
+    /// The messages from which the objects were viewed.
+    pub origins: Vec<Uri>,
+    /// The audiences of the messages from which the objects were viewed.
+    /// Only messages with the same audiences are batched together, so that a
+    /// `View` never reaches an audience the viewed messages weren't sent to.
+    pub audiences: Vec<Uri>,
+}
+
+impl RelayBatch {
+    fn new() -> Self {
+        RelayBatch {
+            objects: vec![],
+            origins: vec![],
+            audiences: vec![],
+        }
+    }
+
+    fn add(&mut self, message: &MessageFields) {
+        self.objects.extend(message.object().iter().cloned());
+        self.origins.push(message.id().clone());
+        if self.audiences.is_empty() {
+            if let Some(to) = message.to() {
+                self.audiences.extend(to.iter().cloned());
+            }
+        }
+    }
+}
+
+/// The key grouping the messages with the same audiences into one batch.
+fn audiences_key(message: &MessageFields) -> Vec<String> {
+    let mut key: Vec<String> = message
+        .to()
+        .as_ref()
+        .map(|x| x.iter().map(|x| x.to_string()).collect())
+        .unwrap_or_default();
+    key.sort();
+    key.dedup();
+    key
+}
+
+#[derive(Debug, Default)]
+struct RelayState {
+    window_start: Option<DateTime<Utc>>,
+    window_count: u64,
+    window_count_by_author: HashMap<String, u64>,
+    /// Batches waiting to be filled, keyed by the audiences of their messages.
+    pending: HashMap<Vec<String>, (DateTime<Utc>, RelayBatch)>,
+}
+
+/// Applies a [`RelayPolicy`] to the messages seen by the server, keeping
+/// track of rates and pending batches.
+#[derive(Debug, Default)]
+pub struct Relay {
+    policy: RelayPolicy,
+    state: Mutex<RelayState>,
+}
+
+impl Relay {
+    pub fn new(policy: RelayPolicy) -> Self {
+        Relay {
+            policy,
+            state: Mutex::new(RelayState::default()),
+        }
+    }
+
+    pub fn policy(&self) -> &RelayPolicy {
+        &self.policy
+    }
+
+    /// How often pending batches should be checked, if batching is used.
+    pub fn flush_period(&self) -> Option<std::time::Duration> {
+        if !self.policy.enabled || self.policy.batch_size() <= 1 {
+            return None;
+        }
+        Some(std::time::Duration::from_secs(
+            self.policy.batch_max_age_seconds.max(1),
+        ))
+    }
+
+    /// Check the rate caps and count `message` against them if it can be
+    /// relayed at time `now`.
+    fn use_rate(
+        &self,
+        state: &mut RelayState,
+        message: &MessageFields,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let rate_cap = match &self.policy.rate_cap {
+            Some(rate_cap) => rate_cap,
+            None => return true,
+        };
+        let window = Duration::seconds(rate_cap.window_seconds as i64);
+        if state.window_start.map_or(true, |x| now - x >= window) {
+            state.window_start = Some(now);
+            state.window_count = 0;
+            state.window_count_by_author.clear();
+        }
+        let author = message.actor().as_str();
+        let author_count = state
+            .window_count_by_author
+            .get(author)
+            .copied()
+            .unwrap_or(0);
+        if rate_cap
+            .max_messages
+            .map_or(false, |x| state.window_count >= x)
+            || rate_cap
+                .max_messages_per_author
+                .map_or(false, |x| author_count >= x)
+        {
+            return false;
+        }
+        state.window_count += 1;
+        state
+            .window_count_by_author
+            .insert(author.to_string(), author_count + 1);
+        true
+    }
+
+    /// Offer `message` to be relayed at time `now`.
+    ///
+    /// Returns the batches which are ready to be sent as a result.
+    pub fn offer(&self, message: &MessageFields, now: DateTime<Utc>) -> Vec<RelayBatch> {
+        if self.policy.decide(message) != RelayAction::Relay {
+            return vec![];
+        }
+        // the `View` is also addressed to the server followers, which must fit
+        // alongside the message audiences
+        if message.to().as_ref().map_or(0, |x| x.len()) >= MAX_AUDIENCES {
+            return vec![];
+        }
+        let mut state = self.state.lock().unwrap();
+        if !self.use_rate(&mut state, message, now) {
+            return vec![];
+        }
+        let batch_size = self.policy.batch_size();
+        let mut ready = vec![];
+        let key = audiences_key(message);
+        // the objects of one message are never split across batches, so a
+        // batch which can't hold them is sent before it reaches the cap
+        if let Some((_, pending)) = state.pending.get(&key) {
+            if pending.objects.len() + message.object().len() > MAX_BATCH_SIZE {
+                ready.push(state.pending.remove(&key).unwrap().1);
+            }
+        }
+        let (_, pending) = state
+            .pending
+            .entry(key.clone())
+            .or_insert_with(|| (now, RelayBatch::new()));
+        pending.add(message);
+        if pending.objects.len() >= batch_size {
+            ready.push(state.pending.remove(&key).unwrap().1);
+        }
+        ready
+    }
+
+    /// Take the pending batches which have waited too long at time `now`, or
+    /// regardless of their age if `force` is set.
+    pub fn flush(&self, now: DateTime<Utc>, force: bool) -> Vec<RelayBatch> {
+        let mut state = self.state.lock().unwrap();
+        let max_age = Duration::seconds(self.policy.batch_max_age_seconds as i64);
+        let keys: Vec<Vec<String>> = state
+            .pending
+            .iter()
+            .filter(|(_, (since, _))| force || now - *since >= max_age)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut batches: Vec<(DateTime<Utc>, RelayBatch)> = keys
+            .iter()
+            .filter_map(|key| state.pending.remove(key))
+            .collect();
+        batches.sort_by_key(|(since, _)| *since);
+        batches.into_iter().map(|(_, x)| x).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chatternet::didkey::{actor_id_from_did, build_jwk, did_from_jwk};
+    use chatternet::model::MessageBuilder;
+    use ssi::jwk::JWK;
+    use tokio;
+
+    use super::*;
+
+    async fn build_message(
+        jwk: &JWK,
+        type_: ActivityType,
+        objects: &[&str],
+        to: &[&str],
+    ) -> MessageFields {
+        MessageBuilder::new(
+            jwk,
+            type_,
+            objects
+                .iter()
+                .map(|x| Uri::try_from(*x).unwrap())
+                .collect::<Vec<Uri>>()
+                .try_into()
+                .unwrap(),
+        )
+        .to(to
+            .iter()
+            .map(|x| Uri::try_from(*x).unwrap())
+            .collect::<Vec<Uri>>()
+            .try_into()
+            .unwrap())
+        .build()
+        .await
+        .unwrap()
+    }
+
+    fn actor_id(jwk: &JWK) -> String {
+        actor_id_from_did(&did_from_jwk(jwk).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn default_policy_relays_each_message() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let message = build_message(&jwk, ActivityType::Create, &["id:1"], &["tag:1"]).await;
+        let relay = Relay::default();
+        let batches = relay.offer(&message, Utc::now());
+        assert_eq!(
+            batches,
+            [RelayBatch {
+                objects: vec![Uri::try_from("id:1").unwrap()],
+                origins: vec![message.id().clone()],
+                audiences: vec![Uri::try_from("tag:1").unwrap()],
+            }]
+        );
+        assert!(relay.flush(Utc::now(), true).is_empty());
+    }
+
+    #[tokio::test]
+    async fn disabled_policy_relays_nothing() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let message = build_message(&jwk, ActivityType::Create, &["id:1"], &[]).await;
+        let relay = Relay::new(RelayPolicy::disabled());
+        assert_eq!(relay.policy().decide(&message), RelayAction::Skip);
+        assert!(relay.offer(&message, Utc::now()).is_empty());
+        assert!(relay.flush_period().is_none());
+    }
+
+    #[tokio::test]
+    async fn decides_by_rules() {
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let policy: RelayPolicy = serde_json::from_value(serde_json::json!({
+            "defaultAction": "Skip",
+            "rules": [
+                { "action": "Skip", "types": ["Like"] },
+                { "action": "Relay", "authors": [actor_id(&jwk_1)] },
+                { "action": "Relay", "tags": ["urn:cid:tag"] },
+                {
+                    "action": "Relay",
+                    "audiences": ["id:group"],
+                    "types": ["Create"]
+                },
+            ]
+        }))
+        .unwrap();
+
+        // matches the author rule
+        let message = build_message(&jwk_1, ActivityType::Create, &["id:1"], &[]).await;
+        assert_eq!(policy.decide(&message), RelayAction::Relay);
+        // matches the type rule before the author rule
+        let message = build_message(&jwk_1, ActivityType::Like, &["id:1"], &[]).await;
+        assert_eq!(policy.decide(&message), RelayAction::Skip);
+        // matches no rule
+        let message = build_message(&jwk_2, ActivityType::Create, &["id:1"], &["tag:x"]).await;
+        assert_eq!(policy.decide(&message), RelayAction::Skip);
+        // matches the tag rule
+        let message = build_message(
+            &jwk_2,
+            ActivityType::Create,
+            &["id:1"],
+            &["tag:x", "urn:cid:tag/followers"],
+        )
+        .await;
+        assert_eq!(policy.decide(&message), RelayAction::Relay);
+        // matches the audience rule only with all its criteria
+        let message = build_message(&jwk_2, ActivityType::Create, &["id:1"], &["id:group"]).await;
+        assert_eq!(policy.decide(&message), RelayAction::Relay);
+        let message = build_message(&jwk_2, ActivityType::Add, &["id:1"], &["id:group"]).await;
+        assert_eq!(policy.decide(&message), RelayAction::Skip);
+    }
+
+    #[tokio::test]
+    async fn caps_rates() {
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let relay = Relay::new(RelayPolicy {
+            rate_cap: Some(RelayRateCap {
+                window_seconds: 60,
+                max_messages: Some(3),
+                max_messages_per_author: Some(2),
+            }),
+            ..Default::default()
+        });
+        let message_1 = build_message(&jwk_1, ActivityType::Create, &["id:1"], &[]).await;
+        let message_2 = build_message(&jwk_2, ActivityType::Create, &["id:1"], &[]).await;
+        let now = Utc::now();
+        assert_eq!(relay.offer(&message_1, now).len(), 1);
+        assert_eq!(relay.offer(&message_1, now).len(), 1);
+        // capped for the author
+        assert!(relay.offer(&message_1, now).is_empty());
+        assert_eq!(relay.offer(&message_2, now).len(), 1);
+        // capped for all
+        assert!(relay.offer(&message_2, now).is_empty());
+        // next window
+        let now = now + Duration::seconds(60);
+        assert_eq!(relay.offer(&message_1, now).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn batches_objects() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let relay = Relay::new(RelayPolicy {
+            batch_size: 3,
+            batch_max_age_seconds: 10,
+            ..Default::default()
+        });
+        assert!(relay.flush_period().is_some());
+        let message_1 = build_message(&jwk, ActivityType::Create, &["id:1"], &["tag:1"]).await;
+        let message_2 =
+            build_message(&jwk, ActivityType::Create, &["id:2", "id:3"], &["tag:1"]).await;
+        let message_3 = build_message(&jwk, ActivityType::Create, &["id:4"], &[]).await;
+        let now = Utc::now();
+
+        assert!(relay.offer(&message_1, now).is_empty());
+        let batches = relay.offer(&message_2, now);
+        assert_eq!(
+            batches,
+            [RelayBatch {
+                objects: ["id:1", "id:2", "id:3"]
+                    .into_iter()
+                    .map(|x| Uri::try_from(x).unwrap())
+                    .collect(),
+                origins: vec![message_1.id().clone(), message_2.id().clone()],
+                audiences: vec![Uri::try_from("tag:1").unwrap()],
+            }]
+        );
+
+        // a partial batch is sent only once it is old enough
+        assert!(relay.offer(&message_3, now).is_empty());
+        assert!(relay.flush(now + Duration::seconds(9), false).is_empty());
+        let batches = relay.flush(now + Duration::seconds(10), false);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].origins, vec![message_3.id().clone()]);
+    }
+
+    #[tokio::test]
+    async fn batches_only_same_audiences() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let relay = Relay::new(RelayPolicy {
+            batch_size: 2,
+            ..Default::default()
+        });
+        let message_1 = build_message(&jwk, ActivityType::Create, &["id:1"], &["tag:1"]).await;
+        let message_2 = build_message(&jwk, ActivityType::Create, &["id:2"], &["tag:2"]).await;
+        let message_3 =
+            build_message(&jwk, ActivityType::Create, &["id:3"], &["tag:2", "tag:1"]).await;
+        let message_4 =
+            build_message(&jwk, ActivityType::Create, &["id:4"], &["tag:1", "tag:2"]).await;
+        let now = Utc::now();
+
+        // messages with different audiences wait in separate batches
+        assert!(relay.offer(&message_1, now).is_empty());
+        assert!(relay.offer(&message_2, now).is_empty());
+        assert!(relay.offer(&message_3, now).is_empty());
+        // the audiences match regardless of their order
+        let batches = relay.offer(&message_4, now);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(
+            batches[0].origins,
+            vec![message_3.id().clone(), message_4.id().clone()]
+        );
+
+        let batches = relay.flush(now, true);
+        assert_eq!(batches.len(), 2);
+        for batch in batches {
+            assert_eq!(batch.origins.len(), 1);
+            assert_eq!(batch.audiences.len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_batch_at_cap() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let relay = Relay::new(RelayPolicy {
+            batch_size: MAX_BATCH_SIZE,
+            ..Default::default()
+        });
+        let objects_1: Vec<String> = (0..MAX_BATCH_SIZE - 1)
+            .map(|x| format!("id:{}", x))
+            .collect();
+        let objects_1: Vec<&str> = objects_1.iter().map(|x| x.as_str()).collect();
+        let message_1 = build_message(&jwk, ActivityType::Create, &objects_1, &["tag:1"]).await;
+        let message_2 =
+            build_message(&jwk, ActivityType::Create, &["id:a", "id:b"], &["tag:1"]).await;
+        let now = Utc::now();
+
+        assert!(relay.offer(&message_1, now).is_empty());
+        // the full batch is sent rather than truncated
+        let batches = relay.offer(&message_2, now);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].objects.len(), MAX_BATCH_SIZE - 1);
+        let batches = relay.flush(now, true);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].objects.len(), 2);
+    }
+}
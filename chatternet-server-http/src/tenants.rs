@@ -0,0 +1,432 @@
+//! Host several server actors from one process.
+//!
+//! Each tenant is a server actor with its own key, relay policy and URL
+//! prefix. Tenants can store their data in their own DB or share one, as the
+//! data in a DB is already keyed by actor. The tenants are listed in a JSON
+//! file which the server reads at startup and which the admin commands edit.
+//!
+//! A message posted through any tenant of a shared DB is offered to the
+//! relay of every tenant of that DB. The views which aren't keyed by actor,
+//! the public and local messages and the statistics, cover the whole DB, so
+//! they are the same for all tenants sharing it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Error, Result};
+use axum::Router;
+use chatternet::didkey::{did_from_actor_id, did_from_jwk};
+use chatternet::model::{Actor, ActorFields, Document};
+use serde::{Deserialize, Serialize};
+use ssi::jwk::JWK;
+
+use crate::handlers::{build_api, AppState, RelayPeer};
+use crate::keys::read_key_file;
+
+pub struct ParsedUrl {
+    pub did: String,
+    pub prefix: String,
+}
+
+/// Get the DID and the URL prefix at which the server `actor` is served.
+pub fn parse_actor_url(actor: &(impl Actor + Document)) -> Result<ParsedUrl> {
+    let actor_id = actor.id().as_str();
+    let slash_actor_id = format!("/{}", actor_id);
+    let actor_url = actor
+        .url()
+        .as_ref()
+        .ok_or(Error::msg("server actor has no URL"))?
+        .as_str();
+    let actor_url = if actor_url.starts_with("https://") {
+        &actor_url["https://".len()..]
+    } else if actor_url.starts_with("http://") {
+        &actor_url["http://".len()..]
+    } else {
+        Err(Error::msg("actor URL is not an HTTP endpoint"))?
+    };
+    if !actor_url.ends_with(&slash_actor_id) {
+        Err(Error::msg("actor URL is not a path to the actor ID"))?;
+    }
+    let actor_url = &actor_url[..actor_url.len() - slash_actor_id.len()];
+    let prefix = match actor_url.split_once('/') {
+        Some((_, prefix)) => prefix,
+        None => "",
+    }
+    .to_string();
+    let did = did_from_actor_id(actor_id)?;
+    Ok(ParsedUrl { did, prefix })
+}
+
+/// The files describing a tenant.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantConfig {
+    pub path_actor: PathBuf,
+    pub path_key: PathBuf,
+    /// The tenant's own DB, or the server's shared DB when absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_db: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_relay_policy: Option<PathBuf>,
+}
+
+impl TenantConfig {
+    /// Read the tenant's actor, without its key.
+    pub fn read_actor(&self) -> Result<ActorFields> {
+        Ok(serde_json::from_slice(&fs::read(&self.path_actor)?)?)
+    }
+
+    /// Read the tenant's actor and key, ensuring the key is that of the actor.
+    /// Prompts for the passphrase of an encrypted key.
+    pub fn read(&self) -> Result<(ActorFields, JWK)> {
+        let actor = self.read_actor()?;
+        let jwk = read_key_file(&self.path_key)?;
+        if did_from_jwk(&jwk)? != did_from_actor_id(actor.id().as_str())? {
+            Err(Error::msg("key doesn't match the actor"))?;
+        }
+        Ok((actor, jwk))
+    }
+}
+
+/// The tenants hosted in addition to the server's main actor.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TenantsConfig {
+    pub tenants: Vec<TenantConfig>,
+}
+
+impl TenantsConfig {
+    /// Read the tenants from `path`, or none if there is no file at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Add the `tenant`, which must not share a DID or prefix with the
+    /// existing tenants. Only the key of the added tenant is read.
+    pub fn add(&mut self, tenant: TenantConfig) -> Result<()> {
+        let (actor, _) = tenant.read()?;
+        let parsed_url = parse_actor_url(&actor)?;
+        for other in self.tenants.iter() {
+            let other = other.read_actor()?;
+            let other = parse_actor_url(&other)?;
+            if other.did == parsed_url.did {
+                Err(Error::msg("a tenant already has this DID"))?;
+            }
+            if other.prefix == parsed_url.prefix {
+                Err(Error::msg("a tenant already has this prefix"))?;
+            }
+        }
+        self.tenants.push(tenant);
+        Ok(())
+    }
+
+    /// Remove the tenant whose actor has the DID `did`.
+    pub fn remove(&mut self, did: &str) -> Result<TenantConfig> {
+        let mut index = None;
+        for (i, tenant) in self.tenants.iter().enumerate() {
+            if did_from_actor_id(tenant.read_actor()?.id().as_str())? == did {
+                index = Some(i);
+            }
+        }
+        let index = index.ok_or(Error::msg("no tenant has this DID"))?;
+        Ok(self.tenants.remove(index))
+    }
+}
+
+/// A server actor and the state used to serve it.
+pub struct Tenant {
+    pub state: AppState,
+    pub prefix: String,
+    pub did: String,
+}
+
+/// Make each tenant relay the messages posted through the other tenants
+/// sharing its DB.
+pub fn link_relay_peers(tenants: &mut [Tenant]) {
+    let states = tenants
+        .iter()
+        .map(|x| x.state.clone())
+        .collect::<Vec<AppState>>();
+    for tenant in tenants.iter_mut() {
+        let peers = states
+            .iter()
+            .filter(|x| {
+                Arc::ptr_eq(&x.connector, &tenant.state.connector)
+                    && !Arc::ptr_eq(&x.jwk, &tenant.state.jwk)
+            })
+            .map(|x| RelayPeer {
+                jwk: x.jwk.clone(),
+                relay: x.relay.clone(),
+            })
+            .collect();
+        tenant.state.peers = Arc::new(peers);
+    }
+}
+
+/// Build the API serving every tenant under its own prefix.
+pub fn build_tenants_api(tenants: &[Tenant]) -> Result<Router> {
+    let mut router = Router::new();
+    for (i, tenant) in tenants.iter().enumerate() {
+        if tenants[..i].iter().any(|x| x.prefix == tenant.prefix) {
+            Err(Error::msg("tenants share a prefix"))?;
+        }
+        if tenants.len() > 1 && tenant.prefix.is_empty() {
+            Err(Error::msg("tenants must have a prefix"))?;
+        }
+        router = router.merge(build_api(tenant.state.clone(), &tenant.prefix, &tenant.did));
+    }
+    Ok(router)
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode;
+    use chatternet::didkey::{actor_id_from_did, build_jwk};
+    use chatternet::model::{ActorType, CollectionPageFields, Message, MessageFields};
+    use tokio;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::handlers::test_utils::*;
+    use crate::relay::RelayPolicy;
+
+    async fn build_actor(jwk: &JWK, prefix: &str) -> ActorFields {
+        let did = did_from_jwk(jwk).unwrap();
+        let actor_id = actor_id_from_did(&did).unwrap();
+        let url = format!("https://abc.example/{}/{}", prefix, actor_id);
+        ActorFields::new(jwk, ActorType::Service, None, Some(url))
+            .await
+            .unwrap()
+    }
+
+    async fn write_tenant(dir: &Path, name: &str, prefix: &str) -> (TenantConfig, String) {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let actor = build_actor(&jwk, prefix).await;
+        let config = TenantConfig {
+            path_actor: dir.join(format!("{}.actor.json", name)),
+            path_key: dir.join(format!("{}.key.json", name)),
+            path_db: None,
+            path_relay_policy: None,
+        };
+        fs::write(&config.path_actor, serde_json::to_string(&actor).unwrap()).unwrap();
+        fs::write(&config.path_key, serde_json::to_string(&jwk).unwrap()).unwrap();
+        (config, did_from_jwk(&jwk).unwrap())
+    }
+
+    #[tokio::test]
+    async fn parses_actor_url_no_prefix() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let actor_id = actor_id_from_did(&did).unwrap();
+        let url = format!("https://abc.example/{}", actor_id);
+        let actor = ActorFields::new(&jwk, ActorType::Service, None, Some(url))
+            .await
+            .unwrap();
+        let parsed_url = parse_actor_url(&actor).unwrap();
+        assert_eq!(parsed_url.did, did);
+        assert_eq!(parsed_url.prefix, "");
+    }
+
+    #[tokio::test]
+    async fn parses_actor_url_with_prefix() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let actor = build_actor(&jwk, "a/b").await;
+        let parsed_url = parse_actor_url(&actor).unwrap();
+        assert_eq!(parsed_url.did, did_from_jwk(&jwk).unwrap());
+        assert_eq!(parsed_url.prefix, "a/b");
+    }
+
+    #[tokio::test]
+    async fn adds_and_removes_tenants() {
+        let dir = std::env::temp_dir().join(format!("tenants-{}", rand::random::<u64>()));
+        fs::create_dir(&dir).unwrap();
+        let (config_a, did_a) = write_tenant(&dir, "a", "a").await;
+        let (config_b, did_b) = write_tenant(&dir, "b", "b").await;
+        let (config_c, _) = write_tenant(&dir, "c", "a").await;
+
+        let path = dir.join("tenants.json");
+        let mut tenants = TenantsConfig::load(&path).unwrap();
+        assert!(tenants.tenants.is_empty());
+        tenants.add(config_a.clone()).unwrap();
+        tenants.add(config_b.clone()).unwrap();
+        // can't add the same actor twice, or two actors at the same prefix
+        tenants.add(config_a.clone()).unwrap_err();
+        tenants.add(config_c).unwrap_err();
+        // can't add a key which isn't the actor's
+        tenants
+            .add(TenantConfig {
+                path_key: config_b.path_key.clone(),
+                ..config_a.clone()
+            })
+            .unwrap_err();
+        tenants.save(&path).unwrap();
+
+        // the keys of the existing tenants aren't read
+        fs::remove_file(&config_b.path_key).unwrap();
+        let (config_d, did_d) = write_tenant(&dir, "d", "d").await;
+        tenants.add(config_d).unwrap();
+        tenants.remove(&did_d).unwrap();
+
+        let mut tenants = TenantsConfig::load(&path).unwrap();
+        assert_eq!(tenants.tenants, [config_a.clone(), config_b.clone()]);
+        assert_eq!(tenants.remove(&did_a).unwrap(), config_a);
+        tenants.remove(&did_a).unwrap_err();
+        assert_eq!(tenants.tenants, [config_b]);
+        tenants.remove(&did_b).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_tenants_with_shared_db() {
+        let jwk_a = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_b = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_3 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_a = did_from_jwk(&jwk_a).unwrap();
+        let did_b = did_from_jwk(&jwk_b).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let did_2 = did_from_jwk(&jwk_2).unwrap();
+        let did_3 = did_from_jwk(&jwk_3).unwrap();
+
+        let state_a = build_test_state(jwk_a.clone(), RelayPolicy::default()).await;
+        let state_b = AppState::new(state_a.connector.clone(), jwk_b.clone());
+        let mut tenants = [
+            Tenant {
+                state: state_a,
+                prefix: "a".to_string(),
+                did: did_a.clone(),
+            },
+            Tenant {
+                state: state_b,
+                prefix: "b".to_string(),
+                did: did_b.clone(),
+            },
+        ];
+        link_relay_peers(&mut tenants);
+        let api = build_tenants_api(&tenants).unwrap();
+
+        // each tenant only serves its own server actor
+        for (prefix, did, status) in [
+            ("a", &did_a, StatusCode::OK),
+            ("b", &did_b, StatusCode::OK),
+            ("a", &did_b, StatusCode::NOT_FOUND),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(request_empty(
+                    "GET",
+                    &format!("/{}/{}/actor/stats/tags", prefix, did),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+
+        // both servers follow 1, 2 follows server b and 3 follows server a,
+        // each through its own tenant
+        for (prefix, did, message) in [
+            (
+                "b",
+                &did_b,
+                build_follow(vec![format!("{}/actor", did_1)], &jwk_b).await,
+            ),
+            (
+                "b",
+                &did_2,
+                build_follow(vec![format!("{}/actor", did_b)], &jwk_2).await,
+            ),
+            (
+                "a",
+                &did_a,
+                build_follow(vec![format!("{}/actor", did_1)], &jwk_a).await,
+            ),
+            (
+                "a",
+                &did_3,
+                build_follow(vec![format!("{}/actor", did_a)], &jwk_3).await,
+            ),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(request_json(
+                    "POST",
+                    &format!("/{}/{}/actor/outbox", prefix, did),
+                    &message,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        // 1 posts through tenant b, and each server relays the message with
+        // its key
+        let message = build_message(
+            &jwk_1,
+            "id:1",
+            Some(vec![format!("{}/actor/followers", did_1)]),
+        )
+        .await;
+        let response = api
+            .clone()
+            .oneshot(request_json(
+                "POST",
+                &format!("/b/{}/actor/outbox", did_1),
+                &message,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the shared DB is visible from both tenants
+        for (prefix, did, did_server) in [
+            ("a", &did_2, &did_b),
+            ("b", &did_2, &did_b),
+            ("a", &did_3, &did_a),
+            ("b", &did_3, &did_a),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(request_empty(
+                    "GET",
+                    &format!("/{}/{}/actor/inbox", prefix, did),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let inbox: CollectionPageFields<MessageFields> = get_body(response).await;
+            assert_eq!(
+                inbox
+                    .items()
+                    .iter()
+                    .map(|x| x.actor().as_str().to_string())
+                    .collect::<Vec<String>>(),
+                [actor_id_from_did(did_server).unwrap()]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn doesnt_build_tenants_with_same_prefix() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let state = build_test_state(jwk, RelayPolicy::default()).await;
+        let tenant = |prefix: &str| Tenant {
+            state: state.clone(),
+            prefix: prefix.to_string(),
+            did: "did:example:a".to_string(),
+        };
+        build_tenants_api(&[tenant("a"), tenant("a")]).unwrap_err();
+        build_tenants_api(&[tenant("a"), tenant("")]).unwrap_err();
+        build_tenants_api(&[tenant("")]).unwrap();
+    }
+}
@@ -0,0 +1,554 @@
+use std::sync::Arc;
+
+use chatternet::client::{server_error, Client, PageQuery, ServerErrorKind, StatsWindow};
+use chatternet::didkey::{actor_id_from_did, build_jwk, did_from_jwk};
+use chatternet::model::{
+    ActivityType, ActorFields, ActorType, Collection, CollectionPage, Document, Message,
+    MessageBuilder, MessageFields, NoteMd1kFields, Tag30Fields, Uri, PUBLIC_AUDIENCE_ID,
+};
+use ssi::jwk::JWK;
+use tokio::sync::RwLock;
+
+use chatternet_server_http::db::Connector;
+use chatternet_server_http::handlers::{spawn_local_api, AppState};
+
+/// Serve the API on a free local port, returning a client of it.
+async fn serve_api() -> Client {
+    serve_api_jwk(build_jwk(&mut rand::thread_rng()).unwrap()).await
+}
+
+/// Serve the API as the server actor with `jwk`.
+async fn serve_api_jwk(jwk: JWK) -> Client {
+    let connector = Arc::new(RwLock::new(
+        Connector::new("sqlite::memory:").await.unwrap(),
+    ));
+    Client::new(&spawn_local_api(AppState::new(connector, jwk), "api"))
+}
+
+fn build_actor_jwk() -> (JWK, String) {
+    let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+    let did = did_from_jwk(&jwk).unwrap();
+    (jwk, did)
+}
+
+async fn build_message(
+    jwk: &JWK,
+    type_: ActivityType,
+    object: &str,
+    to: Option<&str>,
+    target: Option<&str>,
+) -> MessageFields {
+    let mut builder = MessageBuilder::new(
+        jwk,
+        type_,
+        vec![Uri::try_from(object).unwrap()].try_into().unwrap(),
+    );
+    if let Some(to) = to {
+        builder = builder.to(vec![Uri::try_from(to).unwrap()].try_into().unwrap());
+    }
+    if let Some(target) = target {
+        builder = builder.target(vec![Uri::try_from(target).unwrap()].try_into().unwrap());
+    }
+    builder.build().await.unwrap()
+}
+
+fn message_objects(messages: &[MessageFields]) -> Vec<String> {
+    messages.iter().map(|x| x.object()[0].to_string()).collect()
+}
+
+#[tokio::test]
+async fn client_posts_and_gets_actor() {
+    let client = serve_api().await;
+    let (jwk, did) = build_actor_jwk();
+
+    let error = client.get_actor(&did).await.unwrap_err();
+    assert_eq!(
+        server_error(&error).unwrap().kind,
+        ServerErrorKind::ActorNotKnown
+    );
+    assert_eq!(server_error(&error).unwrap().status, 404);
+
+    let actor = ActorFields::new(&jwk, ActorType::Person, Some("abc".to_string()), None)
+        .await
+        .unwrap();
+    client.post_actor(&actor).await.unwrap();
+    let actor_back = client.get_actor(&did).await.unwrap();
+    assert_eq!(actor_back.id(), actor.id());
+
+    let error = client.get_actor("did:example:a").await.unwrap_err();
+    assert_eq!(
+        server_error(&error).unwrap().kind,
+        ServerErrorKind::DidNotValid
+    );
+}
+
+#[tokio::test]
+async fn client_gets_following_and_followers() {
+    let client = serve_api().await;
+    let (jwk_1, did_1) = build_actor_jwk();
+    let actor_id_1 = actor_id_from_did(&did_1).unwrap();
+
+    let mut followers = Vec::new();
+    for _ in 0..5 {
+        let (jwk, did) = build_actor_jwk();
+        let actor_id = actor_id_from_did(&did).unwrap();
+        let message = build_message(
+            &jwk,
+            ActivityType::Add,
+            &actor_id_1,
+            None,
+            Some(&format!("{}/following", actor_id)),
+        )
+        .await;
+        client.post_message(&message).await.unwrap();
+        followers.push(actor_id);
+    }
+
+    let (_, did_2) = build_actor_jwk();
+    let following = client.get_following(&did_2).await.unwrap();
+    assert!(following.items().is_empty());
+
+    let page = client
+        .get_followers_page(
+            &did_1,
+            PageQuery {
+                page_size: Some(2),
+                start_idx: None,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(page.items().len(), 2);
+    assert!(PageQuery::next(&page).is_some());
+
+    // all pages are followed through their next links
+    let mut followers_back = client
+        .get_followers(
+            &did_1,
+            PageQuery {
+                page_size: Some(2),
+                start_idx: None,
+            },
+            usize::MAX,
+        )
+        .await
+        .unwrap();
+    followers_back.sort();
+    followers.sort();
+    assert_eq!(followers_back, followers);
+
+    // following lists the IDs the actor added
+    let message = build_message(
+        &jwk_1,
+        ActivityType::Add,
+        &followers[0],
+        None,
+        Some(&format!("{}/following", actor_id_1)),
+    )
+    .await;
+    client.post_message(&message).await.unwrap();
+    let following = client.get_following(&did_1).await.unwrap();
+    assert!(following.items().contains(&followers[0]));
+}
+
+#[tokio::test]
+async fn client_gets_inbox_pages() {
+    let client = serve_api().await;
+    let (jwk_1, did_1) = build_actor_jwk();
+    let (jwk_2, did_2) = build_actor_jwk();
+    let actor_id_1 = actor_id_from_did(&did_1).unwrap();
+    let actor_id_2 = actor_id_from_did(&did_2).unwrap();
+
+    // actor 1 follows actor 2
+    let message = build_message(
+        &jwk_1,
+        ActivityType::Add,
+        &actor_id_2,
+        None,
+        Some(&format!("{}/following", actor_id_1)),
+    )
+    .await;
+    client.post_message(&message).await.unwrap();
+
+    let followers_2 = format!("{}/followers", actor_id_2);
+    for i in 0..3 {
+        let message = build_message(
+            &jwk_1,
+            ActivityType::Create,
+            &format!("id:1-{}", i),
+            Some(&actor_id_1),
+            None,
+        )
+        .await;
+        client.post_message(&message).await.unwrap();
+        let message = build_message(
+            &jwk_2,
+            ActivityType::Create,
+            &format!("id:2-{}", i),
+            Some(&followers_2),
+            None,
+        )
+        .await;
+        client.post_message(&message).await.unwrap();
+    }
+
+    let page = client
+        .get_inbox_page(
+            &did_1,
+            PageQuery {
+                page_size: Some(4),
+                start_idx: None,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        message_objects(page.items()),
+        ["id:2-2", "id:1-2", "id:2-1", "id:1-1"]
+    );
+
+    let small_pages = PageQuery {
+        page_size: Some(1),
+        start_idx: None,
+    };
+    let messages = client.get_inbox(&did_1, small_pages, 5).await.unwrap();
+    assert_eq!(
+        message_objects(&messages),
+        ["id:2-2", "id:1-2", "id:2-1", "id:1-1", "id:2-0"]
+    );
+
+    let messages = client
+        .get_inbox_from(&did_1, &did_2, small_pages, usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(message_objects(&messages), ["id:2-2", "id:2-1", "id:2-0"]);
+    let page = client
+        .get_inbox_from_page(&did_1, &did_2, small_pages)
+        .await
+        .unwrap();
+    assert_eq!(message_objects(page.items()), ["id:2-2"]);
+
+    let messages = client
+        .get_inbox_with(&did_1, &[&actor_id_1], small_pages, usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(message_objects(&messages), ["id:1-2", "id:1-1", "id:1-0"]);
+    let page = client
+        .get_inbox_with_page(&did_1, &[&actor_id_1], small_pages)
+        .await
+        .unwrap();
+    assert_eq!(message_objects(page.items()), ["id:1-2"]);
+}
+
+#[tokio::test]
+async fn client_gets_blocked_and_revoked() {
+    let client = serve_api().await;
+    let (jwk_1, did_1) = build_actor_jwk();
+    let (_, did_2) = build_actor_jwk();
+    let actor_id_1 = actor_id_from_did(&did_1).unwrap();
+    let actor_id_2 = actor_id_from_did(&did_2).unwrap();
+
+    assert!(client.get_blocked(&did_1).await.unwrap().items().is_empty());
+    assert!(client.get_revoked(&did_1).await.unwrap().items().is_empty());
+
+    for (object, collection) in [(actor_id_2.as_str(), "blocked"), ("urn:cid:a", "revoked")] {
+        let message = build_message(
+            &jwk_1,
+            ActivityType::Add,
+            object,
+            None,
+            Some(&format!("{}/{}", actor_id_1, collection)),
+        )
+        .await;
+        client.post_message(&message).await.unwrap();
+    }
+
+    assert_eq!(
+        client.get_blocked(&did_1).await.unwrap().items(),
+        &[actor_id_2.clone()]
+    );
+    assert_eq!(
+        client.get_revoked(&did_1).await.unwrap().items(),
+        &["urn:cid:a".to_string()]
+    );
+}
+
+#[tokio::test]
+async fn client_gets_conversation_and_notifications() {
+    let client = serve_api().await;
+    let (jwk_1, did_1) = build_actor_jwk();
+    let (jwk_2, did_2) = build_actor_jwk();
+    let (jwk_3, did_3) = build_actor_jwk();
+    let actor_id_1 = actor_id_from_did(&did_1).unwrap();
+    let actor_id_2 = actor_id_from_did(&did_2).unwrap();
+    let actor_id_3 = actor_id_from_did(&did_3).unwrap();
+
+    // actor 2 follows actor 1, which notifies actor 1
+    let follow = build_message(
+        &jwk_2,
+        ActivityType::Add,
+        &actor_id_1,
+        None,
+        Some(&format!("{}/following", actor_id_2)),
+    )
+    .await;
+    client.post_message(&follow).await.unwrap();
+
+    for (jwk, object, to) in [
+        (&jwk_1, "id:1", &actor_id_2),
+        (&jwk_2, "id:2", &actor_id_1),
+        (&jwk_3, "id:3", &actor_id_1),
+        (&jwk_1, "id:4", &actor_id_2),
+    ] {
+        let message = build_message(jwk, ActivityType::Create, object, Some(to), None).await;
+        client.post_message(&message).await.unwrap();
+    }
+
+    let small_pages = PageQuery {
+        page_size: Some(1),
+        start_idx: None,
+    };
+    let messages = client
+        .get_conversation(&did_1, &did_2, small_pages, usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(message_objects(&messages), ["id:4", "id:2", "id:1"]);
+    let page = client
+        .get_conversation_page(&did_1, &did_2, small_pages)
+        .await
+        .unwrap();
+    assert_eq!(message_objects(page.items()), ["id:4"]);
+    assert!(PageQuery::next(&page).is_some());
+    let messages = client
+        .get_conversation(&did_1, &did_3, small_pages, usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(message_objects(&messages), ["id:3"]);
+
+    let notifications = client
+        .get_notifications(&did_1, small_pages, usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].id(), follow.id());
+    let page = client
+        .get_notifications_page(&did_2, small_pages)
+        .await
+        .unwrap();
+    assert!(page.items().is_empty());
+
+    let page = client.get_flagged_page(&did_1, small_pages).await.unwrap();
+    assert!(page.items().is_empty());
+}
+
+#[tokio::test]
+async fn client_gets_timelines_and_stats() {
+    let (jwk_server, did_server) = build_actor_jwk();
+    let client = serve_api_jwk(jwk_server.clone()).await;
+    let server_actor_id = actor_id_from_did(&did_server).unwrap();
+    let (jwk_1, did_1) = build_actor_jwk();
+    let (jwk_2, did_2) = build_actor_jwk();
+    let actor_id_1 = actor_id_from_did(&did_1).unwrap();
+    let actor_id_2 = actor_id_from_did(&did_2).unwrap();
+
+    // the server actor follows actor 1
+    let message = build_message(
+        &jwk_server,
+        ActivityType::Add,
+        &actor_id_1,
+        None,
+        Some(&format!("{}/following", server_actor_id)),
+    )
+    .await;
+    client.post_message(&message).await.unwrap();
+
+    for (jwk, object, to) in [
+        (&jwk_1, "id:1", PUBLIC_AUDIENCE_ID),
+        (&jwk_2, "id:2", PUBLIC_AUDIENCE_ID),
+        (&jwk_1, "id:3", "urn:cid:a/followers"),
+    ] {
+        let message = build_message(jwk, ActivityType::Create, object, Some(to), None).await;
+        client.post_message(&message).await.unwrap();
+    }
+
+    let small_pages = PageQuery {
+        page_size: Some(1),
+        start_idx: None,
+    };
+    let messages = client.get_public(small_pages, usize::MAX).await.unwrap();
+    assert_eq!(message_objects(&messages), ["id:2", "id:1"]);
+    let page = client.get_public_page(small_pages).await.unwrap();
+    assert_eq!(message_objects(page.items()), ["id:2"]);
+    let messages = client.get_local(small_pages, usize::MAX).await.unwrap();
+    assert_eq!(message_objects(&messages), ["id:1"]);
+    let page = client.get_local_page(small_pages).await.unwrap();
+    assert_eq!(message_objects(page.items()), ["id:1"]);
+
+    let stats = client
+        .get_stats_actors(&did_server, Some(StatsWindow::Hour), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        stats
+            .items()
+            .iter()
+            .map(|x| (x.id.as_str(), x.count))
+            .collect::<Vec<_>>(),
+        [(actor_id_1.as_str(), 2), (actor_id_2.as_str(), 1)]
+    );
+    let stats = client
+        .get_stats_actors(&did_server, None, Some(1))
+        .await
+        .unwrap();
+    assert_eq!(stats.items().len(), 1);
+    let stats = client
+        .get_stats_tags(&did_server, Some(StatsWindow::Day), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        stats
+            .items()
+            .iter()
+            .map(|x| (x.id.as_str(), x.count))
+            .collect::<Vec<_>>(),
+        [("urn:cid:a", 1)]
+    );
+
+    // only the server serves statistics
+    let error = client.get_stats_tags(&did_1, None, None).await.unwrap_err();
+    assert_eq!(
+        server_error(&error).unwrap().kind,
+        ServerErrorKind::ActorNotKnown
+    );
+}
+
+#[tokio::test]
+async fn client_gets_reactions_and_tag_notes() {
+    let client = serve_api().await;
+    let (jwk_1, did_1) = build_actor_jwk();
+    let (jwk_2, did_2) = build_actor_jwk();
+    let actor_id_1 = actor_id_from_did(&did_1).unwrap();
+    let actor_id_2 = actor_id_from_did(&did_2).unwrap();
+
+    let tag = Tag30Fields::new("abc".to_string()).await.unwrap();
+    let tag_followers = format!("{}/followers", tag.id().as_str());
+    let mut notes_id = Vec::new();
+    for content in ["about #abc", "also #abc"] {
+        let note = NoteMd1kFields::new(
+            content.to_string(),
+            Uri::try_from(actor_id_1.as_str()).unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        let message = build_message(
+            &jwk_1,
+            ActivityType::Create,
+            note.id().as_str(),
+            Some(&tag_followers),
+            None,
+        )
+        .await;
+        client.post_message(&message).await.unwrap();
+        client.post_document(&note).await.unwrap();
+        notes_id.push(note.id().as_str().to_string());
+    }
+
+    let small_pages = PageQuery {
+        page_size: Some(1),
+        start_idx: None,
+    };
+    let notes_id_back = client
+        .get_tag_notes(tag.id().as_str(), small_pages, usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(notes_id_back, [notes_id[1].clone(), notes_id[0].clone()]);
+    let page = client
+        .get_tag_notes_page(tag.id().as_str(), small_pages)
+        .await
+        .unwrap();
+    assert_eq!(page.items(), &[notes_id[1].clone()]);
+    assert!(PageQuery::next(&page).is_some());
+
+    for (jwk, type_) in [
+        (&jwk_1, ActivityType::Like),
+        (&jwk_2, ActivityType::Like),
+        (&jwk_2, ActivityType::Announce),
+    ] {
+        let message = build_message(jwk, type_, &notes_id[0], None, None).await;
+        client.post_message(&message).await.unwrap();
+    }
+
+    let likes = client.get_likes(&notes_id[0], None).await.unwrap();
+    assert_eq!(likes.total_items(), Some(2));
+    assert_eq!(likes.items(), &[actor_id_2.clone(), actor_id_1.clone()]);
+    let likes = client.get_likes(&notes_id[0], Some(1)).await.unwrap();
+    assert_eq!(likes.total_items(), Some(2));
+    assert_eq!(likes.items(), &[actor_id_2.clone()]);
+    let shares = client.get_shares(&notes_id[0], None).await.unwrap();
+    assert_eq!(shares.total_items(), Some(1));
+    assert_eq!(shares.items(), &[actor_id_2.clone()]);
+    let shares = client.get_shares(&notes_id[1], None).await.unwrap();
+    assert_eq!(shares.total_items(), Some(0));
+}
+
+#[tokio::test]
+async fn client_posts_and_gets_documents() {
+    let client = serve_api().await;
+    let (jwk, did) = build_actor_jwk();
+    let actor_id = actor_id_from_did(&did).unwrap();
+
+    let note = NoteMd1kFields::new(
+        "abc".to_string(),
+        Uri::try_from(actor_id.as_str()).unwrap(),
+        None,
+    )
+    .await
+    .unwrap();
+
+    // documents are accepted only once a message references them
+    let error = client.post_document(&note).await.unwrap_err();
+    assert_eq!(server_error(&error).unwrap().status, 404);
+
+    let message = build_message(
+        &jwk,
+        ActivityType::Create,
+        note.id().as_str(),
+        Some(&actor_id),
+        None,
+    )
+    .await;
+    client.post_message(&message).await.unwrap();
+    client.post_document(&note).await.unwrap();
+
+    let note_back: NoteMd1kFields = client.get_document(note.id().as_str()).await.unwrap();
+    assert_eq!(note_back.id(), note.id());
+    let message_back: MessageFields = client.get_document(message.id().as_str()).await.unwrap();
+    assert_eq!(message_back.id(), message.id());
+    let create = client
+        .get_document_create(note.id().as_str(), &did)
+        .await
+        .unwrap();
+    assert_eq!(create.id(), message.id());
+
+    let error = client
+        .get_document::<NoteMd1kFields>("urn:cid:a")
+        .await
+        .unwrap_err();
+    assert_eq!(
+        server_error(&error).unwrap().kind,
+        ServerErrorKind::DocumentNotKnown
+    );
+
+    // a message by another actor isn't accepted in this actor's outbox
+    let (jwk_2, _) = build_actor_jwk();
+    let message = build_message(&jwk_2, ActivityType::Create, "id:1", None, None).await;
+    let mut message_value = serde_json::to_value(&message).unwrap();
+    message_value["actor"] = serde_json::Value::String(actor_id.clone());
+    let message: MessageFields = serde_json::from_value(message_value).unwrap();
+    let error = client.post_message(&message).await.unwrap_err();
+    assert_eq!(
+        server_error(&error).unwrap().kind,
+        ServerErrorKind::MessageNotValid
+    );
+}
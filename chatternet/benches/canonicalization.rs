@@ -0,0 +1,45 @@
+//! Compare building and verifying messages canonicalized with URDNA2015 and
+//! with JCS.
+
+use chatternet::canon::Canonicalization;
+use chatternet::didkey::build_jwk;
+use chatternet::model::{ActivityType, Message, MessageBuilder, MessageFields};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use ssi::jwk::JWK;
+use tokio::runtime::Runtime;
+
+async fn build_message(jwk: &JWK, canonicalization: Canonicalization) -> MessageFields {
+    MessageBuilder::new(
+        jwk,
+        ActivityType::Create,
+        vec!["id:a".try_into().unwrap()].try_into().unwrap(),
+    )
+    .to(vec!["as:Public".try_into().unwrap()].try_into().unwrap())
+    .canonicalization(canonicalization)
+    .build()
+    .await
+    .unwrap()
+}
+
+fn bench_canonicalization(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+
+    let mut group = c.benchmark_group("message");
+    for canonicalization in [Canonicalization::Urdna2015, Canonicalization::Jcs] {
+        let name = format!("{:?}", canonicalization);
+        group.bench_function(BenchmarkId::new("build", &name), |b| {
+            b.to_async(&runtime)
+                .iter(|| build_message(&jwk, canonicalization))
+        });
+        let message = runtime.block_on(build_message(&jwk, canonicalization));
+        group.bench_function(BenchmarkId::new("verify", &name), |b| {
+            b.to_async(&runtime)
+                .iter(|| async { message.verify().await.unwrap() })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_canonicalization);
+criterion_main!(benches);
@@ -0,0 +1,192 @@
+//! Remember which objects have already been verified.
+//!
+//! Verifying CIDs and proofs requires canonicalizing documents, which is
+//! expensive, and the same objects are often verified many times. A
+//! [`VerificationCache`] records the hashes of the canonical bytes of objects
+//! which passed verification, so that identical objects can skip it.
+//!
+//! Only successful verifications are recorded, and the hash covers the whole
+//! object (including its ID and proof), so that any change to an object
+//! misses the cache and is verified in full. Objects signed by DID Web keys
+//! aren't cached at all, since the keys behind them can be rotated.
+
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::canon::jcs_from_json;
+
+type CacheKey = [u8; 32];
+
+#[derive(Debug, Default)]
+struct Entries {
+    keys: HashSet<CacheKey>,
+    order: VecDeque<CacheKey>,
+}
+
+/// A bounded set of verified objects, which evicts the oldest entries first.
+#[derive(Debug)]
+pub struct VerificationCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl VerificationCache {
+    /// Build a cache which remembers up to `capacity` verified objects.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Build the key of `object` for a verification of `kind`.
+    fn key(kind: &str, object: &impl Serialize) -> Result<CacheKey> {
+        let value = serde_json::to_value(object)?;
+        let mut hasher = Sha256::new();
+        hasher.update(kind.as_bytes());
+        hasher.update([0u8]);
+        hasher.update(jcs_from_json(&value)?.as_bytes());
+        Ok(hasher.finalize().into())
+    }
+
+    fn contains(&self, key: &CacheKey) -> bool {
+        self.entries.lock().unwrap().keys.contains(key)
+    }
+
+    fn insert(&self, key: CacheKey) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.keys.insert(key) {
+            return;
+        }
+        entries.order.push_back(key);
+        while entries.order.len() > self.capacity {
+            if let Some(oldest) = entries.order.pop_front() {
+                entries.keys.remove(&oldest);
+            }
+        }
+    }
+
+    /// Run the `verify` verification of `object`, unless the same `kind` of
+    /// verification already succeeded for an identical object.
+    pub async fn verify(
+        &self,
+        kind: &str,
+        object: &impl Serialize,
+        verify: impl Future<Output = Result<()>>,
+    ) -> Result<()> {
+        let key = Self::key(kind, object)?;
+        if self.contains(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        verify.await?;
+        self.insert(key);
+        Ok(())
+    }
+
+    /// The number of verifications skipped thanks to the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The number of verifications which had to be run.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The number of objects currently remembered.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().keys.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Error;
+    use serde_json::json;
+    use tokio;
+
+    use super::*;
+    use crate::didkey::build_jwk;
+    use crate::model::{ActivityType, Message, MessageBuilder, MessageFields};
+
+    #[tokio::test]
+    async fn caches_successful_verifications() {
+        let cache = VerificationCache::new(2);
+        cache
+            .verify("a", &json!({"id": 1}), async { Ok(()) })
+            .await
+            .unwrap();
+        cache
+            .verify("a", &json!({"id": 1}), async { Err(Error::msg("not run")) })
+            .await
+            .unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        // another kind of verification doesn't hit
+        cache
+            .verify("b", &json!({"id": 1}), async { Err(Error::msg("failed")) })
+            .await
+            .unwrap_err();
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn evicts_oldest_entries() {
+        let cache = VerificationCache::new(2);
+        for id in [1, 2, 3] {
+            cache
+                .verify("a", &json!({ "id": id }), async { Ok(()) })
+                .await
+                .unwrap();
+        }
+        assert_eq!(cache.len(), 2);
+        cache
+            .verify("a", &json!({"id": 1}), async { Err(Error::msg("failed")) })
+            .await
+            .unwrap_err();
+        cache
+            .verify("a", &json!({"id": 3}), async { Err(Error::msg("not run")) })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn doesnt_hit_tampered_message() {
+        let cache = VerificationCache::new(16);
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let message = MessageBuilder::new(
+            &jwk,
+            ActivityType::Create,
+            vec!["id:a".try_into().unwrap()].try_into().unwrap(),
+        )
+        .build()
+        .await
+        .unwrap();
+        message.verify_cached(&cache).await.unwrap();
+        message.verify_cached(&cache).await.unwrap();
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        let mut tampered = serde_json::to_value(&message).unwrap();
+        tampered["object"] = json!(["id:b"]);
+        let tampered: MessageFields = serde_json::from_value(tampered).unwrap();
+        tampered.verify_cached(&cache).await.unwrap_err();
+        tampered.verify_cached(&cache).await.unwrap_err();
+        assert_eq!((cache.hits(), cache.misses()), (1, 3));
+        assert_eq!(cache.len(), 1);
+    }
+}
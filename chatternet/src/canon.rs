@@ -0,0 +1,99 @@
+//! Select how documents are canonicalized before being hashed into CIDs and
+//! signed into proofs.
+//!
+//! By default documents are JSON-LD which is expanded and canonicalized with
+//! URDNA2015. This is thorough but slow, so signed objects can instead opt
+//! into the JSON Canonicalization Scheme (RFC 8785) by including the
+//! [`ldcontexts::DATA_INTEGRITY_V1_URI`] context.
+
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::ldcontexts;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Canonicalization {
+    Urdna2015,
+    Jcs,
+}
+
+impl Default for Canonicalization {
+    fn default() -> Self {
+        Self::Urdna2015
+    }
+}
+
+/// Check if the JSON-LD `context` (a URI or array of URIs) includes `uri`.
+fn context_has(context: &Value, uri: &str) -> bool {
+    match context {
+        Value::String(x) => x == uri,
+        Value::Array(x) => x.iter().any(|x| x.as_str() == Some(uri)),
+        _ => false,
+    }
+}
+
+impl Canonicalization {
+    /// Select the canonicalization of the JSON `value` from its context, and
+    /// from the `more_contexts` JSON of its parent if any.
+    pub fn from_json(value: &Value, more_contexts: Option<&str>) -> Self {
+        let more_contexts = more_contexts.and_then(|x| serde_json::from_str::<Value>(x).ok());
+        let is_jcs = value
+            .get("@context")
+            .into_iter()
+            .chain(more_contexts.as_ref())
+            .any(|x| context_has(x, ldcontexts::DATA_INTEGRITY_V1_URI));
+        if is_jcs {
+            Self::Jcs
+        } else {
+            Self::Urdna2015
+        }
+    }
+}
+
+/// Serialize the JSON `value` per the JSON Canonicalization Scheme.
+pub fn jcs_from_json(value: &Value) -> Result<String> {
+    Ok(serde_jcs::to_string(value)?)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::CONTEXT_ACTIVITY_STREAMS;
+
+    #[test]
+    fn selects_canonicalization_from_context() {
+        let urdna = json!({"@context": [CONTEXT_ACTIVITY_STREAMS], "content": "abc"});
+        assert_eq!(
+            Canonicalization::from_json(&urdna, None),
+            Canonicalization::Urdna2015
+        );
+        let jcs = json!({
+            "@context": [ldcontexts::DATA_INTEGRITY_V1_URI, CONTEXT_ACTIVITY_STREAMS],
+            "content": "abc",
+        });
+        assert_eq!(
+            Canonicalization::from_json(&jcs, None),
+            Canonicalization::Jcs
+        );
+        let more_contexts = serde_json::to_string(&json!([
+            ldcontexts::DATA_INTEGRITY_V1_URI,
+            CONTEXT_ACTIVITY_STREAMS
+        ]))
+        .unwrap();
+        assert_eq!(
+            Canonicalization::from_json(&json!({"content": "abc"}), Some(&more_contexts)),
+            Canonicalization::Jcs
+        );
+    }
+
+    #[test]
+    fn serializes_jcs() {
+        let value = json!({"b": [1.0, "\u{20ac}"], "a": {"d": true, "c": null}});
+        assert_eq!(
+            jcs_from_json(&value).unwrap(),
+            r#"{"a":{"c":null,"d":true},"b":[1,"€"]}"#
+        );
+    }
+}
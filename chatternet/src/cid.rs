@@ -10,19 +10,30 @@ use serde_json;
 use ssi::jsonld::{json_to_dataset, ContextLoader};
 use ssi::urdna2015;
 
+use crate::cache::VerificationCache;
+use crate::canon::{jcs_from_json, Canonicalization};
 use crate::model::Uri;
 use crate::new_context_loader;
 
 /// Build a CID from a JSON-LD document.
+///
+/// The document is canonicalized per its context, see [`Canonicalization`].
 pub async fn cid_from_json(
     object: &impl Serialize,
     context_loader: &mut ContextLoader,
     more_contexts: Option<&String>,
 ) -> Result<Cid> {
-    let json = serde_json::to_string(object)?;
-    let dataset = json_to_dataset(&json, more_contexts, false, None, context_loader).await?;
-    let dataset_normalized = urdna2015::normalize(&dataset)?;
-    let doc_normalized = dataset_normalized.to_nquads()?;
+    let value = serde_json::to_value(object)?;
+    let doc_normalized =
+        match Canonicalization::from_json(&value, more_contexts.map(|x| x.as_str())) {
+            Canonicalization::Jcs => jcs_from_json(&value)?,
+            Canonicalization::Urdna2015 => {
+                let json = serde_json::to_string(&value)?;
+                let dataset =
+                    json_to_dataset(&json, more_contexts, false, None, context_loader).await?;
+                urdna2015::normalize(&dataset)?.to_nquads()?
+            }
+        };
     Ok(Cid::new_v1(
         0x55,
         Code::Sha2_256.digest(doc_normalized.as_bytes()),
@@ -61,6 +72,14 @@ pub trait CidVerifier<WithoutCid: Serialize + Sized + Sync> {
         }
         Ok(())
     }
+
+    /// Verify the object's CID, unless it was already verified with `cache`.
+    async fn verify_cid_cached(&self, cache: &VerificationCache) -> Result<()> {
+        let (cid, without_cid) = self.extract_cid()?;
+        cache
+            .verify("cid", &(cid, without_cid), self.verify_cid())
+            .await
+    }
 }
 
 #[cfg(test)]
@@ -91,6 +110,36 @@ mod test {
         assert_ne!(cid_1.to_string(), cid_2.to_string());
     }
 
+    #[tokio::test]
+    async fn builds_cid_from_object_with_jcs() {
+        let activity_1 = json!({
+            "@context": CtxSigStream::jcs(),
+            "content": "abc",
+            "published": "2000-01-01T00:00:00Z",
+        });
+        let activity_2 = json!({
+            "published": "2000-01-01T00:00:00Z",
+            "content": "abc",
+            "@context": CtxSigStream::jcs(),
+        });
+        let cid_1 = cid_from_json(&activity_1, &mut new_context_loader(), None)
+            .await
+            .unwrap();
+        let cid_2 = cid_from_json(&activity_2, &mut new_context_loader(), None)
+            .await
+            .unwrap();
+        assert_eq!(cid_1.to_string(), cid_2.to_string());
+        let activity_3 = json!({
+            "@context": CtxSigStream::new(),
+            "content": "abc",
+            "published": "2000-01-01T00:00:00Z",
+        });
+        let cid_3 = cid_from_json(&activity_3, &mut new_context_loader(), None)
+            .await
+            .unwrap();
+        assert_ne!(cid_1.to_string(), cid_3.to_string());
+    }
+
     #[derive(Debug, Serialize)]
     #[serde(rename_all = "camelCase")]
     pub struct Data {
@@ -0,0 +1,626 @@
+//! Call the API of a ChatterNet server.
+//!
+//! A [`Client`] wraps the routes served by the server, decoding their
+//! responses into the types of the [`crate::model`] module. Paginated
+//! collections can be read a page at a time, or followed through their
+//! `next` links until enough items are collected.
+//!
+//! Error responses are decoded into a [`ServerError`], which can be
+//! recovered from the returned [`anyhow::Error`] with `downcast_ref`.
+
+use anyhow::{Error, Result};
+use reqwest::{Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::didkey::did_from_actor_id;
+use crate::model::{
+    ActorFields, CollectionFields, CollectionPage, CollectionPageFields, Document, Message,
+    MessageFields,
+};
+
+/// The reasons a server gives for rejecting a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServerErrorKind {
+    DbConnectionFailed,
+    DbQueryFailed,
+    DidNotValid,
+    ActorNotKnown,
+    ActorNotValid,
+    ActorIdWrong,
+    DocumentNotKnown,
+    DocumentNotValid,
+    DocumentIdWrong,
+    MessageNotValid,
+    ServerMisconfigured,
+    StaleMessage,
+    /// A reason this client doesn't know of.
+    Other,
+}
+
+impl ServerErrorKind {
+    /// Identify the reason from the message in an error response.
+    pub fn from_message(message: &str) -> Self {
+        match message {
+            "database connection failed" => Self::DbConnectionFailed,
+            "database query failed" => Self::DbQueryFailed,
+            "DID is not valid" => Self::DidNotValid,
+            "actor is not known" => Self::ActorNotKnown,
+            "actor is not valid" => Self::ActorNotValid,
+            "actor ID is wrong" => Self::ActorIdWrong,
+            "document is not known" => Self::DocumentNotKnown,
+            "document is not valid" => Self::DocumentNotValid,
+            "document ID is wrong" => Self::DocumentIdWrong,
+            "message is not valid" => Self::MessageNotValid,
+            "server is misconfigured" => Self::ServerMisconfigured,
+            "a newer timestamp is known for this object" => Self::StaleMessage,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// An error response from the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerError {
+    pub status: u16,
+    pub kind: ServerErrorKind,
+    pub message: String,
+}
+
+impl std::fmt::Display for ServerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "server responded {}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for ServerError {}
+
+/// Select a page of a paginated collection.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PageQuery {
+    pub page_size: Option<u64>,
+    pub start_idx: Option<u64>,
+}
+
+impl PageQuery {
+    /// Get the query of the page after `page`, if there is one.
+    pub fn next<T>(page: &CollectionPageFields<T>) -> Option<Self> {
+        let next = page.next().as_ref()?;
+        let (_, query) = next.split_once('?')?;
+        let mut next_query = Self::default();
+        for pair in query.split('&') {
+            match pair.split_once('=') {
+                Some(("startIdx", value)) => next_query.start_idx = value.parse().ok(),
+                Some(("pageSize", value)) => next_query.page_size = value.parse().ok(),
+                _ => (),
+            }
+        }
+        next_query.start_idx?;
+        Some(next_query)
+    }
+
+    fn pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = Vec::new();
+        if let Some(page_size) = self.page_size {
+            pairs.push(("pageSize", page_size.to_string()));
+        }
+        if let Some(start_idx) = self.start_idx {
+            pairs.push(("startIdx", start_idx.to_string()));
+        }
+        pairs
+    }
+}
+
+/// The time over which the server counts its statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StatsWindow {
+    Hour,
+    Day,
+}
+
+impl StatsWindow {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+}
+
+/// An item in a statistics collection, and the number of messages counted
+/// for it.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsItem {
+    pub id: String,
+    pub count: u64,
+}
+
+/// A client of the API served at a base URL.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl Client {
+    /// Build a client for the API at `base_url`, which includes the
+    /// server's prefix.
+    pub fn new(base_url: &str) -> Self {
+        Self::with_http(base_url, reqwest::Client::new())
+    }
+
+    pub fn with_http(base_url: &str, http: reqwest::Client) -> Self {
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    async fn check(response: Response) -> Result<Response> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let message = response.text().await.unwrap_or_default();
+        Err(ServerError {
+            status: status.as_u16(),
+            kind: ServerErrorKind::from_message(&message),
+            message,
+        })?
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T> {
+        let response = self.http.get(self.url(path)).query(query).send().await?;
+        Ok(Self::check(response).await?.json().await?)
+    }
+
+    async fn post(&self, path: &str, body: &impl Serialize) -> Result<StatusCode> {
+        let response = self.http.post(self.url(path)).json(body).send().await?;
+        Ok(Self::check(response).await?.status())
+    }
+
+    /// Get pages from `path` starting at the page selected by `first`, and
+    /// following their `next` links until `limit` items are collected or
+    /// there are no more pages.
+    async fn get_pages<T: DeserializeOwned + Clone>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+        first: PageQuery,
+        limit: usize,
+    ) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut page_query = Some(first);
+        while let Some(current) = page_query {
+            if items.len() >= limit {
+                break;
+            }
+            let mut pairs = query.to_vec();
+            pairs.extend(current.pairs());
+            let page: CollectionPageFields<T> = self.get(path, &pairs).await?;
+            if page.items().is_empty() {
+                break;
+            }
+            items.extend(page.items().iter().cloned());
+            page_query = PageQuery::next(&page);
+        }
+        items.truncate(limit);
+        Ok(items)
+    }
+
+    /// Get the actor document of the actor with `did`, following it if it
+    /// has moved.
+    pub async fn get_actor(&self, did: &str) -> Result<ActorFields> {
+        self.get(&format!("{}/actor", did), &[]).await
+    }
+
+    pub async fn post_actor(&self, actor: &ActorFields) -> Result<StatusCode> {
+        let did = did_from_actor_id(actor.id().as_str())?;
+        self.post(&format!("{}/actor", did), actor).await
+    }
+
+    /// Get the IDs followed by the actor with `did`.
+    pub async fn get_following(&self, did: &str) -> Result<CollectionFields<String>> {
+        self.get(&format!("{}/actor/following", did), &[]).await
+    }
+
+    pub async fn get_followers_page(
+        &self,
+        did: &str,
+        query: PageQuery,
+    ) -> Result<CollectionPageFields<String>> {
+        self.get(&format!("{}/actor/followers", did), &query.pairs())
+            .await
+    }
+
+    /// Get the IDs of up to `limit` followers of the actor with `did`.
+    pub async fn get_followers(
+        &self,
+        did: &str,
+        first: PageQuery,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        self.get_pages(&format!("{}/actor/followers", did), &[], first, limit)
+            .await
+    }
+
+    /// Get the IDs blocked by the actor with `did`.
+    pub async fn get_blocked(&self, did: &str) -> Result<CollectionFields<String>> {
+        self.get(&format!("{}/actor/blocked", did), &[]).await
+    }
+
+    /// Get the IDs of the delegations revoked by the actor with `did`.
+    pub async fn get_revoked(&self, did: &str) -> Result<CollectionFields<String>> {
+        self.get(&format!("{}/actor/revoked", did), &[]).await
+    }
+
+    /// Post a `message` to the outbox of its actor.
+    pub async fn post_message(&self, message: &MessageFields) -> Result<StatusCode> {
+        let did = did_from_actor_id(message.actor().as_str())?;
+        self.post(&format!("{}/actor/outbox", did), message).await
+    }
+
+    pub async fn get_inbox_page(
+        &self,
+        did: &str,
+        query: PageQuery,
+    ) -> Result<CollectionPageFields<MessageFields>> {
+        self.get(&format!("{}/actor/inbox", did), &query.pairs())
+            .await
+    }
+
+    /// Get up to `limit` messages from the inbox of the actor with `did`,
+    /// most recent first.
+    pub async fn get_inbox(
+        &self,
+        did: &str,
+        first: PageQuery,
+        limit: usize,
+    ) -> Result<Vec<MessageFields>> {
+        self.get_pages(&format!("{}/actor/inbox", did), &[], first, limit)
+            .await
+    }
+
+    pub async fn get_inbox_from_page(
+        &self,
+        did: &str,
+        from_did: &str,
+        query: PageQuery,
+    ) -> Result<CollectionPageFields<MessageFields>> {
+        self.get(
+            &format!("{}/actor/inbox/from/{}/actor", did, from_did),
+            &query.pairs(),
+        )
+        .await
+    }
+
+    /// Get up to `limit` messages from the actor with `from_did` in the
+    /// inbox of the actor with `did`.
+    pub async fn get_inbox_from(
+        &self,
+        did: &str,
+        from_did: &str,
+        first: PageQuery,
+        limit: usize,
+    ) -> Result<Vec<MessageFields>> {
+        self.get_pages(
+            &format!("{}/actor/inbox/from/{}/actor", did, from_did),
+            &[],
+            first,
+            limit,
+        )
+        .await
+    }
+
+    pub async fn get_inbox_with_page(
+        &self,
+        did: &str,
+        audiences: &[&str],
+        query: PageQuery,
+    ) -> Result<CollectionPageFields<MessageFields>> {
+        let mut pairs = vec![("audiences", serde_json::to_string(audiences)?)];
+        pairs.extend(query.pairs());
+        self.get(&format!("{}/actor/inbox/with", did), &pairs).await
+    }
+
+    /// Get up to `limit` messages addressed to any of `audiences` in the
+    /// inbox of the actor with `did`.
+    pub async fn get_inbox_with(
+        &self,
+        did: &str,
+        audiences: &[&str],
+        first: PageQuery,
+        limit: usize,
+    ) -> Result<Vec<MessageFields>> {
+        self.get_pages(
+            &format!("{}/actor/inbox/with", did),
+            &[("audiences", serde_json::to_string(audiences)?)],
+            first,
+            limit,
+        )
+        .await
+    }
+
+    pub async fn get_conversation_page(
+        &self,
+        did: &str,
+        other_did: &str,
+        query: PageQuery,
+    ) -> Result<CollectionPageFields<MessageFields>> {
+        self.get(
+            &format!("{}/actor/conversation/{}/actor", did, other_did),
+            &query.pairs(),
+        )
+        .await
+    }
+
+    /// Get up to `limit` messages addressed directly between the actors with
+    /// `did` and `other_did`, in both directions.
+    pub async fn get_conversation(
+        &self,
+        did: &str,
+        other_did: &str,
+        first: PageQuery,
+        limit: usize,
+    ) -> Result<Vec<MessageFields>> {
+        self.get_pages(
+            &format!("{}/actor/conversation/{}/actor", did, other_did),
+            &[],
+            first,
+            limit,
+        )
+        .await
+    }
+
+    pub async fn get_notifications_page(
+        &self,
+        did: &str,
+        query: PageQuery,
+    ) -> Result<CollectionPageFields<MessageFields>> {
+        self.get(&format!("{}/actor/notifications", did), &query.pairs())
+            .await
+    }
+
+    /// Get up to `limit` messages notifying the actor with `did`, such as of
+    /// new followers.
+    pub async fn get_notifications(
+        &self,
+        did: &str,
+        first: PageQuery,
+        limit: usize,
+    ) -> Result<Vec<MessageFields>> {
+        self.get_pages(&format!("{}/actor/notifications", did), &[], first, limit)
+            .await
+    }
+
+    pub async fn get_flagged_page(
+        &self,
+        did: &str,
+        query: PageQuery,
+    ) -> Result<CollectionPageFields<MessageFields>> {
+        self.get(&format!("{}/actor/flagged", did), &query.pairs())
+            .await
+    }
+
+    /// Get up to `limit` messages by the actor with `did` which were stored
+    /// flagged after it moved.
+    pub async fn get_flagged(
+        &self,
+        did: &str,
+        first: PageQuery,
+        limit: usize,
+    ) -> Result<Vec<MessageFields>> {
+        self.get_pages(&format!("{}/actor/flagged", did), &[], first, limit)
+            .await
+    }
+
+    pub async fn get_public_page(
+        &self,
+        query: PageQuery,
+    ) -> Result<CollectionPageFields<MessageFields>> {
+        self.get("public", &query.pairs()).await
+    }
+
+    /// Get up to `limit` of the public messages known to the server.
+    pub async fn get_public(&self, first: PageQuery, limit: usize) -> Result<Vec<MessageFields>> {
+        self.get_pages("public", &[], first, limit).await
+    }
+
+    pub async fn get_local_page(
+        &self,
+        query: PageQuery,
+    ) -> Result<CollectionPageFields<MessageFields>> {
+        self.get("local", &query.pairs()).await
+    }
+
+    /// Get up to `limit` of the public messages by the server actor and the
+    /// actors it follows.
+    pub async fn get_local(&self, first: PageQuery, limit: usize) -> Result<Vec<MessageFields>> {
+        self.get_pages("local", &[], first, limit).await
+    }
+
+    fn stats_pairs(
+        window: Option<StatsWindow>,
+        page_size: Option<u64>,
+    ) -> Vec<(&'static str, String)> {
+        let mut pairs = Vec::new();
+        if let Some(window) = window {
+            pairs.push(("window", window.as_str().to_string()));
+        }
+        if let Some(page_size) = page_size {
+            pairs.push(("pageSize", page_size.to_string()));
+        }
+        pairs
+    }
+
+    /// Get up to `page_size` of the tags most used in the `window` by the
+    /// notes seen by the server with `did`.
+    pub async fn get_stats_tags(
+        &self,
+        did: &str,
+        window: Option<StatsWindow>,
+        page_size: Option<u64>,
+    ) -> Result<CollectionFields<StatsItem>> {
+        self.get(
+            &format!("{}/actor/stats/tags", did),
+            &Self::stats_pairs(window, page_size),
+        )
+        .await
+    }
+
+    /// Get up to `page_size` of the actors which created the most notes in
+    /// the `window`, as seen by the server with `did`.
+    pub async fn get_stats_actors(
+        &self,
+        did: &str,
+        window: Option<StatsWindow>,
+        page_size: Option<u64>,
+    ) -> Result<CollectionFields<StatsItem>> {
+        self.get(
+            &format!("{}/actor/stats/actors", did),
+            &Self::stats_pairs(window, page_size),
+        )
+        .await
+    }
+
+    /// Get the document with `id`, which can be a message, a CID document
+    /// or a DID document.
+    pub async fn get_document<T: DeserializeOwned>(&self, id: &str) -> Result<T> {
+        self.get(id, &[]).await
+    }
+
+    /// Post a CID `document`, which the server accepts only once a message
+    /// references it.
+    pub async fn post_document(
+        &self,
+        document: &(impl Document + Serialize + Sync),
+    ) -> Result<StatusCode> {
+        self.post(document.id().as_str(), document).await
+    }
+
+    /// Get the last message with which the actor with `did` created the
+    /// document with `id`.
+    pub async fn get_document_create(&self, id: &str, did: &str) -> Result<MessageFields> {
+        self.get(&format!("{}/createdBy/{}/actor", id, did), &[])
+            .await
+    }
+
+    /// Get the last `page_size` actors who liked the document with `id`,
+    /// and the number of all of them.
+    pub async fn get_likes(
+        &self,
+        id: &str,
+        page_size: Option<u64>,
+    ) -> Result<CollectionFields<String>> {
+        let query = PageQuery {
+            page_size,
+            start_idx: None,
+        };
+        self.get(&format!("{}/likes", id), &query.pairs()).await
+    }
+
+    /// Get the last `page_size` actors who shared the document with `id`,
+    /// and the number of all of them.
+    pub async fn get_shares(
+        &self,
+        id: &str,
+        page_size: Option<u64>,
+    ) -> Result<CollectionFields<String>> {
+        let query = PageQuery {
+            page_size,
+            start_idx: None,
+        };
+        self.get(&format!("{}/shares", id), &query.pairs()).await
+    }
+
+    pub async fn get_tag_notes_page(
+        &self,
+        tag_id: &str,
+        query: PageQuery,
+    ) -> Result<CollectionPageFields<String>> {
+        self.get(&format!("{}/notes", tag_id), &query.pairs()).await
+    }
+
+    /// Get the IDs of up to `limit` notes with a hashtag for the tag with
+    /// `tag_id`, most recent first.
+    pub async fn get_tag_notes(
+        &self,
+        tag_id: &str,
+        first: PageQuery,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        self.get_pages(&format!("{}/notes", tag_id), &[], first, limit)
+            .await
+    }
+}
+
+/// Get the [`ServerError`] behind `error`, if the server caused it.
+pub fn server_error(error: &Error) -> Option<&ServerError> {
+    error.downcast_ref::<ServerError>()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::model::{CollectionPageType, Uri};
+
+    fn build_page(next: Option<&str>) -> CollectionPageFields<String> {
+        CollectionPageFields::new(
+            Uri::try_from("did:example:a/actor/inbox").unwrap(),
+            CollectionPageType::OrderedCollectionPage,
+            vec![],
+            Uri::try_from("did:example:a/actor/inbox").unwrap(),
+            next.map(|x| Uri::try_from(x).unwrap()),
+        )
+    }
+
+    #[test]
+    fn builds_next_page_query() {
+        assert_eq!(
+            PageQuery::next(&build_page(Some(
+                "did:example:a/actor/inbox?startIdx=3&pageSize=4"
+            ))),
+            Some(PageQuery {
+                page_size: Some(4),
+                start_idx: Some(3)
+            })
+        );
+        assert_eq!(PageQuery::next(&build_page(None)), None);
+        assert_eq!(
+            PageQuery::next(&build_page(Some("did:example:a/actor/inbox?pageSize=4"))),
+            None
+        );
+    }
+
+    #[test]
+    fn identifies_server_error() {
+        assert_eq!(
+            ServerErrorKind::from_message("actor is not known"),
+            ServerErrorKind::ActorNotKnown
+        );
+        assert_eq!(
+            ServerErrorKind::from_message("something else"),
+            ServerErrorKind::Other
+        );
+        let error = Error::new(ServerError {
+            status: 404,
+            kind: ServerErrorKind::ActorNotKnown,
+            message: "actor is not known".to_string(),
+        });
+        assert_eq!(
+            server_error(&error).unwrap().kind,
+            ServerErrorKind::ActorNotKnown
+        );
+        assert!(server_error(&Error::msg("other")).is_none());
+    }
+}
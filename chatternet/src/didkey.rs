@@ -1,20 +1,75 @@
-//! Convert between [`JWK`] key meterial, DID Key IDs, and ChatterNet actor IDs.
+//! Convert between [`JWK`] key meterial, DID IDs, and ChatterNet actor IDs.
 
 use anyhow::{Error, Result};
+use bip39::Mnemonic;
 use did_method_key::DIDKey;
 use ed25519_dalek::{Keypair, PublicKey, SecretKey, SECRET_KEY_LENGTH};
+use hmac::{Hmac, Mac};
 use lazy_static::lazy_static;
 use rand::{CryptoRng, RngCore};
 use regex::Regex;
+use sha2::Sha512;
 use ssi::did::{DIDMethod, Source};
-use ssi::jwk::{Base64urlUInt, OctetParams, Params, JWK};
+use ssi::jwk::{Base64urlUInt, ECParams, OctetParams, Params, JWK};
+
+use crate::ldcontexts;
+
+const MNEMONIC_ENTROPY_LENGTH: usize = 32;
+const DERIVATION_COIN_TYPE: u32 = 7337;
+const HARDENED_OFFSET: u32 = 0x8000_0000;
+
+/// The curves of the keys which can be represented by DID Key and used to
+/// sign ChatterNet documents.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyCurve {
+    Ed25519,
+    P256,
+    Secp256k1,
+}
+
+impl KeyCurve {
+    /// Identify the curve of the `jwk` key.
+    pub fn from_jwk(jwk: &JWK) -> Result<Self> {
+        match &jwk.params {
+            Params::OKP(OctetParams { curve, .. }) if curve == "Ed25519" => Ok(Self::Ed25519),
+            Params::EC(ECParams {
+                curve: Some(curve), ..
+            }) if curve == "P-256" => Ok(Self::P256),
+            Params::EC(ECParams {
+                curve: Some(curve), ..
+            }) if curve == "secp256k1" => Ok(Self::Secp256k1),
+            _ => Err(Error::msg("key curve is not supported")),
+        }
+    }
+
+    /// The type of the proofs signed with keys on this curve.
+    pub fn proof_type(&self) -> &'static str {
+        match self {
+            Self::Ed25519 => "Ed25519Signature2020",
+            Self::P256 => "EcdsaSecp256r1Signature2019",
+            Self::Secp256k1 => "EcdsaSecp256k1Signature2019",
+        }
+    }
+
+    /// The URI of the JSON-LD context defining the proof type.
+    pub fn signature_context(&self) -> &'static str {
+        match self {
+            Self::Ed25519 => ldcontexts::ED25519_2020_URI,
+            Self::P256 | Self::Secp256k1 => ldcontexts::SECURITY_V2_URI,
+        }
+    }
+}
 
 /// Build a new [`JWK`] key which can be represnted by DID Key and used to
 /// represent a ChatterNet user.
 pub fn build_jwk(rng: &mut (impl CryptoRng + RngCore)) -> Result<JWK> {
     let mut secret_key_bytes = [0u8; SECRET_KEY_LENGTH];
     rng.fill_bytes(&mut secret_key_bytes);
-    let secret = SecretKey::from_bytes(&secret_key_bytes).unwrap();
+    build_jwk_from_secret(&secret_key_bytes)
+}
+
+fn build_jwk_from_secret(secret_key_bytes: &[u8; SECRET_KEY_LENGTH]) -> Result<JWK> {
+    let secret = SecretKey::from_bytes(secret_key_bytes)?;
     let public: PublicKey = (&secret).into();
     let keypair = Keypair { secret, public };
     Ok(JWK::from(Params::OKP(OctetParams {
@@ -24,6 +79,82 @@ pub fn build_jwk(rng: &mut (impl CryptoRng + RngCore)) -> Result<JWK> {
     })))
 }
 
+/// Build a new mnemonic phrase from which [`JWK`] keys can be derived with
+/// [`build_jwk_from_mnemonic`].
+pub fn build_mnemonic(rng: &mut (impl CryptoRng + RngCore)) -> Result<String> {
+    let mut entropy = [0u8; MNEMONIC_ENTROPY_LENGTH];
+    rng.fill_bytes(&mut entropy);
+    Ok(Mnemonic::from_entropy(&entropy)?.to_string())
+}
+
+/// Derive the Ed25519 [`JWK`] key at `index` from the BIP39 `mnemonic` and
+/// its `passphrase`.
+///
+/// The key is derived from the BIP39 seed following SLIP-0010, along the
+/// path `m/44'/7337'/index'`, so that the same mnemonic always rebuilds the
+/// same keys.
+pub fn build_jwk_from_mnemonic(mnemonic: &str, passphrase: &str, index: u32) -> Result<JWK> {
+    let seed = Mnemonic::parse(mnemonic)
+        .map_err(|_| Error::msg("mnemonic is invalid"))?
+        .to_seed(passphrase);
+    let (secret_key_bytes, _) = derive_slip10_ed25519(&seed, &[44, DERIVATION_COIN_TYPE, index])?;
+    build_jwk_from_secret(&secret_key_bytes)
+}
+
+/// Compute the HMAC-SHA512 of `data` with `key`, split into a key and a
+/// chain code.
+fn hmac_split(
+    key: &[u8],
+    data: &[&[u8]],
+) -> Result<([u8; SECRET_KEY_LENGTH], [u8; SECRET_KEY_LENGTH])> {
+    let mut mac =
+        Hmac::<Sha512>::new_from_slice(key).map_err(|_| Error::msg("failed to build the HMAC"))?;
+    for data in data {
+        mac.update(data);
+    }
+    let bytes = mac.finalize().into_bytes();
+    let mut key = [0u8; SECRET_KEY_LENGTH];
+    let mut chain_code = [0u8; SECRET_KEY_LENGTH];
+    key.copy_from_slice(&bytes[..SECRET_KEY_LENGTH]);
+    chain_code.copy_from_slice(&bytes[SECRET_KEY_LENGTH..]);
+    Ok((key, chain_code))
+}
+
+/// Derive the SLIP-0010 Ed25519 key and chain code at the hardened `path`
+/// from `seed`.
+fn derive_slip10_ed25519(
+    seed: &[u8],
+    path: &[u32],
+) -> Result<([u8; SECRET_KEY_LENGTH], [u8; SECRET_KEY_LENGTH])> {
+    let (mut key, mut chain_code) = hmac_split(b"ed25519 seed", &[seed])?;
+    for index in path {
+        if *index >= HARDENED_OFFSET {
+            Err(Error::msg("derivation index is too large"))?;
+        }
+        (key, chain_code) = hmac_split(
+            &chain_code,
+            &[
+                &[0u8][..],
+                &key[..],
+                &(index + HARDENED_OFFSET).to_be_bytes()[..],
+            ],
+        )?;
+    }
+    Ok((key, chain_code))
+}
+
+/// Build a new [`JWK`] key on the given `curve`.
+///
+/// Only Ed25519 keys are built from `rng`, the others use the operating
+/// system's random number generator.
+pub fn build_jwk_on_curve(curve: KeyCurve, rng: &mut (impl CryptoRng + RngCore)) -> Result<JWK> {
+    match curve {
+        KeyCurve::Ed25519 => build_jwk(rng),
+        KeyCurve::P256 => Ok(JWK::generate_p256()?),
+        KeyCurve::Secp256k1 => Ok(JWK::generate_secp256k1()?),
+    }
+}
+
 /// Build the DID representation of the `jwk` key.
 pub fn did_from_jwk(jwk: &JWK) -> Result<String> {
     DIDKey
@@ -32,12 +163,32 @@ pub fn did_from_jwk(jwk: &JWK) -> Result<String> {
 }
 
 lazy_static! {
-    static ref RE_DID: Regex = Regex::new(r"^did:key:z[a-km-zA-HJ-NP-Z1-9]+$").unwrap();
+    static ref RE_DID_KEY: Regex = Regex::new(r"^did:key:z[a-km-zA-HJ-NP-Z1-9]+$").unwrap();
+    static ref RE_DID_WEB: Regex =
+        Regex::new(r"^did:web:[a-zA-Z0-9.-]+(%3A[0-9]+)?(:[a-zA-Z0-9._-]+)*$").unwrap();
+    static ref RE_ACTOR_MENTION: Regex = Regex::new(
+        r"\b(did:key:z[a-km-zA-HJ-NP-Z1-9]+|did:web:[a-zA-Z0-9.-]+(%3A[0-9]+)?(:[a-zA-Z0-9._-]+)*)/actor\b"
+    )
+    .unwrap();
 }
 
 /// Check if the given string `did` is a valid DID Key.
+pub fn is_valid_did_key(did: &str) -> bool {
+    RE_DID_KEY.is_match(did)
+}
+
+/// Check if the given string `did` is a valid DID Web.
+///
+/// A DID Web ties an actor to a domain, and optionally a path under it, such
+/// as `did:web:example.com:org:acme`.
+pub fn is_valid_did_web(did: &str) -> bool {
+    RE_DID_WEB.is_match(did)
+}
+
+/// Check if the given string `did` is a valid DID of a method which can
+/// identify a ChatterNet actor.
 pub fn is_valid_did(did: &str) -> bool {
-    RE_DID.is_match(did)
+    is_valid_did_key(did) || is_valid_did_web(did)
 }
 
 /// Build the ChatterNet actor ID from the given `did`.
@@ -64,15 +215,95 @@ pub fn did_from_actor_id(actor_id: &str) -> Result<String> {
     Ok(did.to_string())
 }
 
+/// Find the ChatterNet actor IDs mentioned in the `text`.
+///
+/// Each actor ID is listed once, in the order of its first mention.
+pub fn find_actor_mentions(text: &str) -> Vec<String> {
+    let mut actors_id: Vec<String> = Vec::new();
+    for mention in RE_ACTOR_MENTION.find_iter(text) {
+        if !actors_id.iter().any(|x| x == mention.as_str()) {
+            actors_id.push(mention.as_str().to_string());
+        }
+    }
+    actors_id
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|x| format!("{:02x}", x)).collect()
+    }
+
     #[test]
     fn builds_jwk() {
         build_jwk(&mut rand::thread_rng()).unwrap();
     }
 
+    #[test]
+    fn derives_slip10_ed25519_keys() {
+        // test vector 1 of SLIP-0010
+        let seed = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
+            0x0e, 0x0f,
+        ];
+        let (key, chain_code) = derive_slip10_ed25519(&seed, &[]).unwrap();
+        assert_eq!(
+            hex(&key),
+            "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"
+        );
+        assert_eq!(
+            hex(&chain_code),
+            "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb"
+        );
+        let (key, _) = derive_slip10_ed25519(&seed, &[0]).unwrap();
+        assert_eq!(
+            hex(&key),
+            "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"
+        );
+        derive_slip10_ed25519(&seed, &[HARDENED_OFFSET]).unwrap_err();
+    }
+
+    #[test]
+    fn builds_jwk_from_mnemonic() {
+        let mnemonic = "abandon abandon abandon abandon abandon abandon \
+            abandon abandon abandon abandon abandon about";
+        for (passphrase, index, did) in [
+            (
+                "",
+                0,
+                "did:key:z6MksQExeAVVQ4zMas6Ayi7L6ifEn8g8yd4Y435mhMndG9ez",
+            ),
+            (
+                "",
+                1,
+                "did:key:z6MktCWyLWghCjyAF8zpq79WK9bAonydzyu3WR3pUXTwaLfS",
+            ),
+            (
+                "TREZOR",
+                0,
+                "did:key:z6MkmnFz3Xz8UGM41GR61koPZFYWJoxnJfFgzq83G4uR2SxQ",
+            ),
+        ] {
+            let jwk = build_jwk_from_mnemonic(mnemonic, passphrase, index).unwrap();
+            assert_eq!(did_from_jwk(&jwk).unwrap(), did);
+        }
+        // the checksum word is wrong
+        build_jwk_from_mnemonic(&mnemonic.replace("about", "abandon"), "", 0).unwrap_err();
+    }
+
+    #[test]
+    fn rebuilds_jwk_from_new_mnemonic() {
+        let mnemonic = build_mnemonic(&mut rand::thread_rng()).unwrap();
+        assert_eq!(mnemonic.split_whitespace().count(), 24);
+        let did = |passphrase: &str| {
+            did_from_jwk(&build_jwk_from_mnemonic(&mnemonic, passphrase, 0).unwrap()).unwrap()
+        };
+        assert_eq!(did("abc"), did("abc"));
+        assert_ne!(did("abc"), did("abd"));
+    }
+
     #[test]
     fn builds_did_from_jwk() {
         let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
@@ -80,6 +311,20 @@ mod test {
         assert!(did.starts_with("did:key:"));
     }
 
+    #[test]
+    fn builds_did_from_jwk_on_curves() {
+        for curve in [KeyCurve::Ed25519, KeyCurve::P256, KeyCurve::Secp256k1] {
+            let jwk = build_jwk_on_curve(curve, &mut rand::thread_rng()).unwrap();
+            assert_eq!(KeyCurve::from_jwk(&jwk).unwrap(), curve);
+            let did = did_from_jwk(&jwk).unwrap();
+            assert!(is_valid_did(&did));
+            assert_eq!(
+                did_from_actor_id(&actor_id_from_did(&did).unwrap()).unwrap(),
+                did
+            );
+        }
+    }
+
     #[test]
     fn transforms_did_to_and_from_actor_id() {
         assert_eq!(actor_id_from_did("did:key:za").unwrap(), "did:key:za/actor");
@@ -92,4 +337,38 @@ mod test {
         did_from_actor_id("did:key:za").unwrap_err();
         did_from_actor_id("").unwrap_err();
     }
+
+    #[test]
+    fn transforms_did_web_to_and_from_actor_id() {
+        for did in [
+            "did:web:example.com",
+            "did:web:example.com%3A8080",
+            "did:web:example.com:org:acme",
+        ] {
+            assert!(is_valid_did_web(did));
+            assert!(!is_valid_did_key(did));
+            let actor_id = actor_id_from_did(did).unwrap();
+            assert_eq!(did_from_actor_id(&actor_id).unwrap(), did);
+        }
+        actor_id_from_did("did:web:").unwrap_err();
+        actor_id_from_did("did:web:example.com/a").unwrap_err();
+        actor_id_from_did("did:web:example.com::a").unwrap_err();
+        did_from_actor_id("did:web:example.com/other").unwrap_err();
+    }
+
+    #[test]
+    fn finds_actor_mentions() {
+        assert_eq!(
+            find_actor_mentions(
+                "hi did:key:za/actor, did:key:zb/actor and did:key:za/actor \
+                but not did:key:zc or did:key:zd/actors, did:web:a.com:org/actor"
+            ),
+            [
+                "did:key:za/actor",
+                "did:key:zb/actor",
+                "did:web:a.com:org/actor"
+            ]
+        );
+        assert!(find_actor_mentions("").is_empty());
+    }
 }
@@ -0,0 +1,151 @@
+//! Encrypt data for DID Key IDs and decrypt it with their [`JWK`] keys.
+//!
+//! The Ed25519 keys of DID Key IDs are converted to their X25519
+//! equivalents. Data is encrypted with a key agreed between a new one-time
+//! X25519 key and the recipient's key, and the one-time public key is
+//! prepended to the ciphertext so that the recipient can agree on the same
+//! key.
+
+use anyhow::{Error, Result};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use hkdf::Hkdf;
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256, Sha512};
+use ssi::jwk::{OctetParams, Params, JWK};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const KEY_LENGTH: usize = 32;
+const ED25519_MULTICODEC: [u8; 2] = [0xed, 0x01];
+const KDF_INFO: &[u8] = b"chatternet-encrypt-v1";
+
+/// Build the X25519 public key equivalent to the Ed25519 key of `did`.
+pub fn x25519_public_from_did(did: &str) -> Result<PublicKey> {
+    let encoded = did
+        .strip_prefix("did:key:z")
+        .ok_or(Error::msg("DID is not a base58 DID Key"))?;
+    let decoded = bs58::decode(encoded).into_vec()?;
+    if decoded.len() != ED25519_MULTICODEC.len() + KEY_LENGTH
+        || decoded[..ED25519_MULTICODEC.len()] != ED25519_MULTICODEC
+    {
+        Err(Error::msg("DID is not an Ed25519 key"))?;
+    }
+    let mut bytes = [0u8; KEY_LENGTH];
+    bytes.copy_from_slice(&decoded[ED25519_MULTICODEC.len()..]);
+    let point = CompressedEdwardsY(bytes)
+        .decompress()
+        .ok_or(Error::msg("DID key is not a valid Ed25519 point"))?;
+    Ok(PublicKey::from(point.to_montgomery().to_bytes()))
+}
+
+/// Build the X25519 secret key equivalent to the Ed25519 secret key of `jwk`.
+pub fn x25519_secret_from_jwk(jwk: &JWK) -> Result<StaticSecret> {
+    let secret = match &jwk.params {
+        Params::OKP(OctetParams {
+            curve,
+            private_key: Some(private_key),
+            ..
+        }) if curve == "Ed25519" => &private_key.0,
+        _ => Err(Error::msg("key is not an Ed25519 secret key"))?,
+    };
+    let hash = Sha512::digest(secret);
+    let mut bytes = [0u8; KEY_LENGTH];
+    bytes.copy_from_slice(&hash[..KEY_LENGTH]);
+    Ok(StaticSecret::from(bytes))
+}
+
+fn build_cipher(
+    secret: &StaticSecret,
+    public: &PublicKey,
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> Result<ChaCha20Poly1305> {
+    let shared = secret.diffie_hellman(public);
+    let salt = Sha256::new()
+        .chain_update(ephemeral.as_bytes())
+        .chain_update(recipient.as_bytes())
+        .finalize();
+    let mut key = [0u8; KEY_LENGTH];
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(KDF_INFO, &mut key)
+        .map_err(|_| Error::msg("failed to derive encryption key"))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Encrypt `plaintext` such that only the holder of the key of `did` can
+/// decrypt it.
+pub fn encrypt_for_did(
+    plaintext: &[u8],
+    did: &str,
+    rng: &mut (impl CryptoRng + RngCore),
+) -> Result<Vec<u8>> {
+    let recipient = x25519_public_from_did(did)?;
+    let mut ephemeral_bytes = [0u8; KEY_LENGTH];
+    rng.fill_bytes(&mut ephemeral_bytes);
+    let ephemeral_secret = StaticSecret::from(ephemeral_bytes);
+    let ephemeral = PublicKey::from(&ephemeral_secret);
+    // the key is used only once so the nonce needn't be random
+    let ciphertext = build_cipher(&ephemeral_secret, &recipient, &ephemeral, &recipient)?
+        .encrypt(Nonce::from_slice(&[0u8; 12]), plaintext)
+        .map_err(|_| Error::msg("failed to encrypt"))?;
+    let mut data = ephemeral.as_bytes().to_vec();
+    data.extend(ciphertext);
+    Ok(data)
+}
+
+/// Decrypt `data` which was encrypted for the DID of `jwk`.
+pub fn decrypt_with_jwk(data: &[u8], jwk: &JWK) -> Result<Vec<u8>> {
+    if data.len() < KEY_LENGTH {
+        Err(Error::msg("encrypted data is too short"))?;
+    }
+    let secret = x25519_secret_from_jwk(jwk)?;
+    let recipient = PublicKey::from(&secret);
+    let mut ephemeral_bytes = [0u8; KEY_LENGTH];
+    ephemeral_bytes.copy_from_slice(&data[..KEY_LENGTH]);
+    let ephemeral = PublicKey::from(ephemeral_bytes);
+    build_cipher(&secret, &ephemeral, &ephemeral, &recipient)?
+        .decrypt(Nonce::from_slice(&[0u8; 12]), &data[KEY_LENGTH..])
+        .map_err(|_| Error::msg("failed to decrypt"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::didkey::{build_jwk, did_from_jwk};
+
+    #[test]
+    fn converts_did_and_jwk_to_same_x25519_key() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let public = x25519_public_from_did(&did).unwrap();
+        let secret = x25519_secret_from_jwk(&jwk).unwrap();
+        assert_eq!(public.as_bytes(), PublicKey::from(&secret).as_bytes());
+    }
+
+    #[test]
+    fn encrypts_and_decrypts() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let data = encrypt_for_did(b"abc", &did, &mut rand::thread_rng()).unwrap();
+        assert_eq!(decrypt_with_jwk(&data, &jwk).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn doesnt_decrypt_with_other_key() {
+        let jwk_1 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_2 = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_1 = did_from_jwk(&jwk_1).unwrap();
+        let data = encrypt_for_did(b"abc", &did_1, &mut rand::thread_rng()).unwrap();
+        decrypt_with_jwk(&data, &jwk_2).unwrap_err();
+    }
+
+    #[test]
+    fn doesnt_decrypt_modified_data() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let mut data = encrypt_for_did(b"abc", &did, &mut rand::thread_rng()).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        decrypt_with_jwk(&data, &jwk).unwrap_err();
+    }
+}
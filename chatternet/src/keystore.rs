@@ -0,0 +1,217 @@
+//! Store [`JWK`] keys encrypted with a passphrase.
+//!
+//! A key is encrypted with ChaCha20-Poly1305, under a key derived from the
+//! passphrase with Argon2id. The KDF parameters, salt and nonce are stored
+//! along with the ciphertext so that they can be changed for new files
+//! without breaking old ones.
+//!
+//! Key files which hold a plain JSON [`JWK`] are still read, so that they can
+//! be migrated by encrypting them.
+
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use ssi::jwk::JWK;
+
+const KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const VERSION: u32 = 1;
+const KDF: &str = "argon2id";
+const CIPHER: &str = "chacha20poly1305";
+
+/// The cost of deriving the encryption key from a passphrase.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct KdfParams {
+    /// Memory used in KiB.
+    pub memory_cost: u32,
+    /// Number of passes over the memory.
+    pub time_cost: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_cost: 64 * 1024,
+            time_cost: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+/// A [`JWK`] encrypted with a passphrase, as stored in a key file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptedKey {
+    pub version: u32,
+    pub kdf: String,
+    pub kdf_params: KdfParams,
+    pub salt: String,
+    pub cipher: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+fn build_cipher(passphrase: &str, salt: &[u8], params: &KdfParams) -> Result<ChaCha20Poly1305> {
+    let params = Params::new(
+        params.memory_cost,
+        params.time_cost,
+        params.parallelism,
+        Some(KEY_LENGTH),
+    )
+    .map_err(|_| Error::msg("KDF parameters are invalid"))?;
+    let mut key = [0u8; KEY_LENGTH];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| Error::msg("failed to derive encryption key"))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+/// Encrypt `jwk` with `passphrase`, deriving the encryption key with `params`.
+pub fn encrypt_key(
+    jwk: &JWK,
+    passphrase: &str,
+    params: KdfParams,
+    rng: &mut (impl CryptoRng + RngCore),
+) -> Result<EncryptedKey> {
+    let mut salt = [0u8; SALT_LENGTH];
+    rng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rng.fill_bytes(&mut nonce);
+    let plaintext = serde_json::to_vec(jwk)?;
+    let ciphertext = build_cipher(passphrase, &salt, &params)?
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .map_err(|_| Error::msg("failed to encrypt"))?;
+    Ok(EncryptedKey {
+        version: VERSION,
+        kdf: KDF.to_string(),
+        kdf_params: params,
+        salt: base64::encode(salt),
+        cipher: CIPHER.to_string(),
+        nonce: base64::encode(nonce),
+        ciphertext: base64::encode(ciphertext),
+    })
+}
+
+/// Decrypt the key in `encrypted` with `passphrase`.
+pub fn decrypt_key(encrypted: &EncryptedKey, passphrase: &str) -> Result<JWK> {
+    if encrypted.version != VERSION || encrypted.kdf != KDF || encrypted.cipher != CIPHER {
+        Err(Error::msg("key file format is not supported"))?;
+    }
+    let salt = base64::decode(&encrypted.salt)?;
+    let nonce = base64::decode(&encrypted.nonce)?;
+    if nonce.len() != NONCE_LENGTH {
+        Err(Error::msg("key file nonce is invalid"))?;
+    }
+    let ciphertext = base64::decode(&encrypted.ciphertext)?;
+    let plaintext = build_cipher(passphrase, &salt, &encrypted.kdf_params)?
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| Error::msg("failed to decrypt, the passphrase may be wrong"))?;
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+/// The contents of a key file, which is either encrypted or a plain [`JWK`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum KeyFile {
+    Encrypted(EncryptedKey),
+    Plain(JWK),
+}
+
+impl FromStr for KeyFile {
+    type Err = Error;
+
+    fn from_str(contents: &str) -> Result<Self> {
+        serde_json::from_str(contents).map_err(|_| Error::msg("key file is invalid"))
+    }
+}
+
+impl KeyFile {
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, Self::Encrypted(_))
+    }
+
+    /// Get the key, using `passphrase` to decrypt it only if it is encrypted.
+    pub fn unlock(&self, passphrase: impl FnOnce() -> Result<String>) -> Result<JWK> {
+        match self {
+            Self::Encrypted(encrypted) => decrypt_key(encrypted, &passphrase()?),
+            Self::Plain(jwk) => Ok(jwk.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::didkey::{build_jwk, did_from_jwk};
+
+    // keep the tests fast, the default costs are for real keys
+    const TEST_PARAMS: KdfParams = KdfParams {
+        memory_cost: 64,
+        time_cost: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn encrypts_and_decrypts_key() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let encrypted = encrypt_key(&jwk, "abc", TEST_PARAMS, &mut rand::thread_rng()).unwrap();
+        let jwk_back = decrypt_key(&encrypted, "abc").unwrap();
+        assert_eq!(
+            did_from_jwk(&jwk_back).unwrap(),
+            did_from_jwk(&jwk).unwrap()
+        );
+        // the secret isn't in the file
+        let contents = serde_json::to_string(&encrypted).unwrap();
+        assert!(!contents.contains("\"d\""));
+    }
+
+    #[test]
+    fn doesnt_decrypt_with_wrong_passphrase() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let encrypted = encrypt_key(&jwk, "abc", TEST_PARAMS, &mut rand::thread_rng()).unwrap();
+        decrypt_key(&encrypted, "abd").unwrap_err();
+    }
+
+    #[test]
+    fn doesnt_decrypt_modified_key() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let mut encrypted = encrypt_key(&jwk, "abc", TEST_PARAMS, &mut rand::thread_rng()).unwrap();
+        let mut ciphertext = base64::decode(&encrypted.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        encrypted.ciphertext = base64::encode(ciphertext);
+        decrypt_key(&encrypted, "abc").unwrap_err();
+        // nor with other KDF parameters
+        let mut encrypted = encrypt_key(&jwk, "abc", TEST_PARAMS, &mut rand::thread_rng()).unwrap();
+        encrypted.kdf_params.time_cost += 1;
+        decrypt_key(&encrypted, "abc").unwrap_err();
+    }
+
+    #[test]
+    fn reads_plain_and_encrypted_key_files() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+
+        let plain = KeyFile::from_str(&serde_json::to_string(&jwk).unwrap()).unwrap();
+        assert!(!plain.is_encrypted());
+        let jwk_back = plain
+            .unlock(|| Err(Error::msg("no passphrase needed")))
+            .unwrap();
+        assert_eq!(did_from_jwk(&jwk_back).unwrap(), did);
+
+        let encrypted = encrypt_key(&jwk, "abc", TEST_PARAMS, &mut rand::thread_rng()).unwrap();
+        let encrypted = KeyFile::from_str(&serde_json::to_string(&encrypted).unwrap()).unwrap();
+        assert!(encrypted.is_encrypted());
+        let jwk_back = encrypted.unlock(|| Ok("abc".to_string())).unwrap();
+        assert_eq!(did_from_jwk(&jwk_back).unwrap(), did);
+
+        KeyFile::from_str("{\"a\": 1}").unwrap_err();
+    }
+}
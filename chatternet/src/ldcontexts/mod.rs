@@ -3,3 +3,5 @@
 pub const ACTIVITY_STREAMS: &str = include_str!("activitystreams.jsonld");
 pub const ACTIVITY_STREAMS_URI: &str = "https://www.w3.org/ns/activitystreams";
 pub const ED25519_2020_URI: &str = "https://w3id.org/security/suites/ed25519-2020/v1";
+pub const DATA_INTEGRITY_V1_URI: &str = "https://w3id.org/security/data-integrity/v1";
+pub const SECURITY_V2_URI: &str = "https://w3id.org/security/v2";
@@ -2,11 +2,18 @@ use std::collections::HashMap;
 
 use ssi::jsonld::ContextLoader;
 
+pub mod cache;
+pub mod canon;
 pub mod cid;
+#[cfg(feature = "client")]
+pub mod client;
 pub mod didkey;
+pub mod encrypt;
+pub mod keystore;
 pub mod ldcontexts;
 pub mod model;
 pub mod proof;
+pub mod resolver;
 
 const CONTEXT_ACTIVITY_STREAMS: &str = ldcontexts::ACTIVITY_STREAMS_URI;
 const CONTEXT_SIGNATURE: &str = ldcontexts::ED25519_2020_URI;
@@ -4,6 +4,7 @@ use chrono::prelude::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{self, Value};
 use ssi::did::VerificationRelationship as ProofPurpose;
+use ssi::did_resolve::DIDResolver;
 use ssi::jsonld::{json_to_dataset, ContextLoader};
 use ssi::jwk::JWK;
 use ssi::ldp::{now_ms, LinkedDataDocument};
@@ -12,7 +13,8 @@ use ssi::rdf::DataSet;
 
 use crate::didkey::{actor_id_from_did, did_from_actor_id, did_from_jwk};
 use crate::model::Uri;
-use crate::proof::{build_proof, get_proof_did, ProofVerifier};
+use crate::proof::{build_proof, get_proof_did, is_did_web_proof, ProofVerifier};
+use crate::resolver::default_resolver;
 
 use super::document::Document;
 use super::stringmax::StringMaxChars;
@@ -68,7 +70,7 @@ impl ActorFields {
         let id = Uri::try_from(actor_id)?;
         let published = now_ms();
         let actor = ActorNoProof {
-            context: CtxSigStream::new(),
+            context: CtxSigStream::for_jwk(jwk)?,
             id,
             type_,
             published,
@@ -136,6 +138,9 @@ impl Document for ActorFields {
         &self.no_proof.id
     }
     async fn verify(&self) -> Result<()> {
+        self.verify_with(default_resolver()).await
+    }
+    async fn verify_with(&self, resolver: &dyn DIDResolver) -> Result<()> {
         let actor_id = self.id().as_str();
         let did = did_from_actor_id(actor_id)?;
         let proof_did =
@@ -143,9 +148,12 @@ impl Document for ActorFields {
         if &did != proof_did {
             Err(Error::msg("actor proof doesn't match DID"))?;
         }
-        self.verify_proof().await?;
+        self.verify_proof_with(resolver).await?;
         Ok(())
     }
+    fn uses_did_web(&self) -> bool {
+        is_did_web_proof(self.proof())
+    }
 }
 
 impl Actor for ActorFields {
@@ -17,6 +17,8 @@ pub struct CollectionFields<T> {
     #[serde(rename = "type")]
     type_: CollectionType,
     items: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_items: Option<u64>,
 }
 
 impl<T> CollectionFields<T> {
@@ -26,14 +28,23 @@ impl<T> CollectionFields<T> {
             id,
             type_,
             items,
+            total_items: None,
         }
     }
+
+    /// Set the number of items in the collection, which can be greater than
+    /// the number of items listed.
+    pub fn with_total_items(mut self, total_items: u64) -> Self {
+        self.total_items = Some(total_items);
+        self
+    }
 }
 
 pub trait Collection<T> {
     fn id(&self) -> &Uri;
     fn type_(&self) -> CollectionType;
     fn items(&self) -> &Vec<T>;
+    fn total_items(&self) -> Option<u64>;
 }
 
 impl<T> Collection<T> for CollectionFields<T> {
@@ -46,6 +57,9 @@ impl<T> Collection<T> for CollectionFields<T> {
     fn items(&self) -> &Vec<T> {
         &self.items
     }
+    fn total_items(&self) -> Option<u64> {
+        self.total_items
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
@@ -128,6 +142,20 @@ mod test {
         assert_eq!(collection.id.as_str(), "id:a");
         assert_eq!(collection.items()[0], "abc");
         assert_eq!(collection.items()[1], "def");
+        assert!(collection.total_items().is_none());
+    }
+
+    #[tokio::test]
+    async fn builds_collection_with_total_items() {
+        let collection = CollectionFields::new(
+            Uri::try_from("id:a".to_string()).unwrap(),
+            CollectionType::Collection,
+            vec!["abc"],
+        )
+        .with_total_items(2);
+        assert_eq!(collection.total_items(), Some(2));
+        let value = serde_json::to_value(&collection).unwrap();
+        assert_eq!(value.get("totalItems").unwrap().as_u64(), Some(2));
     }
 
     #[tokio::test]
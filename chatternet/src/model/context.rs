@@ -1,6 +1,11 @@
 use anyhow::Error;
 use serde::{Deserialize, Serialize};
 
+use ssi::jwk::JWK;
+
+use crate::canon::Canonicalization;
+use crate::didkey::KeyCurve;
+use crate::ldcontexts;
 use crate::{CONTEXT_ACTIVITY_STREAMS, CONTEXT_SIGNATURE};
 
 /// A context array that only contains the activity streams context.
@@ -37,16 +42,62 @@ impl CtxSigStream {
     pub fn new() -> CtxSigStream {
         CtxSigStream([CONTEXT_SIGNATURE, CONTEXT_ACTIVITY_STREAMS])
     }
+
+    /// Builds a new context with the signature context for proofs signed by
+    /// `jwk`.
+    pub fn for_jwk(jwk: &JWK) -> Result<CtxSigStream, Error> {
+        Ok(CtxSigStream([
+            KeyCurve::from_jwk(jwk)?.signature_context(),
+            CONTEXT_ACTIVITY_STREAMS,
+        ]))
+    }
+
+    /// Builds a new context for objects canonicalized with JCS, whose proofs
+    /// are signed by Ed25519 keys.
+    pub fn jcs() -> CtxSigStream {
+        CtxSigStream([ldcontexts::DATA_INTEGRITY_V1_URI, CONTEXT_ACTIVITY_STREAMS])
+    }
+
+    /// Builds a new context for proofs signed by `jwk`, with documents
+    /// canonicalized per `canonicalization`.
+    pub fn for_canonicalization(
+        jwk: &JWK,
+        canonicalization: Canonicalization,
+    ) -> Result<CtxSigStream, Error> {
+        match canonicalization {
+            Canonicalization::Urdna2015 => Self::for_jwk(jwk),
+            Canonicalization::Jcs => match KeyCurve::from_jwk(jwk)? {
+                KeyCurve::Ed25519 => Ok(Self::jcs()),
+                _ => Err(Error::msg("JCS proofs require an Ed25519 key")),
+            },
+        }
+    }
+
+    /// The canonicalization of objects with this context.
+    pub fn canonicalization(&self) -> Canonicalization {
+        if self.0[0] == ldcontexts::DATA_INTEGRITY_V1_URI {
+            Canonicalization::Jcs
+        } else {
+            Canonicalization::Urdna2015
+        }
+    }
 }
 
 impl std::convert::TryFrom<[String; 2]> for CtxSigStream {
     type Error = Error;
     /// Attempts to build a new context from a slice of strings.
     fn try_from(value: [String; 2]) -> Result<Self, Self::Error> {
-        if value[0] != CONTEXT_SIGNATURE || value[1] != CONTEXT_ACTIVITY_STREAMS {
-            Err(Error::msg("context is invalid"))?
+        let signature = [KeyCurve::Ed25519, KeyCurve::P256, KeyCurve::Secp256k1]
+            .into_iter()
+            .map(|x| x.signature_context())
+            .chain([ldcontexts::DATA_INTEGRITY_V1_URI])
+            .find(|x| *x == value[0]);
+        match signature {
+            Some(signature) if value[1] == CONTEXT_ACTIVITY_STREAMS => {
+                Ok(CtxSigStream([signature, CONTEXT_ACTIVITY_STREAMS]))
+            }
+            _ => Err(Error::msg("context is invalid")),
         }
-        Ok(CtxSigStream::new())
     }
 }
 
@@ -97,6 +148,23 @@ mod test {
         assert_eq!(value, value_back);
     }
 
+    #[test]
+    fn serializes_and_deserializes_ctx_sig_stream_other_curves() {
+        let value = json!([ldcontexts::SECURITY_V2_URI, CONTEXT_ACTIVITY_STREAMS]);
+        let ctx: CtxSigStream = serde_json::from_value(value.clone()).unwrap();
+        let value_back = serde_json::to_value(&ctx).unwrap();
+        assert_eq!(value, value_back);
+    }
+
+    #[test]
+    fn serializes_and_deserializes_ctx_sig_stream_jcs() {
+        let value = json!([ldcontexts::DATA_INTEGRITY_V1_URI, CONTEXT_ACTIVITY_STREAMS]);
+        let ctx: CtxSigStream = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(ctx.canonicalization(), Canonicalization::Jcs);
+        let value_back = serde_json::to_value(&ctx).unwrap();
+        assert_eq!(value, value_back);
+    }
+
     #[test]
     fn doesnt_serialize_invalid_ctx_sig_stream() {
         serde_json::from_value::<CtxSigStream>(json!(["a:b"])).unwrap_err();
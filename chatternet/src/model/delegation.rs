@@ -0,0 +1,282 @@
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use chrono::prelude::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+use ssi::did::VerificationRelationship as ProofPurpose;
+use ssi::did_resolve::DIDResolver;
+use ssi::jsonld::{json_to_dataset, ContextLoader};
+use ssi::jwk::JWK;
+use ssi::ldp::{now_ms, LinkedDataDocument};
+use ssi::ldp::{Error as LdpError, Proof};
+use ssi::rdf::DataSet;
+
+use crate::cid::{cid_from_json, uri_from_cid, CidVerifier};
+use crate::didkey::{actor_id_from_did, did_from_actor_id, did_from_jwk, is_valid_did};
+use crate::model::Uri;
+use crate::new_context_loader;
+use crate::proof::{build_proof, ProofVerifier};
+use crate::resolver::default_resolver;
+
+use super::vecmax::VecMax;
+use super::{ActivityType, CtxSigStream};
+
+pub type VecCapabilities = VecMax<Uri, 16>;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum DelegationType {
+    Relationship,
+}
+
+/// Build the ID of the ActivityStreams type of `type_`, which is how a
+/// delegation lists the activities it grants.
+fn capability_id(type_: ActivityType) -> Result<Uri> {
+    let name = serde_json::to_value(type_)?;
+    let name = name
+        .as_str()
+        .ok_or(Error::msg("activity type is not a string"))?;
+    Uri::try_from(format!("as:{}", name))
+}
+
+/// A grant from an actor (the subject) to a secondary DID Key (the object)
+/// to sign messages on the actor's behalf.
+///
+/// The grant is limited to the activity types listed in `relationship`, and
+/// expires at `endTime`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DelegationNoIdProof {
+    #[serde(rename = "@context")]
+    context: CtxSigStream,
+    #[serde(rename = "type")]
+    type_: DelegationType,
+    subject: Uri,
+    object: Uri,
+    relationship: VecCapabilities,
+    published: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DelegationNoId {
+    proof: Proof,
+    #[serde(flatten)]
+    no_proof: DelegationNoIdProof,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DelegationFields {
+    id: Uri,
+    #[serde(flatten)]
+    no_id: DelegationNoId,
+}
+
+impl DelegationFields {
+    /// Build a delegation signed with the actor's `jwk`, allowing the key of
+    /// `delegate_did` to sign the `capabilities` activities until `end_time`.
+    pub async fn new(
+        jwk: &JWK,
+        delegate_did: &str,
+        capabilities: &[ActivityType],
+        end_time: DateTime<Utc>,
+    ) -> Result<Self> {
+        if !is_valid_did(delegate_did) {
+            Err(Error::msg("delegate is not a valid DID"))?;
+        }
+        let did = did_from_jwk(jwk)?;
+        let subject = Uri::try_from(actor_id_from_did(&did)?)?;
+        let relationship = capabilities
+            .iter()
+            .map(|x| capability_id(*x))
+            .collect::<Result<Vec<Uri>>>()?
+            .try_into()?;
+        let delegation = DelegationNoIdProof {
+            context: CtxSigStream::for_jwk(jwk)?,
+            type_: DelegationType::Relationship,
+            subject,
+            object: Uri::try_from(delegate_did.to_string())?,
+            relationship,
+            published: now_ms(),
+            end_time,
+        };
+        let proof = build_proof(&delegation, &jwk).await?;
+        let delegation_with_proof = DelegationNoId {
+            proof,
+            no_proof: delegation,
+        };
+        let id = uri_from_cid(
+            cid_from_json(&delegation_with_proof, &mut new_context_loader(), None).await?,
+        )
+        .unwrap();
+        Ok(DelegationFields {
+            id,
+            no_id: delegation_with_proof,
+        })
+    }
+}
+
+#[async_trait]
+impl LinkedDataDocument for DelegationNoIdProof {
+    fn get_contexts(&self) -> Result<Option<String>, LdpError> {
+        Ok(serde_json::to_string(&self.context).ok())
+    }
+
+    async fn to_dataset_for_signing(
+        &self,
+        parent: Option<&(dyn LinkedDataDocument + Sync)>,
+        context_loader: &mut ContextLoader,
+    ) -> Result<DataSet, LdpError> {
+        let json = serde_json::to_string(&self)?;
+        let more_contexts = match parent {
+            Some(parent) => parent.get_contexts()?,
+            None => None,
+        };
+        Ok(json_to_dataset(&json, more_contexts.as_ref(), false, None, context_loader).await?)
+    }
+
+    fn to_value(&self) -> Result<Value, LdpError> {
+        Ok(serde_json::to_value(&self)?)
+    }
+
+    fn get_default_proof_purpose(&self) -> Option<ProofPurpose> {
+        Some(ProofPurpose::AssertionMethod)
+    }
+}
+
+impl ProofVerifier<DelegationNoIdProof> for DelegationFields {
+    fn get_proof_issuer_did(&self) -> Result<String> {
+        Ok(did_from_actor_id(self.no_id.no_proof.subject.as_str())?)
+    }
+    fn extract_proof(&self) -> Result<(&Proof, &DelegationNoIdProof)> {
+        Ok((&self.no_id.proof, &self.no_id.no_proof))
+    }
+}
+
+impl CidVerifier<DelegationNoId> for DelegationFields {
+    fn extract_cid(&self) -> Result<(&Uri, &DelegationNoId)> {
+        Ok((&self.id, &self.no_id))
+    }
+}
+
+#[async_trait]
+pub trait Delegation: CidVerifier<DelegationNoId> + ProofVerifier<DelegationNoIdProof> {
+    fn id(&self) -> &Uri;
+    fn proof(&self) -> &Proof;
+    fn context(&self) -> &CtxSigStream;
+    fn type_(&self) -> DelegationType;
+    fn subject(&self) -> &Uri;
+    fn object(&self) -> &Uri;
+    fn relationship(&self) -> &VecCapabilities;
+    fn published(&self) -> &DateTime<Utc>;
+    fn end_time(&self) -> &DateTime<Utc>;
+
+    /// Check if the delegate can sign an activity of `type_` at `time`.
+    fn allows(&self, type_: ActivityType, time: &DateTime<Utc>) -> bool {
+        if time < self.published() || time > self.end_time() {
+            return false;
+        }
+        match capability_id(type_) {
+            Ok(capability) => self.relationship().contains(&capability),
+            Err(_) => false,
+        }
+    }
+
+    async fn verify(&self) -> Result<()> {
+        self.verify_with(default_resolver()).await
+    }
+
+    /// Verify the delegation, resolving the DID of the actor with `resolver`.
+    async fn verify_with(&self, resolver: &dyn DIDResolver) -> Result<()> {
+        if !is_valid_did(self.object().as_str()) {
+            Err(Error::msg("delegate is not a valid DID"))?;
+        }
+        self.verify_cid().await?;
+        self.verify_proof_with(resolver).await?;
+        Ok(())
+    }
+}
+
+impl Delegation for DelegationFields {
+    fn id(&self) -> &Uri {
+        &self.id
+    }
+    fn proof(&self) -> &Proof {
+        &self.no_id.proof
+    }
+    fn context(&self) -> &CtxSigStream {
+        &self.no_id.no_proof.context
+    }
+    fn type_(&self) -> DelegationType {
+        self.no_id.no_proof.type_
+    }
+    fn subject(&self) -> &Uri {
+        &self.no_id.no_proof.subject
+    }
+    fn object(&self) -> &Uri {
+        &self.no_id.no_proof.object
+    }
+    fn relationship(&self) -> &VecCapabilities {
+        &self.no_id.no_proof.relationship
+    }
+    fn published(&self) -> &DateTime<Utc> {
+        &self.no_id.no_proof.published
+    }
+    fn end_time(&self) -> &DateTime<Utc> {
+        &self.no_id.no_proof.end_time
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+    use tokio;
+
+    use super::*;
+    use crate::didkey;
+
+    #[tokio::test]
+    async fn builds_and_verifies_delegation() {
+        let jwk = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_delegate = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_delegate = did_from_jwk(&jwk_delegate).unwrap();
+        let delegation = DelegationFields::new(
+            &jwk,
+            &did_delegate,
+            &[ActivityType::Create],
+            Utc::now() + Duration::days(1),
+        )
+        .await
+        .unwrap();
+        delegation.verify().await.unwrap();
+        assert_eq!(delegation.object().as_str(), did_delegate);
+        assert!(delegation.allows(ActivityType::Create, &Utc::now()));
+        assert!(!delegation.allows(ActivityType::Delete, &Utc::now()));
+        assert!(!delegation.allows(ActivityType::Create, &(Utc::now() + Duration::days(2))));
+    }
+
+    #[tokio::test]
+    async fn doesnt_verify_modified_delegation() {
+        let jwk = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_delegate = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_delegate = did_from_jwk(&jwk_delegate).unwrap();
+        let delegation = DelegationFields::new(
+            &jwk,
+            &did_delegate,
+            &[ActivityType::Create],
+            Utc::now() + Duration::days(1),
+        )
+        .await
+        .unwrap();
+        let delegation = DelegationFields {
+            id: delegation.id,
+            no_id: DelegationNoId {
+                proof: delegation.no_id.proof,
+                no_proof: DelegationNoIdProof {
+                    end_time: Utc::now() + Duration::days(365),
+                    ..delegation.no_id.no_proof
+                },
+            },
+        };
+        delegation.verify().await.unwrap_err();
+    }
+}
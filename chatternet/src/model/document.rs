@@ -1,10 +1,18 @@
-use anyhow::Result;
+use anyhow::{Error, Result};
 use async_trait::async_trait;
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use ssi::did_resolve::DIDResolver;
+use ssi::jwk::JWK;
 
+use crate::cache::VerificationCache;
 use crate::cid::{cid_from_json, uri_from_cid, CidVerifier};
+use crate::didkey::did_from_actor_id;
+use crate::encrypt::{decrypt_with_jwk, encrypt_for_did};
 use crate::model::Uri;
 use crate::new_context_loader;
+use crate::resolver::default_resolver;
 
 use super::stringmax::{StringMaxBytes, StringMaxChars};
 use super::CtxStream;
@@ -13,6 +21,45 @@ use super::CtxStream;
 pub trait Document {
     fn id(&self) -> &Uri;
     async fn verify(&self) -> Result<()>;
+
+    /// Verify the document, resolving the DIDs of its signers with
+    /// `resolver`. Documents identified by their CID have no signers.
+    async fn verify_with(&self, _resolver: &dyn DIDResolver) -> Result<()> {
+        self.verify().await
+    }
+
+    /// Check if verifying the document depends on a DID Web document, which
+    /// can change at any time.
+    fn uses_did_web(&self) -> bool {
+        false
+    }
+
+    /// Verify the document, unless an identical document was already
+    /// verified with `cache`.
+    async fn verify_cached(&self, cache: &VerificationCache) -> Result<()>
+    where
+        Self: Serialize + Sync,
+    {
+        self.verify_cached_with(cache, default_resolver()).await
+    }
+
+    /// Verify the document as [`Document::verify_cached`], resolving the
+    /// DIDs of its signers with `resolver`.
+    async fn verify_cached_with(
+        &self,
+        cache: &VerificationCache,
+        resolver: &dyn DIDResolver,
+    ) -> Result<()>
+    where
+        Self: Serialize + Sync,
+    {
+        if self.uses_did_web() {
+            return self.verify_with(resolver).await;
+        }
+        cache
+            .verify("document", self, self.verify_with(resolver))
+            .await
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -77,6 +124,8 @@ pub trait NoteMd1k: CidVerifier<NoteMd1kNoId> + Document {
     fn context(&self) -> &CtxStream;
     fn type_(&self) -> NoteType;
     fn content(&self) -> &StringMaxBytes<1024>;
+    fn attributed_to(&self) -> &Uri;
+    fn in_reply_to(&self) -> &Option<Uri>;
 }
 
 #[async_trait]
@@ -100,6 +149,116 @@ impl NoteMd1k for NoteMd1kFields {
     fn content(&self) -> &StringMaxBytes<1024> {
         &self.no_id.content
     }
+    fn attributed_to(&self) -> &Uri {
+        &self.no_id.attributed_to
+    }
+    fn in_reply_to(&self) -> &Option<Uri> {
+        &self.no_id.in_reply_to
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum EncryptedMediaType {
+    #[serde(rename = "application/x-chatternet-encrypted")]
+    Encrypted,
+}
+
+/// A note whose markdown content is encrypted for a single recipient.
+///
+/// The content is the base64 encoding of the ciphertext, so the CID verifies
+/// the ciphertext and anyone can store and serve the note without reading
+/// it. A note sent to many recipients is encrypted once for each.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptedNoteNoId {
+    #[serde(rename = "@context")]
+    context: CtxStream,
+    #[serde(rename = "type")]
+    type_: NoteType,
+    content: StringMaxBytes<2048>,
+    media_type: EncryptedMediaType,
+    attributed_to: Uri,
+    to: Uri,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncryptedNoteFields {
+    id: Uri,
+    #[serde(flatten)]
+    no_id: EncryptedNoteNoId,
+}
+
+impl EncryptedNoteFields {
+    /// Build a note with the markdown `content` encrypted for the actor `to`.
+    pub async fn new(content: String, attributed_to: Uri, to: Uri) -> Result<Self> {
+        let content: StringMaxBytes<1024> = content.try_into()?;
+        let did = did_from_actor_id(to.as_str())?;
+        let ciphertext = encrypt_for_did(content.as_bytes(), &did, &mut rand::thread_rng())?;
+        let object = EncryptedNoteNoId {
+            context: CtxStream::new(),
+            type_: NoteType::Note,
+            content: base64::encode(ciphertext).try_into()?,
+            media_type: EncryptedMediaType::Encrypted,
+            attributed_to,
+            to,
+        };
+        let id =
+            uri_from_cid(cid_from_json(&object, &mut new_context_loader(), None).await?).unwrap();
+        Ok(EncryptedNoteFields { id, no_id: object })
+    }
+
+    /// Decrypt the markdown content with the `jwk` of the recipient.
+    pub fn decrypt(&self, jwk: &JWK) -> Result<StringMaxBytes<1024>> {
+        let ciphertext = base64::decode(self.no_id.content.as_str())?;
+        let plaintext = decrypt_with_jwk(&ciphertext, jwk)?;
+        String::from_utf8(plaintext)
+            .map_err(|_| Error::msg("decrypted content is not UTF-8"))?
+            .try_into()
+    }
+}
+
+impl CidVerifier<EncryptedNoteNoId> for EncryptedNoteFields {
+    fn extract_cid(&self) -> Result<(&Uri, &EncryptedNoteNoId)> {
+        Ok((&self.id, &self.no_id))
+    }
+}
+
+#[async_trait]
+pub trait EncryptedNote: CidVerifier<EncryptedNoteNoId> + Document {
+    fn context(&self) -> &CtxStream;
+    fn type_(&self) -> NoteType;
+    fn content(&self) -> &StringMaxBytes<2048>;
+    fn attributed_to(&self) -> &Uri;
+    fn to(&self) -> &Uri;
+}
+
+#[async_trait]
+impl Document for EncryptedNoteFields {
+    fn id(&self) -> &Uri {
+        &self.id
+    }
+    async fn verify(&self) -> Result<()> {
+        self.verify_cid().await?;
+        Ok(())
+    }
+}
+
+impl EncryptedNote for EncryptedNoteFields {
+    fn context(&self) -> &CtxStream {
+        &self.no_id.context
+    }
+    fn type_(&self) -> NoteType {
+        self.no_id.type_
+    }
+    fn content(&self) -> &StringMaxBytes<2048> {
+        &self.no_id.content
+    }
+    fn attributed_to(&self) -> &Uri {
+        &self.no_id.attributed_to
+    }
+    fn to(&self) -> &Uri {
+        &self.no_id.to
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -137,6 +296,26 @@ impl Tag30Fields {
     }
 }
 
+lazy_static! {
+    static ref RE_HASHTAG: Regex = Regex::new(r"(?:^|\s)#(\w+)").unwrap();
+}
+
+/// Find the names of the tags written as `#name` in the `text`.
+///
+/// Each name is listed once, in the order of its first use. Names too long to
+/// be a [`Tag30Fields`] name are ignored.
+pub fn find_hashtags(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for captures in RE_HASHTAG.captures_iter(text) {
+        let name = &captures[1];
+        if name.chars().count() > 30 || names.iter().any(|x| x == name) {
+            continue;
+        }
+        names.push(name.to_string());
+    }
+    names
+}
+
 impl CidVerifier<Tag30NoId> for Tag30Fields {
     fn extract_cid(&self) -> Result<(&Uri, &Tag30NoId)> {
         Ok((&self.id, &self.no_id))
@@ -178,6 +357,7 @@ mod test {
     use tokio;
 
     use super::*;
+    use crate::didkey::{actor_id_from_did, build_jwk, did_from_jwk};
 
     #[tokio::test]
     async fn builds_and_verifies_note1k() {
@@ -222,6 +402,45 @@ mod test {
         document.verify().await.unwrap_err();
     }
 
+    #[tokio::test]
+    async fn builds_verifies_and_decrypts_encrypted_note() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let actor_id = actor_id_from_did(&did_from_jwk(&jwk).unwrap()).unwrap();
+        let document = EncryptedNoteFields::new(
+            "abc".to_string(),
+            "did:example:a".to_string().try_into().unwrap(),
+            actor_id.try_into().unwrap(),
+        )
+        .await
+        .unwrap();
+        document.verify().await.unwrap();
+        assert_ne!(document.content().as_str(), "abc");
+        assert_eq!(document.decrypt(&jwk).unwrap().as_str(), "abc");
+        let jwk_other = build_jwk(&mut rand::thread_rng()).unwrap();
+        document.decrypt(&jwk_other).unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn doesnt_verify_encrypted_note_modified_data() {
+        let jwk = build_jwk(&mut rand::thread_rng()).unwrap();
+        let actor_id = actor_id_from_did(&did_from_jwk(&jwk).unwrap()).unwrap();
+        let document = EncryptedNoteFields::new(
+            "abc".to_string(),
+            "did:example:a".to_string().try_into().unwrap(),
+            actor_id.try_into().unwrap(),
+        )
+        .await
+        .unwrap();
+        let document = EncryptedNoteFields {
+            id: document.id,
+            no_id: EncryptedNoteNoId {
+                content: base64::encode(b"abcd").try_into().unwrap(),
+                ..document.no_id
+            },
+        };
+        document.verify().await.unwrap_err();
+    }
+
     #[tokio::test]
     async fn builds_and_verifies_tag30() {
         let document = Tag30Fields::new("abc".to_string()).await.unwrap();
@@ -248,4 +467,14 @@ mod test {
         };
         document.verify().await.unwrap_err();
     }
+
+    #[test]
+    fn finds_hashtags() {
+        assert_eq!(
+            find_hashtags("#abc and #déf, # heading, a#b #abc\n#g_1"),
+            ["abc", "déf", "g_1"]
+        );
+        assert!(find_hashtags(&format!("#{}", "a".repeat(31))).is_empty());
+        assert!(find_hashtags("").is_empty());
+    }
 }
@@ -10,22 +10,40 @@ pub fn new_inbox(
     start_idx: u64,
     end_idx: Option<u64>,
 ) -> Result<CollectionPageFields<MessageFields>> {
-    let collection_id = format!("{}/inbox", actor_id).pipe(Uri::try_from)?;
+    new_messages_page(
+        &format!("{}/inbox", actor_id),
+        messages,
+        page_size,
+        start_idx,
+        end_idx,
+    )
+}
+
+/// Build a page of `messages` in the collection `collection_id`, ordered
+/// from most to least recent like an inbox.
+pub fn new_messages_page(
+    collection_id: &str,
+    messages: Vec<MessageFields>,
+    page_size: u64,
+    start_idx: u64,
+    end_idx: Option<u64>,
+) -> Result<CollectionPageFields<MessageFields>> {
     let id = format!(
-        "{}/inbox?startIdx={}&pageSize={}",
-        actor_id, start_idx, page_size
+        "{}?startIdx={}&pageSize={}",
+        collection_id, start_idx, page_size
     )
     .pipe(Uri::try_from)?;
     let next = match end_idx {
         Some(end_idx) => Some(
             format!(
-                "{}/inbox?startIdx={}&pageSize={}",
-                actor_id, end_idx, page_size
+                "{}?startIdx={}&pageSize={}",
+                collection_id, end_idx, page_size
             )
             .pipe(Uri::try_from)?,
         ),
         None => None,
     };
+    let collection_id = Uri::try_from(collection_id)?;
     Ok(CollectionPageFields::new(
         id,
         CollectionPageType::OrderedCollectionPage,
@@ -1,31 +1,47 @@
-use anyhow::Result;
+use anyhow::{Error, Result};
 use async_trait::async_trait;
 use chrono::prelude::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::{self, Value};
 use ssi::did::VerificationRelationship as ProofPurpose;
+use ssi::did_resolve::DIDResolver;
 use ssi::jsonld::{json_to_dataset, ContextLoader};
 use ssi::jwk::JWK;
 use ssi::ldp::{now_ms, LinkedDataDocument};
 use ssi::ldp::{Error as LdpError, Proof};
 use ssi::rdf::DataSet;
 
+use crate::cache::VerificationCache;
+use crate::canon::Canonicalization;
 use crate::cid::{cid_from_json, uri_from_cid, CidVerifier};
 use crate::didkey::{actor_id_from_did, did_from_actor_id, did_from_jwk};
 use crate::model::Uri;
 use crate::new_context_loader;
-use crate::proof::{build_proof, ProofVerifier};
+use crate::proof::{build_proof, is_did_web_proof, ProofVerifier};
+use crate::resolver::default_resolver;
 
 use super::vecmax::VecMax;
-use super::CtxSigStream;
+use super::{CtxSigStream, Delegation, DelegationFields};
 
 pub type VecUris = VecMax<Uri, 256>;
 
+/// The ID of the ActivityStreams collection addressing all actors.
+pub const PUBLIC_AUDIENCE_ID: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+/// Check if `id` is the ActivityStreams public collection, either as its full
+/// IRI or compacted with the `as` prefix.
+pub fn is_public_audience(id: &str) -> bool {
+    id == PUBLIC_AUDIENCE_ID || id == "as:Public"
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum ActivityType {
     Add,
+    Announce,
     Create,
     Delete,
+    Like,
+    Move,
     Remove,
     View,
 }
@@ -43,6 +59,8 @@ pub struct MessageNoIdProof {
     to: Option<VecUris>,
     origin: Option<VecUris>,
     target: Option<VecUris>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instrument: Option<DelegationFields>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -66,6 +84,9 @@ pub struct MessageBuilder<'a> {
     to: Option<VecUris>,
     origin: Option<VecUris>,
     target: Option<VecUris>,
+    instrument: Option<DelegationFields>,
+    canonicalization: Canonicalization,
+    published: Option<DateTime<Utc>>,
 }
 
 impl<'a> MessageBuilder<'a> {
@@ -77,6 +98,9 @@ impl<'a> MessageBuilder<'a> {
             to: None,
             origin: None,
             target: None,
+            instrument: None,
+            canonicalization: Canonicalization::default(),
+            published: None,
         }
     }
 
@@ -95,16 +119,63 @@ impl<'a> MessageBuilder<'a> {
         self
     }
 
+    /// Sign on behalf of the subject of `delegation`, which must delegate to
+    /// the builder's key.
+    pub fn delegation(mut self, delegation: DelegationFields) -> Self {
+        self.instrument = Some(delegation);
+        self
+    }
+
+    /// Canonicalize the message with `canonicalization` for its CID and
+    /// proof.
+    pub fn canonicalization(mut self, canonicalization: Canonicalization) -> Self {
+        self.canonicalization = canonicalization;
+        self
+    }
+
+    /// Set the time the message is published at, which is otherwise the time
+    /// it is built at.
+    pub fn published(mut self, published: DateTime<Utc>) -> Self {
+        self.published = Some(published);
+        self
+    }
+
     pub async fn build(self) -> Result<MessageFields> {
-        MessageFields::new(
-            self.jwk,
-            self.type_,
-            self.object,
-            self.to,
-            self.origin,
-            self.target,
+        let did = did_from_jwk(self.jwk)?;
+        let actor_id = match &self.instrument {
+            Some(delegation) => {
+                if delegation.object().as_str() != did {
+                    Err(Error::msg("delegation is not to the signing key"))?;
+                }
+                delegation.subject().clone()
+            }
+            None => Uri::try_from(actor_id_from_did(&did)?)?,
+        };
+        let published = self.published.unwrap_or_else(now_ms);
+        let message = MessageNoIdProof {
+            context: CtxSigStream::for_canonicalization(self.jwk, self.canonicalization)?,
+            type_: self.type_,
+            actor: actor_id,
+            object: self.object,
+            published,
+            to: self.to,
+            origin: self.origin,
+            target: self.target,
+            instrument: self.instrument,
+        };
+        let proof = build_proof(&message, self.jwk).await?;
+        let message_with_proof = MessageNoId {
+            proof,
+            no_proof: message,
+        };
+        let id = uri_from_cid(
+            cid_from_json(&message_with_proof, &mut new_context_loader(), None).await?,
         )
-        .await
+        .unwrap();
+        Ok(MessageFields {
+            id,
+            no_id: message_with_proof,
+        })
     }
 }
 
@@ -116,33 +187,22 @@ impl MessageFields {
         to: Option<VecUris>,
         origin: Option<VecUris>,
         target: Option<VecUris>,
+        instrument: Option<DelegationFields>,
+        canonicalization: Canonicalization,
     ) -> Result<Self> {
-        let did = did_from_jwk(jwk)?;
-        let actor_id = Uri::try_from(actor_id_from_did(&did)?)?;
-        let published = now_ms();
-        let message = MessageNoIdProof {
-            context: CtxSigStream::new(),
+        MessageBuilder {
+            jwk,
             type_,
-            actor: actor_id,
             object,
-            published,
             to,
             origin,
             target,
-        };
-        let proof = build_proof(&message, &jwk).await?;
-        let message_with_proof = MessageNoId {
-            proof,
-            no_proof: message,
-        };
-        let id = uri_from_cid(
-            cid_from_json(&message_with_proof, &mut new_context_loader(), None).await?,
-        )
-        .unwrap();
-        Ok(MessageFields {
-            id,
-            no_id: message_with_proof,
-        })
+            instrument,
+            canonicalization,
+            published: None,
+        }
+        .build()
+        .await
     }
 }
 
@@ -175,8 +235,13 @@ impl LinkedDataDocument for MessageNoIdProof {
 }
 
 impl ProofVerifier<MessageNoIdProof> for MessageFields {
+    /// The issuer is the actor, or the delegate if the message is signed on
+    /// behalf of the actor.
     fn get_proof_issuer_did(&self) -> Result<String> {
-        Ok(did_from_actor_id(self.no_id.no_proof.actor.as_str())?)
+        match &self.no_id.no_proof.instrument {
+            Some(delegation) => Ok(delegation.object().to_string()),
+            None => Ok(did_from_actor_id(self.no_id.no_proof.actor.as_str())?),
+        }
     }
     fn extract_proof(&self) -> Result<(&Proof, &MessageNoIdProof)> {
         Ok((&self.no_id.proof, &self.no_id.no_proof))
@@ -201,12 +266,63 @@ pub trait Message: CidVerifier<MessageNoId> + ProofVerifier<MessageNoIdProof> {
     fn to(&self) -> &Option<VecUris>;
     fn origin(&self) -> &Option<VecUris>;
     fn target(&self) -> &Option<VecUris>;
+    fn instrument(&self) -> &Option<DelegationFields>;
 
     async fn verify(&self) -> Result<()> {
+        self.verify_with(default_resolver()).await
+    }
+
+    /// Verify the message, resolving the DIDs of its signers with `resolver`.
+    async fn verify_with(&self, resolver: &dyn DIDResolver) -> Result<()> {
         self.verify_cid().await?;
-        self.verify_proof().await?;
+        self.verify_proof_with(resolver).await?;
+        // a delegate must have been granted this activity by the actor
+        if let Some(delegation) = self.instrument() {
+            delegation.verify_with(resolver).await?;
+            if delegation.subject() != self.actor() {
+                Err(Error::msg("delegation is not from the actor"))?;
+            }
+            if !delegation.allows(self.type_(), self.published()) {
+                Err(Error::msg("delegation does not allow the message"))?;
+            }
+        }
         Ok(())
     }
+
+    /// Verify the message, unless an identical message was already verified
+    /// with `cache`.
+    async fn verify_cached(&self, cache: &VerificationCache) -> Result<()>
+    where
+        Self: Serialize + Sync,
+    {
+        self.verify_cached_with(cache, default_resolver()).await
+    }
+
+    /// Verify the message as [`Message::verify_cached`], resolving the DIDs
+    /// of its signers with `resolver`.
+    ///
+    /// Messages signed by DID Web keys are always verified in full, as the
+    /// keys can be rotated.
+    async fn verify_cached_with(
+        &self,
+        cache: &VerificationCache,
+        resolver: &dyn DIDResolver,
+    ) -> Result<()>
+    where
+        Self: Serialize + Sync,
+    {
+        let uses_did_web = is_did_web_proof(self.proof())
+            || self
+                .instrument()
+                .as_ref()
+                .map_or(false, |x| is_did_web_proof(x.proof()));
+        if uses_did_web {
+            return self.verify_with(resolver).await;
+        }
+        cache
+            .verify("message", self, self.verify_with(resolver))
+            .await
+    }
 }
 
 impl Message for MessageFields {
@@ -240,14 +356,18 @@ impl Message for MessageFields {
     fn target(&self) -> &Option<VecUris> {
         &self.no_id.no_proof.target
     }
+    fn instrument(&self) -> &Option<DelegationFields> {
+        &self.no_id.no_proof.instrument
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use chrono::Duration;
     use tokio;
 
     use super::*;
-    use crate::didkey;
+    use crate::didkey::{self, KeyCurve};
 
     #[tokio::test]
     async fn builds_and_verifies_message() {
@@ -263,6 +383,52 @@ mod test {
         message.verify().await.unwrap();
     }
 
+    #[tokio::test]
+    async fn builds_and_verifies_message_on_curves() {
+        for curve in [KeyCurve::P256, KeyCurve::Secp256k1] {
+            let jwk = didkey::build_jwk_on_curve(curve, &mut rand::thread_rng()).unwrap();
+            let message = MessageBuilder::new(
+                &jwk,
+                ActivityType::Create,
+                vec!["id:a".try_into().unwrap()].try_into().unwrap(),
+            )
+            .build()
+            .await
+            .unwrap();
+            message.verify().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn builds_and_verifies_message_with_jcs() {
+        let jwk = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
+        let message = MessageBuilder::new(
+            &jwk,
+            ActivityType::Create,
+            vec!["id:a".try_into().unwrap()].try_into().unwrap(),
+        )
+        .canonicalization(Canonicalization::Jcs)
+        .build()
+        .await
+        .unwrap();
+        assert_eq!(message.context().canonicalization(), Canonicalization::Jcs);
+        message.verify().await.unwrap();
+        let message: MessageFields =
+            serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap();
+        message.verify().await.unwrap();
+        // JCS proofs are only built by Ed25519 keys
+        let jwk = didkey::build_jwk_on_curve(KeyCurve::P256, &mut rand::thread_rng()).unwrap();
+        MessageBuilder::new(
+            &jwk,
+            ActivityType::Create,
+            vec!["id:a".try_into().unwrap()].try_into().unwrap(),
+        )
+        .canonicalization(Canonicalization::Jcs)
+        .build()
+        .await
+        .unwrap_err();
+    }
+
     #[tokio::test]
     async fn doesnt_verify_modified_data() {
         let jwk = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
@@ -294,4 +460,54 @@ mod test {
         };
         message.verify().await.unwrap_err();
     }
+
+    #[tokio::test]
+    async fn builds_and_verifies_delegated_message() {
+        let jwk = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
+        let jwk_delegate = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let did_delegate = did_from_jwk(&jwk_delegate).unwrap();
+        let delegation = DelegationFields::new(
+            &jwk,
+            &did_delegate,
+            &[ActivityType::Create],
+            Utc::now() + Duration::days(1),
+        )
+        .await
+        .unwrap();
+        let message = MessageBuilder::new(
+            &jwk_delegate,
+            ActivityType::Create,
+            vec!["id:a".try_into().unwrap()].try_into().unwrap(),
+        )
+        .delegation(delegation.clone())
+        .build()
+        .await
+        .unwrap();
+        assert_eq!(message.actor().as_str(), actor_id_from_did(&did).unwrap());
+        message.verify().await.unwrap();
+
+        // the delegation doesn't allow deleting
+        let message = MessageBuilder::new(
+            &jwk_delegate,
+            ActivityType::Delete,
+            vec!["id:a".try_into().unwrap()].try_into().unwrap(),
+        )
+        .delegation(delegation.clone())
+        .build()
+        .await
+        .unwrap();
+        message.verify().await.unwrap_err();
+
+        // the delegation is to another key
+        MessageBuilder::new(
+            &jwk,
+            ActivityType::Create,
+            vec!["id:a".try_into().unwrap()].try_into().unwrap(),
+        )
+        .delegation(delegation)
+        .build()
+        .await
+        .unwrap_err();
+    }
 }
@@ -16,6 +16,7 @@
 mod actor;
 mod collection;
 mod context;
+mod delegation;
 mod document;
 mod inbox;
 mod message;
@@ -26,6 +27,7 @@ mod vecmax;
 pub use actor::*;
 pub use collection::*;
 pub use context::*;
+pub use delegation::*;
 pub use document::*;
 pub use inbox::*;
 pub use message::*;
@@ -1,16 +1,20 @@
 use anyhow::{Error, Result};
 use async_trait::async_trait;
-use did_method_key::DIDKey;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use ssi::did::VerificationRelationship as ProofPurpose;
-use ssi::did_resolve;
-use ssi::jwk::JWK;
+use ssi::did_resolve::{self, DIDResolver};
+use ssi::jwk::{Algorithm, JWK};
+use ssi::jws;
 use ssi::ldp::Proof;
-use ssi::ldp::{LinkedDataDocument, LinkedDataProofs};
+use ssi::ldp::{now_ms, LinkedDataDocument, LinkedDataProofs};
 use ssi::vc::{LinkedDataProofOptions, URI};
 use std::str::FromStr;
 
-use crate::didkey::did_from_jwk;
+use crate::canon::{jcs_from_json, Canonicalization};
+use crate::didkey::{did_from_jwk, KeyCurve};
 use crate::new_context_loader;
+use crate::resolver::default_resolver;
 
 use std::fmt::Debug;
 
@@ -21,27 +25,121 @@ pub fn get_proof_did(proof: &Proof) -> Option<&str> {
     proof.verification_method.as_ref()?.split('#').next()
 }
 
+/// Check if the `proof` is signed by a DID Web key.
+///
+/// DID Web documents can change at any time, so the outcome of verifying
+/// such a proof can't be remembered.
+pub fn is_did_web_proof(proof: &Proof) -> bool {
+    get_proof_did(proof).map_or(false, |x| x.starts_with("did:web:"))
+}
+
+/// The type of proofs over documents canonicalized with JCS.
+pub const JCS_PROOF_TYPE: &str = "JcsEd25519Signature2020";
+
+/// Build the bytes signed by a JCS proof: the hash of the proof options
+/// followed by the hash of the `document`.
+fn jcs_signing_input(proof: &Proof, document: &Value) -> Result<Vec<u8>> {
+    let mut options = serde_json::to_value(proof)?;
+    options
+        .as_object_mut()
+        .ok_or(Error::msg("proof is not an object"))?
+        .remove("proofValue");
+    let mut input = Sha256::digest(jcs_from_json(&options)?.as_bytes()).to_vec();
+    input.extend(Sha256::digest(jcs_from_json(document)?.as_bytes()));
+    Ok(input)
+}
+
+/// Build the JCS `Proof` of `document` signed by the Ed25519 `jwk` key.
+fn build_jcs_proof(document: &Value, jwk: &JWK, verification_method: &str) -> Result<Proof> {
+    if KeyCurve::from_jwk(jwk)? != KeyCurve::Ed25519 {
+        Err(Error::msg("JCS proofs require an Ed25519 key"))?;
+    }
+    let mut proof: Proof = serde_json::from_value(json!({
+        "type": JCS_PROOF_TYPE,
+        "proofPurpose": "assertionMethod",
+        "verificationMethod": verification_method,
+        "created": now_ms(),
+    }))?;
+    let signature = jws::sign_bytes(Algorithm::EdDSA, &jcs_signing_input(&proof, document)?, jwk)?;
+    proof.proof_value = Some(format!("z{}", bs58::encode(signature).into_string()));
+    Ok(proof)
+}
+
+/// Verify the JCS `proof` of `document`.
+async fn verify_jcs_proof(
+    proof: &Proof,
+    document: &Value,
+    proof_purpose: ProofPurpose,
+    resolver: &dyn DIDResolver,
+) -> Result<()> {
+    if serde_json::to_value(proof)?
+        .get("type")
+        .and_then(|x| x.as_str())
+        != Some(JCS_PROOF_TYPE)
+    {
+        Err(Error::msg("proof type doesn't match canonicalization"))?;
+    }
+    if proof.proof_purpose.as_ref() != Some(&proof_purpose) {
+        Err(Error::msg("proof purpose doesn't match"))?;
+    }
+    let verification_method = proof
+        .verification_method
+        .as_ref()
+        .ok_or(Error::msg("proof cannot be verified"))?;
+    let signature = proof
+        .proof_value
+        .as_ref()
+        .and_then(|x| x.strip_prefix('z'))
+        .ok_or(Error::msg("proof has no value"))?;
+    let signature = bs58::decode(signature).into_vec()?;
+    let key = did_resolve::resolve_key(verification_method, resolver).await?;
+    jws::verify_bytes(
+        Algorithm::EdDSA,
+        &jcs_signing_input(proof, document)?,
+        &key,
+        &signature,
+    )?;
+    Ok(())
+}
+
 /// Build the `Proof` for a `LinkedDataDocument`.
 pub async fn build_proof(
     document: &(impl LinkedDataDocument + Sync + Debug),
     jwk: &JWK,
 ) -> Result<Proof> {
     let did = did_from_jwk(jwk)?;
+    build_proof_with(document, jwk, &did, default_resolver()).await
+}
+
+/// Build the `Proof` for a `LinkedDataDocument` signed by the `jwk` key on
+/// behalf of `did`, whose document is found with `resolver`.
+///
+/// The document is canonicalized per its context, see [`Canonicalization`].
+pub async fn build_proof_with(
+    document: &(impl LinkedDataDocument + Sync + Debug),
+    jwk: &JWK,
+    did: &str,
+    resolver: &dyn DIDResolver,
+) -> Result<Proof> {
     let mut options = LinkedDataProofOptions::default();
     let proof_purpose = ProofPurpose::AssertionMethod;
     let verification_methods =
-        did_resolve::get_verification_methods(&did, proof_purpose, &DIDKey).await?;
+        did_resolve::get_verification_methods(did, proof_purpose, resolver).await?;
     let verification_method = verification_methods
         .keys()
         .next()
         .ok_or(Error::msg("document has no verification method"))?;
+    let value = document.to_value()?;
+    if Canonicalization::from_json(&value, None) == Canonicalization::Jcs {
+        return build_jcs_proof(&value, jwk, verification_method);
+    }
     let verification_method = URI::from_str(verification_method)?;
-    options.type_ = Some("Ed25519Signature2020".to_string());
+    options.type_ = Some(KeyCurve::from_jwk(jwk)?.proof_type().to_string());
     options.verification_method = Some(verification_method);
     Ok(LinkedDataProofs::sign(
         document,
         &options,
-        &DIDKey,
+        resolver,
         &mut new_context_loader(),
         &jwk,
         None,
@@ -61,6 +159,12 @@ pub trait ProofVerifier<WithoutProof: LinkedDataDocument + Sync> {
 
     /// Verify the object's proof.
     async fn verify_proof(&self) -> Result<()> {
+        self.verify_proof_with(default_resolver()).await
+    }
+
+    /// Verify the object's proof, finding the issuer's DID document with
+    /// `resolver`.
+    async fn verify_proof_with(&self, resolver: &dyn DIDResolver) -> Result<()> {
         let issuer = self.get_proof_issuer_did()?.as_str().to_owned();
 
         let (proof, without_proof) = self.extract_proof()?;
@@ -70,7 +174,7 @@ pub trait ProofVerifier<WithoutProof: LinkedDataDocument + Sync> {
             .ok_or(Error::msg("proof has no purpose"))?;
 
         let verification_methods =
-            did_resolve::get_verification_methods(&issuer, proof_purpose, &DIDKey).await?;
+            did_resolve::get_verification_methods(&issuer, proof_purpose, resolver).await?;
         match &proof.verification_method {
             Some(verification_method) => {
                 if !verification_methods.contains_key(verification_method) {
@@ -82,7 +186,16 @@ pub trait ProofVerifier<WithoutProof: LinkedDataDocument + Sync> {
             }
         };
 
-        LinkedDataProofs::verify(proof, without_proof, &DIDKey, &mut new_context_loader()).await?;
+        let value = without_proof.to_value()?;
+        match Canonicalization::from_json(&value, None) {
+            Canonicalization::Jcs => {
+                verify_jcs_proof(proof, &value, proof_purpose, resolver).await?;
+            }
+            Canonicalization::Urdna2015 => {
+                LinkedDataProofs::verify(proof, without_proof, resolver, &mut new_context_loader())
+                    .await?;
+            }
+        }
 
         Ok(())
     }
@@ -98,7 +211,8 @@ mod test {
     use tokio;
 
     use super::*;
-    use crate::{didkey, CONTEXT_ACTIVITY_STREAMS};
+    use crate::resolver::{Resolver, StaticDidFetcher};
+    use crate::{didkey, ldcontexts, CONTEXT_ACTIVITY_STREAMS};
 
     #[derive(Debug, Serialize)]
     #[serde(rename_all = "camelCase")]
@@ -187,4 +301,87 @@ mod test {
         };
         data_with_proof.verify_proof().await.unwrap_err();
     }
+
+    #[tokio::test]
+    async fn builds_and_verifies_jcs_proof() {
+        let jwk = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let data = Data {
+            context: vec![
+                ldcontexts::DATA_INTEGRITY_V1_URI.to_string(),
+                CONTEXT_ACTIVITY_STREAMS.to_string(),
+            ],
+            id: URI::try_from(did).unwrap(),
+            content: "abc".to_string(),
+        };
+        let proof = build_proof(&data, &jwk).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(&proof).unwrap()["type"],
+            JCS_PROOF_TYPE
+        );
+        let data_with_proof = DataWithProof { proof, data };
+        data_with_proof.verify_proof().await.unwrap();
+        let data_with_proof = DataWithProof {
+            data: Data {
+                content: "abcd".to_string(),
+                ..data_with_proof.data
+            },
+            ..data_with_proof
+        };
+        data_with_proof.verify_proof().await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn doesnt_verify_jcs_proof_of_other_canonicalization() {
+        let jwk = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
+        let did = did_from_jwk(&jwk).unwrap();
+        let data = Data {
+            context: vec![CONTEXT_ACTIVITY_STREAMS.to_string()],
+            id: URI::try_from(did).unwrap(),
+            content: "abc".to_string(),
+        };
+        let proof = build_proof(&data, &jwk).await.unwrap();
+        let data_with_proof = DataWithProof {
+            proof,
+            data: Data {
+                context: vec![
+                    ldcontexts::DATA_INTEGRITY_V1_URI.to_string(),
+                    CONTEXT_ACTIVITY_STREAMS.to_string(),
+                ],
+                ..data
+            },
+        };
+        data_with_proof.verify_proof().await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn builds_and_verifies_proof_for_did_web() {
+        let jwk = didkey::build_jwk(&mut rand::thread_rng()).unwrap();
+        let did_key = did_from_jwk(&jwk).unwrap();
+        let did = "did:web:example.com";
+        let (_, document, _) = did_method_key::DIDKey
+            .resolve(&did_key, &did_resolve::ResolutionInputMetadata::default())
+            .await;
+        let document = serde_json::to_string(&document.unwrap())
+            .unwrap()
+            .replace(&did_key, did);
+        let mut fetcher = StaticDidFetcher::new();
+        fetcher.insert("https://example.com/.well-known/did.json", &document);
+        let resolver = Resolver::new(std::sync::Arc::new(fetcher));
+
+        let data = Data {
+            context: vec![CONTEXT_ACTIVITY_STREAMS.to_string()],
+            id: URI::try_from(did.to_string()).unwrap(),
+            content: "abc".to_string(),
+        };
+        let proof = build_proof_with(&data, &jwk, did, &resolver).await.unwrap();
+        assert!(is_did_web_proof(&proof));
+        assert!(!is_did_web_proof(
+            &build_proof_with(&data, &jwk, &did_key, &resolver)
+                .await
+                .unwrap()
+        ));
+        let data_with_proof = DataWithProof { proof, data };
+        data_with_proof.verify_proof_with(&resolver).await.unwrap();
+    }
 }
@@ -0,0 +1,497 @@
+//! Resolve DIDs to their DID documents.
+//!
+//! ChatterNet actors are identified by DIDs. DID Key documents are derived
+//! from the DID itself, while DID Web documents are fetched from the domain
+//! named in the DID, which lets an organisation tie its actor to a domain.
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Error, Result};
+use async_trait::async_trait;
+use did_method_key::DIDKey;
+use lazy_static::lazy_static;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use ssi::did::Document;
+use ssi::did_resolve::{
+    DIDResolver, DocumentMetadata, ResolutionInputMetadata, ResolutionMetadata, ERROR_INVALID_DID,
+    ERROR_METHOD_NOT_SUPPORTED, ERROR_NOT_FOUND,
+};
+
+use crate::didkey::is_valid_did;
+
+/// The longest time to wait for a DID document to be fetched.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// The largest DID document which is fetched.
+const FETCH_MAX_BYTES: usize = 64 * 1024;
+/// How long a fetched DID document is used before it is fetched again.
+const DOCUMENTS_TTL: Duration = Duration::from_secs(5 * 60);
+/// The number of fetched DID documents to remember.
+const DOCUMENTS_CAPACITY: usize = 1024;
+
+/// Retrieve the DID document published at a URL.
+#[async_trait]
+pub trait DidFetcher: Send + Sync {
+    async fn fetch(&self, url: &str) -> Result<String>;
+}
+
+/// Check if `ip` is an address on the public internet.
+///
+/// DID Web documents are fetched from domains named by anyone, so this keeps
+/// the fetches from reaching the loopback, private and link-local networks
+/// of the host.
+pub fn is_public_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // shared address space
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_ip(&IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local
+                || (first & 0xfe00) == 0xfc00
+                // link local
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Resolve host names only to public addresses, so that a name can't point
+/// the fetcher at the host's own network.
+struct PublicDnsResolver;
+
+impl PublicDnsResolver {
+    async fn lookup(name: Name) -> Result<Addrs, Box<dyn StdError + Send + Sync>> {
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+            .await?
+            .filter(|x| is_public_ip(&x.ip()))
+            .collect();
+        if addrs.is_empty() {
+            Err(Error::msg("host has no public address"))?;
+        }
+        Ok(Box::new(addrs.into_iter()))
+    }
+}
+
+impl Resolve for PublicDnsResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(Self::lookup(name))
+    }
+}
+
+/// Fetch DID documents over HTTP.
+///
+/// The fetches are limited in time and size, don't follow redirects, and
+/// reach only public addresses.
+#[derive(Debug)]
+pub struct HttpDidFetcher {
+    client: reqwest::Client,
+    max_bytes: usize,
+}
+
+impl HttpDidFetcher {
+    /// Build a fetcher which gives up on a document after `timeout`, or once
+    /// it is larger than `max_bytes`.
+    pub fn new(timeout: Duration, max_bytes: usize) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicDnsResolver))
+            .build()?;
+        Ok(Self { client, max_bytes })
+    }
+}
+
+impl Default for HttpDidFetcher {
+    fn default() -> Self {
+        Self::new(FETCH_TIMEOUT, FETCH_MAX_BYTES).expect("HTTP client can be built")
+    }
+}
+
+#[async_trait]
+impl DidFetcher for HttpDidFetcher {
+    async fn fetch(&self, url: &str) -> Result<String> {
+        // hosts given as addresses aren't looked up, so are checked here
+        let url = reqwest::Url::parse(url)?;
+        let host = url.host_str().ok_or(Error::msg("URL has no host"))?;
+        if let Ok(ip) = host
+            .trim_matches(|x| x == '[' || x == ']')
+            .parse::<IpAddr>()
+        {
+            if !is_public_ip(&ip) {
+                Err(Error::msg("host is not a public address"))?;
+            }
+        }
+        let mut response = self
+            .client
+            .get(url)
+            .header(reqwest::header::ACCEPT, "application/did+json")
+            .send()
+            .await?
+            .error_for_status()?;
+        if response
+            .content_length()
+            .map_or(false, |x| x > self.max_bytes as u64)
+        {
+            Err(Error::msg("DID document is too large"))?;
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > self.max_bytes {
+                Err(Error::msg("DID document is too large"))?;
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(String::from_utf8(body)?)
+    }
+}
+
+/// Serve DID documents from memory, keyed by URL.
+///
+/// Stands in for [`HttpDidFetcher`] where there is no network, such as in
+/// tests.
+#[derive(Debug, Default)]
+pub struct StaticDidFetcher {
+    documents: HashMap<String, String>,
+}
+
+impl StaticDidFetcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, url: &str, document: &str) {
+        self.documents.insert(url.to_string(), document.to_string());
+    }
+}
+
+#[async_trait]
+impl DidFetcher for StaticDidFetcher {
+    async fn fetch(&self, url: &str) -> Result<String> {
+        self.documents
+            .get(url)
+            .cloned()
+            .ok_or(Error::msg("no document at URL"))
+    }
+}
+
+/// Build the URL at which the document of the DID Web `did` is published.
+///
+/// `did:web:example.com` resolves to
+/// `https://example.com/.well-known/did.json`, and
+/// `did:web:example.com:a:b` resolves to `https://example.com/a/b/did.json`.
+pub fn did_web_url(did: &str) -> Result<String> {
+    let id = did
+        .strip_prefix("did:web:")
+        .ok_or(Error::msg("DID is not a DID Web"))?;
+    let mut parts = id.split(':');
+    let domain = parts
+        .next()
+        .filter(|x| !x.is_empty())
+        .ok_or(Error::msg("DID Web has no domain"))?
+        .replace("%3A", ":");
+    let path = parts.collect::<Vec<&str>>();
+    if path.iter().any(|x| x.is_empty()) {
+        Err(Error::msg("DID Web has an empty path segment"))?;
+    }
+    if path.is_empty() {
+        Ok(format!("https://{}/.well-known/did.json", domain))
+    } else {
+        Ok(format!("https://{}/{}/did.json", domain, path.join("/")))
+    }
+}
+
+/// Resolve the DIDs which can identify ChatterNet actors: DID Key, and
+/// DID Web through the given [`DidFetcher`].
+///
+/// DID Web documents are remembered for a few minutes, so that verifying
+/// many objects by the same actor fetches its document only once, while a
+/// change to the document still takes effect.
+#[derive(Clone)]
+pub struct Resolver {
+    fetcher: Arc<dyn DidFetcher>,
+    documents: Arc<Mutex<HashMap<String, (Instant, Document)>>>,
+}
+
+impl Resolver {
+    pub fn new(fetcher: Arc<dyn DidFetcher>) -> Self {
+        Self {
+            fetcher,
+            documents: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn get_document(&self, did: &str) -> Option<Document> {
+        let documents = self.documents.lock().unwrap();
+        match documents.get(did) {
+            Some((fetched, document)) if fetched.elapsed() < DOCUMENTS_TTL => {
+                Some(document.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn put_document(&self, did: &str, document: &Document) {
+        let mut documents = self.documents.lock().unwrap();
+        if documents.len() >= DOCUMENTS_CAPACITY {
+            documents.retain(|_, (fetched, _)| fetched.elapsed() < DOCUMENTS_TTL);
+        }
+        if documents.len() >= DOCUMENTS_CAPACITY {
+            let oldest = documents
+                .iter()
+                .min_by_key(|(_, (fetched, _))| *fetched)
+                .map(|(did, _)| did.clone());
+            if let Some(oldest) = oldest {
+                documents.remove(&oldest);
+            }
+        }
+        documents.insert(did.to_string(), (Instant::now(), document.clone()));
+    }
+
+    async fn resolve_web(&self, did: &str) -> Result<Document> {
+        if let Some(document) = self.get_document(did) {
+            return Ok(document);
+        }
+        let url = did_web_url(did)?;
+        let document = self.fetcher.fetch(&url).await?;
+        let document: Document = serde_json::from_str(&document)?;
+        if document.id != did {
+            Err(Error::msg("DID document ID doesn't match DID"))?;
+        }
+        self.put_document(did, &document);
+        Ok(document)
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new(Arc::new(HttpDidFetcher::default()))
+    }
+}
+
+impl std::fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Resolver").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl DIDResolver for Resolver {
+    async fn resolve(
+        &self,
+        did: &str,
+        input_metadata: &ResolutionInputMetadata,
+    ) -> (
+        ResolutionMetadata,
+        Option<Document>,
+        Option<DocumentMetadata>,
+    ) {
+        if !is_valid_did(did) {
+            return (
+                ResolutionMetadata::from_error(ERROR_INVALID_DID),
+                None,
+                None,
+            );
+        }
+        if did.starts_with("did:key:") {
+            return DIDKey.resolve(did, input_metadata).await;
+        }
+        if did.starts_with("did:web:") {
+            return match self.resolve_web(did).await {
+                Ok(document) => (
+                    ResolutionMetadata::default(),
+                    Some(document),
+                    Some(DocumentMetadata::default()),
+                ),
+                Err(_) => (ResolutionMetadata::from_error(ERROR_NOT_FOUND), None, None),
+            };
+        }
+        (
+            ResolutionMetadata::from_error(ERROR_METHOD_NOT_SUPPORTED),
+            None,
+            None,
+        )
+    }
+}
+
+lazy_static! {
+    static ref DEFAULT_RESOLVER: Resolver = Resolver::default();
+}
+
+/// The resolver used when none is given, which fetches DID Web documents
+/// over HTTP.
+pub fn default_resolver() -> &'static Resolver {
+    &DEFAULT_RESOLVER
+}
+
+#[cfg(test)]
+mod test {
+    use tokio;
+
+    use super::*;
+    use crate::didkey::{build_jwk, did_from_jwk};
+
+    /// Build the document of the DID Web `did` controlled by the key of the
+    /// DID Key `did_key`.
+    async fn build_did_web_document(did: &str, did_key: &str) -> String {
+        let (_, document, _) = DIDKey
+            .resolve(did_key, &ResolutionInputMetadata::default())
+            .await;
+        serde_json::to_string(&document.unwrap())
+            .unwrap()
+            .replace(did_key, did)
+    }
+
+    #[test]
+    fn builds_did_web_url() {
+        assert_eq!(
+            did_web_url("did:web:example.com").unwrap(),
+            "https://example.com/.well-known/did.json"
+        );
+        assert_eq!(
+            did_web_url("did:web:example.com%3A8080").unwrap(),
+            "https://example.com:8080/.well-known/did.json"
+        );
+        assert_eq!(
+            did_web_url("did:web:example.com:org:acme").unwrap(),
+            "https://example.com/org/acme/did.json"
+        );
+        did_web_url("did:web:").unwrap_err();
+        did_web_url("did:web:example.com::a").unwrap_err();
+        did_web_url("did:key:za").unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn resolves_did_key() {
+        let did = did_from_jwk(&build_jwk(&mut rand::thread_rng()).unwrap()).unwrap();
+        let resolver = Resolver::new(Arc::new(StaticDidFetcher::new()));
+        let (_, document, _) = resolver
+            .resolve(&did, &ResolutionInputMetadata::default())
+            .await;
+        assert_eq!(document.unwrap().id, did);
+    }
+
+    #[tokio::test]
+    async fn resolves_did_web_with_fetcher() {
+        let did_key = did_from_jwk(&build_jwk(&mut rand::thread_rng()).unwrap()).unwrap();
+        let did = "did:web:example.com";
+        let mut fetcher = StaticDidFetcher::new();
+        fetcher.insert(
+            "https://example.com/.well-known/did.json",
+            &build_did_web_document(did, &did_key).await,
+        );
+        fetcher.insert(
+            "https://example.com/other/did.json",
+            &build_did_web_document("did:web:other.com", &did_key).await,
+        );
+        let resolver = Resolver::new(Arc::new(fetcher));
+        let (_, document, _) = resolver
+            .resolve(did, &ResolutionInputMetadata::default())
+            .await;
+        assert_eq!(document.unwrap().id, did);
+        // document doesn't match the DID
+        let (_, document, _) = resolver
+            .resolve(
+                "did:web:example.com:other",
+                &ResolutionInputMetadata::default(),
+            )
+            .await;
+        assert!(document.is_none());
+        // document doesn't exist
+        let (_, document, _) = resolver
+            .resolve("did:web:missing.com", &ResolutionInputMetadata::default())
+            .await;
+        assert!(document.is_none());
+    }
+
+    #[test]
+    fn checks_public_ip() {
+        for ip in ["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public_ip(&ip.parse().unwrap()));
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(&ip.parse().unwrap()));
+        }
+    }
+
+    #[tokio::test]
+    async fn http_fetcher_rejects_private_hosts() {
+        let fetcher = HttpDidFetcher::default();
+        for url in [
+            "https://127.0.0.1/.well-known/did.json",
+            "https://[::1]/.well-known/did.json",
+            "https://localhost/.well-known/did.json",
+        ] {
+            let error = fetcher.fetch(url).await.unwrap_err();
+            assert!(format!("{:?}", error).contains("public"));
+        }
+    }
+
+    #[tokio::test]
+    async fn remembers_did_web_documents() {
+        struct CountingFetcher {
+            inner: StaticDidFetcher,
+            count: Mutex<usize>,
+        }
+
+        #[async_trait]
+        impl DidFetcher for CountingFetcher {
+            async fn fetch(&self, url: &str) -> Result<String> {
+                *self.count.lock().unwrap() += 1;
+                self.inner.fetch(url).await
+            }
+        }
+
+        let did_key = did_from_jwk(&build_jwk(&mut rand::thread_rng()).unwrap()).unwrap();
+        let did = "did:web:example.com";
+        let mut inner = StaticDidFetcher::new();
+        inner.insert(
+            "https://example.com/.well-known/did.json",
+            &build_did_web_document(did, &did_key).await,
+        );
+        let fetcher = Arc::new(CountingFetcher {
+            inner,
+            count: Mutex::new(0),
+        });
+        let resolver = Resolver::new(fetcher.clone());
+        for _ in 0..2 {
+            let (_, document, _) = resolver
+                .resolve(did, &ResolutionInputMetadata::default())
+                .await;
+            assert_eq!(document.unwrap().id, did);
+        }
+        assert_eq!(*fetcher.count.lock().unwrap(), 1);
+    }
+}